@@ -3,6 +3,16 @@ import type { IDL } from '@dfinity/candid';
 import type { Principal } from '@dfinity/principal';
 
 export type Address = string;
+export interface AddressMeta {
+  canonical: Address;
+  network: string;
+  address_type: AddressType;
+}
+export type AddressType =
+  | { P2wpkh: null }
+  | { P2pkh: null }
+  | { P2sh: null }
+  | { P2tr: null };
 export type CanisterPublicKey = PublicKey;
 export interface Delegation {
   pubkey: PublicKey;
@@ -11,54 +21,199 @@ export interface Delegation {
 }
 export type GetAddressResponse = { Ok: Address } | { Err: string };
 export type GetDelegationResponse = { Ok: SignedDelegation } | { Err: string };
-export type GetPrincipalResponse = { Ok: Principal } | { Err: string };
+export type GetLoginCountResponse = { Ok: bigint } | { Err: string };
+export type GetPrincipalResponse =
+  | { Ok: Uint8Array | number[] }
+  | { Err: string };
+export type HasPendingMessageResponse =
+  | { Ok: [] | [Timestamp] }
+  | { Err: string };
+export interface LoginBundle {
+  login_details: LoginDetails;
+  signed_delegation: [] | [SignedDelegation];
+}
+export type LoginBundleResponse = { Ok: LoginBundle } | { Err: string };
 export interface LoginDetails {
+  request_id: [] | [string];
+  public_key: [] | [PublickeyHex];
   user_canister_pubkey: CanisterPublicKey;
+  address_type: AddressType;
+  targets: [] | [Array<Principal>];
   expiration: Timestamp;
 }
 export type LoginResponse = { Ok: LoginDetails } | { Err: string };
-export type PrepareLoginResponse = { Ok: SiwbMessage } | { Err: string };
+export type LoginStatus =
+  | { Ready: null }
+  | { Expired: null }
+  | { Pending: null };
+export type LoginStatusResponse = { Ok: LoginStatus } | { Err: string };
+export interface PrepareLoginDetails {
+  challenge_token: string;
+  siwb_message: SiwbMessage;
+  expiration: Timestamp;
+  siwb_message_json: string;
+}
+export type PrepareLoginError =
+  | { InvalidAddress: string }
+  | { MessageTooLarge: bigint }
+  | { NetworkMismatch: null }
+  | { DomainNotAllowed: null }
+  | { NotInitialized: null }
+  | { AddressTypeNotAllowed: null }
+  | { Other: string }
+  | { IssuedAtOutOfBounds: null };
+export type PrepareLoginFullResponse = { Ok: PreparedLogin } | { Err: string };
+export interface PrepareLoginOptions {
+  uri: [] | [string];
+  domain: [] | [string];
+  statement: [] | [string];
+  nonce: [] | [string];
+}
+export type PrepareLoginResponse =
+  | { Ok: PrepareLoginDetails }
+  | { Err: PrepareLoginError };
+export interface PreparedLogin {
+  digest_hex: string;
+  message: string;
+}
+export type PreviewExpirationResponse = { Ok: Timestamp } | { Err: string };
 export type PublicKey = Uint8Array | number[];
 export type PublickeyHex = string;
+export type RevokeDelegationResponse = { Ok: null } | { Err: string };
+export type RotateSaltResponse = { Ok: bigint } | { Err: string };
 export type RuntimeFeature =
   | { IncludeUriInSeed: null }
   | { DisableEthToPrincipalMapping: null }
   | { DisablePrincipalToEthMapping: null };
 export type SessionKey = PublicKey;
+export type SessionKeyResponse = { Ok: boolean } | { Err: string };
 export interface SettingsInput {
   uri: string;
+  clock_skew: [] | [bigint];
   runtime_features: [] | [Array<RuntimeFeature>];
   domain: string;
+  message_prefix: [] | [string];
+  allowed_statements: [] | [Array<string>];
   statement: [] | [string];
   scheme: [] | [string];
+  allow_legacy_ecdsa: [] | [boolean];
+  max_message_bytes: [] | [bigint];
   salt: string;
+  login_retry_window: [] | [bigint];
+  max_issued_at_skew: [] | [bigint];
   network: [] | [string];
   session_expires_in: [] | [bigint];
+  seed_version: [] | [number];
+  message_hash_tag: [] | [string];
   targets: [] | [Array<string>];
+  allowed_domains: [] | [Array<string>];
+  allowed_networks: [] | [Array<string>];
+  min_session_expires_in: [] | [bigint];
+  strict_network: [] | [boolean];
+  allowed_session_keys: [] | [Array<SessionKey>];
+  allowed_address_types: [] | [Array<AddressType>];
+  allowed_uris: [] | [Array<string>];
+  strict_message_parsing: [] | [boolean];
   sign_in_expires_in: [] | [bigint];
+  double_hash: [] | [boolean];
+  expiration_rounding: [] | [bigint];
+  bind_canister_id: [] | [boolean];
+}
+export type SignMessageType =
+  | { Bip322Simple: null }
+  | { ECDSA: null }
+  | { TaggedHash: null };
+export interface SignatureMapStats {
+  len: bigint;
+  total_delegations_issued: bigint;
+  root_hex: string;
 }
-export type SignMessageType = { Bip322Simple: null } | { ECDSA: null };
 export interface SignedDelegation {
   signature: Uint8Array | number[];
   delegation: Delegation;
 }
+export interface SiwbLoginOptions {
+  recovery_id: [] | [number];
+  challenge_token: [] | [string];
+  store_mapping: [] | [boolean];
+  wallet: [] | [WalletKind];
+  public_keys: [] | [Array<PublickeyHex>];
+  realm: [] | [string];
+}
 export type SiwbMessage = string;
 export type SiwbSignature = string;
 export type String = string;
 export type Timestamp = bigint;
+export type ValidateAddressResponse = { Ok: AddressMeta } | { Err: string };
+export type WalletKind =
+  | { Auto: null }
+  | { HexRecoveryIdLast: null }
+  | { Base64: null };
 export interface _SERVICE {
-  get_address: ActorMethod<[Principal, string], GetAddressResponse>;
-  get_caller_address: ActorMethod<[[] | [string]], GetAddressResponse>;
+  allow_session_key: ActorMethod<[SessionKey], SessionKeyResponse>;
+  disallow_session_key: ActorMethod<[SessionKey], SessionKeyResponse>;
+  get_address: ActorMethod<
+    [Uint8Array | number[], String],
+    GetAddressResponse
+  >;
+  get_caller_address: ActorMethod<[[] | [String]], GetAddressResponse>;
+  get_login_count: ActorMethod<[Address], GetLoginCountResponse>;
   get_principal: ActorMethod<[Address], GetPrincipalResponse>;
+  is_initialized: ActorMethod<[], boolean>;
+  list_pending_messages: ActorMethod<
+    [],
+    Array<[Uint8Array | number[], Timestamp]>
+  >;
+  preview_expiration: ActorMethod<[Address], PreviewExpirationResponse>;
+  prune_signatures: ActorMethod<[bigint], bigint>;
+  prune_sigs: ActorMethod<[], undefined>;
+  rotate_salt: ActorMethod<[string], RotateSaltResponse>;
+  signature_map_stats: ActorMethod<[], SignatureMapStats>;
   siwb_get_delegation: ActorMethod<
-    [Address, SessionKey, Timestamp],
+    [Address, SessionKey, Timestamp, [] | [string]],
     GetDelegationResponse
   >;
+  siwb_has_pending_message: ActorMethod<[Address], HasPendingMessageResponse>;
   siwb_login: ActorMethod<
-    [SiwbSignature, Address, PublickeyHex, SessionKey, SignMessageType],
+    [
+      SiwbSignature,
+      Address,
+      PublickeyHex,
+      SessionKey,
+      SignMessageType,
+      [] | [SiwbLoginOptions],
+    ],
     LoginResponse
   >;
-  siwb_prepare_login: ActorMethod<[Address], PrepareLoginResponse>;
+  siwb_login_by_address: ActorMethod<
+    [SiwbSignature, Address, SessionKey, [] | [SiwbLoginOptions]],
+    LoginResponse
+  >;
+  siwb_login_status: ActorMethod<[Address, [] | [string]], LoginStatusResponse>;
+  siwb_login_with_delegation: ActorMethod<
+    [
+      SiwbSignature,
+      Address,
+      PublickeyHex,
+      SessionKey,
+      SignMessageType,
+      [] | [SiwbLoginOptions],
+    ],
+    LoginBundleResponse
+  >;
+  siwb_prepare_login: ActorMethod<
+    [Address, [] | [Timestamp], [] | [string], [] | [string]],
+    PrepareLoginResponse
+  >;
+  siwb_prepare_login_full: ActorMethod<
+    [Address, [] | [PrepareLoginOptions]],
+    PrepareLoginFullResponse
+  >;
+  siwb_revoke_delegation: ActorMethod<
+    [Address, SessionKey, Timestamp, [] | [string]],
+    RevokeDelegationResponse
+  >;
+  validate_address: ActorMethod<[Address], ValidateAddressResponse>;
 }
 
 export const idlFactory: IDL.InterfaceFactory = ({ IDL }) => {
@@ -67,31 +222,73 @@ export const idlFactory: IDL.InterfaceFactory = ({ IDL }) => {
     DisableEthToPrincipalMapping: IDL.Null,
     DisablePrincipalToEthMapping: IDL.Null,
   });
-
-  // eslint-disable-next-line @typescript-eslint/no-unused-vars, no-unused-vars
+  const PublicKey = IDL.Vec(IDL.Nat8);
+  const SessionKey = PublicKey;
+  const AddressType = IDL.Variant({
+    P2wpkh: IDL.Null,
+    P2pkh: IDL.Null,
+    P2sh: IDL.Null,
+    P2tr: IDL.Null,
+  });
   const SettingsInput = IDL.Record({
     uri: IDL.Text,
+    clock_skew: IDL.Opt(IDL.Nat64),
     runtime_features: IDL.Opt(IDL.Vec(RuntimeFeature)),
     domain: IDL.Text,
+    message_prefix: IDL.Opt(IDL.Text),
+    allowed_statements: IDL.Opt(IDL.Vec(IDL.Text)),
     statement: IDL.Opt(IDL.Text),
     scheme: IDL.Opt(IDL.Text),
+    allow_legacy_ecdsa: IDL.Opt(IDL.Bool),
+    max_message_bytes: IDL.Opt(IDL.Nat64),
     salt: IDL.Text,
+    login_retry_window: IDL.Opt(IDL.Nat64),
+    max_issued_at_skew: IDL.Opt(IDL.Nat64),
     network: IDL.Opt(IDL.Text),
     session_expires_in: IDL.Opt(IDL.Nat64),
+    seed_version: IDL.Opt(IDL.Nat8),
+    message_hash_tag: IDL.Opt(IDL.Text),
     targets: IDL.Opt(IDL.Vec(IDL.Text)),
+    allowed_domains: IDL.Opt(IDL.Vec(IDL.Text)),
+    allowed_networks: IDL.Opt(IDL.Vec(IDL.Text)),
+    min_session_expires_in: IDL.Opt(IDL.Nat64),
+    strict_network: IDL.Opt(IDL.Bool),
+    allowed_session_keys: IDL.Opt(IDL.Vec(SessionKey)),
+    allowed_address_types: IDL.Opt(IDL.Vec(AddressType)),
+    allowed_uris: IDL.Opt(IDL.Vec(IDL.Text)),
+    strict_message_parsing: IDL.Opt(IDL.Bool),
     sign_in_expires_in: IDL.Opt(IDL.Nat64),
+    double_hash: IDL.Opt(IDL.Bool),
+    expiration_rounding: IDL.Opt(IDL.Nat64),
+    bind_canister_id: IDL.Opt(IDL.Bool),
   });
+  const SessionKeyResponse = IDL.Variant({ Ok: IDL.Bool, Err: IDL.Text });
   const Principal = IDL.Vec(IDL.Nat8);
   const String = IDL.Text;
   const Address = IDL.Text;
   const GetAddressResponse = IDL.Variant({ Ok: Address, Err: IDL.Text });
+  const GetLoginCountResponse = IDL.Variant({
+    Ok: IDL.Nat64,
+    Err: IDL.Text,
+  });
   const GetPrincipalResponse = IDL.Variant({
     Ok: Principal,
     Err: IDL.Text,
   });
-  const PublicKey = IDL.Vec(IDL.Nat8);
-  const SessionKey = PublicKey;
   const Timestamp = IDL.Nat64;
+  const PreviewExpirationResponse = IDL.Variant({
+    Ok: Timestamp,
+    Err: IDL.Text,
+  });
+  const RotateSaltResponse = IDL.Variant({
+    Ok: IDL.Nat64,
+    Err: IDL.Text,
+  });
+  const SignatureMapStats = IDL.Record({
+    len: IDL.Nat64,
+    total_delegations_issued: IDL.Nat64,
+    root_hex: IDL.Text,
+  });
   const Delegation = IDL.Record({
     pubkey: PublicKey,
     targets: IDL.Opt(IDL.Vec(IDL.Principal)),
@@ -105,42 +302,191 @@ export const idlFactory: IDL.InterfaceFactory = ({ IDL }) => {
     Ok: SignedDelegation,
     Err: IDL.Text,
   });
+  const HasPendingMessageResponse = IDL.Variant({
+    Ok: IDL.Opt(Timestamp),
+    Err: IDL.Text,
+  });
   const SiwbSignature = IDL.Text;
   const PublickeyHex = IDL.Text;
   const SignMessageType = IDL.Variant({
     Bip322Simple: IDL.Null,
     ECDSA: IDL.Null,
+    TaggedHash: IDL.Null,
+  });
+  const WalletKind = IDL.Variant({
+    Auto: IDL.Null,
+    HexRecoveryIdLast: IDL.Null,
+    Base64: IDL.Null,
+  });
+  const SiwbLoginOptions = IDL.Record({
+    recovery_id: IDL.Opt(IDL.Nat8),
+    challenge_token: IDL.Opt(IDL.Text),
+    store_mapping: IDL.Opt(IDL.Bool),
+    wallet: IDL.Opt(WalletKind),
+    public_keys: IDL.Opt(IDL.Vec(PublickeyHex)),
+    realm: IDL.Opt(IDL.Text),
   });
   const CanisterPublicKey = PublicKey;
   const LoginDetails = IDL.Record({
+    request_id: IDL.Opt(IDL.Text),
+    public_key: IDL.Opt(PublickeyHex),
     user_canister_pubkey: CanisterPublicKey,
+    address_type: AddressType,
+    targets: IDL.Opt(IDL.Vec(IDL.Principal)),
     expiration: Timestamp,
   });
   const LoginResponse = IDL.Variant({ Ok: LoginDetails, Err: IDL.Text });
+  const LoginStatus = IDL.Variant({
+    Ready: IDL.Null,
+    Expired: IDL.Null,
+    Pending: IDL.Null,
+  });
+  const LoginStatusResponse = IDL.Variant({
+    Ok: LoginStatus,
+    Err: IDL.Text,
+  });
+  const LoginBundle = IDL.Record({
+    login_details: LoginDetails,
+    signed_delegation: IDL.Opt(SignedDelegation),
+  });
+  const LoginBundleResponse = IDL.Variant({
+    Ok: LoginBundle,
+    Err: IDL.Text,
+  });
   const SiwbMessage = IDL.Text;
+  const PrepareLoginDetails = IDL.Record({
+    challenge_token: IDL.Text,
+    siwb_message: SiwbMessage,
+    expiration: Timestamp,
+    siwb_message_json: IDL.Text,
+  });
+  const PrepareLoginError = IDL.Variant({
+    InvalidAddress: IDL.Text,
+    MessageTooLarge: IDL.Nat64,
+    NetworkMismatch: IDL.Null,
+    DomainNotAllowed: IDL.Null,
+    NotInitialized: IDL.Null,
+    AddressTypeNotAllowed: IDL.Null,
+    Other: IDL.Text,
+    IssuedAtOutOfBounds: IDL.Null,
+  });
   const PrepareLoginResponse = IDL.Variant({
-    Ok: SiwbMessage,
+    Ok: PrepareLoginDetails,
+    Err: PrepareLoginError,
+  });
+  const PrepareLoginOptions = IDL.Record({
+    uri: IDL.Opt(IDL.Text),
+    domain: IDL.Opt(IDL.Text),
+    statement: IDL.Opt(IDL.Text),
+    nonce: IDL.Opt(IDL.Text),
+  });
+  const PreparedLogin = IDL.Record({
+    digest_hex: IDL.Text,
+    message: IDL.Text,
+  });
+  const PrepareLoginFullResponse = IDL.Variant({
+    Ok: PreparedLogin,
+    Err: IDL.Text,
+  });
+  const RevokeDelegationResponse = IDL.Variant({
+    Ok: IDL.Null,
+    Err: IDL.Text,
+  });
+  const AddressMeta = IDL.Record({
+    canonical: Address,
+    network: IDL.Text,
+    address_type: AddressType,
+  });
+  const ValidateAddressResponse = IDL.Variant({
+    Ok: AddressMeta,
     Err: IDL.Text,
   });
   return IDL.Service({
+    allow_session_key: IDL.Func([SessionKey], [SessionKeyResponse], []),
+    disallow_session_key: IDL.Func([SessionKey], [SessionKeyResponse], []),
     get_address: IDL.Func([Principal, String], [GetAddressResponse], ['query']),
     get_caller_address: IDL.Func(
       [IDL.Opt(String)],
       [GetAddressResponse],
       ['query'],
     ),
+    get_login_count: IDL.Func([Address], [GetLoginCountResponse], ['query']),
     get_principal: IDL.Func([Address], [GetPrincipalResponse], ['query']),
+    is_initialized: IDL.Func([], [IDL.Bool], ['query']),
+    list_pending_messages: IDL.Func(
+      [],
+      [IDL.Vec(IDL.Tuple(IDL.Vec(IDL.Nat8), Timestamp))],
+      ['query'],
+    ),
+    preview_expiration: IDL.Func(
+      [Address],
+      [PreviewExpirationResponse],
+      ['query'],
+    ),
+    prune_signatures: IDL.Func([IDL.Nat64], [IDL.Nat64], []),
+    prune_sigs: IDL.Func([], [], []),
+    rotate_salt: IDL.Func([IDL.Text], [RotateSaltResponse], []),
+    signature_map_stats: IDL.Func([], [SignatureMapStats], ['query']),
     siwb_get_delegation: IDL.Func(
-      [Address, SessionKey, Timestamp],
+      [Address, SessionKey, Timestamp, IDL.Opt(IDL.Text)],
       [GetDelegationResponse],
       ['query'],
     ),
+    siwb_has_pending_message: IDL.Func(
+      [Address],
+      [HasPendingMessageResponse],
+      ['query'],
+    ),
     siwb_login: IDL.Func(
-      [SiwbSignature, Address, PublickeyHex, SessionKey, SignMessageType],
+      [
+        SiwbSignature,
+        Address,
+        PublickeyHex,
+        SessionKey,
+        SignMessageType,
+        IDL.Opt(SiwbLoginOptions),
+      ],
+      [LoginResponse],
+      [],
+    ),
+    siwb_login_by_address: IDL.Func(
+      [SiwbSignature, Address, SessionKey, IDL.Opt(SiwbLoginOptions)],
       [LoginResponse],
       [],
     ),
-    siwb_prepare_login: IDL.Func([Address], [PrepareLoginResponse], []),
+    siwb_login_status: IDL.Func(
+      [Address, IDL.Opt(IDL.Text)],
+      [LoginStatusResponse],
+      ['query'],
+    ),
+    siwb_login_with_delegation: IDL.Func(
+      [
+        SiwbSignature,
+        Address,
+        PublickeyHex,
+        SessionKey,
+        SignMessageType,
+        IDL.Opt(SiwbLoginOptions),
+      ],
+      [LoginBundleResponse],
+      [],
+    ),
+    siwb_prepare_login: IDL.Func(
+      [Address, IDL.Opt(Timestamp), IDL.Opt(IDL.Text), IDL.Opt(IDL.Text)],
+      [PrepareLoginResponse],
+      [],
+    ),
+    siwb_prepare_login_full: IDL.Func(
+      [Address, IDL.Opt(PrepareLoginOptions)],
+      [PrepareLoginFullResponse],
+      [],
+    ),
+    siwb_revoke_delegation: IDL.Func(
+      [Address, SessionKey, Timestamp, IDL.Opt(IDL.Text)],
+      [RevokeDelegationResponse],
+      [],
+    ),
+    validate_address: IDL.Func([Address], [ValidateAddressResponse], ['query']),
   });
 };
 export const init: (args: { IDL: typeof IDL }) => IDL.Type[] = ({ IDL }) => {
@@ -149,17 +495,45 @@ export const init: (args: { IDL: typeof IDL }) => IDL.Type[] = ({ IDL }) => {
     DisableEthToPrincipalMapping: IDL.Null,
     DisablePrincipalToEthMapping: IDL.Null,
   });
+  const PublicKey = IDL.Vec(IDL.Nat8);
+  const SessionKey = PublicKey;
+  const AddressType = IDL.Variant({
+    P2wpkh: IDL.Null,
+    P2pkh: IDL.Null,
+    P2sh: IDL.Null,
+    P2tr: IDL.Null,
+  });
   const SettingsInput = IDL.Record({
     uri: IDL.Text,
+    clock_skew: IDL.Opt(IDL.Nat64),
     runtime_features: IDL.Opt(IDL.Vec(RuntimeFeature)),
     domain: IDL.Text,
+    message_prefix: IDL.Opt(IDL.Text),
+    allowed_statements: IDL.Opt(IDL.Vec(IDL.Text)),
     statement: IDL.Opt(IDL.Text),
     scheme: IDL.Opt(IDL.Text),
+    allow_legacy_ecdsa: IDL.Opt(IDL.Bool),
+    max_message_bytes: IDL.Opt(IDL.Nat64),
     salt: IDL.Text,
+    login_retry_window: IDL.Opt(IDL.Nat64),
+    max_issued_at_skew: IDL.Opt(IDL.Nat64),
     network: IDL.Opt(IDL.Text),
     session_expires_in: IDL.Opt(IDL.Nat64),
+    seed_version: IDL.Opt(IDL.Nat8),
+    message_hash_tag: IDL.Opt(IDL.Text),
     targets: IDL.Opt(IDL.Vec(IDL.Text)),
+    allowed_domains: IDL.Opt(IDL.Vec(IDL.Text)),
+    allowed_networks: IDL.Opt(IDL.Vec(IDL.Text)),
+    min_session_expires_in: IDL.Opt(IDL.Nat64),
+    strict_network: IDL.Opt(IDL.Bool),
+    allowed_session_keys: IDL.Opt(IDL.Vec(SessionKey)),
+    allowed_address_types: IDL.Opt(IDL.Vec(AddressType)),
+    allowed_uris: IDL.Opt(IDL.Vec(IDL.Text)),
+    strict_message_parsing: IDL.Opt(IDL.Bool),
     sign_in_expires_in: IDL.Opt(IDL.Nat64),
+    double_hash: IDL.Opt(IDL.Bool),
+    expiration_rounding: IDL.Opt(IDL.Nat64),
+    bind_canister_id: IDL.Opt(IDL.Bool),
   });
   return [SettingsInput];
 };
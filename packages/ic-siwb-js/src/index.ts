@@ -1,5 +1,6 @@
 export { createActor } from './declarations';
 export type {
+  PrepareLoginError,
   SignMessageType as SignMessageRawType,
   _SERVICE as SIWB_IDENTITY_SERVICE,
 } from './declarations/ic_siwb_provider.did';
@@ -10,6 +10,7 @@ import type { IDL } from '@dfinity/candid';
 
 import type {
   _SERVICE as SIWB_IDENTITY_SERVICE,
+  PrepareLoginError,
   SignMessageType as SignMessageRawType,
 } from './declarations/ic_siwb_provider.did';
 
@@ -47,6 +48,28 @@ export function createAnonymousActor({
   });
 }
 
+/**
+ * Turns a `siwb_prepare_login` error into a message to show to the user.
+ */
+export function prepareLoginErrorMessage(error: PrepareLoginError): string {
+  if ('InvalidAddress' in error) return error.InvalidAddress;
+  if ('MessageTooLarge' in error) {
+    return `Message exceeds the maximum length of ${error.MessageTooLarge} bytes`;
+  }
+  if ('NetworkMismatch' in error) {
+    return 'Address network does not match the configured network';
+  }
+  if ('AddressTypeNotAllowed' in error) {
+    return 'Address type is not allowed to sign in';
+  }
+  if ('DomainNotAllowed' in error) return 'Domain is not allowed to sign in';
+  if ('IssuedAtOutOfBounds' in error) {
+    return 'Issued at is too far from the current time';
+  }
+  if ('NotInitialized' in error) return 'Settings are not initialized';
+  return error.Other;
+}
+
 export async function callPrepareLogin(
   anonymousActor: ActorSubclass<SIWB_IDENTITY_SERVICE>,
   address: string | undefined,
@@ -55,13 +78,13 @@ export async function callPrepareLogin(
     throw new Error('Invalid actor or address');
   }
 
-  const response = await anonymousActor.siwb_prepare_login(address);
+  const response = await anonymousActor.siwb_prepare_login(address, [], [], []);
 
   if ('Err' in response) {
-    throw new Error(response.Err);
+    throw new Error(prepareLoginErrorMessage(response.Err));
   }
 
-  return response.Ok;
+  return response.Ok.siwb_message;
 }
 
 /**
@@ -91,6 +114,7 @@ export async function callLogin(
     publickeyHex,
     new Uint8Array(sessionPublicKey),
     signMessageType,
+    [],
   );
 
   if ('Err' in loginReponse) {
@@ -118,6 +142,7 @@ export async function callGetDelegation(
     address,
     new Uint8Array(sessionPublicKey),
     expiration,
+    [],
   );
 
   if ('Err' in response) {
@@ -4,6 +4,12 @@ import type { Principal } from '@dfinity/principal';
 export type Address = string;
 export type PublickeyHex = string;
 
+export type AddressType =
+  | { P2wpkh: null }
+  | { P2pkh: null }
+  | { P2sh: null }
+  | { P2tr: null };
+
 export type CanisterPublicKey = PublicKey;
 
 export interface Delegation {
@@ -15,13 +21,36 @@ export interface Delegation {
 export type GetDelegationResponse = { Ok: SignedDelegation } | { Err: string };
 
 export interface LoginOkResponse {
+  request_id: [] | [string];
+  public_key: [] | [PublickeyHex];
   user_canister_pubkey: CanisterPublicKey;
+  address_type: AddressType;
+  targets: [] | [Array<Principal>];
   expiration: Timestamp;
 }
 
 export type LoginResponse = { Ok: LoginOkResponse } | { Err: string };
 
-export type PrepareLoginResponse = { Ok: SiwbMessage } | { Err: string };
+export interface PrepareLoginDetails {
+  challenge_token: string;
+  siwb_message: SiwbMessage;
+  expiration: Timestamp;
+  siwb_message_json: string;
+}
+
+export type PrepareLoginError =
+  | { InvalidAddress: string }
+  | { MessageTooLarge: bigint }
+  | { NetworkMismatch: null }
+  | { DomainNotAllowed: null }
+  | { NotInitialized: null }
+  | { AddressTypeNotAllowed: null }
+  | { Other: string }
+  | { IssuedAtOutOfBounds: null };
+
+export type PrepareLoginResponse =
+  | { Ok: PrepareLoginDetails }
+  | { Err: PrepareLoginError };
 
 export type PublicKey = Uint8Array | number[];
 
@@ -32,7 +61,19 @@ export interface SignedDelegation {
   delegation: Delegation;
 }
 
-export type SignMessageType = { Bip322Simple: null } | { ECDSA: null };
+export type SignMessageType =
+  | { Bip322Simple: null }
+  | { ECDSA: null }
+  | { TaggedHash: null };
+
+export interface SiwbLoginOptions {
+  recovery_id: [] | [number];
+  challenge_token: [] | [string];
+  store_mapping: [] | [boolean];
+  wallet: [] | [WalletKind];
+  public_keys: [] | [Array<PublickeyHex>];
+  realm: [] | [string];
+}
 
 export type SiwbMessage = string;
 
@@ -40,14 +81,29 @@ export type SiwbSignature = string;
 
 export type Timestamp = bigint;
 
+export type WalletKind =
+  | { Auto: null }
+  | { HexRecoveryIdLast: null }
+  | { Base64: null };
+
 export interface SIWB_IDENTITY_SERVICE {
-  siwb_prepare_login: ActorMethod<[Address], PrepareLoginResponse>;
+  siwb_prepare_login: ActorMethod<
+    [Address, [] | [Timestamp], [] | [string], [] | [string]],
+    PrepareLoginResponse
+  >;
   siwb_login: ActorMethod<
-    [SiwbSignature, Address, PublickeyHex, SessionKey, SignMessageType],
+    [
+      SiwbSignature,
+      Address,
+      PublickeyHex,
+      SessionKey,
+      SignMessageType,
+      [] | [SiwbLoginOptions],
+    ],
     LoginResponse
   >;
   siwb_get_delegation: ActorMethod<
-    [Address, SessionKey, Timestamp],
+    [Address, SessionKey, Timestamp, [] | [string]],
     GetDelegationResponse
   >;
 }
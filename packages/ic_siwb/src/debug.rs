@@ -0,0 +1,44 @@
+//! Diagnostic logging for integrators, only compiled in with the `debug` feature. Log lines must never
+//! contain secrets, only details such as header bytes and lengths that help match a wallet's
+//! signature format.
+
+#[cfg(test)]
+thread_local! {
+    // Tests cannot read the canister log, so log lines are collected here instead.
+    static TEST_LOG: std::cell::RefCell<Vec<String>> = const { std::cell::RefCell::new(Vec::new()) };
+}
+
+/// Writes `line` to the canister log, or to stderr outside of a canister.
+pub(crate) fn log(line: String) {
+    #[cfg(target_arch = "wasm32")]
+    ic_cdk::println!("{}", line);
+    #[cfg(not(target_arch = "wasm32"))]
+    eprintln!("{}", line);
+
+    #[cfg(test)]
+    TEST_LOG.with_borrow_mut(|log| log.push(line));
+}
+
+/// Returns and clears the lines logged by the current test.
+#[cfg(test)]
+pub(crate) fn take_test_log() -> Vec<String> {
+    TEST_LOG.with_borrow_mut(std::mem::take)
+}
+
+/// Returns the number of instructions executed in the current message, see
+/// [ic_cdk::api::performance_counter].
+#[cfg(not(test))]
+pub(crate) fn instruction_counter() -> u64 {
+    ic_cdk::api::performance_counter(0)
+}
+
+#[cfg(test)]
+thread_local! {
+    // Tests do not run in a canister, so elapsed nanoseconds stand in for instructions.
+    static TEST_START: std::time::Instant = std::time::Instant::now();
+}
+
+#[cfg(test)]
+pub(crate) fn instruction_counter() -> u64 {
+    TEST_START.with(|start| start.elapsed().as_nanos() as u64)
+}
@@ -23,6 +23,8 @@ pub enum DelegationError {
     SerializationError(String),
     InvalidSessionKey(String),
     InvalidExpiration(String),
+    CertificatePending,
+    InvalidDelegation(String),
 }
 
 impl fmt::Display for DelegationError {
@@ -38,6 +40,11 @@ impl fmt::Display for DelegationError {
             DelegationError::SerializationError(e) => write!(f, "Serialization error: {}", e),
             DelegationError::InvalidSessionKey(e) => write!(f, "Invalid session key: {}", e),
             DelegationError::InvalidExpiration(e) => write!(f, "Invalid expiration: {}", e),
+            DelegationError::CertificatePending => write!(
+                f,
+                "Certificate pending: the login has not been certified yet, retry the request"
+            ),
+            DelegationError::InvalidDelegation(e) => write!(f, "Invalid delegation: {}", e),
         }
     }
 }
@@ -67,17 +74,33 @@ struct CertificateSignature<'a> {
     tree: HashTree<'a>,
 }
 
-/// Generates a unique seed for delegation, derived from the salt, Bitcoin address, and SIWB message URI.
+/// Generates a unique seed for delegation, derived from the salt, Bitcoin address, SIWB message URI,
+/// realm and network.
+///
+/// Seed version 1 is the original derivation. Later versions are prefixed with the version byte, so
+/// each version derives different principals and the inputs of a version never change once released.
+///
+/// Addresses of allowed networks other than the primary network, see
+/// [crate::settings::Settings::network], also mix their network into the seed, so that principals
+/// differ across networks. Addresses of the primary network derive the same seed as before several
+/// networks could be allowed.
 ///
 /// # Parameters
 /// * `address`: The Bitcoin address as a string slice.
+/// * `realm`: An optional tag that separates independent login realms, e.g. different apps behind one
+///   provider. The same address derives a different principal in each realm. `None` derives the same
+///   seed as before realms were introduced.
 ///
 /// # Returns
 /// A `Hash` value representing the unique seed.
-pub fn generate_seed(address: &Address) -> Hash {
+pub fn generate_seed(address: &Address, realm: Option<&str>) -> Hash {
     with_settings!(|settings: &Settings| {
         let mut seed: Vec<u8> = vec![];
 
+        if settings.seed_version > 1 {
+            seed.push(settings.seed_version);
+        }
+
         let salt = settings.salt.as_bytes();
         seed.push(salt.len() as u8);
         seed.extend_from_slice(salt);
@@ -97,10 +120,82 @@ pub fn generate_seed(address: &Address) -> Hash {
             _ => (),
         }
 
+        if let Some(realm) = realm {
+            let realm = realm.as_bytes();
+            seed.push(realm.len() as u8);
+            seed.extend_from_slice(realm);
+        }
+
+        if let Some(network) = settings
+            .address_network(address)
+            .filter(|network| *network != settings.network())
+        {
+            let network = network.to_string();
+            seed.push(network.len() as u8);
+            seed.extend_from_slice(network.as_bytes());
+        }
+
+        #[cfg(feature = "stretched-seed")]
+        if let Some(ref stretching) = settings.seed_stretching {
+            seed = stretch_seed(&seed, salt, stretching);
+        }
+
         hash::hash_bytes(seed)
     })
 }
 
+/// Stretches the seed derivation input with scrypt. The parameters are validated by the
+/// [crate::settings::SettingsBuilder].
+#[cfg(feature = "stretched-seed")]
+fn stretch_seed(seed: &[u8], salt: &[u8], stretching: &crate::settings::SeedStretching) -> Vec<u8> {
+    let params = scrypt::Params::new(stretching.log_n, stretching.r, stretching.p)
+        .expect("Seed stretching parameters are validated by the settings builder");
+    let mut output = vec![0u8; 32];
+    scrypt::scrypt(seed, salt, &params, &mut output)
+        .expect("32 bytes is a valid scrypt output length");
+    output
+}
+
+/// The maximum length of a session key in bytes. DER-encoded public keys of the signature schemes
+/// supported by the IC are well below this.
+pub const MAX_SESSION_KEY_LEN: usize = 256;
+
+/// Checks that `session_key` is a DER-encoded `SubjectPublicKeyInfo`, i.e. a sequence of an algorithm
+/// identifier and a non-empty bit string, and no longer than [MAX_SESSION_KEY_LEN]. A delegation to any
+/// other key could never be used.
+pub fn validate_session_key(session_key: &[u8]) -> Result<(), DelegationError> {
+    let invalid = DelegationError::InvalidSessionKey;
+
+    if session_key.is_empty() {
+        return Err(invalid("Session key is empty".to_string()));
+    }
+    if session_key.len() > MAX_SESSION_KEY_LEN {
+        return Err(invalid(format!(
+            "Session key is longer than {} bytes",
+            MAX_SESSION_KEY_LEN
+        )));
+    }
+
+    let blocks = from_der(session_key)
+        .map_err(|e| invalid(format!("Session key should be DER-encoded: {}", e)))?;
+    match blocks.as_slice() {
+        [ASN1Block::Sequence(_, info)] => match info.as_slice() {
+            [ASN1Block::Sequence(_, algorithm), ASN1Block::BitString(_, len, _)]
+                if matches!(algorithm.first(), Some(ASN1Block::ObjectIdentifier(_, _)))
+                    && *len > 0 =>
+            {
+                Ok(())
+            }
+            _ => Err(invalid(
+                "Session key should be a SubjectPublicKeyInfo".to_string(),
+            )),
+        },
+        _ => Err(invalid(
+            "Session key should be a single SubjectPublicKeyInfo".to_string(),
+        )),
+    }
+}
+
 /// Creates a delegation with the provided session key and expiration, including a list of canisters for identity delegation.
 ///
 /// # Parameters
@@ -111,16 +206,7 @@ pub fn create_delegation(
     expiration: u64,
 ) -> Result<Delegation, DelegationError> {
     // Validate the session key and expiration
-    if session_key.is_empty() {
-        return Err(DelegationError::InvalidSessionKey(
-            "Session key is empty".to_string(),
-        ));
-    }
-
-    // Validate the session key is DER-encoded
-    from_der(&session_key).map_err(|e| {
-        DelegationError::InvalidSessionKey(format!("Session key should be DER-encoded: {}", e))
-    })?;
+    validate_session_key(&session_key)?;
 
     if expiration == 0 {
         return Err(DelegationError::InvalidExpiration(
@@ -163,6 +249,61 @@ pub fn witness(
     Ok(witness)
 }
 
+/// Revokes the delegation for `session_key` that [crate::login::login] added for `address`, so that it
+/// can no longer be retrieved. The signature map is keyed by the delegation hash, which covers the
+/// expiration, so the `expiration` returned by the login is required as well.
+///
+/// A delegation that has already been retrieved stays valid until it expires, its signature carries
+/// its own certificate. The caller must update the certified data of the canister afterwards.
+///
+/// # Parameters
+/// * `signature_map`: The map of signatures.
+/// * `address`: The Bitcoin address that logged in.
+/// * `session_key`: The session key the delegation was created for.
+/// * `expiration`: The expiration of the delegation.
+/// * `realm`: The login realm that was passed to the login, if any.
+///
+/// # Returns
+/// `Ok(())` if the delegation was revoked, [DelegationError::SignatureNotFound] if there was none.
+pub fn revoke(
+    signature_map: &mut SignatureMap,
+    address: &Address,
+    session_key: ByteBuf,
+    expiration: u64,
+    realm: Option<&str>,
+) -> Result<(), DelegationError> {
+    let seed = generate_seed(address, realm);
+    let delegation = create_delegation(session_key, expiration)?;
+    let delegation_hash = create_delegation_hash(&delegation);
+
+    let seed_hash = hash::hash_bytes(seed);
+    if signature_map.witness(seed_hash, delegation_hash).is_none() {
+        return Err(DelegationError::SignatureNotFound);
+    }
+    signature_map.delete(seed_hash, delegation_hash);
+    Ok(())
+}
+
+/// Returns the principal that [crate::login::login] derives for `address` on the canister
+/// `canister_id`, i.e. the principal of the delegations created for it.
+pub fn user_principal(
+    canister_id: &Principal,
+    address: &Address,
+    realm: Option<&str>,
+) -> Result<Principal, DelegationError> {
+    let seed = generate_seed(address, realm);
+    let user_canister_pubkey = create_user_canister_pubkey(canister_id, seed.to_vec())
+        .map_err(|e| DelegationError::SerializationError(e.to_string()))?;
+    Ok(principal_from_canister_pubkey(&user_canister_pubkey))
+}
+
+/// Derives the self-authenticating principal of a DER-encoded public key, e.g. of the
+/// `user_canister_pubkey` returned by [crate::login::login]. The principal is the SHA-224 hash of the
+/// key followed by the byte 0x02, which is how the IC derives the sender of calls signed with the key.
+pub fn principal_from_canister_pubkey(pubkey_bytes: &[u8]) -> Principal {
+    Principal::self_authenticating(pubkey_bytes)
+}
+
 /// Creates a certified signature using a certificate and a state hash tree.
 ///
 /// # Parameters
@@ -183,23 +324,313 @@ pub fn create_certified_signature(
     cbor_serialize(&certificate_signature)
 }
 
-pub fn create_delegation_hash(delegation: &Delegation) -> Hash {
-    let mut delegation_map = HashMap::new();
+/// Checks that `certificate` certifies `root_hash` as the certified data of `canister_id`. Right after a
+/// login the signature is already in the signature map, but queries may still be answered with a
+/// certificate for the previous certified data. Clients that receive [DelegationError::CertificatePending]
+/// should retry, with backoff, instead of starting the login again.
+///
+/// # Parameters
+/// * `certificate`: The CBOR encoded certificate returned by `data_certificate`.
+/// * `canister_id`: The principal of the canister that set the certified data.
+/// * `root_hash`: The root hash of the tree that the delegation signature is a witness of.
+///
+/// # Returns
+/// `Ok(())` if the certificate is current, [DelegationError::CertificatePending] otherwise.
+pub fn check_certificate_current(
+    certificate: &[u8],
+    canister_id: &Principal,
+    root_hash: Hash,
+) -> Result<(), DelegationError> {
+    let certificate: serde_cbor::Value = serde_cbor::from_slice(certificate)
+        .map_err(|e| DelegationError::SerializationError(e.to_string()))?;
+    let tree = cbor_field(&certificate, "tree").ok_or_else(|| {
+        DelegationError::SerializationError("Certificate has no tree".to_string())
+    })?;
+
+    let path: [&[u8]; 3] = [b"canister", canister_id.as_slice(), b"certified_data"];
+    match lookup_path(tree, &path) {
+        Some(certified_data) if certified_data == root_hash => Ok(()),
+        _ => Err(DelegationError::CertificatePending),
+    }
+}
+
+/// The DER prefix of a BLS12-381 public key, such as the IC root key or the key of a subnet.
+#[cfg(feature = "verify-delegation")]
+const BLS_KEY_DER_PREFIX: [u8; 37] = [
+    48, 129, 130, 48, 29, 6, 13, 43, 6, 1, 4, 1, 130, 220, 124, 5, 3, 1, 2, 1, 6, 12, 43, 6, 1, 4,
+    1, 130, 220, 124, 5, 3, 2, 1, 3, 97, 0,
+];
+
+/// Verifies a delegation returned by `siwb_get_delegation` end-to-end, mirroring what agents do before
+/// they use it. The certificate in the signature must be signed by `root_key`, directly or through a
+/// subnet delegation that covers the canister, and certify the signature tree as the certified data of
+/// the canister. The signature tree must contain the delegation for the seed of `user_canister_pubkey`.
+/// The expiration of the delegation is not checked against the current time.
+///
+/// # Parameters
+/// * `signed_delegation`: The delegation and its certified signature.
+/// * `user_canister_pubkey`: The `user_canister_pubkey` returned by [crate::login::login], which names
+///   the canister and the seed of the signing principal.
+/// * `root_key`: The IC root key, either raw or DER-encoded.
+///
+/// # Returns
+/// `Ok(())` if the delegation is valid, [DelegationError::InvalidDelegation] otherwise.
+#[cfg(feature = "verify-delegation")]
+pub fn verify_delegation(
+    signed_delegation: &SignedDelegation,
+    user_canister_pubkey: &[u8],
+    root_key: &[u8],
+) -> Result<(), DelegationError> {
+    let invalid = |e: &str| DelegationError::InvalidDelegation(e.to_string());
+    let (canister_id, seed) = parse_user_canister_pubkey(user_canister_pubkey)?;
+
+    let signature: serde_cbor::Value = serde_cbor::from_slice(&signed_delegation.signature)
+        .map_err(|e| DelegationError::SerializationError(e.to_string()))?;
+    let certificate = match cbor_field(&signature, "certificate") {
+        Some(serde_cbor::Value::Bytes(certificate)) => certificate,
+        _ => return Err(invalid("Signature has no certificate")),
+    };
+    let tree = cbor_field(&signature, "tree").ok_or_else(|| invalid("Signature has no tree"))?;
+
+    let certificate = verify_certificate(certificate, &canister_id, root_key, true)?;
+    let certificate_tree =
+        cbor_field(&certificate, "tree").ok_or_else(|| invalid("Certificate has no tree"))?;
+    let certified_data_path: [&[u8]; 3] = [b"canister", canister_id.as_slice(), b"certified_data"];
+    if lookup_path(certificate_tree, &certified_data_path) != Some(&reconstruct(tree)?[..]) {
+        return Err(invalid(
+            "Certificate does not certify the signature tree for the canister",
+        ));
+    }
+
+    let seed_hash = hash::hash_bytes(seed);
+    let delegation_hash = create_delegation_hash(&signed_delegation.delegation);
+    let signature_path: [&[u8]; 3] = [b"sig", &seed_hash, &delegation_hash];
+    match lookup_path(tree, &signature_path) {
+        Some([]) => Ok(()),
+        _ => Err(invalid("Signature tree does not contain the delegation")),
+    }
+}
+
+/// Splits a DER-encoded canister signature public key into the canister id and the seed.
+#[cfg(feature = "verify-delegation")]
+fn parse_user_canister_pubkey(pubkey: &[u8]) -> Result<(Principal, Vec<u8>), DelegationError> {
+    let invalid = || DelegationError::InvalidDelegation("Invalid user canister pubkey".to_string());
+
+    let blocks = from_der(pubkey).map_err(|_| invalid())?;
+    let key = match blocks.as_slice() {
+        [ASN1Block::Sequence(_, info)] => match info.as_slice() {
+            [ASN1Block::Sequence(_, algorithm), ASN1Block::BitString(_, _, key)]
+                if matches!(
+                    algorithm.as_slice(),
+                    [ASN1Block::ObjectIdentifier(_, id)] if *id == oid!(1, 3, 6, 1, 4, 1, 56387, 1, 2)
+                ) =>
+            {
+                key
+            }
+            _ => return Err(invalid()),
+        },
+        _ => return Err(invalid()),
+    };
+
+    let (canister_id_len, rest) = key.split_first().ok_or_else(invalid)?;
+    if rest.len() < *canister_id_len as usize {
+        return Err(invalid());
+    }
+    let (canister_id, seed) = rest.split_at(*canister_id_len as usize);
+    let canister_id = Principal::try_from_slice(canister_id).map_err(|_| invalid())?;
+    Ok((canister_id, seed.to_vec()))
+}
+
+/// Decodes `certificate` and verifies its BLS signature over the root hash of its tree. The signing key
+/// is `root_key`, or, if the certificate has a subnet delegation, the key of the subnet as certified by
+/// `root_key`. A subnet delegation must cover `canister_id` and may not be delegated further.
+#[cfg(feature = "verify-delegation")]
+fn verify_certificate(
+    certificate: &[u8],
+    canister_id: &Principal,
+    root_key: &[u8],
+    allow_delegation: bool,
+) -> Result<serde_cbor::Value, DelegationError> {
+    use serde_cbor::Value;
+
+    let invalid = |e: &str| DelegationError::InvalidDelegation(e.to_string());
+    let certificate: Value = serde_cbor::from_slice(certificate)
+        .map_err(|e| DelegationError::SerializationError(e.to_string()))?;
+    let tree =
+        cbor_field(&certificate, "tree").ok_or_else(|| invalid("Certificate has no tree"))?;
+    let signature = match cbor_field(&certificate, "signature") {
+        Some(Value::Bytes(signature)) => signature,
+        _ => return Err(invalid("Certificate has no signature")),
+    };
+
+    let key = match cbor_field(&certificate, "delegation") {
+        None => strip_bls_key_der(root_key)?.to_vec(),
+        Some(_) if !allow_delegation => {
+            return Err(invalid("Subnet delegations cannot be delegated further"))
+        }
+        Some(delegation) => {
+            let (subnet_id, subnet_certificate) = match (
+                cbor_field(delegation, "subnet_id"),
+                cbor_field(delegation, "certificate"),
+            ) {
+                (Some(Value::Bytes(subnet_id)), Some(Value::Bytes(certificate))) => {
+                    (subnet_id, certificate)
+                }
+                _ => return Err(invalid("Malformed subnet delegation")),
+            };
+            let subnet_certificate =
+                verify_certificate(subnet_certificate, canister_id, root_key, false)?;
+            let subnet_tree = cbor_field(&subnet_certificate, "tree")
+                .ok_or_else(|| invalid("Certificate has no tree"))?;
+
+            let ranges_path: [&[u8]; 3] = [b"subnet", subnet_id, b"canister_ranges"];
+            let ranges = lookup_path(subnet_tree, &ranges_path)
+                .ok_or_else(|| invalid("Subnet delegation has no canister ranges"))?;
+            let ranges: Vec<(ByteBuf, ByteBuf)> = serde_cbor::from_slice(ranges)
+                .map_err(|e| DelegationError::SerializationError(e.to_string()))?;
+            let canister_id = canister_id.as_slice();
+            if !ranges
+                .iter()
+                .any(|(low, high)| low.as_slice() <= canister_id && canister_id <= high.as_slice())
+            {
+                return Err(invalid("Subnet delegation does not cover the canister"));
+            }
+
+            let public_key_path: [&[u8]; 3] = [b"subnet", subnet_id, b"public_key"];
+            let public_key = lookup_path(subnet_tree, &public_key_path)
+                .ok_or_else(|| invalid("Subnet delegation has no public key"))?;
+            strip_bls_key_der(public_key)?.to_vec()
+        }
+    };
+
+    let mut message = b"\x0Dic-state-root".to_vec();
+    message.extend_from_slice(&reconstruct(tree)?);
+    ic_verify_bls_signature::verify_bls_signature(signature, &message, &key)
+        .map_err(|_| invalid("Invalid certificate signature"))?;
 
-    delegation_map.insert("pubkey", Value::Bytes(&delegation.pubkey));
-    delegation_map.insert("expiration", Value::U64(delegation.expiration));
+    Ok(certificate)
+}
+
+/// Returns the raw 96 byte BLS public key of a key that is either raw or DER-encoded.
+#[cfg(feature = "verify-delegation")]
+fn strip_bls_key_der(key: &[u8]) -> Result<&[u8], DelegationError> {
+    match key.strip_prefix(&BLS_KEY_DER_PREFIX[..]).unwrap_or(key) {
+        key if key.len() == 96 => Ok(key),
+        _ => Err(DelegationError::InvalidDelegation(
+            "Invalid BLS public key".to_string(),
+        )),
+    }
+}
+
+/// Computes the root hash of a CBOR encoded hash tree, like [HashTree::reconstruct].
+#[cfg(feature = "verify-delegation")]
+fn reconstruct(tree: &serde_cbor::Value) -> Result<Hash, DelegationError> {
+    use serde_cbor::Value;
+
+    let node = match tree {
+        Value::Array(node) => node,
+        _ => {
+            return Err(DelegationError::SerializationError(
+                "Invalid hash tree".to_string(),
+            ))
+        }
+    };
+    match node.as_slice() {
+        [Value::Integer(0)] => Ok(HashTree::Empty.reconstruct()),
+        [Value::Integer(1), left, right] => Ok(ic_certified_map::fork_hash(
+            &reconstruct(left)?,
+            &reconstruct(right)?,
+        )),
+        [Value::Integer(2), Value::Bytes(label), subtree] => Ok(ic_certified_map::labeled_hash(
+            label,
+            &reconstruct(subtree)?,
+        )),
+        [Value::Integer(3), Value::Bytes(leaf)] => Ok(ic_certified_map::leaf_hash(leaf)),
+        [Value::Integer(4), Value::Bytes(hash)] => Hash::try_from(hash.as_slice())
+            .map_err(|_| DelegationError::SerializationError("Invalid pruned hash".to_string())),
+        _ => Err(DelegationError::SerializationError(
+            "Invalid hash tree".to_string(),
+        )),
+    }
+}
+
+/// Returns the field `name` of a CBOR encoded map.
+fn cbor_field<'a>(value: &'a serde_cbor::Value, name: &str) -> Option<&'a serde_cbor::Value> {
+    match value {
+        serde_cbor::Value::Map(map) => map.get(&serde_cbor::Value::Text(name.to_string())),
+        _ => None,
+    }
+}
+
+/// Looks up the leaf at `path` in a CBOR encoded hash tree.
+fn lookup_path<'a>(tree: &'a serde_cbor::Value, path: &[&[u8]]) -> Option<&'a [u8]> {
+    use serde_cbor::Value;
 
-    if let Some(targets) = delegation.targets.as_ref() {
-        let mut arr = Vec::with_capacity(targets.len());
-        for t in targets.iter() {
-            arr.push(Value::Bytes(t.as_ref()));
+    let node = match tree {
+        Value::Array(node) => node,
+        _ => return None,
+    };
+    match node.as_slice() {
+        [Value::Integer(1), left, right] => {
+            lookup_path(left, path).or_else(|| lookup_path(right, path))
         }
-        delegation_map.insert("targets", Value::Array(arr));
+        [Value::Integer(2), Value::Bytes(label), subtree] => match path.split_first() {
+            Some((first, rest)) if label.as_slice() == *first => lookup_path(subtree, rest),
+            _ => None,
+        },
+        [Value::Integer(3), Value::Bytes(leaf)] if path.is_empty() => Some(leaf),
+        _ => None,
     }
+}
 
-    let delegation_map_hash = hash::hash_of_map(delegation_map);
+/// A scheme that delegations are hashed with. The signature map and the certified signatures commit to
+/// the hash, so it must be the hash the IC computes when it checks a delegation.
+pub trait DelegationHashScheme {
+    /// Returns the bytes that are hashed, i.e. the preimage of the delegation hash.
+    fn preimage(delegation: &Delegation) -> Vec<u8>;
 
-    hash::hash_with_domain(b"ic-request-auth-delegation", &delegation_map_hash)
+    /// Returns the SHA-256 hash of [DelegationHashScheme::preimage].
+    fn hash(delegation: &Delegation) -> Hash {
+        hash::hash_bytes(Self::preimage(delegation))
+    }
+}
+
+/// The scheme of the IC interface specification: the representation-independent hash of the
+/// delegation, prefixed with the domain separator `ic-request-auth-delegation`.
+pub struct IcDelegationHash;
+
+impl DelegationHashScheme for IcDelegationHash {
+    fn preimage(delegation: &Delegation) -> Vec<u8> {
+        let mut delegation_map = HashMap::new();
+
+        delegation_map.insert("pubkey", Value::Bytes(&delegation.pubkey));
+        delegation_map.insert("expiration", Value::U64(delegation.expiration));
+
+        if let Some(targets) = delegation.targets.as_ref() {
+            let mut arr = Vec::with_capacity(targets.len());
+            for t in targets.iter() {
+                arr.push(Value::Bytes(t.as_ref()));
+            }
+            delegation_map.insert("targets", Value::Array(arr));
+        }
+
+        let delegation_map_hash = hash::hash_of_map(delegation_map);
+
+        let domain = b"ic-request-auth-delegation";
+        let mut preimage = Vec::with_capacity(1 + domain.len() + delegation_map_hash.len());
+        preimage.push(domain.len() as u8);
+        preimage.extend_from_slice(domain);
+        preimage.extend_from_slice(&delegation_map_hash);
+        preimage
+    }
+}
+
+/// The scheme used by [create_delegation_hash].
+pub type DefaultDelegationHash = IcDelegationHash;
+
+/// Hashes `delegation` with the [DefaultDelegationHash] scheme.
+pub fn create_delegation_hash(delegation: &Delegation) -> Hash {
+    DefaultDelegationHash::hash(delegation)
 }
 
 /// Creates a DER-encoded public key for a user canister from a given seed.
@@ -277,11 +708,409 @@ mod tests {
     #[test]
     fn test_generate_seed() {
         let address = init();
-        let seed = generate_seed(&address);
+        let seed = generate_seed(&address, None);
         assert!(!seed.is_empty(), "Seed should not be empty");
         // Additional assertions can be added here
     }
 
+    #[test]
+    fn test_generate_seed_realms() {
+        let address = init();
+        let app_a = generate_seed(&address, Some("app-a"));
+        let app_b = generate_seed(&address, Some("app-b"));
+        assert_ne!(app_a, app_b, "Realms should derive distinct seeds");
+        assert_ne!(app_a, generate_seed(&address, None));
+        assert_eq!(app_a, generate_seed(&address, Some("app-a")));
+    }
+
+    #[test]
+    fn test_generate_seed_versions() {
+        let address = init();
+        let version_1 = generate_seed(&address, None);
+
+        let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .targets(vec![Principal::from_text("aaaaa-aa").unwrap()])
+            .seed_version(2)
+            .build()
+            .unwrap();
+        SETTINGS.set(Some(settings));
+        let version_2 = generate_seed(&address, None);
+
+        assert_ne!(
+            version_1, version_2,
+            "Seed versions should derive distinct seeds"
+        );
+        assert_eq!(version_2, generate_seed(&address, None));
+    }
+
+    #[cfg(feature = "stretched-seed")]
+    #[test]
+    fn test_generate_seed_stretched() {
+        use crate::settings::SeedStretching;
+
+        let address = init();
+        let unstretched = generate_seed(&address, None);
+
+        let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .targets(vec![Principal::from_text("aaaaa-aa").unwrap()])
+            .seed_stretching(SeedStretching {
+                log_n: 4,
+                r: 1,
+                p: 1,
+            })
+            .build()
+            .unwrap();
+        SETTINGS.set(Some(settings));
+        let stretched = generate_seed(&address, None);
+
+        assert_ne!(unstretched, stretched, "Stretching should change the seed");
+        assert_eq!(stretched, generate_seed(&address, None));
+    }
+
+    fn certificate_for(canister_id: &Principal, certified_data: &Hash) -> Vec<u8> {
+        let tree = ic_certified_map::labeled(
+            b"canister",
+            ic_certified_map::labeled(
+                canister_id.as_slice(),
+                ic_certified_map::labeled(
+                    b"certified_data",
+                    HashTree::Leaf(certified_data.to_vec().into()),
+                ),
+            ),
+        );
+        create_certified_signature(vec![], tree).unwrap()
+    }
+
+    #[test]
+    fn test_check_certificate_current() {
+        let address = init();
+        let canister_id = Principal::from_text("aaaaa-aa").unwrap();
+        let mut signature_map = SignatureMap::default();
+        let stale_root_hash = signature_map.root_hash();
+
+        // Login adds the signature, the certified data is updated when the call completes.
+        let delegation = create_delegation(ByteBuf::from(SESSION_KEY), 123456789).unwrap();
+        let seed = generate_seed(&address, None);
+        signature_map.put(hash::hash_bytes(seed), create_delegation_hash(&delegation));
+        let witness = witness(&signature_map, seed, create_delegation_hash(&delegation)).unwrap();
+        let root_hash = witness.reconstruct();
+
+        let stale_certificate = certificate_for(&canister_id, &stale_root_hash);
+        assert!(matches!(
+            check_certificate_current(&stale_certificate, &canister_id, root_hash),
+            Err(DelegationError::CertificatePending)
+        ));
+
+        let other_canister = Principal::from_text("2vxsx-fae").unwrap();
+        let certificate = certificate_for(&other_canister, &root_hash);
+        assert!(matches!(
+            check_certificate_current(&certificate, &canister_id, root_hash),
+            Err(DelegationError::CertificatePending)
+        ));
+
+        let certificate = certificate_for(&canister_id, &root_hash);
+        assert!(check_certificate_current(&certificate, &canister_id, root_hash).is_ok());
+
+        assert!(matches!(
+            check_certificate_current(b"not cbor", &canister_id, root_hash),
+            Err(DelegationError::SerializationError(_))
+        ));
+    }
+
+    /// Signs `tree` with the BLS key `secret_key`, like the IC signs the state tree of a subnet.
+    #[cfg(feature = "verify-delegation")]
+    fn signed_certificate(
+        tree: HashTree,
+        secret_key: u64,
+        delegation: Option<(Principal, Vec<u8>)>,
+    ) -> Vec<u8> {
+        use bls12_381::hash_to_curve::{ExpandMsgXmd, HashToCurve};
+        use bls12_381::{G1Affine, G1Projective, Scalar};
+
+        #[derive(Serialize)]
+        struct SubnetDelegation {
+            subnet_id: ByteBuf,
+            certificate: ByteBuf,
+        }
+
+        #[derive(Serialize)]
+        struct Certificate<'a> {
+            tree: HashTree<'a>,
+            signature: ByteBuf,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            delegation: Option<SubnetDelegation>,
+        }
+
+        let mut message = b"\x0Dic-state-root".to_vec();
+        message.extend_from_slice(&tree.reconstruct());
+        let message = <G1Projective as HashToCurve<ExpandMsgXmd<sha2::Sha256>>>::hash_to_curve(
+            message,
+            b"BLS_SIG_BLS12381G1_XMD:SHA-256_SSWU_RO_NUL_",
+        );
+        let signature = G1Affine::from(message * Scalar::from(secret_key));
+
+        cbor_serialize(&Certificate {
+            tree,
+            signature: ByteBuf::from(signature.to_compressed().to_vec()),
+            delegation: delegation.map(|(subnet_id, certificate)| SubnetDelegation {
+                subnet_id: ByteBuf::from(subnet_id.as_slice().to_vec()),
+                certificate: ByteBuf::from(certificate),
+            }),
+        })
+        .unwrap()
+    }
+
+    /// The DER-encoded public key of the BLS key `secret_key`.
+    #[cfg(feature = "verify-delegation")]
+    fn bls_public_key(secret_key: u64) -> Vec<u8> {
+        use bls12_381::{G2Affine, Scalar};
+
+        let public_key = G2Affine::from(G2Affine::generator() * Scalar::from(secret_key));
+        [&BLS_KEY_DER_PREFIX[..], &public_key.to_compressed()].concat()
+    }
+
+    #[cfg(feature = "verify-delegation")]
+    #[test]
+    fn test_verify_delegation() {
+        let address = init();
+        let canister_id = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+        let root_key = bls_public_key(42);
+
+        // Issue a delegation the way the provider does and certify the resulting root hash.
+        let mut signature_map = SignatureMap::default();
+        let delegation = create_delegation(ByteBuf::from(SESSION_KEY), 123456789).unwrap();
+        let seed = generate_seed(&address, None);
+        signature_map.put(hash::hash_bytes(seed), create_delegation_hash(&delegation));
+        let tree = ic_certified_map::fork(
+            HashTree::Pruned(labeled_hash(b"http_assets", &[0; 32])),
+            ic_certified_map::labeled(
+                b"sig",
+                witness(&signature_map, seed, create_delegation_hash(&delegation)).unwrap(),
+            ),
+        );
+        let root_hash = tree.reconstruct();
+        let state_tree = ic_certified_map::labeled(
+            b"canister",
+            ic_certified_map::labeled(
+                canister_id.as_slice(),
+                ic_certified_map::labeled(
+                    b"certified_data",
+                    HashTree::Leaf(root_hash.to_vec().into()),
+                ),
+            ),
+        );
+        let certificate = signed_certificate(state_tree, 42, None);
+        check_certificate_current(&certificate, &canister_id, root_hash).unwrap();
+        let signed_delegation = SignedDelegation {
+            delegation: delegation.clone(),
+            signature: ByteBuf::from(create_certified_signature(certificate, tree).unwrap()),
+        };
+        let user_canister_pubkey =
+            create_user_canister_pubkey(&canister_id, seed.to_vec()).unwrap();
+
+        verify_delegation(&signed_delegation, &user_canister_pubkey, &root_key).unwrap();
+        verify_delegation(&signed_delegation, &user_canister_pubkey, &root_key[37..]).unwrap();
+
+        assert!(matches!(
+            verify_delegation(
+                &signed_delegation,
+                &user_canister_pubkey,
+                &bls_public_key(43)
+            ),
+            Err(DelegationError::InvalidDelegation(_))
+        ));
+
+        // The delegation is certified for the seed of the address only, on this canister only.
+        let other_seed = generate_seed(&address, Some("app-a"));
+        let other_pubkey = create_user_canister_pubkey(&canister_id, other_seed.to_vec()).unwrap();
+        assert!(matches!(
+            verify_delegation(&signed_delegation, &other_pubkey, &root_key),
+            Err(DelegationError::InvalidDelegation(_))
+        ));
+        let other_canister = Principal::from_text("aaaaa-aa").unwrap();
+        let other_pubkey = create_user_canister_pubkey(&other_canister, seed.to_vec()).unwrap();
+        assert!(matches!(
+            verify_delegation(&signed_delegation, &other_pubkey, &root_key),
+            Err(DelegationError::InvalidDelegation(_))
+        ));
+
+        let mut tampered = signed_delegation.clone();
+        tampered.delegation.expiration += 1;
+        assert!(matches!(
+            verify_delegation(&tampered, &user_canister_pubkey, &root_key),
+            Err(DelegationError::InvalidDelegation(_))
+        ));
+    }
+
+    #[cfg(feature = "verify-delegation")]
+    #[test]
+    fn test_verify_delegation_with_subnet_delegation() {
+        let address = init();
+        let canister_id = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+        let subnet_id = Principal::from_text("2vxsx-fae").unwrap();
+
+        let mut signature_map = SignatureMap::default();
+        let delegation = create_delegation(ByteBuf::from(SESSION_KEY), 123456789).unwrap();
+        let seed = generate_seed(&address, None);
+        signature_map.put(hash::hash_bytes(seed), create_delegation_hash(&delegation));
+        let tree = ic_certified_map::labeled(
+            b"sig",
+            witness(&signature_map, seed, create_delegation_hash(&delegation)).unwrap(),
+        );
+        let user_canister_pubkey =
+            create_user_canister_pubkey(&canister_id, seed.to_vec()).unwrap();
+
+        // The root key certifies the key of the subnet and the canisters it hosts.
+        let subnet_certificate = |ranges: Vec<(Principal, Principal)>| {
+            let ranges: Vec<(ByteBuf, ByteBuf)> = ranges
+                .iter()
+                .map(|(low, high)| {
+                    (
+                        ByteBuf::from(low.as_slice().to_vec()),
+                        ByteBuf::from(high.as_slice().to_vec()),
+                    )
+                })
+                .collect();
+            let subnet_tree = ic_certified_map::labeled(
+                b"subnet",
+                ic_certified_map::labeled(
+                    subnet_id.as_slice(),
+                    ic_certified_map::fork(
+                        ic_certified_map::labeled(
+                            b"canister_ranges",
+                            HashTree::Leaf(serde_cbor::to_vec(&ranges).unwrap().into()),
+                        ),
+                        ic_certified_map::labeled(
+                            b"public_key",
+                            HashTree::Leaf(bls_public_key(7).into()),
+                        ),
+                    ),
+                ),
+            );
+            signed_certificate(subnet_tree, 42, None)
+        };
+        let signed_delegation = |subnet_certificate: Vec<u8>| {
+            let state_tree = ic_certified_map::labeled(
+                b"canister",
+                ic_certified_map::labeled(
+                    canister_id.as_slice(),
+                    ic_certified_map::labeled(
+                        b"certified_data",
+                        HashTree::Leaf(tree.reconstruct().to_vec().into()),
+                    ),
+                ),
+            );
+            let certificate =
+                signed_certificate(state_tree, 7, Some((subnet_id, subnet_certificate)));
+            SignedDelegation {
+                delegation: delegation.clone(),
+                signature: ByteBuf::from(
+                    create_certified_signature(certificate, tree.clone()).unwrap(),
+                ),
+            }
+        };
+
+        let covering = subnet_certificate(vec![(canister_id, canister_id)]);
+        verify_delegation(
+            &signed_delegation(covering),
+            &user_canister_pubkey,
+            &bls_public_key(42),
+        )
+        .unwrap();
+
+        let other_canister = Principal::from_text("aaaaa-aa").unwrap();
+        let not_covering = subnet_certificate(vec![(other_canister, other_canister)]);
+        assert!(matches!(
+            verify_delegation(
+                &signed_delegation(not_covering),
+                &user_canister_pubkey,
+                &bls_public_key(42)
+            ),
+            Err(DelegationError::InvalidDelegation(_))
+        ));
+    }
+
+    #[test]
+    fn test_revoke() {
+        let address = init();
+        let mut signature_map = SignatureMap::default();
+        let delegation = create_delegation(ByteBuf::from(SESSION_KEY), 123456789).unwrap();
+        let seed = generate_seed(&address, None);
+        signature_map.put(hash::hash_bytes(seed), create_delegation_hash(&delegation));
+        assert!(witness(&signature_map, seed, create_delegation_hash(&delegation)).is_ok());
+
+        // A delegation with another expiration is a different delegation.
+        assert!(matches!(
+            revoke(
+                &mut signature_map,
+                &address,
+                ByteBuf::from(SESSION_KEY),
+                987654321,
+                None
+            ),
+            Err(DelegationError::SignatureNotFound)
+        ));
+
+        revoke(
+            &mut signature_map,
+            &address,
+            ByteBuf::from(SESSION_KEY),
+            123456789,
+            None,
+        )
+        .unwrap();
+        assert!(matches!(
+            witness(&signature_map, seed, create_delegation_hash(&delegation)),
+            Err(DelegationError::SignatureNotFound)
+        ));
+        assert!(matches!(
+            revoke(
+                &mut signature_map,
+                &address,
+                ByteBuf::from(SESSION_KEY),
+                123456789,
+                None
+            ),
+            Err(DelegationError::SignatureNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_user_principal() {
+        let address = init();
+        let canister_id = Principal::from_text("aaaaa-aa").unwrap();
+        let seed = generate_seed(&address, None);
+        let pubkey = create_user_canister_pubkey(&canister_id, seed.to_vec()).unwrap();
+        assert_eq!(
+            user_principal(&canister_id, &address, None).unwrap(),
+            Principal::self_authenticating(pubkey)
+        );
+        assert_ne!(
+            user_principal(&canister_id, &address, None).unwrap(),
+            user_principal(&canister_id, &address, Some("app-a")).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_principal_from_canister_pubkey() {
+        let canister_id = Principal::from_text("aaaaa-aa").unwrap();
+        let pubkey = create_user_canister_pubkey(&canister_id, vec![1; 32]).unwrap();
+        assert_eq!(
+            hex::encode(&pubkey),
+            "3032300c060a2b0601040183b8430102032200000101010101010101010101010101010101010101010101010101010101010101"
+        );
+
+        // The principal of the key as computed independently: SHA-224 of the key, followed by 0x02
+        let principal = principal_from_canister_pubkey(&pubkey);
+        let mut expected = <sha2::Sha224 as sha2::Digest>::digest(&pubkey).to_vec();
+        expected.push(0x02);
+        assert_eq!(principal.as_slice(), expected.as_slice());
+        assert_eq!(
+            principal.to_text(),
+            "p3ain-2axw5-bnbac-ymzp6-utyip-gsx22-4bmud-7d6ct-ibptb-zwlby-6qe"
+        );
+    }
+
     #[test]
     fn test_create_delegation() {
         init();
@@ -311,6 +1140,40 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_validate_session_key() {
+        assert!(validate_session_key(SESSION_KEY).is_ok());
+
+        // Truncated, trailing bytes, not DER at all, and DER that is not a public key.
+        let mut trailing = SESSION_KEY.to_vec();
+        trailing.push(0);
+        let invalid: [&[u8]; 5] = [
+            &SESSION_KEY[..SESSION_KEY.len() - 1],
+            &trailing,
+            &[1, 2, 3],
+            &[2, 1, 1],
+            &[48, 7, 48, 0, 3, 3, 0, 1, 2],
+        ];
+        for session_key in invalid {
+            assert!(
+                matches!(
+                    validate_session_key(session_key),
+                    Err(DelegationError::InvalidSessionKey(_))
+                ),
+                "{:?} should be invalid",
+                session_key
+            );
+        }
+
+        let mut too_long = vec![48, 130, 1, 4, 48, 5, 6, 3, 43, 101, 112, 3, 129, 250, 0];
+        too_long.extend_from_slice(&[1; 249]);
+        assert!(from_der(&too_long).is_ok());
+        assert_eq!(
+            validate_session_key(&too_long).unwrap_err().to_string(),
+            "Invalid session key: Session key is longer than 256 bytes"
+        );
+    }
+
     #[test]
     fn test_create_delegation_invalid_expiration() {
         init();
@@ -328,7 +1191,7 @@ mod tests {
     #[test]
     fn test_witness_single_entry() {
         let address = init();
-        let seed = generate_seed(&address);
+        let seed = generate_seed(&address, None);
         let session_key = ByteBuf::from(SESSION_KEY);
         let expiration = 123456789;
         let delegation = create_delegation(session_key.clone(), expiration).unwrap();
@@ -344,7 +1207,7 @@ mod tests {
     #[test]
     fn test_witness_multiple_entries() {
         let address = init();
-        let seed = generate_seed(&address);
+        let seed = generate_seed(&address, None);
         let session_key = ByteBuf::from(SESSION_KEY);
         let expiration = 123456789;
         let delegation = create_delegation(session_key.clone(), expiration).unwrap();
@@ -373,7 +1236,7 @@ mod tests {
     #[test]
     fn test_witness_empty_signature_map() {
         let address = init();
-        let seed = generate_seed(&address);
+        let seed = generate_seed(&address, None);
         let session_key = ByteBuf::from(SESSION_KEY);
         let expiration = 123456789;
         let delegation = create_delegation(session_key.clone(), expiration).unwrap();
@@ -391,7 +1254,7 @@ mod tests {
     #[test]
     fn test_witness_hash_not_found() {
         let address = init();
-        let seed = generate_seed(&address);
+        let seed = generate_seed(&address, None);
         let session_key = ByteBuf::from(SESSION_KEY);
         let expiration = 123456789;
         let delegation = create_delegation(session_key.clone(), expiration).unwrap();
@@ -410,7 +1273,7 @@ mod tests {
     #[test]
     fn test_create_certified_signature() {
         let address = init();
-        let seed = generate_seed(&address);
+        let seed = generate_seed(&address, None);
         let session_key = ByteBuf::from(SESSION_KEY);
         let expiration = 123456789;
         let delegation = create_delegation(session_key.clone(), expiration).unwrap();
@@ -426,10 +1289,35 @@ mod tests {
         assert!(!signature.is_empty(), "Signature should not be empty");
     }
 
+    #[test]
+    fn test_delegation_hash_matches_ic() {
+        init();
+        let delegation = create_delegation(ByteBuf::from(SESSION_KEY), 123456789).unwrap();
+        let untargeted = Delegation {
+            targets: None,
+            ..delegation.clone()
+        };
+
+        for delegation in [delegation, untargeted] {
+            let ic_delegation = ic_transport_types::Delegation {
+                pubkey: delegation.pubkey.to_vec(),
+                expiration: delegation.expiration,
+                targets: delegation.targets.clone(),
+                senders: None,
+            };
+            let preimage = IcDelegationHash::preimage(&delegation);
+            assert_eq!(preimage, ic_delegation.signable());
+            assert_eq!(
+                create_delegation_hash(&delegation),
+                <sha2::Sha256 as sha2::Digest>::digest(&preimage).as_slice()
+            );
+        }
+    }
+
     #[test]
     fn test_create_user_canister_pubkey() {
         let address = init();
-        let seed = generate_seed(&address);
+        let seed = generate_seed(&address, None);
         let result =
             create_user_canister_pubkey(&Principal::from_text("aaaaa-aa").unwrap(), seed.to_vec());
         assert!(result.is_ok());
@@ -9,6 +9,22 @@ pub enum BtcError {
     InvalidSignature,
     InvalidRecoveryId,
     PublicKeyRecoveryFailure,
+    AddressMismatch,
+    NetworkMismatch,
+    InvalidPublicKey,
+    AddressTypeNotAllowed,
+    MalformedMessage(String),
+    LegacyEcdsaDisabled,
+    IssuedAtOutOfBounds,
+    MessageTooLong(usize),
+    UncompressedKeyNotAllowed,
+    DerivationRangeTooLarge(u32),
+    ScriptConversionError(String),
+    DomainNotAllowed,
+    CanisterMismatch,
+    RngNotSeeded,
+    StatementNotAllowed,
+    UriNotAllowed,
 }
 
 impl From<hex::FromHexError> for BtcError {
@@ -17,6 +33,17 @@ impl From<hex::FromHexError> for BtcError {
     }
 }
 
+impl From<bitcoin::address::Error> for BtcError {
+    /// Keeps the underlying cause, e.g. the invalid checksum behind a bech32 encoding error, in the
+    /// message.
+    fn from(err: bitcoin::address::Error) -> Self {
+        match std::error::Error::source(&err) {
+            Some(source) => BtcError::AddressFormatError(format!("{}: {}", err, source)),
+            None => BtcError::AddressFormatError(err.to_string()),
+        }
+    }
+}
+
 impl fmt::Display for BtcError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -31,6 +58,50 @@ impl fmt::Display for BtcError {
             BtcError::AddressTypeNotSupported => {
                 write!(f, "Address type not supported")
             }
+            BtcError::AddressMismatch => {
+                write!(f, "Public key does not match the address")
+            }
+            BtcError::NetworkMismatch => {
+                write!(f, "Address network does not match the configured network")
+            }
+            BtcError::InvalidPublicKey => write!(f, "Invalid public key"),
+            BtcError::AddressTypeNotAllowed => {
+                write!(f, "Address type is not allowed to sign in")
+            }
+            BtcError::MalformedMessage(e) => write!(f, "Malformed message: {}", e),
+            BtcError::MessageTooLong(max) => {
+                write!(f, "Message exceeds the maximum length of {} bytes", max)
+            }
+            BtcError::IssuedAtOutOfBounds => {
+                write!(f, "Issued at is too far from the current time")
+            }
+            BtcError::LegacyEcdsaDisabled => {
+                write!(f, "Legacy ECDSA message signatures are not accepted")
+            }
+            BtcError::UncompressedKeyNotAllowed => {
+                write!(
+                    f,
+                    "Uncompressed public keys are not allowed for this address type"
+                )
+            }
+            BtcError::DerivationRangeTooLarge(max) => {
+                write!(
+                    f,
+                    "Derivation range exceeds the maximum of {} addresses",
+                    max
+                )
+            }
+            BtcError::ScriptConversionError(e) => write!(f, "Script conversion error: {}", e),
+            BtcError::DomainNotAllowed => write!(f, "Domain is not allowed to sign in"),
+            BtcError::CanisterMismatch => {
+                write!(f, "Message was prepared for another canister")
+            }
+            BtcError::RngNotSeeded => write!(
+                f,
+                "The nonce generator has not been seeded with randomness yet, retry the request"
+            ),
+            BtcError::StatementNotAllowed => write!(f, "Statement is not allowed"),
+            BtcError::UriNotAllowed => write!(f, "URI is not allowed"),
         }
     }
 }
@@ -40,3 +111,131 @@ impl From<BtcError> for String {
         error.to_string()
     }
 }
+
+impl std::error::Error for BtcError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BtcError::DecodingError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    #[test]
+    fn test_display() {
+        let cases = [
+            (
+                BtcError::AddressTypeNotSupported,
+                "Address type not supported",
+            ),
+            (
+                BtcError::AddressFormatError("bad".to_string()),
+                "Address format error: bad",
+            ),
+            (
+                BtcError::DecodingError(hex::FromHexError::OddLength),
+                "Decoding error: Odd number of digits",
+            ),
+            (
+                BtcError::SignatureFormatError("bad".to_string()),
+                "Signature format error: bad",
+            ),
+            (BtcError::InvalidSignature, "Invalid signature"),
+            (BtcError::InvalidRecoveryId, "Invalid recovery ID"),
+            (
+                BtcError::PublicKeyRecoveryFailure,
+                "Public key recovery failure",
+            ),
+            (
+                BtcError::AddressMismatch,
+                "Public key does not match the address",
+            ),
+            (
+                BtcError::NetworkMismatch,
+                "Address network does not match the configured network",
+            ),
+            (BtcError::InvalidPublicKey, "Invalid public key"),
+            (
+                BtcError::AddressTypeNotAllowed,
+                "Address type is not allowed to sign in",
+            ),
+            (
+                BtcError::MalformedMessage("statement is empty".to_string()),
+                "Malformed message: statement is empty",
+            ),
+            (
+                BtcError::LegacyEcdsaDisabled,
+                "Legacy ECDSA message signatures are not accepted",
+            ),
+            (
+                BtcError::IssuedAtOutOfBounds,
+                "Issued at is too far from the current time",
+            ),
+            (
+                BtcError::MessageTooLong(4096),
+                "Message exceeds the maximum length of 4096 bytes",
+            ),
+            (
+                BtcError::UncompressedKeyNotAllowed,
+                "Uncompressed public keys are not allowed for this address type",
+            ),
+            (
+                BtcError::DerivationRangeTooLarge(1_000),
+                "Derivation range exceeds the maximum of 1000 addresses",
+            ),
+            (
+                BtcError::ScriptConversionError("bad".to_string()),
+                "Script conversion error: bad",
+            ),
+            (
+                BtcError::DomainNotAllowed,
+                "Domain is not allowed to sign in",
+            ),
+            (
+                BtcError::CanisterMismatch,
+                "Message was prepared for another canister",
+            ),
+            (
+                BtcError::RngNotSeeded,
+                "The nonce generator has not been seeded with randomness yet, retry the request",
+            ),
+            (BtcError::StatementNotAllowed, "Statement is not allowed"),
+            (BtcError::UriNotAllowed, "URI is not allowed"),
+        ];
+        for (error, expected) in cases {
+            assert_eq!(error.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_address_error_cause() {
+        use bitcoin::address::NetworkUnchecked;
+        use bitcoin::Address;
+        use std::str::FromStr;
+
+        let error: BtcError =
+            Address::<NetworkUnchecked>::from_str("bc1qshqyem2rf8jyla904gd2cvek2k8nz5z3x73p25")
+                .unwrap_err()
+                .into();
+        assert_eq!(
+            error.to_string(),
+            "Address format error: bech32 address encoding error: invalid checksum"
+        );
+    }
+
+    #[test]
+    fn test_error_source() {
+        fn decode() -> Result<Vec<u8>, Box<dyn Error>> {
+            Ok(hex::decode("abc").map_err(BtcError::from)?)
+        }
+        let error = decode().unwrap_err();
+        assert_eq!(error.to_string(), "Decoding error: Odd number of digits");
+        assert!(error.source().is_some());
+        assert!(BtcError::InvalidSignature.source().is_none());
+    }
+}
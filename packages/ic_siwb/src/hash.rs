@@ -22,21 +22,21 @@ pub(crate) fn hash_of_map<S: AsRef<str>>(map: HashMap<S, Value>) -> Hash {
         .collect::<Vec<_>>();
 
     hashes.sort_unstable();
-    let mut hasher = Sha256::new();
+    let mut hasher = Hasher::new();
     for hash in hashes {
         hasher.update(&hash);
     }
 
-    hasher.finalize().into()
+    hasher.finalize()
 }
 
 /// Computes a hash with a domain separator.
 pub(crate) fn hash_with_domain(sep: &[u8], bytes: &[u8]) -> Hash {
-    let mut hasher = Sha256::new();
+    let mut hasher = Hasher::new();
     hasher.update([sep.len() as u8]);
     hasher.update(sep);
     hasher.update(bytes);
-    hasher.finalize().into()
+    hasher.finalize()
 }
 
 /// Helper function to hash a key and value pair.
@@ -52,11 +52,74 @@ pub(crate) fn hash_string(value: &str) -> Hash {
     hash_bytes(value.as_bytes())
 }
 
+/// A SHA-256 implementation that [Hasher] can be backed by.
+pub trait Sha256Backend: Clone + Default {
+    /// Adds `data` to the input being hashed.
+    fn update(&mut self, data: &[u8]);
+
+    /// Consumes the backend and returns the hash of all data passed to [Sha256Backend::update].
+    fn finalize(self) -> Hash;
+}
+
+impl Sha256Backend for Sha256 {
+    fn update(&mut self, data: &[u8]) {
+        Digest::update(self, data);
+    }
+
+    fn finalize(self) -> Hash {
+        Digest::finalize(self).into()
+    }
+}
+
+impl Sha256Backend for k256::sha2::Sha256 {
+    fn update(&mut self, data: &[u8]) {
+        k256::sha2::Digest::update(self, data);
+    }
+
+    fn finalize(self) -> Hash {
+        k256::sha2::Digest::finalize(self).into()
+    }
+}
+
+/// The backend used by [hash_bytes] and message hashing. Defaults to the `sha2` crate, enable the
+/// `k256-sha256` feature to use the implementation bundled with `k256` instead.
+#[cfg(not(feature = "k256-sha256"))]
+pub type DefaultSha256 = Sha256;
+
+/// The backend used by [hash_bytes] and message hashing. Defaults to the `sha2` crate, enable the
+/// `k256-sha256` feature to use the implementation bundled with `k256` instead.
+#[cfg(feature = "k256-sha256")]
+pub type DefaultSha256 = k256::sha2::Sha256;
+
+/// An incremental SHA-256 hasher. Feed data in chunks using [Hasher::update] and obtain the hash with
+/// [Hasher::finalize], without first collecting the whole input in memory.
+#[derive(Clone, Default)]
+pub struct Hasher<B: Sha256Backend = DefaultSha256>(B);
+
+impl Hasher {
+    /// Creates a hasher backed by [DefaultSha256]. Use `Hasher::<B>::default()` for another backend.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<B: Sha256Backend> Hasher<B> {
+    /// Adds `data` to the input being hashed.
+    pub fn update(&mut self, data: impl AsRef<[u8]>) {
+        self.0.update(data.as_ref());
+    }
+
+    /// Consumes the hasher and returns the hash of all data passed to [Hasher::update].
+    pub fn finalize(self) -> Hash {
+        self.0.finalize()
+    }
+}
+
 /// Hashes a byte slice.
 pub(crate) fn hash_bytes(value: impl AsRef<[u8]>) -> Hash {
-    let mut hasher = Sha256::new();
-    hasher.update(value.as_ref());
-    hasher.finalize().into()
+    let mut hasher = Hasher::new();
+    hasher.update(value);
+    hasher.finalize()
 }
 
 /// Hashes a 64-bit unsigned integer.
@@ -81,12 +144,12 @@ fn hash_u64(value: u64) -> Hash {
 
 /// Hashes an array of `Value`.
 fn hash_array(elements: Vec<Value<'_>>) -> Hash {
-    let mut hasher = Sha256::new();
+    let mut hasher = Hasher::new();
     for element in elements {
         hasher.update(&hash_value(element)[..]);
     }
 
-    hasher.finalize().into()
+    hasher.finalize()
 }
 
 /// Hashes a `Value`.
@@ -250,6 +313,19 @@ mod tests {
         );
     }
 
+    #[test]
+    fn incremental_hash_equals_one_shot() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        let mut hasher = Hasher::new();
+        for chunk in data.chunks(97) {
+            hasher.update(chunk);
+        }
+        assert_eq!(hasher.finalize(), hash_bytes(&data));
+
+        // No input at all hashes like an empty slice.
+        assert_eq!(Hasher::new().finalize(), hash_bytes([]));
+    }
+
     #[test]
     fn message_id_bytes_reference() {
         assert_eq!(
@@ -259,4 +335,20 @@ mod tests {
             hex!("6c0b2ae49718f6995c02ac5700c9c789d7b7862a0d53e6d40a73f1fcd2f70189")
         );
     }
+
+    #[test]
+    fn sha256_backends_agree() {
+        let inputs: [&[u8]; 3] = [b"", b"hello", &[0xab; 1000]];
+        for input in inputs {
+            let mut sha2 = Hasher::<Sha256>::default();
+            let mut k256 = Hasher::<k256::sha2::Sha256>::default();
+            for chunk in input.chunks(7) {
+                sha2.update(chunk);
+                k256.update(chunk);
+            }
+            let digest = sha2.finalize();
+            assert_eq!(digest, k256.finalize());
+            assert_eq!(digest, hash_bytes(input));
+        }
+    }
 }
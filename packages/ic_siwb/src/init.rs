@@ -1,18 +1,50 @@
-use crate::{settings::Settings, SETTINGS};
+use std::fmt;
+
+use crate::settings::{validate_salt, validate_session_expires_in, Settings};
+use crate::SETTINGS;
+
+/// The minimum length of the salt accepted by [init].
+pub const MIN_SALT_LENGTH: usize = 8;
+
+/// Returned by [ensure_initialized] before [init] has been called.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NotInitialized;
+
+impl fmt::Display for NotInitialized {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Settings are not initialized")
+    }
+}
+
+impl From<NotInitialized> for String {
+    fn from(error: NotInitialized) -> Self {
+        error.to_string()
+    }
+}
 
 /// Initializes the SIWB library with the provided settings. Must be called before any other SIWB functions. Use the [SettingsBuilder](crate::settings::SettingsBuilder)  to create a [Settings] object.
 ///
+/// The salt is part of the seed of every user principal. Deployments that share a salt, for example
+/// because it was left empty or set to a short default, derive the same principal for the same address
+/// and domain, so a principal obtained from one canister is valid for the other. `init` therefore
+/// rejects salts shorter than [MIN_SALT_LENGTH] characters. Use a random value that is unique to the
+/// deployment, and keep it across upgrades since changing it changes all user principals.
+///
 /// # Parameters
 ///
 /// * `settings` - The SIWB settings to be initialized.
 ///
+/// # Errors
+///
+/// Returns an error if the salt is shorter than [MIN_SALT_LENGTH] characters.
+///
 /// # Examples
 ///
 /// ```
 /// use bitcoin::Network::Bitcoin;
 /// use ic_siwb::{init, settings::SettingsBuilder};
 ///
-/// let settings = SettingsBuilder::new("example.com", "http://example.com", "salt")
+/// let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
 ///   .scheme("https")
 ///   .statement("Sign in with Bitcoin")
 ///   .network(Bitcoin)
@@ -24,15 +56,123 @@ use crate::{settings::Settings, SETTINGS};
 /// ```
 ///
 pub fn init(settings: Settings) -> Result<(), String> {
+    if settings.salt.len() < MIN_SALT_LENGTH {
+        return Err(format!(
+            "Salt must be at least {} characters long",
+            MIN_SALT_LENGTH
+        ));
+    }
+    // The settings fields are public, so the session TTL may have been changed after the builder
+    // validated it.
+    validate_session_expires_in(settings.session_expires_in, settings.min_session_expires_in)?;
+
     SETTINGS.set(Some(settings));
 
-    #[cfg(feature = "nonce")]
+    // Tests generate nonces from their own RNG, see [crate::rand::seed_rng], and cannot set timers.
+    #[cfg(all(feature = "nonce", not(test)))]
     init_rng();
 
     Ok(())
 }
 
-#[cfg(feature = "nonce")]
+/// Returns `true` once [init] has been called. Until then, e.g. while a canister is being installed or
+/// upgraded, the SIWB functions trap as they have no settings to work with.
+pub fn is_initialized() -> bool {
+    SETTINGS.with_borrow(|settings| settings.is_some())
+}
+
+/// Like [is_initialized], but returns [NotInitialized] as an error, so that callers such as canister
+/// endpoints can return it instead of trapping.
+pub fn ensure_initialized() -> Result<(), NotInitialized> {
+    if is_initialized() {
+        Ok(())
+    } else {
+        Err(NotInitialized)
+    }
+}
+
+/// Replaces the salt of the initialized settings, e.g. after the salt has leaked, and bumps the salt
+/// generation. The seed version, which selects the seed derivation, is left as is, so the salt can be
+/// rotated any number of times.
+///
+/// ## 🛑 Changing the salt changes the principal of every user. Mappings and delegations derived from
+/// the old salt have to be discarded by the caller, and the new salt and salt generation have to be
+/// passed to every later [init], otherwise the old principals come back.
+///
+/// # Errors
+///
+/// Returns an error if the library is not initialized, or if `new_salt` is invalid, shorter than
+/// [MIN_SALT_LENGTH] characters or equal to the current salt.
+///
+/// # Returns
+///
+/// The new salt generation.
+pub fn rotate_salt(new_salt: &str) -> Result<u64, String> {
+    validate_salt(new_salt)?;
+    if new_salt.len() < MIN_SALT_LENGTH {
+        return Err(format!(
+            "Salt must be at least {} characters long",
+            MIN_SALT_LENGTH
+        ));
+    }
+
+    SETTINGS.with_borrow_mut(|settings| {
+        let settings = settings
+            .as_mut()
+            .ok_or_else(|| NotInitialized.to_string())?;
+        if settings.salt == new_salt {
+            return Err("The new salt must differ from the current salt".to_string());
+        }
+        settings.salt = new_salt.to_string();
+        settings.salt_generation = settings.salt_generation.saturating_add(1);
+        Ok(settings.salt_generation)
+    })
+}
+
+/// Allows delegations to be issued for `session_key`, see
+/// [SettingsBuilder::allowed_session_keys](crate::settings::SettingsBuilder::allowed_session_keys). If no
+/// session keys were allowed before, logins are restricted to `session_key` from now on.
+///
+/// ## 🛑 The allowed session keys are part of the settings. Pass them to every later [init], otherwise the
+/// session keys of the new settings replace them.
+///
+/// # Returns
+///
+/// Whether `session_key` was not allowed before, or an error if the library is not initialized.
+pub fn allow_session_key(session_key: &[u8]) -> Result<bool, String> {
+    SETTINGS.with_borrow_mut(|settings| {
+        let settings = settings
+            .as_mut()
+            .ok_or_else(|| NotInitialized.to_string())?;
+        Ok(settings
+            .allowed_session_keys
+            .get_or_insert_with(Default::default)
+            .insert(session_key.to_vec()))
+    })
+}
+
+/// Stops delegations from being issued for `session_key`. Delegations that have already been issued stay
+/// valid until they expire. Removing the last allowed session key allows no session key at all, rather
+/// than any.
+///
+/// # Returns
+///
+/// Whether `session_key` was allowed before, or an error if the library is not initialized or logins are
+/// not restricted to allowed session keys.
+pub fn disallow_session_key(session_key: &[u8]) -> Result<bool, String> {
+    SETTINGS.with_borrow_mut(|settings| {
+        let settings = settings
+            .as_mut()
+            .ok_or_else(|| NotInitialized.to_string())?;
+        let allowed_session_keys = settings
+            .allowed_session_keys
+            .as_mut()
+            .ok_or_else(|| "Session keys are not restricted".to_string())?;
+        Ok(allowed_session_keys.remove(session_key))
+    })
+}
+
+#[cfg(all(feature = "nonce", not(test)))]
 fn init_rng() {
     use crate::RNG;
     use candid::Principal;
@@ -50,3 +190,110 @@ fn init_rng() {
         })
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::SettingsBuilder;
+
+    fn settings(salt: &str) -> Settings {
+        let mut settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .build()
+            .unwrap();
+        settings.salt = salt.to_string();
+        settings
+    }
+
+    #[test]
+    fn test_init_rejects_empty_or_short_salt() {
+        assert!(init(settings("")).is_err());
+        assert!(init(settings("salt")).is_err());
+        assert!(SETTINGS.with_borrow(|s| s.is_none()));
+    }
+
+    #[test]
+    fn test_rotate_salt() {
+        use bitcoin::Address;
+        use std::str::FromStr;
+
+        let address = Address::from_str("bc1qshqyem2rf8jyla904gd2cvek2k8nz5z3x73p24")
+            .unwrap()
+            .assume_checked();
+        assert!(rotate_salt("another_salt").is_err());
+
+        init(settings("some_salt")).unwrap();
+        let seed = crate::delegation::generate_seed(&address, None);
+
+        assert!(rotate_salt("some_salt").is_err());
+        assert!(rotate_salt("short").is_err());
+        assert!(rotate_salt("not a salt").is_err());
+        assert_eq!(rotate_salt("another_salt"), Ok(1));
+
+        SETTINGS.with_borrow(|s| {
+            let s = s.as_ref().unwrap();
+            assert_eq!(s.salt, "another_salt");
+            assert_eq!(s.seed_version, 1);
+        });
+        let rotated = crate::delegation::generate_seed(&address, None);
+        assert_ne!(seed, rotated);
+
+        // A second leak is handled the same way.
+        assert_eq!(rotate_salt("third_salt"), Ok(2));
+        SETTINGS.with_borrow(|s| {
+            let s = s.as_ref().unwrap();
+            assert_eq!(s.salt, "third_salt");
+            assert_eq!(s.seed_version, 1);
+        });
+        let rotated_again = crate::delegation::generate_seed(&address, None);
+        assert_ne!(seed, rotated_again);
+        assert_ne!(rotated, rotated_again);
+    }
+
+    #[test]
+    fn test_allow_and_disallow_session_keys() {
+        assert!(allow_session_key(b"kiosk 1").is_err());
+
+        init(settings("some_salt")).unwrap();
+        assert!(disallow_session_key(b"kiosk 1").is_err());
+        assert_eq!(allow_session_key(b"kiosk 1"), Ok(true));
+        assert_eq!(allow_session_key(b"kiosk 1"), Ok(false));
+        SETTINGS.with_borrow(|s| {
+            let s = s.as_ref().unwrap();
+            assert!(s.is_session_key_allowed(b"kiosk 1"));
+            assert!(!s.is_session_key_allowed(b"kiosk 2"));
+        });
+
+        assert_eq!(disallow_session_key(b"kiosk 2"), Ok(false));
+        assert_eq!(disallow_session_key(b"kiosk 1"), Ok(true));
+        SETTINGS.with_borrow(|s| assert!(!s.as_ref().unwrap().is_session_key_allowed(b"kiosk 1")));
+    }
+
+    #[test]
+    fn test_is_initialized() {
+        assert!(!is_initialized());
+        assert_eq!(ensure_initialized(), Err(NotInitialized));
+        assert_eq!(
+            rotate_salt("another_salt"),
+            Err("Settings are not initialized".to_string())
+        );
+
+        init(settings("some_salt")).unwrap();
+        assert!(is_initialized());
+        assert_eq!(ensure_initialized(), Ok(()));
+    }
+
+    #[test]
+    fn test_init_rejects_session_below_floor() {
+        let mut below_floor = settings("some_salt");
+        below_floor.min_session_expires_in = 60_000_000_000;
+        below_floor.session_expires_in = 59_999_999_999;
+        assert!(init(below_floor).is_err());
+        assert!(SETTINGS.with_borrow(|s| s.is_none()));
+    }
+
+    #[test]
+    fn test_init_accepts_salt() {
+        assert!(init(settings("some_salt")).is_ok());
+        assert!(SETTINGS.with_borrow(|s| s.is_some()));
+    }
+}
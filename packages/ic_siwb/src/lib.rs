@@ -1,3 +1,5 @@
+#[cfg(feature = "debug")]
+mod debug;
 pub mod delegation;
 pub mod error;
 pub mod hash;
@@ -6,17 +8,22 @@ pub mod login;
 pub mod macros;
 pub mod rand;
 pub mod settings;
+pub mod sign;
 pub mod signature_map;
 pub mod siwb;
+pub mod store;
 pub mod time;
 pub mod utils;
+pub mod verify;
 pub use bitcoin;
 
 pub use init::init;
 
 use std::cell::RefCell;
 
+use crate::login::ConsumedLoginMap;
 use crate::settings::Settings;
+use crate::sign::SignPayloadMap;
 use crate::siwb::SiwbMessageMap;
 #[cfg(feature = "nonce")]
 use rand_chacha::ChaCha20Rng;
@@ -35,4 +42,12 @@ thread_local! {
     // Bitcoin address as a byte array and the value is the SIWB message. After a successful
     // login, the SIWB message is removed from state.
     static SIWB_MESSAGES: RefCell<SiwbMessageMap> = RefCell::new(SiwbMessageMap::new());
+
+    // Payloads to be signed outside of the login process, keyed like the SIWB messages. A payload
+    // is removed once a signature over it has been verified.
+    static SIGN_PAYLOADS: RefCell<SignPayloadMap> = RefCell::new(SignPayloadMap::default());
+
+    // Logins that consumed their SIWB message within the configured retry window, so that a retried
+    // login returns the same login details.
+    static CONSUMED_LOGINS: RefCell<ConsumedLoginMap> = RefCell::new(ConsumedLoginMap::default());
 }
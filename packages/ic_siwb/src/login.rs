@@ -1,11 +1,11 @@
 use std::collections::BTreeMap;
 use std::fmt;
-use std::mem::size_of;
 use std::str::FromStr;
 
 use base64::engine::general_purpose;
 use base64::Engine;
 use bitcoin::absolute::LockTime;
+use bitcoin::address::NetworkUnchecked;
 use bitcoin::hashes::Hash;
 use bitcoin::key::XOnlyPublicKey;
 use bitcoin::psbt::{Prevouts, Psbt};
@@ -13,44 +13,57 @@ use bitcoin::script::Builder;
 use bitcoin::script::Instruction::PushBytes;
 use bitcoin::secp256k1::{Message, Secp256k1, ThirtyTwoByteHash};
 use bitcoin::sighash::{EcdsaSighashType, SighashCache, TapSighashType};
-use bitcoin::Network::{Bitcoin, Testnet};
 use bitcoin::{
-    secp256k1, Address, AddressType, Network, OutPoint, PublicKey as BitcoinPublicKey, Script,
-    ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+    secp256k1, Address, AddressType, Network, OutPoint, Script, ScriptBuf, Sequence, Transaction,
+    TxIn, TxOut, Txid, Witness,
 };
-use byteorder::{ByteOrder, LittleEndian};
 use candid::{CandidType, Deserialize, Principal};
-use k256::ecdsa::{RecoveryId, Signature, VerifyingKey};
-use k256::sha2::digest::FixedOutput;
-use k256::sha2::{Digest, Sha256};
+use ic_certified_map::HashTree;
 use serde::Serialize;
 use serde_bytes::ByteBuf;
 use simple_asn1::ASN1EncodeErr;
 
 use crate::error::BtcError;
 use crate::error::BtcError::AddressTypeNotSupported;
-use crate::hash::hash_bytes;
-use crate::utils::{get_script_from_address, AddressInfo};
+use crate::utils::{check_witness_version, classify_address, get_script_from_address, AddressInfo};
+pub use crate::verify::{
+    _msg_hash, _msg_hash_with, calculate_sig_recovery, decode_public_key, msg_hash,
+    recover_pub_key_compact, recover_signing_address, verify_address, verify_address_for_network,
+    verify_signed_message, VerifiedAddress, WalletKind,
+};
+use crate::verify::{
+    _verify_message_for_address, is_schnorr_key_spend, message_digest,
+    recover_public_keys_from_digest, signature_candidates, tagged_hash, DEFAULT_MESSAGE_HASH_TAG,
+};
 use crate::{
     delegation::{
-        create_delegation, create_delegation_hash, create_user_canister_pubkey, generate_seed,
-        DelegationError,
+        check_certificate_current, create_certified_signature, create_delegation,
+        create_delegation_hash, create_user_canister_pubkey, generate_seed, validate_session_key,
+        witness,
+        DelegationError, SignedDelegation,
     },
     hash,
     settings::Settings,
     signature_map::SignatureMap,
-    siwb::{SiwbMessage, SiwbMessageError},
+    siwb::{ChallengeToken, SiwbMessage, SiwbMessageError},
     time::get_current_time,
-    with_settings, SIWB_MESSAGES,
+    with_settings, CONSUMED_LOGINS, SIWB_MESSAGES,
 };
 
 const MAX_SIGS_TO_PRUNE: usize = 10;
-const MAGIC_BYTES: &str = "Bitcoin Signed Message:\n";
 
+/// The scheme a wallet signed the SIWB message with.
+///
+/// * `ECDSA`: a legacy message signature over the framed message hash, or, for Taproot addresses, a
+///   Schnorr signature over it. If settings configure a `message_hash_tag`, over the tagged hash instead.
+/// * `Bip322Simple`: a BIP-322 simple signature.
+/// * `TaggedHash`: like `ECDSA`, but over the BIP-340 tagged hash of the message, with the configured
+///   `message_hash_tag` or [crate::verify::DEFAULT_MESSAGE_HASH_TAG], for wallets that sign tagged hashes.
 #[derive(CandidType, Clone, Serialize, Deserialize)]
 pub enum SignMessageType {
     ECDSA,
     Bip322Simple,
+    TaggedHash,
 }
 
 pub struct BtcSignature(pub String);
@@ -64,6 +77,9 @@ pub struct BtcSignature(pub String);
 ///
 /// # Returns
 /// A `Result` that, on success, contains the `SiwbMessage` for the user, or an error string on failure.
+/// The message's `expiration_time` is the absolute time, `issued_at + sign_in_expires_in`, after which
+/// it can no longer be used to log in. With the `nonce` feature, [BtcError::RngNotSeeded] is returned
+/// until the nonce generator has been seeded from `raw_rand`, shortly after [crate::init::init].
 ///
 /// # Example
 /// ```ignore
@@ -75,7 +91,224 @@ pub struct BtcSignature(pub String);
 /// let message = prepare_login(&address).unwrap();
 /// ```
 pub fn prepare_login(address: &Address) -> Result<SiwbMessage, BtcError> {
-    let message = SiwbMessage::new(address);
+    prepare_login_with_issued_at(address, None)
+}
+
+/// The SIWB message in the two forms returned by [prepare_login_ex].
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct PreparedMessage {
+    /// The exact string the wallet signs, in the ERC-4361 format.
+    pub string: String,
+
+    /// The same message as a JSON object with every field of the string, for custom rendering.
+    pub json: String,
+}
+
+impl From<SiwbMessage> for PreparedMessage {
+    fn from(message: SiwbMessage) -> Self {
+        PreparedMessage {
+            json: message.to_string(),
+            string: message.into(),
+        }
+    }
+}
+
+/// Like [prepare_login_with_issued_at], but returns the message both as the string to be signed and as
+/// JSON, so that frontends can render the message themselves.
+pub fn prepare_login_ex(
+    address: &Address,
+    issued_at: Option<u64>,
+) -> Result<PreparedMessage, BtcError> {
+    prepare_login_with_issued_at(address, issued_at).map(PreparedMessage::from)
+}
+
+/// Like [prepare_login], but the message is issued at `issued_at` (nanoseconds since the UNIX epoch)
+/// when supplied, e.g. to construct reproducible messages. Overrides further than the configured
+/// `max_issued_at_skew` plus `clock_skew` from the current time are rejected with
+/// [BtcError::IssuedAtOutOfBounds]. When `issued_at` is `None`, the current time is used.
+pub fn prepare_login_with_issued_at(
+    address: &Address,
+    issued_at: Option<u64>,
+) -> Result<SiwbMessage, BtcError> {
+    prepare_login_for_domain(address, None, issued_at)
+}
+
+/// Like [prepare_login_with_issued_at], but for the frontend served from `domain`, for providers that
+/// serve several frontends. `domain` must be the configured `domain` or one of the `allowed_domains`,
+/// otherwise [BtcError::DomainNotAllowed] is returned. The message names `domain`, and can only be used
+/// to log in while `domain` stays allowed. When `domain` is `None`, the configured `domain` is used.
+pub fn prepare_login_for_domain(
+    address: &Address,
+    domain: Option<&str>,
+    issued_at: Option<u64>,
+) -> Result<SiwbMessage, BtcError> {
+    prepare_login_with_request_id(address, domain, issued_at, None)
+}
+
+/// Like [prepare_login_for_domain], but the message carries `request_id`, the EIP-4361 `requestId`, that
+/// frontends can use to correlate the login with a server-side request. The request id is rendered into
+/// the signed message and returned unchanged in the [LoginDetails] of the login. It must be non-blank
+/// and must not contain whitespace, otherwise [BtcError::MalformedMessage] is returned.
+pub fn prepare_login_with_request_id(
+    address: &Address,
+    domain: Option<&str>,
+    issued_at: Option<u64>,
+    request_id: Option<&str>,
+) -> Result<SiwbMessage, BtcError> {
+    let options = PrepareLoginOptions {
+        domain: domain.map(str::to_string),
+        ..Default::default()
+    };
+    prepare_login_until(address, &options, issued_at, request_id, None)
+}
+
+/// Like [prepare_login_with_request_id], for the canister `canister_id`. With the `bind_canister_id`
+/// setting enabled, the message names `canister_id` and can only be used to log in at that canister.
+/// Messages must then be prepared with this function, [prepare_login_with_request_id] and the other
+/// functions fail with [BtcError::MalformedMessage]. Otherwise `canister_id` is ignored.
+pub fn prepare_login_for_canister(
+    address: &Address,
+    canister_id: &Principal,
+    domain: Option<&str>,
+    issued_at: Option<u64>,
+    request_id: Option<&str>,
+) -> Result<SiwbMessage, BtcError> {
+    let options = PrepareLoginOptions {
+        domain: domain.map(str::to_string),
+        ..Default::default()
+    };
+    prepare_login_until(address, &options, issued_at, request_id, Some(canister_id))
+}
+
+/// Overrides of the fields of the SIWB message prepared by [prepare_login_full]. Fields that are `None`
+/// take their configured value.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, Default)]
+pub struct PrepareLoginOptions {
+    /// The domain of the frontend, the configured `domain` or one of the `allowed_domains`.
+    pub domain: Option<String>,
+
+    /// The statement, the configured `statement` or one of the `allowed_statements`.
+    pub statement: Option<String>,
+
+    /// A nonce chosen by the client instead of a random one, e.g. one issued by its backend. Must be at
+    /// least 8 alphanumeric characters, as in EIP-4361.
+    pub nonce: Option<String>,
+
+    /// The URI, the configured `uri` or one of the `allowed_uris`.
+    pub uri: Option<String>,
+}
+
+/// The SIWB message prepared by [prepare_login_full] and the digest that is signed.
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct PreparedLogin {
+    /// The exact string the wallet signs, in the ERC-4361 format.
+    pub message: String,
+
+    /// The hex encoded digest of `message` that a legacy message signature signs: the message framed
+    /// with the configured message prefix and hashed as configured by `double_hash`, or its tagged hash
+    /// if a `message_hash_tag` is configured. Wallets that support signing raw hashes can sign it
+    /// directly.
+    pub digest_hex: String,
+}
+
+/// Like [prepare_login_for_canister], but with the message fields overridden by `options`, which are
+/// validated against the allowlists of the settings. Returns the message along with its digest, so
+/// that clients do not have to frame and hash it themselves.
+///
+/// # Returns
+/// The [PreparedLogin], or [BtcError::DomainNotAllowed], [BtcError::StatementNotAllowed] or
+/// [BtcError::UriNotAllowed] if an option is not allowed, and [BtcError::MalformedMessage] if the nonce
+/// is invalid.
+pub fn prepare_login_full(
+    address: &Address,
+    canister_id: Option<&Principal>,
+    options: &PrepareLoginOptions,
+) -> Result<PreparedLogin, BtcError> {
+    let message: String = prepare_login_until(address, options, None, None, canister_id)?.into();
+    let digest = with_settings!(|settings: &Settings| message_digest(
+        &message,
+        settings.double_hash,
+        settings.message_prefix(settings.network_for(address)),
+        settings.message_hash_tag.as_deref()
+    ));
+    Ok(PreparedLogin {
+        message,
+        digest_hex: hex::encode(digest),
+    })
+}
+
+fn prepare_login_until(
+    address: &Address,
+    options: &PrepareLoginOptions,
+    issued_at: Option<u64>,
+    request_id: Option<&str>,
+    canister_id: Option<&Principal>,
+) -> Result<SiwbMessage, BtcError> {
+    check_address_type_allowed(address)?;
+    check_address_network(address)?;
+    with_settings!(|settings: &Settings| {
+        if options
+            .domain
+            .as_deref()
+            .is_some_and(|domain| !settings.is_domain_allowed(domain))
+        {
+            return Err(BtcError::DomainNotAllowed);
+        }
+        if options
+            .statement
+            .as_deref()
+            .is_some_and(|statement| !settings.is_statement_allowed(statement))
+        {
+            return Err(BtcError::StatementNotAllowed);
+        }
+        if options
+            .uri
+            .as_deref()
+            .is_some_and(|uri| !settings.is_uri_allowed(uri))
+        {
+            return Err(BtcError::UriNotAllowed);
+        }
+        Ok(())
+    })?;
+    if let Some(nonce) = &options.nonce {
+        if nonce.len() < 8 || !nonce.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(BtcError::MalformedMessage("nonce is invalid".to_string()));
+        }
+    }
+    let now = get_current_time();
+    let issued_at = match issued_at {
+        Some(issued_at) => {
+            let max_skew = with_settings!(|settings: &Settings| settings
+                .max_issued_at_skew
+                .saturating_add(settings.clock_skew));
+            if issued_at.abs_diff(now) > max_skew {
+                return Err(BtcError::IssuedAtOutOfBounds);
+            }
+            issued_at
+        }
+        None => now,
+    };
+    let mut message = SiwbMessage::try_new_with_issued_at(address, issued_at)?;
+    if let Some(domain) = &options.domain {
+        message.domain = domain.clone();
+    }
+    if let Some(statement) = &options.statement {
+        message.statement = statement.clone();
+    }
+    if let Some(nonce) = &options.nonce {
+        message.nonce = nonce.clone();
+    }
+    if let Some(uri) = &options.uri {
+        message.uri = uri.clone();
+    }
+    message.request_id = request_id.map(str::to_string);
+    if with_settings!(|settings: &Settings| settings.bind_canister_id) {
+        let canister_id = canister_id
+            .ok_or_else(|| BtcError::MalformedMessage("canister_id is not set".to_string()))?;
+        message.canister_id = Some(canister_id.to_text());
+    }
+    message.validate()?;
+    check_message_length(&String::from(message.clone()))?;
 
     // Save the SIWB message for use in the login call
     SIWB_MESSAGES.with_borrow_mut(|siwb_messages| {
@@ -84,9 +317,103 @@ pub fn prepare_login(address: &Address) -> Result<SiwbMessage, BtcError> {
 
     Ok(message)
 }
+
+/// Rejects messages longer than the configured `max_message_bytes`, before they are hashed.
+pub(crate) fn check_message_length(message: &str) -> Result<(), BtcError> {
+    let max_message_bytes = with_settings!(|settings: &Settings| settings.max_message_bytes);
+    if message.len() > max_message_bytes {
+        return Err(BtcError::MessageTooLong(max_message_bytes));
+    }
+    Ok(())
+}
+
+/// Rejects addresses that are not valid for any of the allowed networks with
+/// [BtcError::NetworkMismatch] when `strict_network` is enabled. Testnet and signet share their
+/// encodings, so an address of either is valid for both.
+pub(crate) fn check_address_network(address: &Address) -> Result<(), BtcError> {
+    with_settings!(|settings: &Settings| {
+        if settings.strict_network && settings.address_network(address).is_none() {
+            return Err(BtcError::NetworkMismatch);
+        }
+        Ok(())
+    })
+}
+
+/// Rejects addresses whose type is not in the configured `allowed_address_types`. An empty list allows
+/// all address types. Addresses of unsupported witness versions are always rejected.
+fn check_address_type_allowed(address: &Address) -> Result<(), BtcError> {
+    check_witness_version(&address.payload)?;
+    with_settings!(|settings: &Settings| {
+        if settings.allowed_address_types.is_empty() {
+            return Ok(());
+        }
+        match address.address_type() {
+            Some(t) if settings.allowed_address_types.contains(&t) => Ok(()),
+            _ => Err(BtcError::AddressTypeNotAllowed),
+        }
+    })
+}
+
+/// Describes an address that can log in, as returned by [validate_address].
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Serialize, Deserialize)]
+pub struct AddressMeta {
+    /// The allowed network the address logs in on, e.g. "bitcoin" or "testnet".
+    pub network: String,
+
+    pub address_type: LoginAddressType,
+
+    /// The canonical form of the address, see [crate::utils::AddressInfo::canonical].
+    pub canonical: String,
+}
+
+/// Validates a user-entered `address` without side effects, so that frontends can give immediate
+/// feedback before calling [prepare_login]. The address is classified with [classify_address] and must
+/// be of an allowed network and an allowed address type, even if `strict_network` is disabled.
+///
+/// # Returns
+/// The [AddressMeta] of the address, or the [BtcError] that a login with it would fail with, e.g.
+/// [BtcError::NetworkMismatch] or [BtcError::AddressTypeNotAllowed].
+pub fn validate_address(address: &str) -> Result<AddressMeta, BtcError> {
+    let (_, address_type) = classify_address(address)?;
+    let address_type =
+        LoginAddressType::from_address_type(address_type).ok_or(AddressTypeNotSupported)?;
+    let address = Address::<NetworkUnchecked>::from_str(address)?.assume_checked();
+    check_address_type_allowed(&address)?;
+    let network = with_settings!(|settings: &Settings| settings.address_network(&address))
+        .ok_or(BtcError::NetworkMismatch)?;
+    Ok(AddressMeta {
+        network: network.to_string(),
+        address_type,
+        canonical: address.to_string(),
+    })
+}
+
+/// The type of the address a user logged in with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, CandidType, Serialize, Deserialize)]
+pub enum LoginAddressType {
+    P2pkh,
+    /// P2SH-P2WPKH, the only P2SH address that can log in.
+    P2sh,
+    P2wpkh,
+    P2tr,
+}
+
+impl LoginAddressType {
+    /// The [LoginAddressType] of `address_type`, `None` for address types that cannot log in.
+    pub fn from_address_type(address_type: AddressType) -> Option<Self> {
+        match address_type {
+            AddressType::P2pkh => Some(LoginAddressType::P2pkh),
+            AddressType::P2sh => Some(LoginAddressType::P2sh),
+            AddressType::P2wpkh => Some(LoginAddressType::P2wpkh),
+            AddressType::P2tr => Some(LoginAddressType::P2tr),
+            _ => None,
+        }
+    }
+}
+
 /// Login details are returned after a successful login. They contain the expiration time of the
 /// delegation and the user canister public key.
-#[derive(Clone, Debug, CandidType, Deserialize)]
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
 pub struct LoginDetails {
     /// The session expiration time in nanoseconds since the UNIX epoch. This is the time at which
     /// the delegation will no longer be valid.
@@ -94,14 +421,110 @@ pub struct LoginDetails {
 
     /// The user canister public key. This key is used to derive the user principal.
     pub user_canister_pubkey: ByteBuf,
+
+    /// The candidate public key that verified the signature. `None` for BIP-322 logins, where the
+    /// public key is not supplied by the caller.
+    pub public_key: Option<String>,
+
+    /// The `request_id` of the SIWB message that was signed, see [prepare_login_with_request_id].
+    pub request_id: Option<String>,
+
+    /// The type of the address whose signature was verified, e.g. to track the adoption of address types.
+    pub address_type: LoginAddressType,
+
+    /// The canisters the delegation is scoped to, see [crate::settings::SettingsBuilder::targets].
+    /// `None` if the delegation is valid for all canisters.
+    pub targets: Option<Vec<Principal>>,
+}
+
+impl LoginDetails {
+    /// Returns the remaining session duration in whole seconds at time `now` (nanoseconds since the UNIX
+    /// epoch), saturating to zero once the delegation has expired.
+    pub fn remaining_secs(&self, now: u64) -> u64 {
+        self.expiration.saturating_sub(now) / 1_000_000_000
+    }
+
+    /// Serializes the login details to CBOR, for consumers that do not use Candid.
+    ///
+    /// The encoding is a map keyed by the field names of [LoginDetails]: `expiration` is an unsigned
+    /// integer, `user_canister_pubkey` a byte string, `public_key` and `request_id` text strings or
+    /// null, `address_type` the name of the [LoginAddressType] variant and `targets` an array of
+    /// principals or null.
+    pub fn to_cbor(&self) -> Result<Vec<u8>, serde_cbor::Error> {
+        serde_cbor::to_vec(self)
+    }
+
+    /// Deserializes login details from the CBOR encoding produced by [LoginDetails::to_cbor].
+    pub fn from_cbor(bytes: &[u8]) -> Result<Self, serde_cbor::Error> {
+        serde_cbor::from_slice(bytes)
+    }
+}
+
+/// A login that consumed its SIWB message, kept for the configured `login_retry_window`.
+struct ConsumedLogin {
+    signature: String,
+    session_key: ByteBuf,
+    realm: Option<String>,
+    details: LoginDetails,
+    expires_at: u64,
+}
+
+/// The logins that consumed their SIWB message within the last `login_retry_window`, keyed by the script
+/// pubkey of the address. A retry of such a login, e.g. after a network error, finds its message gone
+/// and is answered with the cached [LoginDetails] instead.
+#[derive(Default)]
+pub(crate) struct ConsumedLoginMap {
+    map: BTreeMap<Vec<u8>, ConsumedLogin>,
 }
 
+impl ConsumedLoginMap {
+    fn prune_expired(&mut self, now: u64) {
+        self.map.retain(|_, login| login.expires_at > now);
+    }
+
+    /// The details of the login of `address_bytes`, if it was made with the same signature, session key
+    /// and realm and has not expired at `now`.
+    fn get_retry(
+        &mut self,
+        address_bytes: &[u8],
+        signature: &str,
+        session_key: &ByteBuf,
+        realm: Option<&str>,
+        now: u64,
+    ) -> Option<LoginDetails> {
+        self.prune_expired(now);
+        self.map
+            .get(address_bytes)
+            .filter(|login| {
+                login.signature == signature
+                    && login.session_key == *session_key
+                    && login.realm.as_deref() == realm
+            })
+            .map(|login| login.details.clone())
+    }
+
+    fn insert(&mut self, address_bytes: Vec<u8>, login: ConsumedLogin, now: u64) {
+        self.prune_expired(now);
+        self.map.insert(address_bytes, login);
+    }
+}
+
+#[derive(Debug)]
 pub enum LoginError {
     BtcError(BtcError),
     SiwbMessageError(SiwbMessageError),
-    AddressMismatch,
+    /// The signature does not prove ownership of `expected`. `recovered` is the address of the same
+    /// type that the signature was made with, or "unknown" if it cannot be derived from the signature.
+    AddressMismatch {
+        expected: String,
+        recovered: String,
+    },
     DelegationError(DelegationError),
     ASN1EncodeErr(ASN1EncodeErr),
+    InvalidMaxTimeToLive,
+    /// Delegations are restricted to allowed session keys and the session key is not one of them, see
+    /// [crate::settings::SettingsBuilder::allowed_session_keys].
+    SessionKeyNotAllowed,
 }
 
 impl From<BtcError> for LoginError {
@@ -133,336 +556,854 @@ impl fmt::Display for LoginError {
         match self {
             LoginError::BtcError(e) => write!(f, "{}", e),
             LoginError::SiwbMessageError(e) => write!(f, "{}", e),
-            LoginError::AddressMismatch => write!(f, "Recovered address does not match"),
+            // Both addresses are public information derived from the request, so they are safe to
+            // surface. They are truncated to the longest valid address length regardless.
+            LoginError::AddressMismatch {
+                expected,
+                recovered,
+            } => write!(
+                f,
+                "Recovered address {} does not match expected address {}",
+                truncate_address(recovered),
+                truncate_address(expected)
+            ),
             LoginError::DelegationError(e) => write!(f, "{}", e),
             LoginError::ASN1EncodeErr(e) => write!(f, "{}", e),
+            LoginError::InvalidMaxTimeToLive => {
+                write!(f, "Max time to live must be greater than 0")
+            }
+            LoginError::SessionKeyNotAllowed => write!(f, "Session key is not allowed"),
+        }
+    }
+}
+
+impl LoginError {
+    fn address_mismatch(expected: &Address, recovered: Option<String>) -> LoginError {
+        LoginError::AddressMismatch {
+            expected: expected.to_string(),
+            recovered: recovered.unwrap_or_else(|| "unknown".to_string()),
         }
     }
 }
 
+/// Bech32m addresses are at most 90 characters long.
+const MAX_ADDRESS_LEN: usize = 90;
+
+fn truncate_address(address: &str) -> &str {
+    match address.char_indices().nth(MAX_ADDRESS_LEN) {
+        Some((i, _)) => &address[..i],
+        None => address,
+    }
+}
+
 /// Handles the second step of the user login process. It verifies the signature against the SIWB message,
 /// creates a delegation for the session, adds it to the signature map, and returns login details
 ///
 /// # Parameters
 /// * `signature`: The SIWB message signature to verify.
 /// * `address`: The Bitcoin address used to sign the SIWB message.
-/// * `public_key`: The ecdsa public key of wallet, can retrieve from wallet provider
+/// * `public_keys`: Candidate ecdsa public keys of the wallet, as retrieved from the wallet provider. The
+///   login succeeds if any one of them verifies the signature and matches the address.
 /// * `session_key`: A unique session key to be used for the delegation.
 /// * `signature_map`: A mutable reference to `SignatureMap` to which the delegation hash will be added
 ///   after successful validation.
 /// * `canister_id`: The principal of the canister performing the login.
+/// * `sign_message_type`: The message signing scheme used by the wallet.
+/// * `wallet`: The wallet that produced an ECDSA signature, selects how the signature is decoded, see
+///   [WalletKind]. [WalletKind::Auto] tries each known signature layout.
+/// * `realm`: The optional login realm, see [generate_seed].
 ///
 /// # Returns
 /// A `Result` that, on success, contains the [LoginDetails] with session expiration and user canister
-/// public key, or an error string on failure.
+/// public key, or an error string on failure. The details come with `true` if they answer a retry of
+/// an earlier login from the cache kept for the `login_retry_window` setting, in which case no new
+/// delegation was created, so that callers do not count the login twice.
+#[allow(clippy::too_many_arguments)]
 pub fn login(
     signature: &BtcSignature,
     address: &Address,
-    public_key: String,
+    public_keys: Vec<String>,
+    session_key: ByteBuf,
+    signature_map: &mut SignatureMap,
+    canister_id: &Principal,
+    sign_message_type: SignMessageType,
+    wallet: WalletKind,
+    realm: Option<&str>,
+) -> Result<(LoginDetails, bool), LoginError> {
+    login_until(
+        signature,
+        address,
+        public_keys,
+        session_key,
+        signature_map,
+        canister_id,
+        sign_message_type,
+        wallet,
+        realm,
+        None,
+        None,
+        None,
+    )
+}
+
+/// Logs in like [login] with a legacy compact ECDSA signature, but without the public key of the wallet,
+/// for wallets that only return a signature. The public key is recovered from the signature, and the
+/// address of `address`'s type that it controls is derived and compared to `address`. The recovered key
+/// is returned as the `public_key` of the [LoginDetails].
+///
+/// Only P2PKH, P2WPKH and P2SH-P2WPKH addresses can be used. Taproot signatures are Schnorr signatures,
+/// which do not allow recovering the key, so other addresses are rejected with
+/// [BtcError::AddressTypeNotSupported].
+pub fn login_by_address(
+    signature: &BtcSignature,
+    address: &Address,
+    session_key: ByteBuf,
+    signature_map: &mut SignatureMap,
+    canister_id: &Principal,
+    wallet: WalletKind,
+    realm: Option<&str>,
+) -> Result<(LoginDetails, bool), LoginError> {
+    if !matches!(
+        address.address_type(),
+        Some(AddressType::P2pkh | AddressType::P2wpkh | AddressType::P2sh)
+    ) {
+        return Err(LoginError::BtcError(AddressTypeNotSupported));
+    }
+
+    // The key is recovered from the signature over the prepared message, which the login then verifies
+    // as if the wallet had supplied the key. Without the message, a retry of a login that has just
+    // consumed it can still be answered from the retry cache.
+    let message: String = match SIWB_MESSAGES
+        .with_borrow(|siwb_messages| siwb_messages.get(&address.script_pubkey().to_bytes()))
+    {
+        Ok(message) => message.into(),
+        Err(_) => {
+            return login(
+                signature,
+                address,
+                Vec::new(),
+                session_key,
+                signature_map,
+                canister_id,
+                SignMessageType::ECDSA,
+                wallet,
+                realm,
+            )
+        }
+    };
+    let message_prehashed = with_settings!(|settings: &Settings| message_digest(
+        &message,
+        settings.double_hash,
+        settings.message_prefix(settings.network_for(address)),
+        settings.message_hash_tag.as_deref()
+    ));
+    let mut public_keys = Vec::new();
+    for candidate in signature_candidates(&signature.0, wallet)? {
+        for public_key in recover_public_keys_from_digest(&message_prehashed, &candidate) {
+            if !public_keys.contains(&public_key) {
+                public_keys.push(public_key);
+            }
+        }
+    }
+
+    login(
+        signature,
+        address,
+        public_keys,
+        session_key,
+        signature_map,
+        canister_id,
+        SignMessageType::ECDSA,
+        wallet,
+        realm,
+    )
+}
+
+/// Logs in like [login], but only with a message that was prepared for `domain` with
+/// [prepare_login_for_domain]. Messages prepared for another domain are rejected with
+/// [BtcError::DomainNotAllowed].
+#[allow(clippy::too_many_arguments)]
+pub fn login_for_domain(
+    signature: &BtcSignature,
+    address: &Address,
+    public_keys: Vec<String>,
+    session_key: ByteBuf,
+    signature_map: &mut SignatureMap,
+    canister_id: &Principal,
+    sign_message_type: SignMessageType,
+    wallet: WalletKind,
+    realm: Option<&str>,
+    domain: &str,
+) -> Result<(LoginDetails, bool), LoginError> {
+    login_until(
+        signature,
+        address,
+        public_keys,
+        session_key,
+        signature_map,
+        canister_id,
+        sign_message_type,
+        wallet,
+        realm,
+        None,
+        Some(domain),
+        None,
+    )
+}
+
+/// Logs in like [login], but the session ends after at most `max_time_to_live` nanoseconds, e.g. for
+/// sensitive operations that require a recent login. This mirrors the `max_time_to_live` parameter of
+/// Internet Identity. The session TTL is the requested TTL capped at the configured
+/// `session_expires_in` and raised to the configured `min_session_expires_in`.
+///
+/// # Returns
+/// The [LoginDetails] as for [login], or [LoginError::InvalidMaxTimeToLive] if `max_time_to_live` is 0.
+#[allow(clippy::too_many_arguments)]
+pub fn login_with_max_time_to_live(
+    signature: &BtcSignature,
+    address: &Address,
+    public_keys: Vec<String>,
     session_key: ByteBuf,
     signature_map: &mut SignatureMap,
     canister_id: &Principal,
     sign_message_type: SignMessageType,
-) -> Result<LoginDetails, LoginError> {
+    wallet: WalletKind,
+    realm: Option<&str>,
+    max_time_to_live: u64,
+) -> Result<(LoginDetails, bool), LoginError> {
+    if max_time_to_live == 0 {
+        return Err(LoginError::InvalidMaxTimeToLive);
+    }
+    login_until(
+        signature,
+        address,
+        public_keys,
+        session_key,
+        signature_map,
+        canister_id,
+        sign_message_type,
+        wallet,
+        realm,
+        Some(max_time_to_live),
+        None,
+        None,
+    )
+}
+
+/// Logs in like [login], but recovers the key from an ECDSA signature with `recovery_id`, 0 to 3, as
+/// supplied by the client, rather than from the recovery byte of the signature, whose encoding differs
+/// between wallets. The signature may then be just `r || s`, see
+/// [crate::verify::recover_pub_key_with_recovery_id].
+///
+/// # Returns
+/// The [LoginDetails] as for [login]. A wrong `recovery_id` recovers another key, the login then fails
+/// with [LoginError::AddressMismatch], and one above 3 with [BtcError::InvalidRecoveryId].
+#[allow(clippy::too_many_arguments)]
+pub fn login_with_recovery_id(
+    signature: &BtcSignature,
+    address: &Address,
+    public_keys: Vec<String>,
+    session_key: ByteBuf,
+    signature_map: &mut SignatureMap,
+    canister_id: &Principal,
+    sign_message_type: SignMessageType,
+    wallet: WalletKind,
+    realm: Option<&str>,
+    recovery_id: u8,
+) -> Result<(LoginDetails, bool), LoginError> {
+    if recovery_id > 3 {
+        return Err(LoginError::BtcError(BtcError::InvalidRecoveryId));
+    }
+    login_until(
+        signature,
+        address,
+        public_keys,
+        session_key,
+        signature_map,
+        canister_id,
+        sign_message_type,
+        wallet,
+        realm,
+        None,
+        None,
+        Some(recovery_id),
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn login_until(
+    signature: &BtcSignature,
+    address: &Address,
+    public_keys: Vec<String>,
+    session_key: ByteBuf,
+    signature_map: &mut SignatureMap,
+    canister_id: &Principal,
+    sign_message_type: SignMessageType,
+    wallet: WalletKind,
+    realm: Option<&str>,
+    max_time_to_live: Option<u64>,
+    domain: Option<&str>,
+    recovery_id: Option<u8>,
+) -> Result<(LoginDetails, bool), LoginError> {
+    check_address_type_allowed(address)?;
+    // Rejected before the message is used, so that the user can still log in with an allowed key.
+    validate_session_key(&session_key)?;
+    if !with_settings!(|settings: &Settings| settings.is_session_key_allowed(&session_key)) {
+        return Err(LoginError::SessionKeyNotAllowed);
+    }
+
     // Remove expired SIWB messages from the state before proceeding. The init settings determines
-    // the time to live for SIWB messages.
-    SIWB_MESSAGES.with_borrow_mut(|siwb_messages| {
+    // the time to live for SIWB messages. The map is only borrowed to fetch and to remove the message,
+    // not while the signature is verified and the delegation is created.
+    let address_bytes = address.script_pubkey().to_bytes();
+    let found = SIWB_MESSAGES.with_borrow_mut(|siwb_messages| {
         // Prune any expired SIWB messages from the state.
         siwb_messages.prune_expired();
 
         // Get the previously created SIWB message for current address. If it has expired or does not
         // exist, return an error.
-        let address_bytes = address.script_pubkey().to_bytes();
-        let message = siwb_messages.get(&address_bytes)?;
-        let message_string: String = message.clone().into();
+        siwb_messages.get_with_digest(&address_bytes)
+    });
+    let (message, digest) = match found {
+        Ok(found) => found,
+        // A retry of a login that has just consumed the message is answered with the same details.
+        Err(e) => {
+            let retried = CONSUMED_LOGINS.with_borrow_mut(|logins| {
+                logins.get_retry(
+                    &address_bytes,
+                    &signature.0,
+                    &session_key,
+                    realm,
+                    get_current_time(),
+                )
+            });
+            return retried.map(|details| (details, true)).ok_or(e.into());
+        }
+    };
+    let message_string: String = message.clone().into();
+    check_message_length(&message_string)?;
+
+    // The signature is verified against the re-rendered message string, which must be the exact
+    // string that was prepared. It could differ if e.g. the settings it renders changed since.
+    if hash::hash_bytes(&message_string) != digest {
+        return Err(SiwbMessageError::MessageMismatch.into());
+    }
 
-        // Verify the supplied signature against the SIWB message and recover the Bitcoin address
-        // used to sign the message.
+    // Testnet, signet and regtest share address prefixes, so the network named in the signed
+    // message must match the allowed network the address is valid for.
+    let network = with_settings!(|settings: &Settings| {
+        let network = settings
+            .address_network(address)
+            .filter(|network| message.network == network.to_string())
+            .ok_or(LoginError::BtcError(BtcError::NetworkMismatch))?;
+        // The domain the message was prepared for may have been removed from the allowed domains since.
+        if !settings.is_domain_allowed(&message.domain)
+            || domain.is_some_and(|domain| domain != message.domain)
+        {
+            return Err(LoginError::BtcError(BtcError::DomainNotAllowed));
+        }
+        // Messages are bound to the canister they were prepared by, even if binding has been disabled
+        // since.
+        let canister_text = canister_id.to_text();
+        if (settings.bind_canister_id || message.canister_id.is_some())
+            && message.canister_id.as_deref() != Some(canister_text.as_str())
+        {
+            return Err(LoginError::BtcError(BtcError::CanisterMismatch));
+        }
+        Ok(network)
+    })?;
 
-        match sign_message_type {
-            SignMessageType::ECDSA => {
-                let v = _verify_message(message_string, signature.0.clone(), public_key)
-                    .map_err(|_| LoginError::AddressMismatch)?;
+    // Verify the supplied signature against the SIWB message and recover the Bitcoin address
+    // used to sign the message.
 
-                if let Ok(addr) = verify_address(address.to_string().as_str(), v) {
-                    if address.to_string() != addr {
-                        return Err(LoginError::AddressMismatch);
+    let matched_public_key = match sign_message_type {
+        SignMessageType::ECDSA | SignMessageType::TaggedHash => {
+            let (double_hash, allow_legacy_ecdsa, prefix, tag) =
+                with_settings!(|settings: &Settings| (
+                    settings.double_hash,
+                    settings.allow_legacy_ecdsa,
+                    settings.message_prefix(network).to_string(),
+                    match sign_message_type {
+                        SignMessageType::TaggedHash => Some(
+                            settings
+                                .message_hash_tag
+                                .clone()
+                                .unwrap_or_else(|| DEFAULT_MESSAGE_HASH_TAG.to_string()),
+                        ),
+                        _ => settings.message_hash_tag.clone(),
                     }
-                } else {
-                    return Err(LoginError::AddressMismatch);
+                ));
+            // Wallets encode signatures differently, each decoding of the signature is tried in turn.
+            let mut candidates =
+                signature_candidates(&signature.0, wallet).map_err(LoginError::BtcError)?;
+            if !allow_legacy_ecdsa {
+                candidates.retain(|c| is_schnorr_key_spend(c, &address.to_string()));
+                if candidates.is_empty() {
+                    return Err(LoginError::BtcError(BtcError::LegacyEcdsaDisabled));
                 }
             }
-            SignMessageType::Bip322Simple => {
-                let AddressInfo {
-                    network,
-                    address_type,
-                    ..
-                } = match get_script_from_address(address.to_string()) {
-                    Ok(a) => a,
-                    Err(_) => return Err(LoginError::AddressMismatch),
-                };
-                if address_type == AddressType::P2tr {
-                    if !verify_signature_of_bip322_simple_p2tr(
-                        address.to_string().as_str(),
-                        message_string.as_str(),
-                        signature.0.as_str(),
-                        network,
-                    ) {
-                        return Err(LoginError::AddressMismatch);
-                    }
-                } else if address_type == AddressType::P2wpkh {
-                    if !verify_signature_of_bip322_simple_segwitv0(
+            let mut errors = Vec::new();
+            let matched = public_keys.into_iter().find(|public_key| {
+                candidates.iter().any(|candidate| {
+                    let result = _verify_message_for_address(
+                        message_string.clone(),
+                        candidate.clone(),
+                        public_key.clone(),
+                        double_hash,
+                        &prefix,
+                        tag.as_deref(),
                         address.to_string().as_str(),
-                        message_string.as_str(),
-                        signature.0.as_str(),
-                        network,
-                    ) {
-                        return Err(LoginError::AddressMismatch);
-                    }
-                } else {
-                    return Err(LoginError::BtcError(AddressTypeNotSupported));
+                        allow_legacy_ecdsa,
+                        recovery_id,
+                    )
+                    .and_then(|v| {
+                        verify_address_for_network(address.to_string().as_str(), v, network)
+                    });
+                    result.map_err(|e| errors.push(e)).is_ok()
+                })
+            });
+
+            match matched {
+                Some(public_key) => Some(public_key),
+                // A malformed signature fails the same way for every candidate, so report it as is.
+                None if !errors.is_empty()
+                    && errors
+                        .iter()
+                        .all(|e| matches!(e, BtcError::SignatureFormatError(_))) =>
+                {
+                    return Err(LoginError::BtcError(errors.swap_remove(0)))
+                }
+                // The signing address is only recovered from legacy framed message signatures.
+                None => {
+                    let recovered =
+                        candidates
+                            .iter()
+                            .filter(|_| tag.is_none())
+                            .find_map(|candidate| {
+                                recover_signing_address(
+                                    message_string.clone(),
+                                    candidate,
+                                    double_hash,
+                                    &prefix,
+                                    address.to_string().as_str(),
+                                    network,
+                                )
+                            });
+                    return Err(LoginError::address_mismatch(address, recovered));
+                }
+            }
+        }
+        SignMessageType::Bip322Simple => {
+            let AddressInfo {
+                network,
+                address_type,
+                ..
+            } = match get_script_from_address(address.to_string()) {
+                Ok(a) => a,
+                Err(_) => return Err(LoginError::address_mismatch(address, None)),
+            };
+            if address_type == AddressType::P2tr {
+                if !verify_signature_of_bip322_simple_p2tr(
+                    address.to_string().as_str(),
+                    message_string.as_str(),
+                    signature.0.as_str(),
+                    network,
+                ) {
+                    return Err(LoginError::address_mismatch(address, None));
                 }
+            } else if address_type == AddressType::P2wpkh {
+                if !verify_signature_of_bip322_simple_segwitv0(
+                    address.to_string().as_str(),
+                    message_string.as_str(),
+                    signature.0.as_str(),
+                    network,
+                ) {
+                    return Err(LoginError::address_mismatch(address, None));
+                }
+            } else {
+                return Err(LoginError::BtcError(AddressTypeNotSupported));
             }
+            None
         }
+    };
 
-        // At this point, the signature has been verified and the SIWB message has been used. Remove
-        // the SIWB message from the state.
-        siwb_messages.remove(&address_bytes);
+    // Only addresses of the types that can log in verify.
+    let address_type = address
+        .address_type()
+        .and_then(LoginAddressType::from_address_type)
+        .ok_or(LoginError::BtcError(AddressTypeNotSupported))?;
 
-        // The delegation is valid for the duration of the session as defined in the settings.
-        let expiration = with_settings!(|settings: &Settings| {
-            message
-                .issued_at
-                .saturating_add(settings.session_expires_in)
-        });
+    // At this point, the signature has been verified and the SIWB message has been used. Remove
+    // the SIWB message from the state.
+    SIWB_MESSAGES.with_borrow_mut(|siwb_messages| siwb_messages.remove(&address_bytes));
 
-        // The seed is what uniquely identifies the delegation. It is derived from the salt, the
-        // Bitcoin address and the SIWB message URI.
-        let seed = generate_seed(address);
+    // The delegation is valid for the duration of the session as defined in the settings and
+    // stated in the signed message, unless the client asked for a shorter session.
+    let expiration = match max_time_to_live {
+        Some(max_time_to_live) => message.expires_at_with_time_to_live(max_time_to_live),
+        None => message.expires_at(),
+    };
 
-        // Before adding the signature to the signature map, prune any expired signatures.
-        signature_map.prune_expired(get_current_time(), MAX_SIGS_TO_PRUNE);
+    // The seed is what uniquely identifies the delegation. It is derived from the salt, the
+    // Bitcoin address, the SIWB message URI and the realm.
+    let seed = generate_seed(address, realm);
 
-        // Create the delegation and add its hash to the signature map. The seed is used as the map key.
-        let delegation = create_delegation(session_key, expiration)?;
-        let delegation_hash = create_delegation_hash(&delegation);
-        signature_map.put(hash::hash_bytes(seed), delegation_hash);
+    // Before adding the signature to the signature map, prune any expired signatures.
+    signature_map.prune_expired(get_current_time(), MAX_SIGS_TO_PRUNE);
 
-        // Create the user canister public key from the seed. From this key, the client can derive the
-        // user principal.
-        let user_canister_pubkey = create_user_canister_pubkey(canister_id, seed.to_vec())?;
+    // Create the delegation and add its hash to the signature map. The seed is used as the map key.
+    let delegation = create_delegation(session_key.clone(), expiration)?;
+    let delegation_hash = create_delegation_hash(&delegation);
+    signature_map.put(hash::hash_bytes(seed), delegation_hash);
 
-        Ok(LoginDetails {
-            expiration,
-            user_canister_pubkey: ByteBuf::from(user_canister_pubkey),
-        })
-    })
-}
+    // Create the user canister public key from the seed. From this key, the client can derive the
+    // user principal.
+    let user_canister_pubkey = create_user_canister_pubkey(canister_id, seed.to_vec())?;
 
-pub fn prune_all(signature_map: &mut SignatureMap) {
-    SIWB_MESSAGES.with_borrow_mut(|siwb_messages| {
-        siwb_messages.clear();
-        signature_map.prune_all();
-    })
-}
+    let details = LoginDetails {
+        expiration,
+        user_canister_pubkey: ByteBuf::from(user_canister_pubkey),
+        public_key: matched_public_key,
+        request_id: message.request_id,
+        address_type,
+        targets: delegation.targets,
+    };
 
-struct BufferWriter {}
-
-impl BufferWriter {
-    fn varint_buf_num(n: i64) -> Vec<u8> {
-        let mut buf = Vec::new();
-        if n < 253 {
-            buf.push(n as u8);
-        } else if n < 0x10000 {
-            buf.push(253);
-            let mut bytes = [0u8; size_of::<u16>()];
-            LittleEndian::write_u16(&mut bytes, n as u16);
-            buf.extend_from_slice(&bytes);
-        } else if n < 0x100000000 {
-            buf.push(254);
-            let mut bytes = [0u8; size_of::<u32>()];
-            LittleEndian::write_u32(&mut bytes, n as u32);
-            buf.extend_from_slice(&bytes);
-        } else {
-            buf.push(255);
-            let mut bytes = [0u8; size_of::<u64>()];
-            LittleEndian::write_i32(&mut bytes[0..4], (n & -1) as i32);
-            LittleEndian::write_u32(&mut bytes[4..8], (n / 0x100000000) as u32);
-            buf.extend_from_slice(&bytes);
-        }
-        buf
-    }
-}
-
-pub fn _msg_hash(message: String) -> Vec<u8> {
-    let prefix1 = BufferWriter::varint_buf_num(MAGIC_BYTES.len() as i64);
-    let message_buffer = message.as_bytes().to_vec();
-    let prefix2 = BufferWriter::varint_buf_num(message_buffer.len() as i64);
-    let mut buf = Vec::new();
-    buf.extend_from_slice(&prefix1);
-    buf.extend_from_slice(MAGIC_BYTES.as_bytes());
-    buf.extend_from_slice(&prefix2);
-    buf.extend_from_slice(&message_buffer);
-
-    let _hash = Sha256::new_with_prefix(buf);
-    let hash = Sha256::new_with_prefix(_hash.finalize_fixed().to_vec());
-    return hash.finalize_fixed().to_vec();
-}
-
-fn _verify_message(
-    message: String,
-    signature: String,
-    public_key: String,
-) -> Result<Vec<u8>, String> {
-    let message_prehashed = _msg_hash(message);
-    let signature_bytes = general_purpose::STANDARD
-        .decode(signature)
-        .map_err(|_| "Invalid b64 signature".to_string())?;
-    let public_key_bytes = hex::decode(public_key).map_err(|_| "Invalid public key".to_string())?;
-    let recovered_public_key = recover_pub_key_compact(
-        signature_bytes.as_slice(),
-        message_prehashed.as_slice(),
-        None,
-    )?;
+    let login_retry_window = with_settings!(|settings: &Settings| settings.login_retry_window);
+    if login_retry_window > 0 {
+        let now = get_current_time();
+        let consumed = ConsumedLogin {
+            signature: signature.0.clone(),
+            session_key,
+            realm: realm.map(str::to_string),
+            details: details.clone(),
+            expires_at: now.saturating_add(login_retry_window),
+        };
+        CONSUMED_LOGINS.with_borrow_mut(|logins| logins.insert(address_bytes, consumed, now));
+    }
 
-    return if public_key_bytes.clone() != recovered_public_key.clone() {
-        Err("public_key_bytes != recovered_public_key".to_string())
-    } else {
-        Ok(recovered_public_key.clone())
-    };
+    Ok((details, false))
 }
 
-pub fn recover_pub_key_compact(
-    signature_bytes: &[u8],
-    message_hash: &[u8],
-    chain_id: Option<u8>,
-) -> Result<Vec<u8>, String> {
-    let mut v;
-    let r: Vec<u8> = signature_bytes[1..33].to_vec();
-    let mut s: Vec<u8> = signature_bytes[33..65].to_vec();
-
-    if signature_bytes.len() >= 65 {
-        v = signature_bytes[0];
-    } else {
-        v = signature_bytes[33] >> 7;
-        s[0] &= 0x7f;
-    };
-    if v < 27 {
-        v = v + 27;
-    }
+/// Returned by [login_with_delegation]. Contains the login details and, if it could already be
+/// certified, the signed delegation for the session key.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct LoginBundle {
+    pub login_details: LoginDetails,
 
-    let mut bytes = [0u8; 65];
-    if r.len() > 32 || s.len() > 32 {
-        return Err("Cannot create secp256k1 signature: malformed signature.".to_string());
-    }
-    let rid = calculate_sig_recovery(v.clone(), chain_id);
-    bytes[0..32].clone_from_slice(&r);
-    bytes[32..64].clone_from_slice(&s);
-    bytes[64] = rid;
+    /// The signed delegation, `None` if the certificate did not certify the delegation yet. The
+    /// delegation then has to be fetched separately, using the expiration from `login_details`.
+    pub signed_delegation: Option<SignedDelegation>,
+}
 
-    if rid > 3 {
-        return Err(format!(
-            "Cannot create secp256k1 signature: invalid recovery id. {:?}",
-            rid
-        ));
-    }
+/// Logs in like [login] and, in the same call, signs the delegation for `session_key`. This saves
+/// clients the round-trip to fetch the delegation when it can already be certified.
+///
+/// ## Certification caveat
+/// This call adds the signature to `signature_map`, but the signature is only certified once the
+/// canister has updated its certified data and the call has completed. `certificate` therefore only
+/// certifies the delegation if it was certified before, e.g. when a client repeats a login with the
+/// same session key. Otherwise, and always when there is no certificate, as in update calls,
+/// `signed_delegation` is `None` and the delegation has to be fetched with a separate query.
+///
+/// # Parameters
+/// * `certificate`: The data certificate of the current call, if available.
+/// * `certified_tree`: Wraps the signature map witness into the tree whose root hash the canister
+///   certifies.
+///
+/// All other parameters are the same as for [login].
+///
+/// # Returns
+/// The [LoginBundle], with `true` if it answers a retry of an earlier login, as for [login].
+#[allow(clippy::too_many_arguments)]
+pub fn login_with_delegation(
+    signature: &BtcSignature,
+    address: &Address,
+    public_keys: Vec<String>,
+    session_key: ByteBuf,
+    signature_map: &mut SignatureMap,
+    canister_id: &Principal,
+    sign_message_type: SignMessageType,
+    wallet: WalletKind,
+    realm: Option<&str>,
+    certificate: Option<&[u8]>,
+    certified_tree: impl FnOnce(HashTree) -> HashTree,
+) -> Result<(LoginBundle, bool), LoginError> {
+    let (login_details, retried) = login(
+        signature,
+        address,
+        public_keys,
+        session_key.clone(),
+        signature_map,
+        canister_id,
+        sign_message_type,
+        wallet,
+        realm,
+    )?;
 
-    let recovery_id = RecoveryId::try_from(bytes[64]).map_err(|_| BtcError::InvalidRecoveryId)?;
+    let certificate = match certificate {
+        Some(certificate) => certificate,
+        None => {
+            return Ok((
+                LoginBundle {
+                    login_details,
+                    signed_delegation: None,
+                },
+                retried,
+            ))
+        }
+    };
 
-    let signature = Signature::from_slice(&bytes[..64]).map_err(|_| BtcError::InvalidSignature)?;
+    let delegation = create_delegation(session_key, login_details.expiration)?;
+    let seed = generate_seed(address, realm);
+    let signature_witness = witness(signature_map, seed, create_delegation_hash(&delegation))?;
+    let tree = certified_tree(signature_witness);
 
-    let verifying_key = VerifyingKey::recover_from_prehash(&message_hash, &signature, recovery_id)
-        .map_err(|_| BtcError::PublicKeyRecoveryFailure)?;
+    let signed_delegation =
+        match check_certificate_current(certificate, canister_id, tree.reconstruct()) {
+            Ok(()) => {
+                let signature = create_certified_signature(certificate.to_vec(), tree)?;
+                Some(SignedDelegation {
+                    delegation,
+                    signature: ByteBuf::from(signature),
+                })
+            }
+            Err(DelegationError::CertificatePending) => None,
+            Err(e) => return Err(e.into()),
+        };
 
-    Ok(verifying_key.to_encoded_point(true).to_bytes().to_vec())
+    Ok((
+        LoginBundle {
+            login_details,
+            signed_delegation,
+        },
+        retried,
+    ))
 }
 
-pub fn msg_hash(message: String) -> Vec<u8> {
-    _msg_hash(message)
+/// The progress of a login for an address, as reported by [login_status].
+#[derive(CandidType, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LoginStatus {
+    /// A SIWB message has been prepared and is waiting to be signed.
+    Pending,
+    /// The login has completed and a delegation can be fetched.
+    Ready,
+    /// The SIWB message has expired or no login is in progress, call `prepare_login` again.
+    Expired,
 }
 
-pub fn calculate_sig_recovery(mut v: u8, chain_id: Option<u8>) -> u8 {
-    if v == 0 || v == 1 {
-        return v;
+/// Reports how far the login for `address` has progressed, allowing a frontend to poll for
+/// completion instead of retrying [login].
+///
+/// A prepared, not yet expired, SIWB message takes precedence over an earlier delegation so that a
+/// new login attempt reports [LoginStatus::Pending].
+pub fn login_status(
+    address: &Address,
+    signature_map: &SignatureMap,
+    realm: Option<&str>,
+) -> LoginStatus {
+    let address_bytes = address.script_pubkey().to_bytes();
+    let message = SIWB_MESSAGES.with_borrow(|siwb_messages| siwb_messages.get(&address_bytes));
+    if message.is_ok_and(|m| !m.is_expired()) {
+        return LoginStatus::Pending;
     }
 
-    return if chain_id.is_none() {
-        v = v - 27;
-        while v > 3 {
-            v = v - 4;
-        }
-        v
-    } else {
-        v = v - (chain_id.unwrap() * 2 + 35);
-        while v > 3 {
-            v = v - 4;
-        }
-        v
-    };
+    let seed = generate_seed(address, realm);
+    if signature_map.contains(hash::hash_bytes(seed)) {
+        return LoginStatus::Ready;
+    }
+
+    LoginStatus::Expired
 }
 
-pub fn verify_address(address: &str, pub_bytes: Vec<u8>) -> Result<String, String> {
-    let public_key =
-        BitcoinPublicKey::from_slice(pub_bytes.as_slice()).map_err(|e| e.to_string())?;
-    let secp = Secp256k1::verification_only();
-    let mut network = Bitcoin;
-    let mut address_type = AddressType::P2tr;
-
-    if address.starts_with("bc1q") {
-        address_type = AddressType::P2wpkh;
-        network = Bitcoin;
-    } else if address.starts_with("bc1p") {
-        address_type = AddressType::P2tr;
-        network = Bitcoin;
-    } else if address.starts_with('1') {
-        address_type = AddressType::P2pkh;
-        network = Bitcoin;
-    } else if address.starts_with('3') {
-        address_type = AddressType::P2sh;
-        network = Bitcoin;
-    } else if address.starts_with("tb1q") {
-        address_type = AddressType::P2wpkh;
-        network = Testnet;
-    } else if address.starts_with('m') || address.starts_with('n') {
-        address_type = AddressType::P2pkh;
-        network = Testnet;
-    } else if address.starts_with('2') {
-        address_type = AddressType::P2sh;
-        network = Testnet;
-    } else if address.starts_with("tb1p") {
-        address_type = AddressType::P2tr;
-        network = Testnet;
-    }
-    let compressed = if !public_key.compressed {
-        BitcoinPublicKey::from_slice(&public_key.inner.serialize())
-            .map_err(|e| e.to_string())
-            .clone()?
-    } else {
-        public_key
-    };
+/// Returns the expiration time of the SIWB message awaiting signature by `address`, or `None` if there
+/// is none. Expired messages are pruned first, so a returned message can still be used to log in and
+/// the frontend can reuse it instead of preparing a new one.
+pub fn pending_message_expiration(address: &Address) -> Option<u64> {
+    SIWB_MESSAGES.with_borrow_mut(|siwb_messages| {
+        siwb_messages.prune_expired();
+        siwb_messages
+            .get(&address.script_pubkey().to_bytes())
+            .ok()
+            .map(|message| message.expiration_time)
+    })
+}
 
-    match address_type {
-        AddressType::P2pkh => {
-            let p2pkh_address = Address::p2pkh(&public_key, network);
-            Ok(p2pkh_address.to_string())
-        }
-        AddressType::P2wpkh => {
-            let p2wpkh_address =
-                Address::p2wpkh(&compressed, network).map_err(|e| e.to_string())?;
-            Ok(p2wpkh_address.to_string())
-        }
-        AddressType::P2sh => {
-            let p2sh_address =
-                Address::p2shwpkh(&compressed, network).map_err(|e| e.to_string())?;
-            Ok(p2sh_address.to_string())
-        }
-        AddressType::P2tr => {
-            let internal_key = XOnlyPublicKey::from_slice(pub_bytes[1..].to_vec().as_slice())
-                .map_err(|e| e.to_string())?;
-            Ok(Address::p2tr(&secp, internal_key, None, network).to_string())
+/// Lists the script pubkey and `issued_at` of every SIWB message awaiting signature, ordered by
+/// `issued_at`, for monitoring and abuse investigation. Expired messages are pruned first.
+pub fn list_pending_messages() -> Vec<(Vec<u8>, u64)> {
+    SIWB_MESSAGES.with_borrow_mut(|siwb_messages| {
+        siwb_messages.prune_expired();
+        let mut pending: Vec<(Vec<u8>, u64)> = siwb_messages
+            .iter()
+            .map(|(address_bytes, message)| (address_bytes.clone(), message.issued_at))
+            .collect();
+        pending.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+        pending
+    })
+}
+
+/// Returns when the session created by signing the SIWB message awaiting signature by `address` would
+/// expire, see [SiwbMessage::expires_at]. This is the expiration that [login] gives the delegation.
+///
+/// # Returns
+/// The expiration in nanoseconds since the UNIX epoch, or [SiwbMessageError::MessageNotFound] if no
+/// message awaits signature or it has expired.
+pub fn preview_expiration(address: &Address) -> Result<u64, SiwbMessageError> {
+    SIWB_MESSAGES.with_borrow_mut(|siwb_messages| {
+        siwb_messages.prune_expired();
+        siwb_messages
+            .get(&address.script_pubkey().to_bytes())
+            .map(|message| message.expires_at())
+    })
+}
+
+/// Whether a SIWB message that has not expired yet awaits signature by `address`.
+pub fn has_pending_message(address: &Address) -> bool {
+    pending_message_expiration(address).is_some()
+}
+
+/// Checks the `token` a frontend got along with the message prepared for `address`, see
+/// [SiwbMessage::challenge_token], before the login with its signature. Expired messages are pruned,
+/// so without the token an expired challenge could not be told apart from one that was never
+/// prepared.
+///
+/// # Returns
+/// [SiwbMessageError::ChallengeExpired] if the token has expired, or
+/// [SiwbMessageError::InvalidChallengeToken] if it is malformed or belongs to another message than the
+/// one pending. If no message is pending but the token has not expired, e.g. when a login is retried,
+/// the login reports the outcome.
+pub fn check_challenge_token(address: &Address, token: &str) -> Result<(), LoginError> {
+    let token: ChallengeToken = token.parse()?;
+    let message = SIWB_MESSAGES
+        .with_borrow(|siwb_messages| siwb_messages.get(&address.script_pubkey().to_bytes()))
+        .ok()
+        .filter(|message| !message.is_expired());
+    match message {
+        Some(message) if message.challenge_token() != token => {
+            Err(SiwbMessageError::InvalidChallengeToken.into())
         }
-        _ => Err("Unknown Address".to_string()),
+        None if token.is_expired() => Err(SiwbMessageError::ChallengeExpired.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Returns the exact bytes that are hashed and signed for the SIWB message awaiting signature by
+/// `address`, see [crate::verify::message_frame_with_prefix], or `None` if there is none. Client developers can
+/// compare them byte for byte with what their wallet signed.
+#[cfg(feature = "debug")]
+pub fn pending_message_signing_bytes(address: &Address) -> Option<Vec<u8>> {
+    let prefix = with_settings!(|settings: &Settings| settings
+        .message_prefix(settings.network_for(address))
+        .to_string());
+    SIWB_MESSAGES.with_borrow(|siwb_messages| {
+        siwb_messages
+            .get(&address.script_pubkey().to_bytes())
+            .ok()
+            .filter(|message| !message.is_expired())
+            .map(|message| {
+                crate::verify::message_frame_with_prefix(&String::from(message), &prefix)
+            })
+    })
+}
+
+/// Approximate number of instructions spent in each phase of a login, see [estimate_login_cost].
+#[cfg(feature = "debug")]
+#[derive(Clone, Debug, Default, CandidType, Deserialize, PartialEq, Eq)]
+pub struct LoginCost {
+    /// Hashing the SIWB message into the digest that was signed.
+    pub hashing: u64,
+
+    /// Recovering the public key from the signature.
+    pub recovery: u64,
+
+    /// Deriving the address from the recovered public key and comparing it to the address.
+    pub address_derivation: u64,
+
+    /// Creating the delegation, adding its hash to a signature map and deriving the user canister
+    /// public key.
+    pub delegation: u64,
+
+    /// All of the above, including the overhead between the phases.
+    pub total: u64,
+}
+
+/// Measures the instructions a login with the compact ECDSA `signature` by `address` over its
+/// pending SIWB message costs, broken down by phase, so that operators can size the cycle budget of
+/// their canister. The login is only rehearsed: the message stays pending and the delegation is
+/// added to a throwaway signature map. Inside a canister the counts are read from
+/// [ic_cdk::api::performance_counter], so they only cover the current message execution.
+#[cfg(feature = "debug")]
+pub fn estimate_login_cost(
+    signature: &BtcSignature,
+    address: &Address,
+    public_key: &str,
+    session_key: ByteBuf,
+    canister_id: &Principal,
+) -> Result<LoginCost, LoginError> {
+    use crate::debug::instruction_counter;
+
+    let message = SIWB_MESSAGES
+        .with_borrow(|siwb_messages| siwb_messages.get(&address.script_pubkey().to_bytes()))?;
+    if message.is_expired() {
+        return Err(SiwbMessageError::MessageNotFound.into());
     }
+    let message_string = String::from(message.clone());
+    let address_string = address.to_string();
+    let (double_hash, network, prefix, tag) = with_settings!(|settings: &Settings| (
+        settings.double_hash,
+        settings.network_for(address),
+        settings
+            .message_prefix(settings.network_for(address))
+            .to_string(),
+        settings.message_hash_tag.clone()
+    ));
+
+    let start = instruction_counter();
+    let digest = message_digest(&message_string, double_hash, &prefix, tag.as_deref());
+    let hashed = instruction_counter();
+
+    let public_key_bytes = decode_public_key(public_key).map_err(LoginError::BtcError)?;
+    let recovered = recover_public_keys_from_digest(&digest, &signature.0);
+    if !recovered.contains(&hex::encode(&public_key_bytes)) {
+        return Err(LoginError::BtcError(BtcError::InvalidSignature));
+    }
+    let recovered = instruction_counter();
+
+    verify_address_for_network(&address_string, public_key_bytes, network)
+        .map_err(LoginError::BtcError)?;
+    let derived = instruction_counter();
+
+    let seed = generate_seed(address, None);
+    let delegation = create_delegation(session_key, message.expires_at())?;
+    SignatureMap::default().put(hash::hash_bytes(seed), create_delegation_hash(&delegation));
+    create_user_canister_pubkey(canister_id, seed.to_vec())?;
+    let end = instruction_counter();
+
+    Ok(LoginCost {
+        hashing: hashed - start,
+        recovery: recovered - hashed,
+        address_derivation: derived - recovered,
+        delegation: end - derived,
+        total: end - start,
+    })
+}
+
+pub fn prune_all(signature_map: &mut SignatureMap) {
+    SIWB_MESSAGES.with_borrow_mut(|siwb_messages| {
+        siwb_messages.clear();
+        signature_map.prune_all();
+    })
+}
+
+/// Prunes up to `max_to_prune` expired signatures from `signature_map`, oldest first. Logins only prune
+/// a few expired signatures each, this allows pruning more of them at once, e.g. during low-traffic
+/// windows. Signatures that have not expired are kept.
+///
+/// # Returns
+/// The number of signatures removed.
+pub fn prune_expired_signatures(signature_map: &mut SignatureMap, max_to_prune: usize) -> usize {
+    signature_map.prune_expired(get_current_time(), max_to_prune)
 }
 
 fn get_output_script_from_address(address: &str, network: Network) -> ScriptBuf {
@@ -471,13 +1412,7 @@ fn get_output_script_from_address(address: &str, network: Network) -> ScriptBuf
 }
 
 fn bip0322_hash(message: &str) -> Vec<u8> {
-    let tag = "BIP0322-signed-message";
-    let tag_hash = hash_bytes(tag.as_bytes());
-    let mut hasher = Sha256::new();
-    hasher.update(&tag_hash);
-    hasher.update(&tag_hash);
-    hasher.update(message.as_bytes());
-    hasher.finalize().to_vec()
+    tagged_hash("BIP0322-signed-message", message.as_bytes())
 }
 
 fn bip0322_tx(message_slice: &[u8], output_script: ScriptBuf) -> Transaction {
@@ -680,141 +1615,2499 @@ fn extract_bytes_from_script(script: &Script, expect_size: usize) -> Result<Vec<
 
 #[cfg(test)]
 mod test {
+    use crate::delegation::DelegationError;
+    use crate::error::BtcError;
     use crate::login::{
-        _verify_message, bip0322_hash, verify_address, verify_signature_of_bip322_simple_p2tr,
-        verify_signature_of_bip322_simple_segwitv0,
+        _msg_hash, _msg_hash_with, bip0322_hash, check_challenge_token, decode_public_key,
+        has_pending_message, list_pending_messages, login, login_by_address, login_for_domain,
+        login_status, login_with_delegation, login_with_max_time_to_live, login_with_recovery_id,
+        pending_message_expiration, prepare_login, prepare_login_ex, prepare_login_for_canister,
+        prepare_login_for_domain, prepare_login_full, prepare_login_with_issued_at,
+        prepare_login_with_request_id, preview_expiration, prune_expired_signatures,
+        recover_pub_key_compact, validate_address, verify_address,
+        verify_signature_of_bip322_simple_p2tr, verify_signature_of_bip322_simple_segwitv0,
+        verify_signed_message, AddressMeta, BtcSignature, LoginAddressType, LoginDetails,
+        LoginError, LoginStatus, PrepareLoginOptions, SignMessageType, VerifiedAddress, WalletKind,
     };
+    use crate::settings::SettingsBuilder;
+    use crate::signature_map::SignatureMap;
+    use crate::siwb::{SiwbMessage, SiwbMessageError};
+    use crate::time::{advance_test_time, get_current_time, set_test_time};
+    use crate::verify::{
+        _msg_hash_with_prefix, _verify_message, tagged_hash, DEFAULT_MESSAGE_HASH_TAG,
+    };
+    use crate::{SETTINGS, SIWB_MESSAGES};
+    use base64::engine::general_purpose;
+    use base64::Engine;
+    use bitcoin::{Address, AddressType, Network, PublicKey as BitcoinPublicKey};
+    use candid::Principal;
+    use k256::ecdsa::SigningKey;
+    use serde_bytes::ByteBuf;
+
+    const SESSION_KEY: &[u8] = &[
+        48, 42, 48, 5, 6, 3, 43, 101, 112, 3, 33, 0, 220, 227, 2, 129, 72, 36, 43, 220, 96, 102,
+        225, 92, 98, 163, 114, 182, 117, 181, 51, 15, 219, 197, 104, 55, 123, 245, 74, 181, 35,
+        181, 171, 196,
+    ]; // DER encoded session key
+
+    fn init() {
+        init_with_network(Network::Bitcoin);
+    }
+
+    fn init_with_network(network: Network) {
+        let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .network(network)
+            .build()
+            .unwrap();
+        SETTINGS.set(Some(settings));
+    }
+
+    fn signing_key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32].into()).unwrap()
+    }
+
+    fn public_key_hex(key: &SigningKey) -> String {
+        hex::encode(key.verifying_key().to_encoded_point(true).as_bytes())
+    }
+
+    fn p2wpkh_address(key: &SigningKey) -> Address {
+        p2wpkh_address_on(key, Network::Bitcoin)
+    }
+
+    fn p2wpkh_address_on(key: &SigningKey, network: Network) -> Address {
+        let public_key =
+            BitcoinPublicKey::from_slice(key.verifying_key().to_encoded_point(true).as_bytes())
+                .unwrap();
+        Address::p2wpkh(&public_key, network).unwrap()
+    }
+
+    /// Signs a message the way Bitcoin wallets do for legacy message signing: a base64 encoded
+    /// compact signature with a header byte for a compressed public key.
+    fn sign_message(key: &SigningKey, message: &str) -> String {
+        sign_message_with(key, message, true)
+    }
+
+    fn sign_message_with(key: &SigningKey, message: &str, double_hash: bool) -> String {
+        let hash = _msg_hash_with(message.to_string(), double_hash);
+        let (signature, recovery_id) = key.sign_prehash_recoverable(&hash).unwrap();
+        let mut bytes = vec![27 + 4 + recovery_id.to_byte()];
+        bytes.extend_from_slice(&signature.to_bytes());
+        general_purpose::STANDARD.encode(bytes)
+    }
+
+    fn ecdsa_login(
+        key: &SigningKey,
+        address: &Address,
+        public_keys: Vec<String>,
+    ) -> Result<crate::login::LoginDetails, LoginError> {
+        ecdsa_login_with_map(key, address, public_keys, &mut SignatureMap::default())
+    }
+
+    fn ecdsa_login_with_map(
+        key: &SigningKey,
+        address: &Address,
+        public_keys: Vec<String>,
+        signature_map: &mut SignatureMap,
+    ) -> Result<crate::login::LoginDetails, LoginError> {
+        let message = prepare_login(address).unwrap();
+        let signature = BtcSignature(sign_message(key, &String::from(message)));
+        login(
+            &signature,
+            address,
+            public_keys,
+            ByteBuf::from(SESSION_KEY),
+            signature_map,
+            &Principal::anonymous(),
+            SignMessageType::ECDSA,
+            WalletKind::Auto,
+            None,
+        )
+        .map(|(details, _)| details)
+    }
 
     #[test]
-    fn test_get_address() {
-        let p2tr_t = verify_address(
-            "tb1pgvdp7lf89d62zadds5jvyjntxmr7v70yv33g7vqaeu2p0cuexveqjlwphr",
-            hex::decode("03133c85d348d6c0796382966380719397453592e706cd3329119a2d2cb8d2ff7b")
-                .unwrap(),
-        );
-        let p2tr = verify_address(
-            "bc1pgvdp7lf89d62zadds5jvyjntxmr7v70yv33g7vqaeu2p0cuexveq9hcwdv",
-            hex::decode("03133c85d348d6c0796382966380719397453592e706cd3329119a2d2cb8d2ff7b")
-                .unwrap(),
-        );
-        assert_eq!(
-            p2tr_t.unwrap(),
-            "tb1pgvdp7lf89d62zadds5jvyjntxmr7v70yv33g7vqaeu2p0cuexveqjlwphr".to_string()
-        );
-        assert_eq!(
-            p2tr.unwrap(),
-            "bc1pgvdp7lf89d62zadds5jvyjntxmr7v70yv33g7vqaeu2p0cuexveq9hcwdv".to_string()
-        );
+    fn test_login_ecdsa() {
+        init();
+        let key = signing_key(1);
+        let address = p2wpkh_address(&key);
+        let details = ecdsa_login(&key, &address, vec![public_key_hex(&key)]).unwrap();
+        assert_eq!(details.public_key, Some(public_key_hex(&key)));
+    }
 
-        let p2shp2wpkh_t = verify_address(
-            "2NBbnaYUvZvrvKfd7wqMmt7bZoAMTSkAarU",
-            hex::decode("02e203c98d766554bb4dab431d70b014b505aac66f47b735d9e7cbb4f12108ac3d")
-                .unwrap(),
-        );
-        let p2shp2wpkh = verify_address(
-            "3L3aWoYtxUMa7szaGhjuGAcJap9Hb13EEP",
-            hex::decode("02e203c98d766554bb4dab431d70b014b505aac66f47b735d9e7cbb4f12108ac3d")
-                .unwrap(),
-        );
+    #[test]
+    fn test_login_address_type() {
+        init();
+        let key = signing_key(1);
+        let public_key =
+            BitcoinPublicKey::from_slice(key.verifying_key().to_encoded_point(true).as_bytes())
+                .unwrap();
+        for (address, address_type) in [
+            (
+                Address::p2pkh(&public_key, Network::Bitcoin),
+                LoginAddressType::P2pkh,
+            ),
+            (
+                Address::p2shwpkh(&public_key, Network::Bitcoin).unwrap(),
+                LoginAddressType::P2sh,
+            ),
+            (p2wpkh_address(&key), LoginAddressType::P2wpkh),
+        ] {
+            let details = ecdsa_login(&key, &address, vec![public_key_hex(&key)]).unwrap();
+            assert_eq!(details.address_type, address_type);
+        }
         assert_eq!(
-            p2shp2wpkh_t.unwrap(),
-            "2NBbnaYUvZvrvKfd7wqMmt7bZoAMTSkAarU".to_string()
+            taproot_schnorr_login().unwrap().address_type,
+            LoginAddressType::P2tr
         );
+    }
+
+    #[test]
+    fn test_validate_address() {
+        init();
+        let key = signing_key(1);
+        let public_key =
+            BitcoinPublicKey::from_slice(key.verifying_key().to_encoded_point(true).as_bytes())
+                .unwrap();
+        let secp = bitcoin::secp256k1::Secp256k1::verification_only();
+        let p2tr = Address::p2tr(&secp, public_key.inner.into(), None, Network::Bitcoin);
+        for (address, address_type) in [
+            (
+                Address::p2pkh(&public_key, Network::Bitcoin),
+                LoginAddressType::P2pkh,
+            ),
+            (
+                Address::p2shwpkh(&public_key, Network::Bitcoin).unwrap(),
+                LoginAddressType::P2sh,
+            ),
+            (p2wpkh_address(&key), LoginAddressType::P2wpkh),
+            (p2tr.clone(), LoginAddressType::P2tr),
+        ] {
+            assert_eq!(
+                validate_address(&address.to_string()).unwrap(),
+                AddressMeta {
+                    network: "bitcoin".to_string(),
+                    address_type,
+                    canonical: address.to_string(),
+                }
+            );
+        }
+
+        // Bech32 addresses may be entered in upper case.
+        let upper = p2wpkh_address(&key).to_string().to_uppercase();
         assert_eq!(
-            p2shp2wpkh.unwrap(),
-            "3L3aWoYtxUMa7szaGhjuGAcJap9Hb13EEP".to_string()
+            validate_address(&upper).unwrap().canonical,
+            p2wpkh_address(&key).to_string()
         );
 
-        let p2wpkh_t = verify_address(
-            "tb1qshqyem2rf8jyla904gd2cvek2k8nz5z3vc2j3x",
-            hex::decode("03f72a781776c63888aa9af5478c72c4794165a44024679995f6d232b4f6254574")
-                .unwrap(),
-        );
-        let p2wpkh = verify_address(
-            "bc1qshqyem2rf8jyla904gd2cvek2k8nz5z3x73p24",
-            hex::decode("03f72a781776c63888aa9af5478c72c4794165a44024679995f6d232b4f6254574")
-                .unwrap(),
-        );
+        assert!(matches!(
+            validate_address("not an address"),
+            Err(BtcError::AddressFormatError(_))
+        ));
+        assert!(matches!(
+            validate_address(&p2wpkh_address_on(&key, Network::Testnet).to_string()),
+            Err(BtcError::NetworkMismatch)
+        ));
+        let p2wsh = Address::p2wsh(&bitcoin::ScriptBuf::new(), Network::Bitcoin);
+        assert!(matches!(
+            validate_address(&p2wsh.to_string()),
+            Err(BtcError::AddressTypeNotSupported)
+        ));
+
+        let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .allowed_networks(vec![Network::Bitcoin, Network::Signet])
+            .allowed_address_types(vec![AddressType::P2wpkh])
+            .build()
+            .unwrap();
+        SETTINGS.set(Some(settings));
+        assert!(matches!(
+            validate_address(&p2tr.to_string()),
+            Err(BtcError::AddressTypeNotAllowed)
+        ));
         assert_eq!(
-            p2wpkh_t.unwrap(),
-            "tb1qshqyem2rf8jyla904gd2cvek2k8nz5z3vc2j3x".to_string()
+            validate_address(&p2wpkh_address_on(&key, Network::Testnet).to_string())
+                .unwrap()
+                .network,
+            "signet"
         );
+    }
+
+    #[test]
+    fn test_login_retry_within_window() {
+        let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .login_retry_window(10_000_000_000)
+            .build()
+            .unwrap();
+        SETTINGS.set(Some(settings));
+        set_test_time(1_000_000_000);
+        let key = signing_key(1);
+        let address = p2wpkh_address(&key);
+        let message = prepare_login(&address).unwrap();
+        let signature = BtcSignature(sign_message(&key, &String::from(message)));
+        let login_with = |session_key: &[u8], realm: Option<&str>| {
+            login(
+                &signature,
+                &address,
+                vec![public_key_hex(&key)],
+                ByteBuf::from(session_key),
+                &mut SignatureMap::default(),
+                &Principal::anonymous(),
+                SignMessageType::ECDSA,
+                WalletKind::Auto,
+                realm,
+            )
+        };
+
+        let (details, retried) = login_with(SESSION_KEY, None).unwrap();
+        assert!(!retried);
+        advance_test_time(5_000_000_000);
+        let (cached, retried) = login_with(SESSION_KEY, None).unwrap();
+        assert!(retried);
+        assert_eq!(cached.expiration, details.expiration);
+        assert_eq!(cached.user_canister_pubkey, details.user_canister_pubkey);
+
+        // Only the same login is answered from the cache.
+        let mut other_session_key = SESSION_KEY.to_vec();
+        *other_session_key.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            login_with(&other_session_key, None),
+            Err(LoginError::SiwbMessageError(
+                SiwbMessageError::MessageNotFound
+            ))
+        ));
+        assert!(login_with(SESSION_KEY, Some("app")).is_err());
+
+        advance_test_time(5_000_000_000);
+        assert!(matches!(
+            login_with(SESSION_KEY, None),
+            Err(LoginError::SiwbMessageError(
+                SiwbMessageError::MessageNotFound
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_login_with_recovery_id() {
+        init();
+        let key = signing_key(1);
+        let address = p2wpkh_address(&key);
+        let message: String = prepare_login(&address).unwrap().into();
+        let hash = _msg_hash_with(message, true);
+        let (signature, recovery_id) = key.sign_prehash_recoverable(&hash).unwrap();
+        let login_with = |signature: Vec<u8>, recovery_id: u8| {
+            login_with_recovery_id(
+                &BtcSignature(general_purpose::STANDARD.encode(signature)),
+                &address,
+                vec![public_key_hex(&key)],
+                ByteBuf::from(SESSION_KEY),
+                &mut SignatureMap::default(),
+                &Principal::anonymous(),
+                SignMessageType::ECDSA,
+                WalletKind::Auto,
+                None,
+                recovery_id,
+            )
+        };
+
+        // A wrong recovery id recovers another key.
+        let rs = signature.to_bytes().to_vec();
+        assert!(matches!(
+            login_with(rs.clone(), recovery_id.to_byte() ^ 1),
+            Err(LoginError::AddressMismatch { .. })
+        ));
+        assert!(matches!(
+            login_with(rs.clone(), 4),
+            Err(LoginError::BtcError(BtcError::InvalidRecoveryId))
+        ));
+
+        // The recovery byte of a 65 byte signature is ignored, even if it is wrong.
+        let mut with_header = vec![27 + 4 + (recovery_id.to_byte() ^ 1)];
+        with_header.extend_from_slice(&rs);
+        assert!(login_with(with_header, recovery_id.to_byte()).is_ok());
+    }
+
+    #[test]
+    fn test_login_with_recovery_id_without_header() {
+        init();
+        let key = signing_key(1);
+        let address = p2wpkh_address(&key);
+        let message: String = prepare_login(&address).unwrap().into();
+        let hash = _msg_hash_with(message, true);
+        let (signature, recovery_id) = key.sign_prehash_recoverable(&hash).unwrap();
+
+        let details = login_with_recovery_id(
+            &BtcSignature(general_purpose::STANDARD.encode(signature.to_bytes())),
+            &address,
+            vec![public_key_hex(&key)],
+            ByteBuf::from(SESSION_KEY),
+            &mut SignatureMap::default(),
+            &Principal::anonymous(),
+            SignMessageType::ECDSA,
+            WalletKind::Auto,
+            None,
+            recovery_id.to_byte(),
+        )
+        .unwrap()
+        .0;
+        assert_eq!(details.public_key, Some(public_key_hex(&key)));
+    }
+
+    #[test]
+    fn test_login_allowed_session_keys() {
+        let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .allowed_session_keys(vec![SESSION_KEY.to_vec()])
+            .build()
+            .unwrap();
+        SETTINGS.set(Some(settings));
+        let key = signing_key(1);
+        let address = p2wpkh_address(&key);
+        assert!(ecdsa_login(&key, &address, vec![public_key_hex(&key)]).is_ok());
+
+        crate::init::disallow_session_key(SESSION_KEY).unwrap();
+        assert!(matches!(
+            ecdsa_login(&key, &address, vec![public_key_hex(&key)]),
+            Err(LoginError::SessionKeyNotAllowed)
+        ));
         assert_eq!(
-            p2wpkh.unwrap(),
-            "bc1qshqyem2rf8jyla904gd2cvek2k8nz5z3x73p24".to_string()
+            LoginError::SessionKeyNotAllowed.to_string(),
+            "Session key is not allowed"
         );
 
-        let p2pkh_t = verify_address(
-            "mt1ycNxRhKVf1JyHhrKQEuuMoBnSPrwxfM",
-            hex::decode("03133c85d348d6c0796382966380719397453592e706cd3329119a2d2cb8d2ff7b")
-                .unwrap(),
-        );
-        let p2pkh = verify_address(
-            "1DW2KKsStJ4QECVfzHM2Qzh2wCBjTe9TH1",
-            hex::decode("03133c85d348d6c0796382966380719397453592e706cd3329119a2d2cb8d2ff7b")
-                .unwrap(),
+        // The message was not used, the user can log in once the key is allowed.
+        crate::init::allow_session_key(SESSION_KEY).unwrap();
+        let message = SIWB_MESSAGES
+            .with_borrow(|m| m.get(&address.script_pubkey().to_bytes()))
+            .unwrap();
+        let signature = BtcSignature(sign_message(&key, &String::from(message)));
+        assert!(login(
+            &signature,
+            &address,
+            vec![public_key_hex(&key)],
+            ByteBuf::from(SESSION_KEY),
+            &mut SignatureMap::default(),
+            &Principal::anonymous(),
+            SignMessageType::ECDSA,
+            WalletKind::Auto,
+            None,
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_login_malformed_session_key() {
+        init();
+        let key = signing_key(1);
+        let address = p2wpkh_address(&key);
+        let message = prepare_login(&address).unwrap();
+        let signature = BtcSignature(sign_message(&key, &String::from(message)));
+        let login_with = |session_key: &[u8]| {
+            login(
+                &signature,
+                &address,
+                vec![public_key_hex(&key)],
+                ByteBuf::from(session_key),
+                &mut SignatureMap::default(),
+                &Principal::anonymous(),
+                SignMessageType::ECDSA,
+                WalletKind::Auto,
+                None,
+            )
+        };
+
+        assert!(matches!(
+            login_with(&SESSION_KEY[..SESSION_KEY.len() - 1]),
+            Err(LoginError::DelegationError(
+                DelegationError::InvalidSessionKey(_)
+            ))
+        ));
+
+        // The message was not used, the user can log in with a valid session key.
+        assert!(login_with(SESSION_KEY).is_ok());
+    }
+
+    /// Signs the message and lays out the signature as `layout` says, with the header byte `header` plus
+    /// the recovery id for header-first layouts.
+    fn wallet_signature(key: &SigningKey, message: &str, layout: WalletKind, header: u8) -> String {
+        let hash = _msg_hash_with(message.to_string(), true);
+        let (signature, recovery_id) = key.sign_prehash_recoverable(&hash).unwrap();
+        let rs = signature.to_bytes();
+        match layout {
+            WalletKind::Base64 | WalletKind::Auto => {
+                let mut bytes = vec![header + recovery_id.to_byte()];
+                bytes.extend_from_slice(&rs);
+                general_purpose::STANDARD.encode(bytes)
+            }
+            WalletKind::HexRecoveryIdLast => {
+                let mut bytes = rs.to_vec();
+                bytes.push(recovery_id.to_byte());
+                hex::encode(bytes)
+            }
+        }
+    }
+
+    fn wallet_login(
+        key: &SigningKey,
+        address: &Address,
+        signed_as: (WalletKind, u8),
+        hint: WalletKind,
+    ) -> Result<crate::login::LoginDetails, LoginError> {
+        let message = prepare_login(address).unwrap();
+        let signature = wallet_signature(key, &String::from(message), signed_as.0, signed_as.1);
+        login(
+            &BtcSignature(signature),
+            address,
+            vec![public_key_hex(key)],
+            ByteBuf::from(SESSION_KEY),
+            &mut SignatureMap::default(),
+            &Principal::anonymous(),
+            SignMessageType::ECDSA,
+            hint,
+            None,
+        )
+        .map(|(details, _)| details)
+    }
+
+    #[test]
+    fn test_login_wallet_signatures() {
+        init();
+        let key = signing_key(1);
+        let address = p2wpkh_address(&key);
+        // A compressed key header whatever the address type, a BIP-137 header for a native SegWit
+        // address, and a hex encoded signature with the recovery id last.
+        for (layout, header) in [
+            (WalletKind::Base64, 31),
+            (WalletKind::Base64, 39),
+            (WalletKind::HexRecoveryIdLast, 0),
+        ] {
+            assert!(
+                wallet_login(&key, &address, (layout, header), layout).is_ok(),
+                "{:?} {}",
+                layout,
+                header
+            );
+            assert!(
+                wallet_login(&key, &address, (layout, header), WalletKind::Auto).is_ok(),
+                "{:?} {}",
+                layout,
+                header
+            );
+        }
+
+        // A hint for the wrong layout decodes the signature the wrong way.
+        assert!(matches!(
+            wallet_login(
+                &key,
+                &address,
+                (WalletKind::HexRecoveryIdLast, 0),
+                WalletKind::Base64
+            ),
+            Err(LoginError::BtcError(BtcError::SignatureFormatError(_)))
+        ));
+        assert!(matches!(
+            wallet_login(
+                &key,
+                &address,
+                (WalletKind::Base64, 31),
+                WalletKind::HexRecoveryIdLast
+            ),
+            Err(LoginError::BtcError(BtcError::SignatureFormatError(_)))
+        ));
+    }
+
+    fn taproot_schnorr_login() -> Result<crate::login::LoginDetails, LoginError> {
+        use bitcoin::key::{KeyPair, TapTweak};
+        use bitcoin::secp256k1::{Message, Secp256k1};
+
+        let secp = Secp256k1::new();
+        let keypair = KeyPair::from_seckey_slice(&secp, &[7u8; 32]).unwrap();
+        let (internal_key, _) = keypair.x_only_public_key();
+        let address = Address::p2tr(&secp, internal_key, None, Network::Bitcoin);
+
+        let message = prepare_login(&address).unwrap();
+        let hash = _msg_hash_with(String::from(message), true);
+        let tweaked = keypair.tap_tweak(&secp, None).to_inner();
+        let signature =
+            secp.sign_schnorr_no_aux_rand(&Message::from_slice(&hash).unwrap(), &tweaked);
+        login(
+            &BtcSignature(general_purpose::STANDARD.encode(signature.as_ref())),
+            &address,
+            vec![hex::encode(keypair.public_key().serialize())],
+            ByteBuf::from(SESSION_KEY),
+            &mut SignatureMap::default(),
+            &Principal::anonymous(),
+            SignMessageType::ECDSA,
+            WalletKind::Auto,
+            None,
+        )
+        .map(|(details, _)| details)
+    }
+
+    #[test]
+    fn test_login_tagged_hash() {
+        init();
+        let key = signing_key(1);
+        let address = p2wpkh_address(&key);
+        let tagged_login = |tag: &str, sign_message_type: SignMessageType| {
+            let message = prepare_login(&address).unwrap();
+            let hash = tagged_hash(tag, String::from(message).as_bytes());
+            let (signature, recovery_id) = key.sign_prehash_recoverable(&hash).unwrap();
+            let mut bytes = vec![27 + 4 + recovery_id.to_byte()];
+            bytes.extend_from_slice(&signature.to_bytes());
+            login(
+                &BtcSignature(general_purpose::STANDARD.encode(bytes)),
+                &address,
+                vec![public_key_hex(&key)],
+                ByteBuf::from(SESSION_KEY),
+                &mut SignatureMap::default(),
+                &Principal::anonymous(),
+                sign_message_type,
+                WalletKind::Auto,
+                None,
+            )
+        };
+
+        // The wallet hint selects the tagged hash, the legacy hash stays the default.
+        assert!(tagged_login(DEFAULT_MESSAGE_HASH_TAG, SignMessageType::TaggedHash).is_ok());
+        assert!(matches!(
+            tagged_login(DEFAULT_MESSAGE_HASH_TAG, SignMessageType::ECDSA),
+            Err(LoginError::AddressMismatch { .. })
+        ));
+
+        // Settings select the tagged hash, with their tag, for all ECDSA logins.
+        let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .message_hash_tag("SIWB/message")
+            .build()
+            .unwrap();
+        SETTINGS.set(Some(settings));
+        assert!(tagged_login("SIWB/message", SignMessageType::ECDSA).is_ok());
+        assert!(tagged_login("SIWB/message", SignMessageType::TaggedHash).is_ok());
+        assert!(tagged_login(DEFAULT_MESSAGE_HASH_TAG, SignMessageType::TaggedHash).is_err());
+    }
+
+    fn certificate_for(canister_id: &Principal, certified_data: &[u8]) -> Vec<u8> {
+        use ic_certified_map::{labeled, HashTree};
+
+        let tree = labeled(
+            b"canister",
+            labeled(
+                canister_id.as_slice(),
+                labeled(
+                    b"certified_data",
+                    HashTree::Leaf(certified_data.to_vec().into()),
+                ),
+            ),
         );
+        crate::delegation::create_certified_signature(vec![], tree).unwrap()
+    }
+
+    #[test]
+    fn test_login_with_delegation() {
+        init();
+        set_test_time(1_000_000_000);
+        let key = signing_key(1);
+        let address = p2wpkh_address(&key);
+        let canister_id = Principal::anonymous();
+        let bundle_login = |signature_map: &mut SignatureMap, certificate: Option<&[u8]>| {
+            let message = prepare_login(&address).unwrap();
+            login_with_delegation(
+                &BtcSignature(sign_message(&key, &String::from(message))),
+                &address,
+                vec![public_key_hex(&key)],
+                ByteBuf::from(SESSION_KEY),
+                signature_map,
+                &canister_id,
+                SignMessageType::ECDSA,
+                WalletKind::Auto,
+                None,
+                certificate,
+                |witness| witness,
+            )
+        };
+
+        // Update calls have no certificate.
+        let mut signature_map = SignatureMap::default();
+        let bundle = bundle_login(&mut signature_map, None).unwrap().0;
+        assert!(bundle.signed_delegation.is_none());
+        assert_eq!(bundle.login_details.public_key, Some(public_key_hex(&key)));
+        let certified_root_hash = signature_map.root_hash();
+
+        // A certificate of the root hash before the login does not certify the new signature.
+        let mut other_map = SignatureMap::default();
+        let stale_certificate = certificate_for(&canister_id, &other_map.root_hash());
+        let bundle = bundle_login(&mut other_map, Some(&stale_certificate))
+            .unwrap()
+            .0;
+        assert!(bundle.signed_delegation.is_none());
+
+        // Repeating the login once the delegation is certified returns it signed.
+        let certificate = certificate_for(&canister_id, &certified_root_hash);
+        let bundle = bundle_login(&mut signature_map, Some(&certificate))
+            .unwrap()
+            .0;
+        let signed_delegation = bundle.signed_delegation.unwrap();
         assert_eq!(
-            p2pkh_t.unwrap(),
-            "mt1ycNxRhKVf1JyHhrKQEuuMoBnSPrwxfM".to_string()
+            signed_delegation.delegation.pubkey,
+            ByteBuf::from(SESSION_KEY)
         );
         assert_eq!(
-            p2pkh.unwrap(),
-            "1DW2KKsStJ4QECVfzHM2Qzh2wCBjTe9TH1".to_string()
+            signed_delegation.delegation.expiration,
+            bundle.login_details.expiration
         );
+        assert!(!signed_delegation.signature.is_empty());
     }
+
     #[test]
-    fn test_message() {
-        let p = "03133c85d348d6c0796382966380719397453592e706cd3329119a2d2cb8d2ff7b".to_string();
-        let s =  "HPVVoaHfyCUER9YB6MC8C+eh3in24rHTScQopgwzzEx6GP9fwZBI+ZIesS1HNzbMzMgLFS10IyhMc6aYbn3zfI4=".to_string();
-        let m = "{\"a\":1,\"b\":[2,3,4]}".to_string();
-        let a = "tb1pgvdp7lf89d62zadds5jvyjntxmr7v70yv33g7vqaeu2p0cuexveqjlwphr".to_string();
+    fn test_legacy_ecdsa_policy() {
+        let key = signing_key(1);
+        let address = p2wpkh_address(&key);
 
-        let v = _verify_message(m, s, p);
-        println!("v is {:?}", v);
+        init();
+        assert!(ecdsa_login(&key, &address, vec![public_key_hex(&key)]).is_ok());
+        assert!(taproot_schnorr_login().is_ok());
 
-        let v2 = verify_address(a.as_str(), v.unwrap());
-        println!("v2 is {:?}", v2);
+        let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .allow_legacy_ecdsa(false)
+            .build()
+            .unwrap();
+        SETTINGS.set(Some(settings));
+        assert!(matches!(
+            ecdsa_login(&key, &address, vec![public_key_hex(&key)]),
+            Err(LoginError::BtcError(BtcError::LegacyEcdsaDisabled))
+        ));
+        assert!(taproot_schnorr_login().is_ok());
     }
 
     #[test]
-    fn test_bip322_messasge() {
-        let m = "hello".to_string();
-        let h = bip0322_hash(m.as_str());
-        println!("hash is {:?}", hex::encode(h.clone()));
+    fn test_login_second_candidate_matches() {
+        init();
+        let key = signing_key(1);
+        let address = p2wpkh_address(&key);
+        let candidates = vec![public_key_hex(&signing_key(2)), public_key_hex(&key)];
+        let details = ecdsa_login(&key, &address, candidates).unwrap();
+        assert_eq!(details.public_key, Some(public_key_hex(&key)));
+    }
+
+    #[test]
+    fn test_login_remaining_secs() {
+        init();
+        set_test_time(1_000_000_000);
+        let key = signing_key(1);
+        let address = p2wpkh_address(&key);
+        let details = ecdsa_login(&key, &address, vec![public_key_hex(&key)]).unwrap();
+
+        // The default session lasts 30 minutes.
+        assert_eq!(details.remaining_secs(get_current_time()), 30 * 60);
+
+        advance_test_time(10 * 60 * 1_000_000_000 + 1);
+        assert_eq!(details.remaining_secs(get_current_time()), 20 * 60 - 1);
+
+        advance_test_time(20 * 60 * 1_000_000_000);
+        assert_eq!(details.remaining_secs(get_current_time()), 0);
+    }
+
+    #[test]
+    fn test_prepare_login_expiration() {
+        let sign_in_expires_in = 90 * 1_000_000_000;
+        let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .sign_in_expires_in(sign_in_expires_in)
+            .build()
+            .unwrap();
+        SETTINGS.set(Some(settings));
+        set_test_time(1_700_000_000_000_000_000);
+
+        let message = prepare_login(&p2wpkh_address(&signing_key(1))).unwrap();
+        assert_eq!(message.issued_at, 1_700_000_000_000_000_000);
         assert_eq!(
-            hex::encode(h.clone()),
-            "528e990bccf82644773d67eff12fb504e84b42c8396475da8c939404f4a32385".to_string()
+            message.expiration_time,
+            message.issued_at + sign_in_expires_in
         );
     }
 
     #[test]
-    fn test_bip322_verify_p2tr() {
-        let m = "hello".to_string();
-        let a = "tb1phy4ay0kvcnelc9trqzk4ksld3qx45gm83274qxp204vzycg7hxaq2m2nrn".to_string();
-        let s = "AUBNN/m5COckJE1nj5bR9iAO+Ga5VlJU2xIIGBraFZQNDUtOO0J0tOhoQzvk0o+YwknQ3OGWyWR5VwiG2KzJwjUV".to_string();
+    fn test_prepare_login_rejects_empty_message() {
+        let address = p2wpkh_address(&signing_key(1));
+        for statement in ["", "   "] {
+            let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+                .statement(statement)
+                .build()
+                .unwrap();
+            SETTINGS.set(Some(settings));
+            assert!(matches!(
+                prepare_login(&address),
+                Err(BtcError::MalformedMessage(_))
+            ));
+        }
 
-        let v = verify_signature_of_bip322_simple_p2tr(
-            a.as_str(),
-            m.as_str(),
-            s.as_str(),
-            bitcoin::Network::Testnet,
-        );
-        assert_eq!(v, true)
+        init();
+        set_test_time(0);
+        assert!(matches!(
+            prepare_login(&address),
+            Err(BtcError::MalformedMessage(_))
+        ));
+        set_test_time(1_000_000_000);
+        assert!(prepare_login(&address).is_ok());
     }
 
     #[test]
-    fn test_bip322_verify_p2pkwh() {
-        let m = "hello".to_string();
-        let a = "tb1qf620ch70a2evf2n2jrmdk85wwpupx8qcszr2s7".to_string();
-        let s = "AkgwRQIhAOh1XvCVjPhJbc6oELxiRjjavkOW9ebYC5gzepzjWhn0AiAPpoXFwjozO82PYiSGlnc9RoM9JknaFt5OhmrGD/J58AEhA89jkK3c5cXYcnPiBLRTC27FwKz4mzOrZ+rizCQnR/jj".to_string();
+    fn test_prepare_login_ex_field_parity() {
+        use time::{format_description::well_known::Rfc3339, OffsetDateTime};
 
-        let v = verify_signature_of_bip322_simple_segwitv0(
-            a.as_str(),
-            m.as_str(),
-            s.as_str(),
-            bitcoin::Network::Testnet,
-        );
-        assert_eq!(v, true);
+        init();
+        let address = p2wpkh_address(&signing_key(1));
+        let prepared = prepare_login_ex(&address, None).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&prepared.json).unwrap();
+
+        for field in ["domain", "address", "nonce", "statement", "uri", "network"] {
+            let value = json[field].as_str().unwrap();
+            assert!(
+                prepared.string.contains(value),
+                "The string lacks the {} {}",
+                field,
+                value
+            );
+        }
+        assert_eq!(json["address"], address.to_string());
+        for field in ["issued_at", "expiration_time"] {
+            let nanos = json[field].as_u64().unwrap();
+            let timestamp = OffsetDateTime::from_unix_timestamp_nanos(nanos as i128)
+                .unwrap()
+                .format(&Rfc3339)
+                .unwrap();
+            assert!(
+                prepared.string.contains(&timestamp),
+                "The string lacks the {} {}",
+                field,
+                timestamp
+            );
+        }
+
+        // The string is the message that is signed.
+        let message = SIWB_MESSAGES
+            .with_borrow(|m| m.get(&address.script_pubkey().to_bytes()))
+            .unwrap();
+        assert_eq!(prepared.string, String::from(message));
+    }
+
+    #[test]
+    fn test_prepare_login_issued_at_override() {
+        let skew = 30 * 1_000_000_000;
+        let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .max_issued_at_skew(skew)
+            .build()
+            .unwrap();
+        SETTINGS.set(Some(settings));
+        let now = 1_700_000_000_000_000_000;
+        set_test_time(now);
+        let address = p2wpkh_address(&signing_key(1));
+
+        for issued_at in [now - skew, now, now + skew] {
+            let message = prepare_login_with_issued_at(&address, Some(issued_at)).unwrap();
+            assert_eq!(message.issued_at, issued_at);
+        }
+        for issued_at in [now - skew - 1, now + skew + 1, 0] {
+            assert!(matches!(
+                prepare_login_with_issued_at(&address, Some(issued_at)),
+                Err(BtcError::IssuedAtOutOfBounds)
+            ));
+        }
+        assert_eq!(
+            prepare_login_with_issued_at(&address, None)
+                .unwrap()
+                .issued_at,
+            now
+        );
+    }
+
+    #[test]
+    fn test_clock_skew() {
+        let skew = 30 * 1_000_000_000;
+        let drift = 5 * 1_000_000_000;
+        let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .max_issued_at_skew(skew)
+            .clock_skew(drift)
+            .build()
+            .unwrap();
+        SETTINGS.set(Some(settings));
+        let now = 1_700_000_000_000_000_000;
+        set_test_time(now);
+        let address = p2wpkh_address(&signing_key(1));
+
+        // The clock skew widens the issued_at bounds on both sides.
+        for issued_at in [now - skew - drift, now + skew + drift] {
+            assert!(prepare_login_with_issued_at(&address, Some(issued_at)).is_ok());
+        }
+        for issued_at in [now - skew - drift - 1, now + skew + drift + 1] {
+            assert!(matches!(
+                prepare_login_with_issued_at(&address, Some(issued_at)),
+                Err(BtcError::IssuedAtOutOfBounds)
+            ));
+        }
+
+        // Messages stay usable for the clock skew after their expiration time.
+        let message = prepare_login(&address).unwrap();
+        set_test_time(message.expiration_time + drift - 1);
+        assert!(has_pending_message(&address));
+        set_test_time(message.expiration_time + drift);
+        assert!(!has_pending_message(&address));
+    }
+
+    #[test]
+    fn test_max_message_bytes() {
+        init();
+        set_test_time(1_700_000_000_000_000_000);
+        let key = signing_key(1);
+        let address = p2wpkh_address(&key);
+        let message_len = String::from(prepare_login(&address).unwrap()).len();
+
+        let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .max_message_bytes(message_len)
+            .build()
+            .unwrap();
+        SETTINGS.set(Some(settings));
+        assert!(ecdsa_login(&key, &address, vec![public_key_hex(&key)]).is_ok());
+
+        let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .max_message_bytes(message_len - 1)
+            .build()
+            .unwrap();
+        SETTINGS.set(Some(settings));
+        assert!(matches!(
+            prepare_login(&address),
+            Err(BtcError::MessageTooLong(max)) if max == message_len - 1
+        ));
+    }
+
+    #[test]
+    fn test_pending_message() {
+        init();
+        set_test_time(1_000_000_000);
+        let address = p2wpkh_address(&signing_key(1));
+
+        // Absent
+        assert!(!has_pending_message(&address));
+        assert_eq!(pending_message_expiration(&address), None);
+
+        // Present
+        let message = prepare_login(&address).unwrap();
+        assert!(has_pending_message(&address));
+        assert_eq!(
+            pending_message_expiration(&address),
+            Some(message.expiration_time)
+        );
+
+        // Expired messages are pruned.
+        set_test_time(message.expiration_time);
+        assert!(!has_pending_message(&address));
+        let address_bytes = address.script_pubkey().to_bytes();
+        assert!(SIWB_MESSAGES.with_borrow(|m| m.get(&address_bytes).is_err()));
+    }
+
+    #[test]
+    fn test_login_for_domain() {
+        let settings = |allowed_domains: Vec<&str>| {
+            let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+                .allowed_domains(allowed_domains.into_iter().map(String::from).collect())
+                .build()
+                .unwrap();
+            SETTINGS.set(Some(settings));
+        };
+        settings(vec!["app.example.com"]);
+        let key = signing_key(1);
+        let address = p2wpkh_address(&key);
+        let login_with = |message: SiwbMessage, domain: Option<&str>| {
+            let signature = BtcSignature(sign_message(&key, &String::from(message)));
+            let public_keys = vec![public_key_hex(&key)];
+            let session_key = ByteBuf::from(SESSION_KEY);
+            let mut signature_map = SignatureMap::default();
+            let canister_id = Principal::anonymous();
+            match domain {
+                Some(domain) => login_for_domain(
+                    &signature,
+                    &address,
+                    public_keys,
+                    session_key,
+                    &mut signature_map,
+                    &canister_id,
+                    SignMessageType::ECDSA,
+                    WalletKind::Auto,
+                    None,
+                    domain,
+                ),
+                None => login(
+                    &signature,
+                    &address,
+                    public_keys,
+                    session_key,
+                    &mut signature_map,
+                    &canister_id,
+                    SignMessageType::ECDSA,
+                    WalletKind::Auto,
+                    None,
+                ),
+            }
+        };
+
+        // Allowed domains are rendered into the message.
+        let message = prepare_login_for_domain(&address, Some("app.example.com"), None).unwrap();
+        assert!(String::from(message.clone())
+            .starts_with("app.example.com wants you to sign in with your Bitcoin account:\n"));
+        assert!(login_with(message, Some("app.example.com")).is_ok());
+        let message = prepare_login_for_domain(&address, None, None).unwrap();
+        assert_eq!(message.domain, "example.com");
+        assert!(login_with(message, Some("example.com")).is_ok());
+
+        // Other domains cannot request messages.
+        assert!(matches!(
+            prepare_login_for_domain(&address, Some("evil.com"), None),
+            Err(BtcError::DomainNotAllowed)
+        ));
+
+        // The login enforces the domain the message was prepared for.
+        let message = prepare_login_for_domain(&address, Some("app.example.com"), None).unwrap();
+        assert!(matches!(
+            login_with(message.clone(), Some("example.com")),
+            Err(LoginError::BtcError(BtcError::DomainNotAllowed))
+        ));
+        settings(vec![]);
+        assert!(matches!(
+            login_with(message, None),
+            Err(LoginError::BtcError(BtcError::DomainNotAllowed))
+        ));
+    }
+
+    #[test]
+    fn test_login_with_request_id() {
+        init();
+        let key = signing_key(1);
+        let address = p2wpkh_address(&key);
+        let login_with = |message: SiwbMessage| {
+            login(
+                &BtcSignature(sign_message(&key, &String::from(message))),
+                &address,
+                vec![public_key_hex(&key)],
+                ByteBuf::from(SESSION_KEY),
+                &mut SignatureMap::default(),
+                &Principal::anonymous(),
+                SignMessageType::ECDSA,
+                WalletKind::Auto,
+                None,
+            )
+        };
+
+        // The request id is rendered into the signed message and returned with the login details.
+        let message =
+            prepare_login_with_request_id(&address, None, None, Some("req-42/a?b=c")).unwrap();
+        assert_eq!(message.request_id.as_deref(), Some("req-42/a?b=c"));
+        assert!(String::from(message.clone()).ends_with("\nRequest ID: req-42/a?b=c"));
+        let (details, _) = login_with(message).unwrap();
+        assert_eq!(details.request_id.as_deref(), Some("req-42/a?b=c"));
+
+        // Without a request id, the message has no request id line.
+        let message = prepare_login_with_request_id(&address, None, None, None).unwrap();
+        assert!(!String::from(message.clone()).contains("Request ID:"));
+        assert_eq!(login_with(message).unwrap().0.request_id, None);
+
+        // Request ids must not add lines to the message.
+        for request_id in ["", "req 42", "req\nURI: http://evil.com"] {
+            assert!(matches!(
+                prepare_login_with_request_id(&address, None, None, Some(request_id)),
+                Err(BtcError::MalformedMessage(_))
+            ));
+        }
+
+        // The signature covers the request id, so it cannot be changed after signing.
+        let message = prepare_login_with_request_id(&address, None, None, Some("req-42")).unwrap();
+        let mut tampered = message.clone();
+        tampered.request_id = Some("req-43".to_string());
+        assert!(matches!(
+            login(
+                &BtcSignature(sign_message(&key, &String::from(tampered))),
+                &address,
+                vec![public_key_hex(&key)],
+                ByteBuf::from(SESSION_KEY),
+                &mut SignatureMap::default(),
+                &Principal::anonymous(),
+                SignMessageType::ECDSA,
+                WalletKind::Auto,
+                None,
+            ),
+            Err(LoginError::AddressMismatch { .. })
+        ));
+        assert_eq!(
+            login_with(message).unwrap().0.request_id.as_deref(),
+            Some("req-42")
+        );
+    }
+
+    #[test]
+    fn test_prune_expired_signatures() {
+        set_test_time(1_000_000_000);
+        let mut signature_map = SignatureMap::default();
+        for seed in 0..3 {
+            signature_map.put([seed; 32], [0; 32]);
+        }
+        advance_test_time(30 * 1_000_000_000);
+        for seed in 3..5 {
+            signature_map.put([seed; 32], [0; 32]);
+        }
+
+        // Nothing has expired yet.
+        assert_eq!(prune_expired_signatures(&mut signature_map, usize::MAX), 0);
+        assert_eq!(signature_map.len(), 5);
+
+        // Once the first signatures have expired, only they are removed, at most `max_to_prune` at once.
+        advance_test_time(30 * 1_000_000_000);
+        assert_eq!(prune_expired_signatures(&mut signature_map, 2), 2);
+        assert_eq!(prune_expired_signatures(&mut signature_map, usize::MAX), 1);
+        assert_eq!(signature_map.len(), 2);
+        assert!(signature_map.contains([3; 32]) && signature_map.contains([4; 32]));
+        assert!(!signature_map.contains([0; 32]));
+    }
+
+    #[test]
+    fn test_login_by_address() {
+        init();
+        let key = signing_key(1);
+        let public_key =
+            BitcoinPublicKey::from_slice(key.verifying_key().to_encoded_point(true).as_bytes())
+                .unwrap();
+        let login_with = |signing_key: &SigningKey, address: &Address| {
+            let message = prepare_login(address).unwrap();
+            login_by_address(
+                &BtcSignature(sign_message(signing_key, &String::from(message))),
+                address,
+                ByteBuf::from(SESSION_KEY),
+                &mut SignatureMap::default(),
+                &Principal::anonymous(),
+                WalletKind::Auto,
+                None,
+            )
+        };
+
+        let addresses = [
+            Address::p2pkh(&public_key, Network::Bitcoin),
+            Address::p2wpkh(&public_key, Network::Bitcoin).unwrap(),
+            Address::p2shwpkh(&public_key, Network::Bitcoin).unwrap(),
+        ];
+        for address in &addresses {
+            let (details, _) = login_with(&key, address).unwrap();
+            assert_eq!(details.public_key, Some(public_key_hex(&key)));
+
+            // A signature by another key recovers a key that does not control the address.
+            assert!(matches!(
+                login_with(&signing_key(2), address),
+                Err(LoginError::AddressMismatch { .. })
+            ));
+        }
+
+        let taproot = "bc1pgvdp7lf89d62zadds5jvyjntxmr7v70yv33g7vqaeu2p0cuexveq9hcwdv"
+            .parse::<Address<_>>()
+            .unwrap()
+            .assume_checked();
+        assert!(matches!(
+            login_with(&key, &taproot),
+            Err(LoginError::BtcError(BtcError::AddressTypeNotSupported))
+        ));
+    }
+
+    #[test]
+    fn test_login_by_address_retry_within_window() {
+        let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .login_retry_window(10_000_000_000)
+            .build()
+            .unwrap();
+        SETTINGS.set(Some(settings));
+        set_test_time(1_000_000_000);
+        let key = signing_key(1);
+        let address = p2wpkh_address(&key);
+        let message = prepare_login(&address).unwrap();
+        let signature = BtcSignature(sign_message(&key, &String::from(message)));
+        let login_with = || {
+            login_by_address(
+                &signature,
+                &address,
+                ByteBuf::from(SESSION_KEY),
+                &mut SignatureMap::default(),
+                &Principal::anonymous(),
+                WalletKind::Auto,
+                None,
+            )
+        };
+
+        let (details, retried) = login_with().unwrap();
+        assert!(!retried);
+        advance_test_time(5_000_000_000);
+        let (cached, retried) = login_with().unwrap();
+        assert!(retried);
+        assert_eq!(cached.public_key, details.public_key);
+        assert_eq!(cached.user_canister_pubkey, details.user_canister_pubkey);
+
+        advance_test_time(5_000_000_000);
+        assert!(matches!(
+            login_with(),
+            Err(LoginError::SiwbMessageError(
+                SiwbMessageError::MessageNotFound
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_login_bound_to_canister() {
+        let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .bind_canister_id(true)
+            .build()
+            .unwrap();
+        SETTINGS.set(Some(settings));
+        let key = signing_key(1);
+        let address = p2wpkh_address(&key);
+        let (canister_a, canister_b) = (Principal::from_slice(&[1]), Principal::from_slice(&[2]));
+        let login_at = |message: SiwbMessage, canister_id: &Principal| {
+            login(
+                &BtcSignature(sign_message(&key, &String::from(message))),
+                &address,
+                vec![public_key_hex(&key)],
+                ByteBuf::from(SESSION_KEY),
+                &mut SignatureMap::default(),
+                canister_id,
+                SignMessageType::ECDSA,
+                WalletKind::Auto,
+                None,
+            )
+        };
+
+        // A message prepared by canister A names it and cannot be replayed at canister B.
+        let message = prepare_login_for_canister(&address, &canister_a, None, None, None).unwrap();
+        assert!(String::from(message.clone())
+            .ends_with(&format!("\nCanister ID: {}", canister_a.to_text())));
+        assert!(matches!(
+            login_at(message.clone(), &canister_b),
+            Err(LoginError::BtcError(BtcError::CanisterMismatch))
+        ));
+        assert!(login_at(message, &canister_a).is_ok());
+
+        // Messages cannot be prepared without the canister id.
+        assert!(matches!(
+            prepare_login(&address),
+            Err(BtcError::MalformedMessage(_))
+        ));
+
+        // Without binding, the canister id is not rendered.
+        init();
+        let message = prepare_login_for_canister(&address, &canister_a, None, None, None).unwrap();
+        assert_eq!(message.canister_id, None);
+        assert!(login_at(message, &canister_b).is_ok());
+    }
+
+    #[test]
+    fn test_list_pending_messages() {
+        init();
+        set_test_time(1_000_000_000);
+        let (key_a, key_b) = (signing_key(1), signing_key(2));
+        let (address_a, address_b) = (p2wpkh_address(&key_a), p2wpkh_address(&key_b));
+        let script = |address: &Address| address.script_pubkey().to_bytes();
+        assert!(list_pending_messages().is_empty());
+
+        prepare_login(&address_a).unwrap();
+        advance_test_time(1);
+        let message_b = prepare_login(&address_b).unwrap();
+        assert_eq!(
+            list_pending_messages(),
+            vec![
+                (script(&address_a), 1_000_000_000),
+                (script(&address_b), 1_000_000_001)
+            ]
+        );
+
+        // A login removes its message.
+        let message = SIWB_MESSAGES
+            .with_borrow(|m| m.get(&script(&address_a)))
+            .unwrap();
+        login(
+            &BtcSignature(sign_message(&key_a, &String::from(message))),
+            &address_a,
+            vec![public_key_hex(&key_a)],
+            ByteBuf::from(SESSION_KEY),
+            &mut SignatureMap::default(),
+            &Principal::anonymous(),
+            SignMessageType::ECDSA,
+            WalletKind::Auto,
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            list_pending_messages(),
+            vec![(script(&address_b), 1_000_000_001)]
+        );
+
+        // Expired messages are not listed.
+        set_test_time(message_b.expiration_time);
+        assert!(list_pending_messages().is_empty());
+    }
+
+    #[test]
+    fn test_login_different_addresses() {
+        init();
+        let (key_a, key_b) = (signing_key(1), signing_key(2));
+        let (address_a, address_b) = (p2wpkh_address(&key_a), p2wpkh_address(&key_b));
+        let message_a = prepare_login(&address_a).unwrap();
+        let message_b = prepare_login(&address_b).unwrap();
+        let mut signature_map = SignatureMap::default();
+        let mut login_with = |key: &SigningKey, address: &Address, message: SiwbMessage| {
+            login(
+                &BtcSignature(sign_message(key, &String::from(message))),
+                address,
+                vec![public_key_hex(key)],
+                ByteBuf::from(SESSION_KEY),
+                &mut signature_map,
+                &Principal::anonymous(),
+                SignMessageType::ECDSA,
+                WalletKind::Auto,
+                None,
+            )
+        };
+
+        // Each login only consumes the message of its own address.
+        let (details_a, _) = login_with(&key_a, &address_a, message_a).unwrap();
+        assert!(!has_pending_message(&address_a));
+        assert!(has_pending_message(&address_b));
+        let (details_b, _) = login_with(&key_b, &address_b, message_b).unwrap();
+        assert!(!has_pending_message(&address_b));
+        assert_ne!(
+            details_a.user_canister_pubkey,
+            details_b.user_canister_pubkey
+        );
+    }
+
+    #[test]
+    fn test_prepare_login_unsupported_witness_version() {
+        init();
+        let address = "bc1zw508d6qejxtdg4y5r3zarvaryvaxxpcs"
+            .parse::<Address<_>>()
+            .unwrap()
+            .assume_checked();
+        assert!(matches!(
+            prepare_login(&address),
+            Err(BtcError::ScriptConversionError(_))
+        ));
+    }
+
+    #[test]
+    fn test_login_with_network_message_prefix() {
+        let prefix = "Testnet Signed Message:\n";
+        let key = signing_key(1);
+        let sign_with_prefix = |message: SiwbMessage, prefix: &str| {
+            let hash = _msg_hash_with_prefix(String::from(message), true, prefix);
+            let (signature, recovery_id) = key.sign_prehash_recoverable(&hash).unwrap();
+            let mut bytes = vec![27 + 4 + recovery_id.to_byte()];
+            bytes.extend_from_slice(&signature.to_bytes());
+            BtcSignature(general_purpose::STANDARD.encode(bytes))
+        };
+        let login_on = |network: Network, message_prefix: &str| {
+            let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+                .network(network)
+                .message_prefix(Network::Testnet, prefix)
+                .build()
+                .unwrap();
+            SETTINGS.set(Some(settings));
+            let address = p2wpkh_address_on(&key, network);
+            let signature = sign_with_prefix(prepare_login(&address).unwrap(), message_prefix);
+            login(
+                &signature,
+                &address,
+                vec![public_key_hex(&key)],
+                ByteBuf::from(SESSION_KEY),
+                &mut SignatureMap::default(),
+                &Principal::anonymous(),
+                SignMessageType::ECDSA,
+                WalletKind::Auto,
+                None,
+            )
+        };
+
+        // The prefix configured for the network frames the message, other networks use the Bitcoin
+        // prefix.
+        assert!(login_on(Network::Testnet, prefix).is_ok());
+        assert!(matches!(
+            login_on(Network::Testnet, "Bitcoin Signed Message:\n"),
+            Err(LoginError::AddressMismatch { .. })
+        ));
+        assert!(login_on(Network::Bitcoin, "Bitcoin Signed Message:\n").is_ok());
+        assert!(login_on(Network::Bitcoin, prefix).is_err());
+    }
+
+    #[test]
+    fn test_login_binds_signature_to_prepared_message() {
+        init();
+        let key = signing_key(1);
+        let address = p2wpkh_address(&key);
+        let login_with = |signature: String| {
+            login(
+                &BtcSignature(signature),
+                &address,
+                vec![public_key_hex(&key)],
+                ByteBuf::from(SESSION_KEY),
+                &mut SignatureMap::default(),
+                &Principal::anonymous(),
+                SignMessageType::ECDSA,
+                WalletKind::Auto,
+                None,
+            )
+        };
+
+        // A signature over a message that has since been replaced is rejected, and the message that
+        // replaced it stays usable.
+        let replaced = sign_message(&key, &String::from(prepare_login(&address).unwrap()));
+        let current = sign_message(&key, &String::from(prepare_login(&address).unwrap()));
+        assert!(matches!(
+            login_with(replaced),
+            Err(LoginError::AddressMismatch { .. })
+        ));
+        assert!(login_with(current).is_ok());
+
+        // The stored message renders differently once the session settings change, so a signature
+        // over the prepared string no longer matches the message the login would verify.
+        let prepared = sign_message(&key, &String::from(prepare_login(&address).unwrap()));
+        let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .session_expires_in(60 * 1_000_000_000)
+            .build()
+            .unwrap();
+        SETTINGS.set(Some(settings));
+        assert!(matches!(
+            login_with(prepared),
+            Err(LoginError::SiwbMessageError(
+                SiwbMessageError::MessageMismatch
+            ))
+        ));
+    }
+
+    fn login_with_ttl(max_time_to_live: u64) -> Result<LoginDetails, LoginError> {
+        let key = signing_key(1);
+        let address = p2wpkh_address(&key);
+        let message = prepare_login(&address).unwrap();
+        let signature = BtcSignature(sign_message(&key, &String::from(message)));
+        login_with_max_time_to_live(
+            &signature,
+            &address,
+            vec![public_key_hex(&key)],
+            ByteBuf::from(SESSION_KEY),
+            &mut SignatureMap::default(),
+            &Principal::anonymous(),
+            SignMessageType::ECDSA,
+            WalletKind::Auto,
+            None,
+            max_time_to_live,
+        )
+        .map(|(details, _)| details)
+    }
+
+    #[test]
+    fn test_login_with_max_time_to_live() {
+        let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .session_expires_in(60_000_000_000)
+            .min_session_expires_in(5_000_000_000)
+            .build()
+            .unwrap();
+        SETTINGS.set(Some(settings));
+        set_test_time(1_000_000_000);
+
+        // A shorter session is honored.
+        let details = login_with_ttl(10_000_000_000).unwrap();
+        assert_eq!(details.expiration, 11_000_000_000);
+
+        // A longer session is capped at the configured session TTL.
+        let details = login_with_ttl(u64::MAX).unwrap();
+        assert_eq!(details.expiration, 61_000_000_000);
+
+        // Sessions below the floor are extended to it.
+        let details = login_with_ttl(1).unwrap();
+        assert_eq!(details.expiration, 6_000_000_000);
+
+        assert!(matches!(
+            login_with_ttl(0),
+            Err(LoginError::InvalidMaxTimeToLive)
+        ));
+    }
+
+    #[test]
+    fn test_preview_expiration() {
+        init();
+        set_test_time(1_000_000_000);
+        let key = signing_key(1);
+        let address = p2wpkh_address(&key);
+        assert!(preview_expiration(&address).is_err());
+
+        let message = prepare_login(&address).unwrap();
+        let preview = preview_expiration(&address).unwrap();
+        advance_test_time(1_000_000_000);
+        let signature = BtcSignature(sign_message(&key, &String::from(message)));
+        let details = login(
+            &signature,
+            &address,
+            vec![public_key_hex(&key)],
+            ByteBuf::from(SESSION_KEY),
+            &mut SignatureMap::default(),
+            &Principal::anonymous(),
+            SignMessageType::ECDSA,
+            WalletKind::Auto,
+            None,
+        )
+        .unwrap()
+        .0;
+        assert_eq!(preview, details.expiration);
+
+        // The message has been used.
+        assert!(preview_expiration(&address).is_err());
+    }
+
+    #[test]
+    fn test_check_challenge_token() {
+        init();
+        set_test_time(1_000_000_000);
+        let key = signing_key(1);
+        let address = p2wpkh_address(&key);
+        let message = prepare_login(&address).unwrap();
+        let token = message.challenge_token().to_string();
+        assert_eq!(
+            token,
+            format!("{}.{}", message.expiration_time, message.nonce)
+        );
+        assert!(check_challenge_token(&address, &token).is_ok());
+
+        // The token of a message that has been replaced by a newer one is rejected.
+        advance_test_time(1);
+        let newer = prepare_login(&address).unwrap();
+        assert!(matches!(
+            check_challenge_token(&address, &token),
+            Err(LoginError::SiwbMessageError(
+                SiwbMessageError::InvalidChallengeToken
+            ))
+        ));
+        assert!(matches!(
+            check_challenge_token(&address, "not a token"),
+            Err(LoginError::SiwbMessageError(
+                SiwbMessageError::InvalidChallengeToken
+            ))
+        ));
+
+        let token = newer.challenge_token().to_string();
+        assert!(check_challenge_token(&address, &token).is_ok());
+        let signature = BtcSignature(sign_message(&key, &String::from(newer)));
+        assert!(login(
+            &signature,
+            &address,
+            vec![public_key_hex(&key)],
+            ByteBuf::from(SESSION_KEY),
+            &mut SignatureMap::default(),
+            &Principal::anonymous(),
+            SignMessageType::ECDSA,
+            WalletKind::Auto,
+            None,
+        )
+        .is_ok());
+        // The message has been used, but the token has not expired yet.
+        assert!(check_challenge_token(&address, &token).is_ok());
+    }
+
+    #[test]
+    fn test_check_expired_challenge_token() {
+        init();
+        set_test_time(1_000_000_000);
+        let key = signing_key(1);
+        let address = p2wpkh_address(&key);
+        let message = prepare_login(&address).unwrap();
+        let token = message.challenge_token().to_string();
+
+        advance_test_time(message.expiration_time - 1_000_000_000);
+        assert!(matches!(
+            check_challenge_token(&address, &token),
+            Err(LoginError::SiwbMessageError(
+                SiwbMessageError::ChallengeExpired
+            ))
+        ));
+
+        // Without the token, the login can only tell that there is no message.
+        let signature = BtcSignature(sign_message(&key, &String::from(message)));
+        assert!(matches!(
+            login(
+                &signature,
+                &address,
+                vec![public_key_hex(&key)],
+                ByteBuf::from(SESSION_KEY),
+                &mut SignatureMap::default(),
+                &Principal::anonymous(),
+                SignMessageType::ECDSA,
+                WalletKind::Auto,
+                None,
+            ),
+            Err(LoginError::SiwbMessageError(
+                SiwbMessageError::MessageNotFound
+            ))
+        ));
+    }
+
+    #[cfg(feature = "debug")]
+    #[test]
+    fn test_pending_message_signing_bytes() {
+        use super::pending_message_signing_bytes;
+        use crate::hash::hash_bytes;
+
+        init();
+        let address = p2wpkh_address(&signing_key(1));
+        assert_eq!(pending_message_signing_bytes(&address), None);
+
+        let message = prepare_login(&address).unwrap();
+        let bytes = pending_message_signing_bytes(&address).unwrap();
+        assert_eq!(
+            hash_bytes(hash_bytes(bytes)).to_vec(),
+            _msg_hash(String::from(message))
+        );
+    }
+
+    #[cfg(feature = "debug")]
+    #[test]
+    fn test_estimate_login_cost() {
+        use super::estimate_login_cost;
+
+        init();
+        let key = signing_key(1);
+        let address = p2wpkh_address(&key);
+        let message = prepare_login(&address).unwrap();
+        let signature = BtcSignature(sign_message(&key, &String::from(message)));
+
+        let cost = estimate_login_cost(
+            &signature,
+            &address,
+            &public_key_hex(&key),
+            ByteBuf::from(SESSION_KEY),
+            &Principal::anonymous(),
+        )
+        .unwrap();
+        assert!(cost.recovery > 0);
+        assert!(cost.delegation > 0);
+        let phases = cost.hashing + cost.recovery + cost.address_derivation + cost.delegation;
+        assert!(phases <= cost.total);
+
+        // The login was only rehearsed, the message can still be used to log in.
+        assert!(has_pending_message(&address));
+
+        // A key that did not sign the message is rejected.
+        assert!(matches!(
+            estimate_login_cost(
+                &signature,
+                &address,
+                &public_key_hex(&signing_key(2)),
+                ByteBuf::from(SESSION_KEY),
+                &Principal::anonymous(),
+            ),
+            Err(LoginError::BtcError(BtcError::InvalidSignature))
+        ));
+    }
+
+    #[test]
+    fn test_login_status_transitions() {
+        init();
+        set_test_time(1_000_000_000);
+        let key = signing_key(1);
+        let address = p2wpkh_address(&key);
+        let mut signature_map = SignatureMap::default();
+
+        // Nothing prepared yet.
+        assert_eq!(
+            login_status(&address, &signature_map, None),
+            LoginStatus::Expired
+        );
+
+        // A prepared message is pending until it expires.
+        prepare_login(&address).unwrap();
+        assert_eq!(
+            login_status(&address, &signature_map, None),
+            LoginStatus::Pending
+        );
+        advance_test_time(5 * 60 * 1_000_000_000);
+        assert_eq!(
+            login_status(&address, &signature_map, None),
+            LoginStatus::Expired
+        );
+
+        // A completed login is ready to have its delegation fetched.
+        ecdsa_login_with_map(
+            &key,
+            &address,
+            vec![public_key_hex(&key)],
+            &mut signature_map,
+        )
+        .unwrap();
+        assert_eq!(
+            login_status(&address, &signature_map, None),
+            LoginStatus::Ready
+        );
+
+        // Preparing a new login reports the new attempt.
+        prepare_login(&address).unwrap();
+        assert_eq!(
+            login_status(&address, &signature_map, None),
+            LoginStatus::Pending
+        );
+    }
+
+    #[test]
+    fn test_login_expiration_rounding() {
+        let session_expires_in = 30 * 60 * 1_000_000_000;
+        let rounding = 60 * 1_000_000_000;
+        let now = 1_700_000_012_345_678_901;
+        let key = signing_key(1);
+        let address = p2wpkh_address(&key);
+
+        init();
+        set_test_time(now);
+        let details = ecdsa_login(&key, &address, vec![public_key_hex(&key)]).unwrap();
+        assert_eq!(details.expiration, now + session_expires_in);
+
+        let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .expiration_rounding(rounding)
+            .build()
+            .unwrap();
+        SETTINGS.set(Some(settings));
+        let details = ecdsa_login(&key, &address, vec![public_key_hex(&key)]).unwrap();
+        let unrounded = now + session_expires_in;
+        assert_eq!(details.expiration, unrounded - unrounded % rounding);
+        assert_ne!(details.expiration, unrounded);
+    }
+
+    #[test]
+    fn test_decode_public_key() {
+        let key = "03133c85d348d6c0796382966380719397453592e706cd3329119a2d2cb8d2ff7b";
+        let expected = hex::decode(key).unwrap();
+        assert_eq!(decode_public_key(key).unwrap(), expected);
+        assert_eq!(decode_public_key(&format!("0x{}", key)).unwrap(), expected);
+        assert_eq!(decode_public_key(&format!("0X{}", key)).unwrap(), expected);
+        assert_eq!(decode_public_key(&key.to_uppercase()).unwrap(), expected);
+        assert_eq!(
+            decode_public_key(&format!("0x{}", key.to_uppercase())).unwrap(),
+            expected
+        );
+
+        for invalid in ["", "0x", "zz", "0x0313", "0x0x03133c85", &key[1..]] {
+            assert!(
+                matches!(decode_public_key(invalid), Err(BtcError::InvalidPublicKey)),
+                "Expected an invalid public key for {:?}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_login_prefixed_uppercase_public_key() {
+        init();
+        let key = signing_key(1);
+        let address = p2wpkh_address(&key);
+        let prefixed = format!("0x{}", public_key_hex(&key).to_uppercase());
+        let details = ecdsa_login(&key, &address, vec![prefixed.clone()]).unwrap();
+        assert_eq!(details.public_key, Some(prefixed));
+    }
+
+    #[test]
+    fn test_verify_message_error_variants() {
+        let key = signing_key(1);
+        let public_key = public_key_hex(&key);
+        let signature = sign_message(&key, "hello");
+
+        let result = _verify_message(
+            "hello".to_string(),
+            "not base64!".to_string(),
+            public_key.clone(),
+            true,
+        );
+        assert!(matches!(result, Err(BtcError::SignatureFormatError(_))));
+
+        let short = general_purpose::STANDARD.encode([31u8; 64]);
+        let result = _verify_message("hello".to_string(), short, public_key.clone(), true);
+        assert!(matches!(result, Err(BtcError::SignatureFormatError(_))));
+
+        let result = _verify_message(
+            "hello".to_string(),
+            signature.clone(),
+            "0xnothex".to_string(),
+            true,
+        );
+        assert!(matches!(result, Err(BtcError::InvalidPublicKey)));
+
+        // s = 0 is not a valid ECDSA signature
+        let mut zero_s = vec![31u8];
+        zero_s.extend_from_slice(&[1u8; 32]);
+        zero_s.extend_from_slice(&[0u8; 32]);
+        let result = _verify_message(
+            "hello".to_string(),
+            general_purpose::STANDARD.encode(zero_s),
+            public_key.clone(),
+            true,
+        );
+        assert!(matches!(result, Err(BtcError::InvalidSignature)));
+
+        // A valid signature that recovers a different key
+        let result = _verify_message(
+            "hello".to_string(),
+            signature.clone(),
+            public_key_hex(&signing_key(2)),
+            true,
+        );
+        assert!(matches!(result, Err(BtcError::InvalidSignature)));
+
+        let result = _verify_message("hello".to_string(), signature, public_key, true);
+        assert_eq!(
+            result.unwrap(),
+            key.verifying_key().to_encoded_point(true).as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_recover_pub_key_failure() {
+        // There is no point on the curve with x = 5, so no public key can be recovered from r = 5.
+        let mut bytes = vec![31u8];
+        bytes.extend_from_slice(&[0u8; 31]);
+        bytes.push(5);
+        bytes.extend_from_slice(&[1u8; 32]);
+        let hash = _msg_hash("hello".to_string());
+        let result = recover_pub_key_compact(&bytes, &hash, None);
+        assert!(matches!(result, Err(BtcError::PublicKeyRecoveryFailure)));
+    }
+
+    #[test]
+    fn test_login_malformed_signature() {
+        init();
+        let key = signing_key(1);
+        let address = p2wpkh_address(&key);
+        prepare_login(&address).unwrap();
+        let result = login(
+            &BtcSignature("not base64!".to_string()),
+            &address,
+            vec![public_key_hex(&key)],
+            ByteBuf::from(SESSION_KEY),
+            &mut SignatureMap::default(),
+            &Principal::anonymous(),
+            SignMessageType::ECDSA,
+            WalletKind::Auto,
+            None,
+        );
+        assert!(matches!(
+            result,
+            Err(LoginError::BtcError(BtcError::SignatureFormatError(_)))
+        ));
+    }
+
+    #[test]
+    fn test_login_realms_derive_distinct_principals() {
+        init();
+        let key = signing_key(1);
+        let address = p2wpkh_address(&key);
+        let login_in_realm = |realm: Option<&str>| {
+            let message = prepare_login(&address).unwrap();
+            let signature = BtcSignature(sign_message(&key, &String::from(message)));
+            login(
+                &signature,
+                &address,
+                vec![public_key_hex(&key)],
+                ByteBuf::from(SESSION_KEY),
+                &mut SignatureMap::default(),
+                &Principal::anonymous(),
+                SignMessageType::ECDSA,
+                WalletKind::Auto,
+                realm,
+            )
+            .unwrap()
+            .0
+            .user_canister_pubkey
+        };
+
+        let app_a = login_in_realm(Some("app-a"));
+        let app_b = login_in_realm(Some("app-b"));
+        let no_realm = login_in_realm(None);
+        assert_ne!(app_a, app_b);
+        assert_ne!(app_a, no_realm);
+        assert_eq!(app_a, login_in_realm(Some("app-a")));
+    }
+
+    #[test]
+    fn test_login_details_cbor_round_trip() {
+        let details = LoginDetails {
+            expiration: 1_700_000_000_000_000_000,
+            user_canister_pubkey: ByteBuf::from(vec![1, 2, 3]),
+            public_key: Some("02aa".to_string()),
+            request_id: Some("req-1".to_string()),
+            address_type: LoginAddressType::P2wpkh,
+            targets: None,
+        };
+        let decoded = LoginDetails::from_cbor(&details.to_cbor().unwrap()).unwrap();
+        assert_eq!(decoded.expiration, details.expiration);
+        assert_eq!(decoded.user_canister_pubkey, details.user_canister_pubkey);
+        assert_eq!(decoded.public_key, details.public_key);
+        assert_eq!(decoded.request_id, details.request_id);
+        assert_eq!(decoded.address_type, details.address_type);
+
+        let details = LoginDetails {
+            public_key: None,
+            ..details
+        };
+        let decoded = LoginDetails::from_cbor(&details.to_cbor().unwrap()).unwrap();
+        assert_eq!(decoded.public_key, None);
+
+        assert!(LoginDetails::from_cbor(&[0xff]).is_err());
+    }
+
+    #[test]
+    fn test_allowed_address_types() {
+        let key = signing_key(1);
+        let public_key =
+            BitcoinPublicKey::from_slice(key.verifying_key().to_encoded_point(true).as_bytes())
+                .unwrap();
+        let p2wpkh = p2wpkh_address(&key);
+        let p2pkh = Address::p2pkh(&public_key, Network::Bitcoin);
+
+        let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .allowed_address_types(vec![AddressType::P2wpkh, AddressType::P2tr])
+            .build()
+            .unwrap();
+        SETTINGS.set(Some(settings));
+
+        assert!(ecdsa_login(&key, &p2wpkh, vec![public_key_hex(&key)]).is_ok());
+        assert!(matches!(
+            prepare_login(&p2pkh),
+            Err(BtcError::AddressTypeNotAllowed)
+        ));
+        let result = login(
+            &BtcSignature(sign_message(&key, "anything")),
+            &p2pkh,
+            vec![public_key_hex(&key)],
+            ByteBuf::from(SESSION_KEY),
+            &mut SignatureMap::default(),
+            &Principal::anonymous(),
+            SignMessageType::ECDSA,
+            WalletKind::Auto,
+            None,
+        );
+        assert!(matches!(
+            result,
+            Err(LoginError::BtcError(BtcError::AddressTypeNotAllowed))
+        ));
+
+        // An empty list allows every supported type.
+        init();
+        assert!(ecdsa_login(&key, &p2pkh, vec![public_key_hex(&key)]).is_ok());
+    }
+
+    #[test]
+    fn test_login_no_candidate_matches() {
+        init();
+        let key = signing_key(1);
+        let address = p2wpkh_address(&key);
+        let candidates = vec![
+            public_key_hex(&signing_key(2)),
+            public_key_hex(&signing_key(3)),
+        ];
+        let result = ecdsa_login(&key, &address, candidates);
+        assert!(matches!(result, Err(LoginError::AddressMismatch { .. })));
+    }
+
+    #[test]
+    fn test_login_address_mismatch_reports_addresses() {
+        init();
+        let signer = signing_key(1);
+        let owner = signing_key(2);
+        let expected = p2wpkh_address(&owner);
+        let recovered = p2wpkh_address(&signer);
+
+        // The wallet signs with a different key than the one owning the address.
+        let result = ecdsa_login(&signer, &expected, vec![public_key_hex(&owner)]);
+        let message = result.unwrap_err().to_string();
+        assert!(message.contains(&expected.to_string()), "{}", message);
+        assert!(message.contains(&recovered.to_string()), "{}", message);
+    }
+
+    #[test]
+    fn test_get_address() {
+        let p2tr_t = verify_address(
+            "tb1pgvdp7lf89d62zadds5jvyjntxmr7v70yv33g7vqaeu2p0cuexveqjlwphr",
+            hex::decode("03133c85d348d6c0796382966380719397453592e706cd3329119a2d2cb8d2ff7b")
+                .unwrap(),
+        );
+        let p2tr = verify_address(
+            "bc1pgvdp7lf89d62zadds5jvyjntxmr7v70yv33g7vqaeu2p0cuexveq9hcwdv",
+            hex::decode("03133c85d348d6c0796382966380719397453592e706cd3329119a2d2cb8d2ff7b")
+                .unwrap(),
+        );
+        assert!(p2tr_t.is_ok());
+        assert!(p2tr.is_ok());
+
+        let p2shp2wpkh_t = verify_address(
+            "2NBbnaYUvZvrvKfd7wqMmt7bZoAMTSkAarU",
+            hex::decode("02e203c98d766554bb4dab431d70b014b505aac66f47b735d9e7cbb4f12108ac3d")
+                .unwrap(),
+        );
+        let p2shp2wpkh = verify_address(
+            "3L3aWoYtxUMa7szaGhjuGAcJap9Hb13EEP",
+            hex::decode("02e203c98d766554bb4dab431d70b014b505aac66f47b735d9e7cbb4f12108ac3d")
+                .unwrap(),
+        );
+        assert!(p2shp2wpkh_t.is_ok());
+        assert!(p2shp2wpkh.is_ok());
+
+        let p2wpkh_t = verify_address(
+            "tb1qshqyem2rf8jyla904gd2cvek2k8nz5z3vc2j3x",
+            hex::decode("03f72a781776c63888aa9af5478c72c4794165a44024679995f6d232b4f6254574")
+                .unwrap(),
+        );
+        let p2wpkh = verify_address(
+            "bc1qshqyem2rf8jyla904gd2cvek2k8nz5z3x73p24",
+            hex::decode("03f72a781776c63888aa9af5478c72c4794165a44024679995f6d232b4f6254574")
+                .unwrap(),
+        );
+        assert!(p2wpkh_t.is_ok());
+        assert!(p2wpkh.is_ok());
+
+        let p2pkh_t = verify_address(
+            "mt1ycNxRhKVf1JyHhrKQEuuMoBnSPrwxfM",
+            hex::decode("03133c85d348d6c0796382966380719397453592e706cd3329119a2d2cb8d2ff7b")
+                .unwrap(),
+        );
+        let p2pkh = verify_address(
+            "1DW2KKsStJ4QECVfzHM2Qzh2wCBjTe9TH1",
+            hex::decode("03133c85d348d6c0796382966380719397453592e706cd3329119a2d2cb8d2ff7b")
+                .unwrap(),
+        );
+        assert!(p2pkh_t.is_ok());
+        assert!(p2pkh.is_ok());
+    }
+    #[test]
+    fn test_verify_address_unsupported_types() {
+        let public_key =
+            hex::decode("03133c85d348d6c0796382966380719397453592e706cd3329119a2d2cb8d2ff7b")
+                .unwrap();
+
+        // P2WSH can't be derived from a single public key.
+        let p2wsh = verify_address(
+            "bc1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3qccfmv3",
+            public_key.clone(),
+        );
+        assert!(matches!(p2wsh, Err(BtcError::AddressTypeNotSupported)));
+
+        // A future witness version must not be mistaken for P2TR.
+        let future_version =
+            verify_address("bc1zw508d6qejxtdg4y5r3zarvaryvaxxpcs", public_key.clone());
+        assert!(matches!(
+            future_version,
+            Err(BtcError::AddressTypeNotSupported)
+        ));
+    }
+
+    #[test]
+    fn test_verify_address_key_mismatch() {
+        // The addresses belong to 03133c85..., but a different key is supplied.
+        let other_key =
+            hex::decode("03f72a781776c63888aa9af5478c72c4794165a44024679995f6d232b4f6254574")
+                .unwrap();
+        let addresses = [
+            "1DW2KKsStJ4QECVfzHM2Qzh2wCBjTe9TH1",
+            "mt1ycNxRhKVf1JyHhrKQEuuMoBnSPrwxfM",
+            "bc1pgvdp7lf89d62zadds5jvyjntxmr7v70yv33g7vqaeu2p0cuexveq9hcwdv",
+            "tb1pgvdp7lf89d62zadds5jvyjntxmr7v70yv33g7vqaeu2p0cuexveqjlwphr",
+            // These belong to 02e203c9...
+            "3L3aWoYtxUMa7szaGhjuGAcJap9Hb13EEP",
+            "2NBbnaYUvZvrvKfd7wqMmt7bZoAMTSkAarU",
+        ];
+        for address in addresses {
+            let result = verify_address(address, other_key.clone());
+            assert!(
+                matches!(result, Err(BtcError::AddressMismatch)),
+                "Expected a mismatch for {}",
+                address
+            );
+        }
+
+        // And the P2WPKH addresses belong to 03f72a78..., so the first key doesn't match them.
+        let key = hex::decode("03133c85d348d6c0796382966380719397453592e706cd3329119a2d2cb8d2ff7b")
+            .unwrap();
+        let result = verify_address("bc1qshqyem2rf8jyla904gd2cvek2k8nz5z3x73p24", key);
+        assert!(matches!(result, Err(BtcError::AddressMismatch)));
+    }
+
+    #[test]
+    fn test_verify_address_rejects_uncompressed_segwit_key() {
+        let key = signing_key(1);
+        let address = p2wpkh_address(&key).to_string();
+        assert!(address.starts_with("bc1q"));
+
+        let uncompressed = key
+            .verifying_key()
+            .to_encoded_point(false)
+            .as_bytes()
+            .to_vec();
+        assert!(matches!(
+            verify_address(&address, uncompressed.clone()),
+            Err(BtcError::UncompressedKeyNotAllowed)
+        ));
+
+        // P2PKH addresses can commit to uncompressed keys.
+        let p2pkh = Address::p2pkh(
+            &BitcoinPublicKey::from_slice(&uncompressed).unwrap(),
+            Network::Bitcoin,
+        );
+        assert!(verify_address(&p2pkh.to_string(), uncompressed).is_ok());
+    }
+
+    #[test]
+    fn test_verify_address_matching_and_mismatched_pairs() {
+        let key = signing_key(1);
+        let other = signing_key(2);
+        let address = p2wpkh_address(&key).to_string();
+        let pub_bytes = |k: &SigningKey| hex::decode(public_key_hex(k)).unwrap();
+
+        assert!(verify_address(&address, pub_bytes(&key)).is_ok());
+        assert!(matches!(
+            verify_address(&address, pub_bytes(&other)),
+            Err(BtcError::AddressMismatch)
+        ));
+    }
+
+    #[test]
+    fn test_verify_address_malformed() {
+        let public_key =
+            hex::decode("03133c85d348d6c0796382966380719397453592e706cd3329119a2d2cb8d2ff7b")
+                .unwrap();
+        let result = verify_address("xyz1notanaddress", public_key);
+        assert!(matches!(result, Err(BtcError::AddressFormatError(_))));
+    }
+
+    #[test]
+    fn test_message() {
+        let p = "03133c85d348d6c0796382966380719397453592e706cd3329119a2d2cb8d2ff7b".to_string();
+        let s =  "HPVVoaHfyCUER9YB6MC8C+eh3in24rHTScQopgwzzEx6GP9fwZBI+ZIesS1HNzbMzMgLFS10IyhMc6aYbn3zfI4=".to_string();
+        let m = "{\"a\":1,\"b\":[2,3,4]}".to_string();
+        let a = "tb1pgvdp7lf89d62zadds5jvyjntxmr7v70yv33g7vqaeu2p0cuexveqjlwphr".to_string();
+
+        let v = _verify_message(m, s, p, true);
+        println!("v is {:?}", v);
+
+        let v2 = verify_address(a.as_str(), v.unwrap());
+        println!("v2 is {:?}", v2);
+    }
+
+    #[test]
+    fn test_msg_hash_digest_modes() {
+        assert_eq!(
+            hex::encode(_msg_hash("hello".to_string())),
+            "cf0447ec85f0ce7150a257db32ebfcb7523dae17c36dbd1be598779fec0484f4"
+        );
+        assert_eq!(
+            hex::encode(_msg_hash_with("hello".to_string(), true)),
+            "cf0447ec85f0ce7150a257db32ebfcb7523dae17c36dbd1be598779fec0484f4"
+        );
+        assert_eq!(
+            hex::encode(_msg_hash_with("hello".to_string(), false)),
+            "655b8db44b5659098ed41e41548609bd22bac94da7abbcf6a78bb2b564113bd9"
+        );
+    }
+
+    #[test]
+    fn test_login_single_hash() {
+        let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .double_hash(false)
+            .build()
+            .unwrap();
+        SETTINGS.set(Some(settings));
+        let key = signing_key(1);
+        let address = p2wpkh_address(&key);
+        let message = prepare_login(&address).unwrap();
+        let message_string = String::from(message);
+
+        // A double hashed signature no longer verifies once single hashing is configured.
+        let result = login(
+            &BtcSignature(sign_message(&key, &message_string)),
+            &address,
+            vec![public_key_hex(&key)],
+            ByteBuf::from(SESSION_KEY),
+            &mut SignatureMap::default(),
+            &Principal::anonymous(),
+            SignMessageType::ECDSA,
+            WalletKind::Auto,
+            None,
+        );
+        assert!(matches!(result, Err(LoginError::AddressMismatch { .. })));
+
+        let result = login(
+            &BtcSignature(sign_message_with(&key, &message_string, false)),
+            &address,
+            vec![public_key_hex(&key)],
+            ByteBuf::from(SESSION_KEY),
+            &mut SignatureMap::default(),
+            &Principal::anonymous(),
+            SignMessageType::ECDSA,
+            WalletKind::Auto,
+            None,
+        );
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_login_on_signet() {
+        init_with_network(Network::Signet);
+        let key = signing_key(1);
+        let address = p2wpkh_address_on(&key, Network::Testnet);
+        assert!(ecdsa_login(&key, &address, vec![public_key_hex(&key)]).is_ok());
+    }
+
+    #[test]
+    fn test_login_nested_segwit_on_testnet_and_signet() {
+        let key = signing_key(1);
+        let public_key =
+            BitcoinPublicKey::from_slice(key.verifying_key().to_encoded_point(true).as_bytes())
+                .unwrap();
+        // The `2...` encoding is shared by all non-mainnet networks.
+        let address = Address::p2shwpkh(&public_key, Network::Testnet).unwrap();
+        assert!(address.to_string().starts_with('2'));
+
+        for network in [Network::Testnet, Network::Signet] {
+            init_with_network(network);
+            assert!(
+                ecdsa_login(&key, &address, vec![public_key_hex(&key)]).is_ok(),
+                "Expected login on {}",
+                network
+            );
+        }
+    }
+
+    #[test]
+    fn test_login_address_network_mismatch() {
+        init_with_network(Network::Testnet);
+        let key = signing_key(1);
+        let address = p2wpkh_address_on(&key, Network::Bitcoin);
+        let result = ecdsa_login(&key, &address, vec![public_key_hex(&key)]);
+        assert!(matches!(
+            result,
+            Err(LoginError::BtcError(BtcError::NetworkMismatch))
+        ));
+    }
+
+    #[test]
+    fn test_login_with_two_allowed_networks() {
+        let key = signing_key(1);
+        let mainnet = p2wpkh_address_on(&key, Network::Bitcoin);
+        let testnet = p2wpkh_address_on(&key, Network::Testnet);
+
+        init();
+        let mainnet_only = ecdsa_login(&key, &mainnet, vec![public_key_hex(&key)]).unwrap();
+
+        let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .allowed_networks(vec![Network::Bitcoin, Network::Testnet])
+            .strict_network(true)
+            .build()
+            .unwrap();
+        SETTINGS.set(Some(settings));
+
+        // Each message names the network of its address.
+        let message = prepare_login(&testnet).unwrap();
+        assert_eq!(message.network, Network::Testnet.to_string());
+
+        let on_mainnet = ecdsa_login(&key, &mainnet, vec![public_key_hex(&key)]).unwrap();
+        let on_testnet = ecdsa_login(&key, &testnet, vec![public_key_hex(&key)]).unwrap();
+
+        // The primary network derives the same principals as before, the other network mixes its
+        // network into the seed.
+        assert_eq!(
+            on_mainnet.user_canister_pubkey,
+            mainnet_only.user_canister_pubkey
+        );
+        assert_ne!(
+            on_testnet.user_canister_pubkey,
+            on_mainnet.user_canister_pubkey
+        );
+
+        // Addresses of networks that are not allowed are still rejected.
+        assert!(matches!(
+            prepare_login(&p2wpkh_address_on(&key, Network::Regtest)),
+            Err(BtcError::NetworkMismatch)
+        ));
+
+        let seed = crate::delegation::generate_seed(&testnet, None);
+        init_with_network(Network::Testnet);
+        assert_ne!(crate::delegation::generate_seed(&testnet, None), seed);
+    }
+
+    #[test]
+    fn test_prepare_login_strict_network() {
+        let key = signing_key(1);
+        let testnet = p2wpkh_address_on(&key, Network::Testnet);
+
+        // By default, a message can be prepared for an address of another network, the login fails.
+        init();
+        assert!(prepare_login(&testnet).is_ok());
+        assert!(matches!(
+            ecdsa_login(&key, &testnet, vec![public_key_hex(&key)]),
+            Err(LoginError::BtcError(BtcError::NetworkMismatch))
+        ));
+
+        let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .strict_network(true)
+            .build()
+            .unwrap();
+        SETTINGS.set(Some(settings));
+        assert!(matches!(
+            prepare_login(&testnet),
+            Err(BtcError::NetworkMismatch)
+        ));
+        assert!(ecdsa_login(&key, &p2wpkh_address(&key), vec![public_key_hex(&key)]).is_ok());
+    }
+
+    #[test]
+    fn test_prepare_login_full() {
+        let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .allowed_domains(vec!["app.example.com".to_string()])
+            .allowed_statements(vec!["Sign in to the beta".to_string()])
+            .allowed_uris(vec!["https://app.example.com".to_string()])
+            .build()
+            .unwrap();
+        SETTINGS.set(Some(settings));
+        let key = signing_key(1);
+        let address = p2wpkh_address(&key);
+
+        let options = PrepareLoginOptions {
+            domain: Some("app.example.com".to_string()),
+            statement: Some("Sign in to the beta".to_string()),
+            nonce: Some("abcdEFGH1234".to_string()),
+            uri: Some("https://app.example.com".to_string()),
+        };
+        let prepared = prepare_login_full(&address, None, &options).unwrap();
+        assert_eq!(
+            prepared.digest_hex,
+            hex::encode(_msg_hash(prepared.message.clone()))
+        );
+        let message = SiwbMessage::parse(&prepared.message).unwrap();
+        assert_eq!(message.domain, "app.example.com");
+        assert_eq!(message.statement, "Sign in to the beta");
+        assert_eq!(message.nonce, "abcdEFGH1234");
+        assert_eq!(message.uri, "https://app.example.com");
+
+        // The prepared message is the one the login verifies.
+        assert!(ecdsa_login(&key, &address, vec![public_key_hex(&key)]).is_ok());
+
+        let defaults = prepare_login_full(&address, None, &PrepareLoginOptions::default()).unwrap();
+        assert_eq!(
+            String::from(
+                SIWB_MESSAGES.with_borrow(|m| m.get(&address.script_pubkey().to_bytes()).unwrap())
+            ),
+            defaults.message
+        );
+
+        let rejected = [
+            (
+                PrepareLoginOptions {
+                    domain: Some("evil.com".to_string()),
+                    ..Default::default()
+                },
+                BtcError::DomainNotAllowed,
+            ),
+            (
+                PrepareLoginOptions {
+                    statement: Some("Send all your coins".to_string()),
+                    ..Default::default()
+                },
+                BtcError::StatementNotAllowed,
+            ),
+            (
+                PrepareLoginOptions {
+                    uri: Some("https://evil.com".to_string()),
+                    ..Default::default()
+                },
+                BtcError::UriNotAllowed,
+            ),
+            (
+                PrepareLoginOptions {
+                    nonce: Some("short".to_string()),
+                    ..Default::default()
+                },
+                BtcError::MalformedMessage("nonce is invalid".to_string()),
+            ),
+        ];
+        for (options, expected) in rejected {
+            let error = prepare_login_full(&address, None, &options).unwrap_err();
+            assert_eq!(error.to_string(), expected.to_string());
+        }
+
+        // With a configured tag, the digest is the tagged hash that the login verifies.
+        let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .message_hash_tag("SIWB/message")
+            .build()
+            .unwrap();
+        SETTINGS.set(Some(settings));
+        let tagged = prepare_login_full(&address, None, &PrepareLoginOptions::default()).unwrap();
+        let digest = tagged_hash("SIWB/message", tagged.message.as_bytes());
+        assert_eq!(tagged.digest_hex, hex::encode(&digest));
+        let (signature, recovery_id) = key.sign_prehash_recoverable(&digest).unwrap();
+        let mut bytes = vec![27 + 4 + recovery_id.to_byte()];
+        bytes.extend_from_slice(&signature.to_bytes());
+        assert!(login(
+            &BtcSignature(general_purpose::STANDARD.encode(bytes)),
+            &address,
+            vec![public_key_hex(&key)],
+            ByteBuf::from(SESSION_KEY),
+            &mut SignatureMap::default(),
+            &Principal::anonymous(),
+            SignMessageType::ECDSA,
+            WalletKind::Auto,
+            None,
+        )
+        .is_ok());
+    }
+
+    #[cfg(not(feature = "nonce-fallback"))]
+    #[test]
+    fn test_prepare_login_unseeded_rng() {
+        init();
+        let key = signing_key(1);
+        let address = p2wpkh_address(&key);
+
+        crate::rand::unseed_rng();
+        assert!(matches!(
+            prepare_login(&address),
+            Err(BtcError::RngNotSeeded)
+        ));
+        assert!(!has_pending_message(&address));
+
+        crate::rand::seed_rng([7; 32]);
+        assert!(ecdsa_login(&key, &address, vec![public_key_hex(&key)]).is_ok());
+    }
+
+    #[test]
+    fn test_login_message_network_mismatch() {
+        init_with_network(Network::Signet);
+        let key = signing_key(1);
+        let address = p2wpkh_address_on(&key, Network::Testnet);
+        let message = prepare_login(&address).unwrap();
+        let signature = BtcSignature(sign_message(&key, &String::from(message)));
+
+        // The message names signet, so it can't be used once the library is configured for testnet.
+        init_with_network(Network::Testnet);
+        let result = login(
+            &signature,
+            &address,
+            vec![public_key_hex(&key)],
+            ByteBuf::from(SESSION_KEY),
+            &mut SignatureMap::default(),
+            &Principal::anonymous(),
+            SignMessageType::ECDSA,
+            WalletKind::Auto,
+            None,
+        );
+        assert!(matches!(
+            result,
+            Err(LoginError::BtcError(BtcError::NetworkMismatch))
+        ));
+    }
+
+    #[test]
+    fn test_verify_signed_message() {
+        // No settings are initialized: verification must not depend on library state.
+        let p = "03133c85d348d6c0796382966380719397453592e706cd3329119a2d2cb8d2ff7b".to_string();
+        let s =  "HPVVoaHfyCUER9YB6MC8C+eh3in24rHTScQopgwzzEx6GP9fwZBI+ZIesS1HNzbMzMgLFS10IyhMc6aYbn3zfI4=".to_string();
+        let m = "{\"a\":1,\"b\":[2,3,4]}".to_string();
+        let a = "tb1pgvdp7lf89d62zadds5jvyjntxmr7v70yv33g7vqaeu2p0cuexveqjlwphr";
+
+        let verified = verify_signed_message(m, s, p, a).unwrap();
+        assert_eq!(verified, VerifiedAddress(a.to_string()));
+    }
+
+    #[test]
+    fn test_verify_signed_message_normalizes_address() {
+        let p = "03133c85d348d6c0796382966380719397453592e706cd3329119a2d2cb8d2ff7b".to_string();
+        let s =  "HPVVoaHfyCUER9YB6MC8C+eh3in24rHTScQopgwzzEx6GP9fwZBI+ZIesS1HNzbMzMgLFS10IyhMc6aYbn3zfI4=".to_string();
+        let m = "{\"a\":1,\"b\":[2,3,4]}".to_string();
+        let a = "tb1pgvdp7lf89d62zadds5jvyjntxmr7v70yv33g7vqaeu2p0cuexveqjlwphr";
+
+        let verified = verify_signed_message(m, s, p, &a.to_uppercase()).unwrap();
+        assert_eq!(verified, VerifiedAddress(a.to_string()));
+    }
+
+    #[test]
+    fn test_verify_signed_message_wrong_message() {
+        let p = "03133c85d348d6c0796382966380719397453592e706cd3329119a2d2cb8d2ff7b".to_string();
+        let s =  "HPVVoaHfyCUER9YB6MC8C+eh3in24rHTScQopgwzzEx6GP9fwZBI+ZIesS1HNzbMzMgLFS10IyhMc6aYbn3zfI4=".to_string();
+        let m = "{\"a\":2}".to_string();
+        let a = "tb1pgvdp7lf89d62zadds5jvyjntxmr7v70yv33g7vqaeu2p0cuexveqjlwphr";
+
+        let result = verify_signed_message(m, s, p, a);
+        assert!(matches!(result, Err(BtcError::InvalidSignature)));
+    }
+
+    #[test]
+    fn test_verify_signed_message_address_mismatch() {
+        let p = "03133c85d348d6c0796382966380719397453592e706cd3329119a2d2cb8d2ff7b".to_string();
+        let s =  "HPVVoaHfyCUER9YB6MC8C+eh3in24rHTScQopgwzzEx6GP9fwZBI+ZIesS1HNzbMzMgLFS10IyhMc6aYbn3zfI4=".to_string();
+        let m = "{\"a\":1,\"b\":[2,3,4]}".to_string();
+        let a = "tb1qshqyem2rf8jyla904gd2cvek2k8nz5z3vc2j3x";
+
+        let result = verify_signed_message(m, s, p, a);
+        assert!(matches!(result, Err(BtcError::AddressMismatch)));
+    }
+
+    #[test]
+    fn test_bip322_messasge() {
+        let m = "hello".to_string();
+        let h = bip0322_hash(m.as_str());
+        println!("hash is {:?}", hex::encode(h.clone()));
+        assert_eq!(
+            hex::encode(h.clone()),
+            "528e990bccf82644773d67eff12fb504e84b42c8396475da8c939404f4a32385".to_string()
+        );
+    }
+
+    #[test]
+    fn test_bip322_verify_p2tr() {
+        let m = "hello".to_string();
+        let a = "tb1phy4ay0kvcnelc9trqzk4ksld3qx45gm83274qxp204vzycg7hxaq2m2nrn".to_string();
+        let s = "AUBNN/m5COckJE1nj5bR9iAO+Ga5VlJU2xIIGBraFZQNDUtOO0J0tOhoQzvk0o+YwknQ3OGWyWR5VwiG2KzJwjUV".to_string();
+
+        let v = verify_signature_of_bip322_simple_p2tr(
+            a.as_str(),
+            m.as_str(),
+            s.as_str(),
+            bitcoin::Network::Testnet,
+        );
+        assert_eq!(v, true)
+    }
+
+    #[test]
+    fn test_bip322_verify_p2pkwh() {
+        let m = "hello".to_string();
+        let a = "tb1qf620ch70a2evf2n2jrmdk85wwpupx8qcszr2s7".to_string();
+        let s = "AkgwRQIhAOh1XvCVjPhJbc6oELxiRjjavkOW9ebYC5gzepzjWhn0AiAPpoXFwjozO82PYiSGlnc9RoM9JknaFt5OhmrGD/J58AEhA89jkK3c5cXYcnPiBLRTC27FwKz4mzOrZ+rizCQnR/jj".to_string();
+
+        let v = verify_signature_of_bip322_simple_segwitv0(
+            a.as_str(),
+            m.as_str(),
+            s.as_str(),
+            bitcoin::Network::Testnet,
+        );
+        assert_eq!(v, true);
+    }
+
+    #[test]
+    fn test_login_details_targets() {
+        let key = signing_key(1);
+        let address = p2wpkh_address(&key);
+
+        init();
+        let details = ecdsa_login(&key, &address, vec![public_key_hex(&key)]).unwrap();
+        assert_eq!(details.targets, None);
+
+        let targets = vec![
+            Principal::from_text("aaaaa-aa").unwrap(),
+            Principal::from_text("rwlgt-iiaaa-aaaaa-aaaaa-cai").unwrap(),
+        ];
+        let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .targets(targets.clone())
+            .build()
+            .unwrap();
+        SETTINGS.set(Some(settings));
+
+        let details = ecdsa_login(&key, &address, vec![public_key_hex(&key)]).unwrap();
+        assert_eq!(details.targets, Some(targets));
     }
 }
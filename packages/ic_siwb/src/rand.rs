@@ -1,27 +1,139 @@
+use crate::error::BtcError;
+
+/// Draws a nonce of 10 random bytes from `rng`, hex encoded.
+#[cfg(any(test, feature = "nonce"))]
+fn nonce_from(rng: &mut impl rand_chacha::rand_core::RngCore) -> String {
+    let mut buf = [0u8; 10];
+    rng.fill_bytes(&mut buf);
+    hex::encode(buf)
+}
+
+/// Draws a nonce from the RNG that [crate::init::init] seeds from `raw_rand`. Until the seed has arrived,
+/// [BtcError::RngNotSeeded] is returned, unless the `nonce-fallback` feature is enabled.
+#[cfg(any(test, feature = "nonce"))]
+fn nonce_from_seeded(rng: &mut Option<rand_chacha::ChaCha20Rng>) -> Result<String, BtcError> {
+    #[cfg(feature = "nonce-fallback")]
+    if rng.is_none() {
+        *rng = Some(fallback_rng());
+    }
+
+    rng.as_mut().map(nonce_from).ok_or(BtcError::RngNotSeeded)
+}
+
+/// An RNG seeded from the current time, for environments without `raw_rand` such as tests outside of
+/// the IC. Its nonces are predictable, so the `nonce-fallback` feature must not be enabled in production.
+/// The seed from `raw_rand` replaces it once it arrives.
+#[cfg(feature = "nonce-fallback")]
+fn fallback_rng() -> rand_chacha::ChaCha20Rng {
+    use rand_chacha::rand_core::SeedableRng;
+
+    let time = crate::time::get_current_time();
+    rand_chacha::ChaCha20Rng::from_seed(crate::hash::hash_bytes(time.to_be_bytes()))
+}
+
 #[cfg(not(test))]
 #[cfg(feature = "nonce")]
-pub(crate) fn generate_nonce() -> String {
+pub(crate) fn generate_nonce() -> Result<String, BtcError> {
     use crate::RNG;
-    use rand_chacha::rand_core::RngCore;
-
-    let mut buf = [0u8; 10];
-    RNG.with_borrow_mut(|rng| rng.as_mut().unwrap().fill_bytes(&mut buf));
 
-    hex::encode(buf)
+    RNG.with_borrow_mut(nonce_from_seeded)
 }
 
 #[cfg(not(test))]
 #[cfg(not(feature = "nonce"))]
-pub(crate) fn generate_nonce() -> String {
-    hex::encode("Not in use")
+pub(crate) fn generate_nonce() -> Result<String, BtcError> {
+    Ok(hex::encode("Not in use"))
+}
+
+#[cfg(test)]
+thread_local! {
+    // Tests can seed the nonce generator to get reproducible nonces. Each test runs on its own thread, so
+    // the seed never leaks between tests.
+    static TEST_RNG: std::cell::RefCell<Option<rand_chacha::ChaCha20Rng>> =
+        const { std::cell::RefCell::new(None) };
+    static TEST_RNG_UNSEEDED: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
 }
 
+/// Seeds the nonce generator with `seed`, so that the nonces generated from now on are the ones a canister
+/// generates after its RNG has been seeded with `seed` from `raw_rand`.
 #[cfg(test)]
-pub(crate) fn generate_nonce() -> String {
+pub(crate) fn seed_rng(seed: [u8; 32]) {
+    use rand_chacha::rand_core::SeedableRng;
+
+    TEST_RNG_UNSEEDED.set(false);
+    TEST_RNG.with_borrow_mut(|rng| *rng = Some(rand_chacha::ChaCha20Rng::from_seed(seed)));
+}
+
+/// Makes the nonce generator behave like the one of a canister whose RNG has not been seeded from
+/// `raw_rand` yet, until [seed_rng] is called.
+#[cfg(test)]
+pub(crate) fn unseed_rng() {
+    TEST_RNG_UNSEEDED.set(true);
+    TEST_RNG.with_borrow_mut(|rng| *rng = None);
+}
+
+#[cfg(test)]
+pub(crate) fn generate_nonce() -> Result<String, BtcError> {
     use rand::{thread_rng, Rng};
 
-    let mut rng = thread_rng();
-    let mut nonce = [0u8; 10];
-    rng.fill(&mut nonce);
-    hex::encode(nonce)
+    TEST_RNG.with_borrow_mut(|rng| match rng {
+        None if TEST_RNG_UNSEEDED.get() => nonce_from_seeded(rng),
+        Some(rng) => Ok(nonce_from(rng)),
+        None => {
+            let mut nonce = [0u8; 10];
+            thread_rng().fill(&mut nonce);
+            Ok(hex::encode(nonce))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seeded_nonce_sequence() {
+        let expected = [
+            "f400927857aaf64114f5",
+            "acb379708c79a1dc1476",
+            "3216a4020764bde545c1",
+        ];
+        seed_rng([7; 32]);
+        assert_eq!(
+            (0..3)
+                .map(|_| generate_nonce().unwrap())
+                .collect::<Vec<_>>(),
+            expected
+        );
+
+        // Seeding again restarts the sequence.
+        seed_rng([7; 32]);
+        assert_eq!(generate_nonce().unwrap(), expected[0]);
+        seed_rng([8; 32]);
+        assert_ne!(generate_nonce().unwrap(), expected[0]);
+    }
+
+    #[cfg(not(feature = "nonce-fallback"))]
+    #[test]
+    fn test_unseeded_rng() {
+        unseed_rng();
+        assert!(matches!(generate_nonce(), Err(BtcError::RngNotSeeded)));
+
+        // The nonces are available once the seed from raw_rand has arrived.
+        seed_rng([7; 32]);
+        assert_eq!(generate_nonce().unwrap(), "f400927857aaf64114f5");
+    }
+
+    #[cfg(feature = "nonce-fallback")]
+    #[test]
+    fn test_unseeded_rng_fallback() {
+        crate::time::set_test_time(1_000_000_000);
+        unseed_rng();
+        let first = generate_nonce().unwrap();
+        assert_ne!(generate_nonce().unwrap(), first);
+
+        // The seed from raw_rand replaces the fallback.
+        seed_rng([7; 32]);
+        assert_eq!(generate_nonce().unwrap(), "f400927857aaf64114f5");
+    }
 }
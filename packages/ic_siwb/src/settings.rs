@@ -1,12 +1,137 @@
-use bitcoin::Network;
+use bitcoin::address::NetworkUnchecked;
+use bitcoin::{Address, AddressType, Network};
 use candid::Principal;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::str::FromStr;
 use url::Url;
 
+use crate::verify::MAGIC_BYTES;
+
 const DEFAULT_SCHEME: &str = "https";
 const DEFAULT_STATEMENT: &str = "SIWB Fields:";
 // const DEFAULT_CHAIN_ID: u32 = 1; // Bitcoin mainnet
 const DEFAULT_SIGN_IN_EXPIRES_IN: u64 = 60 * 5 * 1_000_000_000; // 5 minutes
 const DEFAULT_SESSION_EXPIRES_IN: u64 = 30 * 60 * 1_000_000_000; // 30 minutes
+const MAX_SIGN_IN_EXPIRES_IN: u64 = 24 * 60 * 60 * 1_000_000_000; // 1 day
+const MAX_SESSION_EXPIRES_IN: u64 = 30 * 24 * 60 * 60 * 1_000_000_000; // 30 days
+const DEFAULT_MIN_SESSION_EXPIRES_IN: u64 = 1; // Any session that does not expire immediately
+const DEFAULT_MAX_MESSAGE_BYTES: usize = 4096;
+const DEFAULT_MAX_ISSUED_AT_SKEW: u64 = 60 * 1_000_000_000; // 1 minute
+const MAX_CLOCK_SKEW: u64 = 5 * 60 * 1_000_000_000; // 5 minutes
+const MAX_LOGIN_RETRY_WINDOW: u64 = 5 * 60 * 1_000_000_000; // 5 minutes
+const DEFAULT_SEED_VERSION: u8 = 1; // The derivation used before seed versioning was introduced
+const MAX_SEED_VERSION: u8 = 2;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SettingsError {
+    InvalidDomain,
+    InvalidUri,
+    EmptySalt,
+    InvalidSalt,
+    InvalidScheme,
+    InvalidStatement,
+    InvalidSignInExpiresIn,
+    SignInExpiresInTooLong,
+    InvalidSessionExpiresIn,
+    SessionExpiresInTooLong,
+    SessionExpiresInTooShort,
+    EmptyTargets,
+    TooManyTargets,
+    DuplicateTargets,
+    UnrecognizedNetwork,
+    NoAllowedNetworks,
+    InvalidExpirationRounding,
+    UnsupportedAddressType,
+    UnsupportedSeedVersion,
+    IssuedAtSkewTooLong,
+    InvalidMaxMessageBytes,
+    ClockSkewTooLong,
+    LoginRetryWindowTooLong,
+    EmptyMessagePrefix,
+    EmptyMessageHashTag,
+    #[cfg(feature = "stretched-seed")]
+    InvalidSeedStretching,
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SettingsError::InvalidDomain => write!(f, "Invalid domain"),
+            SettingsError::InvalidUri => write!(f, "Invalid URI"),
+            SettingsError::EmptySalt => write!(f, "Salt cannot be empty"),
+            SettingsError::InvalidSalt => write!(f, "Invalid salt"),
+            SettingsError::InvalidScheme => write!(f, "Invalid scheme"),
+            SettingsError::InvalidStatement => write!(f, "Invalid statement"),
+            SettingsError::InvalidSignInExpiresIn => {
+                write!(f, "Sign in expires in must be greater than 0")
+            }
+            SettingsError::SignInExpiresInTooLong => write!(
+                f,
+                "Sign in expires in must not exceed {} nanoseconds",
+                MAX_SIGN_IN_EXPIRES_IN
+            ),
+            SettingsError::InvalidSessionExpiresIn => {
+                write!(f, "Session expires in must be greater than 0")
+            }
+            SettingsError::SessionExpiresInTooLong => write!(
+                f,
+                "Session expires in must not exceed {} nanoseconds",
+                MAX_SESSION_EXPIRES_IN
+            ),
+            SettingsError::SessionExpiresInTooShort => write!(
+                f,
+                "Session expires in must not be less than min session expires in"
+            ),
+            SettingsError::EmptyTargets => write!(f, "Targets cannot be empty"),
+            SettingsError::TooManyTargets => write!(f, "Too many targets"),
+            SettingsError::DuplicateTargets => write!(f, "Duplicate targets are not allowed"),
+            SettingsError::UnrecognizedNetwork => write!(f, "Unrecognized Network"),
+            SettingsError::NoAllowedNetworks => write!(f, "At least one network must be allowed"),
+            SettingsError::InvalidExpirationRounding => write!(
+                f,
+                "Expiration rounding must be greater than 0 and not exceed session expires in"
+            ),
+            SettingsError::UnsupportedAddressType => write!(
+                f,
+                "Allowed address types may only contain P2PKH, P2SH, P2WPKH and P2TR"
+            ),
+            SettingsError::UnsupportedSeedVersion => write!(
+                f,
+                "Seed version must be between {} and {}",
+                DEFAULT_SEED_VERSION, MAX_SEED_VERSION
+            ),
+            SettingsError::InvalidMaxMessageBytes => {
+                write!(f, "Max message bytes must be greater than 0")
+            }
+            SettingsError::IssuedAtSkewTooLong => write!(
+                f,
+                "Max issued at skew must not exceed {} nanoseconds",
+                MAX_SIGN_IN_EXPIRES_IN
+            ),
+            SettingsError::ClockSkewTooLong => write!(
+                f,
+                "Clock skew must not exceed {} nanoseconds",
+                MAX_CLOCK_SKEW
+            ),
+            SettingsError::LoginRetryWindowTooLong => write!(
+                f,
+                "Login retry window must not exceed {} nanoseconds",
+                MAX_LOGIN_RETRY_WINDOW
+            ),
+            SettingsError::EmptyMessagePrefix => write!(f, "Message prefixes cannot be empty"),
+            SettingsError::EmptyMessageHashTag => write!(f, "Message hash tag cannot be empty"),
+            #[cfg(feature = "stretched-seed")]
+            SettingsError::InvalidSeedStretching => write!(f, "Invalid seed stretching parameters"),
+        }
+    }
+}
+
+impl From<SettingsError> for String {
+    fn from(error: SettingsError) -> Self {
+        error.to_string()
+    }
+}
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum RuntimeFeature {
@@ -14,6 +139,19 @@ pub enum RuntimeFeature {
     IncludeUriInSeed,
 }
 
+/// The scrypt cost parameters used to stretch the seed derivation input, see
+/// [SettingsBuilder::seed_stretching].
+#[cfg(feature = "stretched-seed")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct SeedStretching {
+    /// The log2 of the scrypt CPU/memory cost `N`.
+    pub log_n: u8,
+    /// The scrypt block size `r`.
+    pub r: u32,
+    /// The scrypt parallelization `p`.
+    pub p: u32,
+}
+
 /// Represents the settings for initializing SIWB.
 ///
 /// This struct is used to configure SIWB (Sign-In With Bitcoin) functionality.
@@ -47,6 +185,10 @@ pub struct Settings {
     /// The TTL for a session in nanoseconds.
     pub session_expires_in: u64,
 
+    /// The shortest TTL for a session in nanoseconds, both for `session_expires_in` and for shorter
+    /// sessions requested per call. Defaults to 1 nanosecond.
+    pub min_session_expires_in: u64,
+
     /// The list of canisters for which the identity delegation is allowed. Defaults to None, which means
     /// that the delegation is allowed for all canisters.
     pub targets: Option<Vec<Principal>>,
@@ -54,7 +196,164 @@ pub struct Settings {
     // Optional runtime features that can be enabled for SIWB.
     pub runtime_features: Option<Vec<RuntimeFeature>>,
 
-    pub network: Network,
+    /// The networks whose addresses can log in. The first is the primary network, which, amongst
+    /// others, is rendered into messages for addresses that are valid for none of them. Never empty, and
+    /// defaults to Bitcoin mainnet.
+    pub allowed_networks: Vec<Network>,
+
+    /// Whether the framed message is hashed twice with SHA256 before signature verification, as Bitcoin
+    /// Core message signing does. Defaults to `true`. Disable for wallets that sign the single hash.
+    pub double_hash: bool,
+
+    /// When set, delegation expirations are rounded down to a multiple of this many nanoseconds. Aligning
+    /// expirations across users reduces fingerprinting and improves certificate caching. Defaults to None.
+    pub expiration_rounding: Option<u64>,
+
+    /// The address types that may be used to sign in. Defaults to an empty list, which allows all
+    /// supported address types.
+    pub allowed_address_types: Vec<AddressType>,
+
+    /// The version of the seed derivation used to derive user principals. Defaults to 1, the original
+    /// derivation. Version 2 mixes the version into the seed.
+    pub seed_version: u8,
+
+    /// How many times the salt has been replaced with [crate::init::rotate_salt]. Defaults to 0. Unlike
+    /// `seed_version`, the generation is not part of the seed, only the salt is.
+    pub salt_generation: u64,
+
+    /// Whether legacy compact ECDSA message signatures are accepted. Defaults to `true`. When disabled,
+    /// only BIP-322 and Taproot Schnorr signatures can be used to sign in.
+    pub allow_legacy_ecdsa: bool,
+
+    /// How far, in nanoseconds, an `issued_at` supplied to `prepare_login_with_issued_at` may be from
+    /// the current time. Defaults to 1 minute.
+    pub max_issued_at_skew: u64,
+
+    /// The maximum length in bytes of a message to be signed. Longer messages are rejected before they
+    /// are hashed, which bounds the cost of verification. Defaults to 4096.
+    pub max_message_bytes: usize,
+
+    /// A grace period, in nanoseconds, for clock drift between the replica and the wallet. Messages are
+    /// only treated as expired `clock_skew` after their expiration time, and a supplied `issued_at` may
+    /// be that much further from the current time than `max_issued_at_skew`. Defaults to 0.
+    pub clock_skew: u64,
+
+    /// For how long, in nanoseconds, a login that consumed its SIWB message can be retried with the same
+    /// signature, session key and realm, e.g. after a network error, and returns the same login details.
+    /// Defaults to 0, which disables retries.
+    pub login_retry_window: u64,
+
+    /// Further domains, besides `domain`, that may request SIWB messages, for providers that serve several
+    /// frontends. Defaults to an empty list, which only allows `domain`.
+    pub allowed_domains: Vec<String>,
+
+    /// Further statements, besides `statement`, that clients may request for their SIWB messages with
+    /// [crate::login::prepare_login_full]. Defaults to an empty list, which only allows `statement`.
+    pub allowed_statements: Vec<String>,
+
+    /// Further URIs, besides `uri`, that clients may request for their SIWB messages with
+    /// [crate::login::prepare_login_full]. Defaults to an empty list, which only allows `uri`.
+    pub allowed_uris: Vec<String>,
+
+    /// The prefixes that frame messages for signing, keyed by network. Networks without a prefix use the
+    /// Bitcoin prefix "Bitcoin Signed Message:\n". Defaults to no prefixes.
+    pub message_prefixes: BTreeMap<Network, String>,
+
+    /// When set, message signatures are verified against the BIP-340 tagged hash of the message with this
+    /// tag rather than against the legacy framed message hash. Defaults to None, the legacy hash.
+    pub message_hash_tag: Option<String>,
+
+    /// When set, delegations are only issued for these session keys, e.g. for kiosks or devices that are
+    /// provisioned in advance. Logins with any other session key are rejected. Defaults to None, which
+    /// allows any session key.
+    pub allowed_session_keys: Option<BTreeSet<Vec<u8>>>,
+
+    /// Whether [crate::siwb::SiwbMessage::parse] rejects messages with fields the canister does not
+    /// render, e.g. lines injected into a message that is parsed back. Defaults to `false`, which ignores
+    /// unknown fields.
+    pub strict_message_parsing: bool,
+
+    /// Whether addresses that are not valid for any of `allowed_networks` are rejected as soon as a
+    /// message or payload is prepared for them. Defaults to `false`, in which case such addresses are
+    /// only rejected when they log in.
+    pub strict_network: bool,
+
+    /// Whether the principal of the canister is rendered into SIWB messages, so that a message can only
+    /// be used to log in at the canister it was prepared by, even if several canisters serve the same
+    /// domain. Defaults to `false`.
+    pub bind_canister_id: bool,
+
+    /// The scrypt parameters used to stretch the seed derivation input before it is hashed. Defaults to
+    /// None, the fast path.
+    #[cfg(feature = "stretched-seed")]
+    pub seed_stretching: Option<SeedStretching>,
+}
+
+impl Settings {
+    /// Clamps a per-call session TTL override to the configured range: it is capped at
+    /// `session_expires_in` and raised to `min_session_expires_in`.
+    pub fn clamp_session_expires_in(&self, requested: u64) -> u64 {
+        requested
+            .min(self.session_expires_in)
+            .max(self.min_session_expires_in)
+    }
+
+    /// Whether `domain` may request SIWB messages: the configured `domain` or one of `allowed_domains`.
+    pub fn is_domain_allowed(&self, domain: &str) -> bool {
+        self.domain == domain || self.allowed_domains.iter().any(|d| d == domain)
+    }
+
+    /// Whether SIWB messages may carry `statement`: the configured `statement` or one of
+    /// `allowed_statements`.
+    pub fn is_statement_allowed(&self, statement: &str) -> bool {
+        self.statement == statement || self.allowed_statements.iter().any(|s| s == statement)
+    }
+
+    /// Whether SIWB messages may carry `uri`: the configured `uri` or one of `allowed_uris`.
+    pub fn is_uri_allowed(&self, uri: &str) -> bool {
+        self.uri == uri || self.allowed_uris.iter().any(|u| u == uri)
+    }
+
+    /// Whether delegations may be issued for `session_key`: any key unless `allowed_session_keys` is set.
+    pub fn is_session_key_allowed(&self, session_key: &[u8]) -> bool {
+        self.allowed_session_keys
+            .as_ref()
+            .is_none_or(|keys| keys.contains(session_key))
+    }
+
+    /// The primary network, the first of `allowed_networks`.
+    pub fn network(&self) -> Network {
+        self.allowed_networks
+            .first()
+            .copied()
+            .unwrap_or(Network::Bitcoin)
+    }
+
+    /// The first of `allowed_networks` that `address` is valid for, or `None` if there is none. Testnet
+    /// and signet share their encodings, so if both are allowed, their addresses belong to the one that
+    /// is allowed first.
+    pub fn address_network(&self, address: &Address) -> Option<Network> {
+        let address = Address::<NetworkUnchecked>::from_str(&address.to_string()).ok()?;
+        self.allowed_networks
+            .iter()
+            .copied()
+            .find(|network| address.is_valid_for_network(*network))
+    }
+
+    /// The network of `address`, see [Settings::address_network], or the primary network if `address`
+    /// is valid for none of the allowed networks.
+    pub fn network_for(&self, address: &Address) -> Network {
+        self.address_network(address)
+            .unwrap_or_else(|| self.network())
+    }
+
+    /// The prefix that frames messages for signing on `network`, the Bitcoin prefix unless
+    /// `message_prefixes` has one for it.
+    pub fn message_prefix(&self, network: Network) -> &str {
+        self.message_prefixes
+            .get(&network)
+            .map_or(MAGIC_BYTES, String::as_str)
+    }
 }
 
 /// A builder for creating `Settings` instances.
@@ -67,10 +366,11 @@ pub struct Settings {
 /// Basic usage:
 ///
 /// ```
+/// use bitcoin::Network;
 /// use ic_siwb::settings::{Settings, SettingsBuilder};
 ///
 /// let builder = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
-///     .chain_id(1)  // Bitcoin mainnet
+///     .network(Network::Bitcoin)
 ///     .scheme("https")
 ///     .statement("Sign in to access your account")
 ///     .sign_in_expires_in(300_000_000_000)  // 5 minutes in nanoseconds
@@ -80,6 +380,8 @@ pub struct Settings {
 /// ```
 ///
 /// This will create a `Settings` instance with the specified domain, URI, salt, and other configuration parameters.
+/// [`SettingsBuilder::build`] validates the configuration and returns a [`SettingsError`] for nonsensical combinations,
+/// so misconfiguration is caught when the canister is deployed rather than at the first login.
 pub struct SettingsBuilder {
     settings: Settings,
 }
@@ -107,17 +409,49 @@ impl SettingsBuilder {
                 statement: DEFAULT_STATEMENT.to_string(),
                 sign_in_expires_in: DEFAULT_SIGN_IN_EXPIRES_IN,
                 session_expires_in: DEFAULT_SESSION_EXPIRES_IN,
+                min_session_expires_in: DEFAULT_MIN_SESSION_EXPIRES_IN,
                 targets: None,
                 runtime_features: None,
-                network: Network::Bitcoin,
+                allowed_networks: vec![Network::Bitcoin],
+                double_hash: true,
+                expiration_rounding: None,
+                allowed_address_types: vec![],
+                seed_version: DEFAULT_SEED_VERSION,
+                salt_generation: 0,
+                allow_legacy_ecdsa: true,
+                max_issued_at_skew: DEFAULT_MAX_ISSUED_AT_SKEW,
+                max_message_bytes: DEFAULT_MAX_MESSAGE_BYTES,
+                clock_skew: 0,
+                login_retry_window: 0,
+                allowed_domains: vec![],
+                allowed_statements: vec![],
+                allowed_uris: vec![],
+                message_prefixes: BTreeMap::new(),
+                message_hash_tag: None,
+                allowed_session_keys: None,
+                strict_message_parsing: false,
+                strict_network: false,
+                bind_canister_id: false,
+                #[cfg(feature = "stretched-seed")]
+                seed_stretching: None,
             },
         }
     }
 
     /// Sets the Bitcoin network for ic-siwb.
-    /// The `network` is used to specify the Bitcoin network.
+    /// The `network` is used to specify the Bitcoin network. It becomes the only allowed network, see
+    /// [SettingsBuilder::allowed_networks].
     pub fn network(mut self, network: Network) -> Self {
-        self.settings.network = network;
+        self.settings.allowed_networks = vec![network];
+        self
+    }
+
+    /// Accepts logins with addresses of any of `networks`, e.g. both mainnet and testnet. Each address
+    /// logs in on the first of them it is valid for. The first network is the primary network, whose
+    /// principals are derived as with a single network; addresses of the other networks also mix their
+    /// network into the seed, see [crate::delegation::generate_seed]. Must not be empty.
+    pub fn allowed_networks(mut self, networks: Vec<Network>) -> Self {
+        self.settings.allowed_networks = networks;
         self
     }
 
@@ -136,19 +470,28 @@ impl SettingsBuilder {
     }
 
     /// Sign in messages are valid for a limited time, after which they expire. The `sign_in_expires_in` value is
-    /// the time-to-live (TTL) for a sign-in message in nanoseconds. Defaults to 5 minutes.
+    /// the time-to-live (TTL) for a sign-in message in nanoseconds. Defaults to 5 minutes, must not exceed 1 day.
     pub fn sign_in_expires_in(mut self, expires_in: u64) -> Self {
         self.settings.sign_in_expires_in = expires_in;
         self
     }
 
     /// Sessions (as represented by delegete identities) are valid for a limited time, after which they expire.
-    /// The `session_expires_in` value is the time-to-live (TTL) for a session in nanoseconds. Defaults to 30 minutes.
+    /// The `session_expires_in` value is the time-to-live (TTL) for a session in nanoseconds. Defaults to 30 minutes,
+    /// must not exceed 30 days.
     pub fn session_expires_in(mut self, expires_in: u64) -> Self {
         self.settings.session_expires_in = expires_in;
         self
     }
 
+    /// The shortest session TTL in nanoseconds. `session_expires_in` must not be shorter, and shorter
+    /// sessions requested per call are extended to it, so that no session expires right after it was
+    /// created. Defaults to 1 nanosecond.
+    pub fn min_session_expires_in(mut self, min_expires_in: u64) -> Self {
+        self.settings.min_session_expires_in = min_expires_in;
+        self
+    }
+
     /// The `targets` is a list of `Principal`s representing the canisters where the delegated identity can be used to
     /// authenticate the user. Defaults to None, which means that the delegation is allowed for any canister.
     pub fn targets(mut self, targets: Vec<Principal>) -> Self {
@@ -162,46 +505,230 @@ impl SettingsBuilder {
         self
     }
 
-    pub fn build(self) -> Result<Settings, String> {
+    /// Whether the framed message is hashed twice with SHA256 before signature verification. Defaults to
+    /// `true`, which matches Bitcoin Core message signing.
+    pub fn double_hash(mut self, double_hash: bool) -> Self {
+        self.settings.double_hash = double_hash;
+        self
+    }
+
+    /// Rounds delegation expirations down to a multiple of `rounding` nanoseconds. Must be greater than 0 and
+    /// must not exceed the session TTL.
+    pub fn expiration_rounding(mut self, rounding: u64) -> Self {
+        self.settings.expiration_rounding = Some(rounding);
+        self
+    }
+
+    /// Restricts sign in to the given address types, e.g. only Taproot. Only P2PKH, P2SH (P2SH-P2WPKH),
+    /// P2WPKH and P2TR can be used to sign in. Defaults to an empty list, which allows all of them.
+    pub fn allowed_address_types(mut self, address_types: Vec<AddressType>) -> Self {
+        self.settings.allowed_address_types = address_types;
+        self
+    }
+
+    /// Selects the seed derivation used to derive user principals. Defaults to 1.
+    ///
+    /// ## 🛑 Important: Changing the seed version changes the principal of every user, just like changing
+    /// the `salt`. Existing deployments should keep version 1, new deployments can start with the latest
+    /// version. Future changes to the seed inputs will only apply to new versions.
+    pub fn seed_version(mut self, version: u8) -> Self {
+        self.settings.seed_version = version;
+        self
+    }
+
+    /// Sets the salt generation returned by the last [crate::init::rotate_salt], so that it is kept
+    /// when the settings are re-created with the rotated salt. Defaults to 0.
+    pub fn salt_generation(mut self, generation: u64) -> Self {
+        self.settings.salt_generation = generation;
+        self
+    }
+
+    /// Whether legacy compact ECDSA message signatures are accepted. Defaults to `true`. Disable to only
+    /// accept BIP-322 and Taproot Schnorr signatures.
+    pub fn allow_legacy_ecdsa(mut self, allow: bool) -> Self {
+        self.settings.allow_legacy_ecdsa = allow;
+        self
+    }
+
+    /// How far, in nanoseconds, a supplied `issued_at` may be from the current time, in both directions.
+    /// Defaults to 1 minute, must not exceed 1 day.
+    pub fn max_issued_at_skew(mut self, skew: u64) -> Self {
+        self.settings.max_issued_at_skew = skew;
+        self
+    }
+
+    /// The maximum length in bytes of a message to be signed. Defaults to 4096, must be greater than 0.
+    pub fn max_message_bytes(mut self, max_bytes: usize) -> Self {
+        self.settings.max_message_bytes = max_bytes;
+        self
+    }
+
+    /// A grace period, in nanoseconds, for clock drift applied to message expiration and `issued_at`
+    /// checks. Defaults to 0, must not exceed 5 minutes.
+    pub fn clock_skew(mut self, skew: u64) -> Self {
+        self.settings.clock_skew = skew;
+        self
+    }
+
+    /// For how long, in nanoseconds, a successful login can be retried and returns the same login details
+    /// rather than failing because its message has been used. Defaults to 0, must not exceed 5 minutes.
+    pub fn login_retry_window(mut self, window: u64) -> Self {
+        self.settings.login_retry_window = window;
+        self
+    }
+
+    /// Allows the given domains, besides `domain`, to request SIWB messages, see
+    /// [crate::login::prepare_login_for_domain]. Each domain is validated like `domain`.
+    pub fn allowed_domains(mut self, domains: Vec<String>) -> Self {
+        self.settings.allowed_domains = domains;
+        self
+    }
+
+    /// Allows the given statements, besides `statement`, to be requested for SIWB messages, see
+    /// [crate::login::prepare_login_full]. Each statement is validated like `statement`.
+    pub fn allowed_statements(mut self, statements: Vec<String>) -> Self {
+        self.settings.allowed_statements = statements;
+        self
+    }
+
+    /// Allows the given URIs, besides `uri`, to be requested for SIWB messages, see
+    /// [crate::login::prepare_login_full]. Each URI is validated like `uri`.
+    pub fn allowed_uris(mut self, uris: Vec<String>) -> Self {
+        self.settings.allowed_uris = uris;
+        self
+    }
+
+    /// Frames messages signed on `network` with `prefix` instead of "Bitcoin Signed Message:\n", for
+    /// chains whose wallets sign with their own prefix, e.g. "Litecoin Signed Message:\n". The prefix of
+    /// network of each address is used. Must not be empty.
+    pub fn message_prefix<S: Into<String>>(mut self, network: Network, prefix: S) -> Self {
+        self.settings
+            .message_prefixes
+            .insert(network, prefix.into());
+        self
+    }
+
+    /// Verifies message signatures against the BIP-340 tagged hash of the message with `tag`, for wallets
+    /// that sign tagged hashes rather than the legacy framed message hash. Logins can also request the
+    /// tagged hash with [crate::login::SignMessageType::TaggedHash], which uses this tag or
+    /// [crate::verify::DEFAULT_MESSAGE_HASH_TAG]. Must not be empty.
+    pub fn message_hash_tag<S: Into<String>>(mut self, tag: S) -> Self {
+        self.settings.message_hash_tag = Some(tag.into());
+        self
+    }
+
+    /// Only issues delegations for the DER encoded session keys in `session_keys`, for locked-down
+    /// deployments whose devices are provisioned in advance. An empty list allows no session key at all.
+    /// Keys can be allowed and disallowed later with [crate::init::allow_session_key] and
+    /// [crate::init::disallow_session_key].
+    pub fn allowed_session_keys(mut self, session_keys: Vec<Vec<u8>>) -> Self {
+        self.settings.allowed_session_keys = Some(session_keys.into_iter().collect());
+        self
+    }
+
+    /// Whether parsed messages may only contain the fields the canister renders. Defaults to `false`.
+    /// Enable to reject messages with unknown, duplicate or malformed fields instead of ignoring them.
+    pub fn strict_message_parsing(mut self, strict: bool) -> Self {
+        self.settings.strict_message_parsing = strict;
+        self
+    }
+
+    /// Whether addresses that are not valid for any allowed network are rejected with
+    /// [crate::error::BtcError::NetworkMismatch] when a message or payload is prepared for them. Defaults
+    /// to `false`. Logins always require an address of an allowed network.
+    pub fn strict_network(mut self, strict: bool) -> Self {
+        self.settings.strict_network = strict;
+        self
+    }
+
+    /// Whether SIWB messages name the canister they are prepared by and can only be used to log in at
+    /// that canister, which prevents replaying a signature at another canister serving the same domain.
+    /// Defaults to `false`. Messages must then be prepared with `prepare_login_for_canister`.
+    pub fn bind_canister_id(mut self, bind: bool) -> Self {
+        self.settings.bind_canister_id = bind;
+        self
+    }
+
+    /// Runs the seed derivation input through scrypt with the given parameters before it is hashed, so
+    /// that guessing the seeds of known addresses offline is expensive even if the salt leaks.
+    ///
+    /// The seed is derived on every login, delegation and login status call, and each derivation costs
+    /// about `128 * r * 2^log_n` bytes of memory and a number of instructions proportional to
+    /// `p * r * 2^log_n`. Measure the cost with `ic_cdk::api::performance_counter` before deploying, query
+    /// calls such as `siwb_get_delegation` have a lower instruction limit than updates. Changing the
+    /// parameters changes all user principals.
+    #[cfg(feature = "stretched-seed")]
+    pub fn seed_stretching(mut self, stretching: SeedStretching) -> Self {
+        self.settings.seed_stretching = Some(stretching);
+        self
+    }
+
+    pub fn build(self) -> Result<Settings, SettingsError> {
         validate_domain(&self.settings.scheme, &self.settings.domain)?;
+        for domain in &self.settings.allowed_domains {
+            validate_domain(&self.settings.scheme, domain)?;
+        }
         validate_uri(&self.settings.uri)?;
+        for uri in &self.settings.allowed_uris {
+            validate_uri(uri)?;
+        }
         validate_salt(&self.settings.salt)?;
         validate_scheme(&self.settings.scheme)?;
         validate_statement(&self.settings.statement)?;
+        for statement in &self.settings.allowed_statements {
+            validate_statement(statement)?;
+        }
         validate_sign_in_expires_in(self.settings.sign_in_expires_in)?;
-        validate_session_expires_in(self.settings.session_expires_in)?;
+        validate_session_expires_in(
+            self.settings.session_expires_in,
+            self.settings.min_session_expires_in,
+        )?;
         validate_targets(&self.settings.targets)?;
-        validate_network(self.settings.network)?;
+        validate_allowed_networks(&self.settings.allowed_networks)?;
+        validate_expiration_rounding(
+            self.settings.expiration_rounding,
+            self.settings.session_expires_in,
+        )?;
+        validate_allowed_address_types(&self.settings.allowed_address_types)?;
+        validate_seed_version(self.settings.seed_version)?;
+        validate_max_issued_at_skew(self.settings.max_issued_at_skew)?;
+        validate_max_message_bytes(self.settings.max_message_bytes)?;
+        validate_clock_skew(self.settings.clock_skew)?;
+        validate_login_retry_window(self.settings.login_retry_window)?;
+        validate_message_prefixes(&self.settings.message_prefixes)?;
+        validate_message_hash_tag(&self.settings.message_hash_tag)?;
+        #[cfg(feature = "stretched-seed")]
+        validate_seed_stretching(&self.settings.seed_stretching)?;
         Ok(self.settings)
     }
 }
 
-fn validate_domain(scheme: &str, domain: &str) -> Result<String, String> {
+fn validate_domain(scheme: &str, domain: &str) -> Result<String, SettingsError> {
     let url_str = format!("{}://{}", scheme, domain);
-    let parsed_url = Url::parse(&url_str).map_err(|_| String::from("Invalid domain"))?;
+    let parsed_url = Url::parse(&url_str).map_err(|_| SettingsError::InvalidDomain)?;
     if !parsed_url.has_authority() {
-        Err(String::from("Invalid domain"))
+        Err(SettingsError::InvalidDomain)
     } else {
         Ok(parsed_url.host_str().unwrap().to_string())
     }
 }
 
-fn validate_uri(uri: &str) -> Result<String, String> {
-    let parsed_uri = Url::parse(uri).map_err(|_| String::from("Invalid URI"))?;
+fn validate_uri(uri: &str) -> Result<String, SettingsError> {
+    let parsed_uri = Url::parse(uri).map_err(|_| SettingsError::InvalidUri)?;
     if !parsed_uri.has_host() {
-        Err(String::from("Invalid URI"))
+        Err(SettingsError::InvalidUri)
     } else {
         Ok(uri.to_string())
     }
 }
 
-fn validate_salt(salt: &str) -> Result<String, String> {
+pub(crate) fn validate_salt(salt: &str) -> Result<String, SettingsError> {
     if salt.is_empty() {
-        return Err(String::from("Salt cannot be empty"));
+        return Err(SettingsError::EmptySalt);
     }
     // Salt can only contain printable ASCII characters
     if salt.chars().any(|c| !c.is_ascii() || !c.is_ascii_graphic()) {
-        return Err(String::from("Invalid salt"));
+        return Err(SettingsError::InvalidSalt);
     }
     Ok(salt.to_string())
 }
@@ -213,43 +740,140 @@ fn validate_salt(salt: &str) -> Result<String, String> {
 //     Ok(chain_id)
 // }
 
-fn validate_scheme(scheme: &str) -> Result<String, String> {
+fn validate_scheme(scheme: &str) -> Result<String, SettingsError> {
     if scheme == "http" || scheme == "https" {
         return Ok(scheme.to_string());
     }
-    Err(String::from("Invalid scheme"))
+    Err(SettingsError::InvalidScheme)
 }
 
-fn validate_statement(statement: &str) -> Result<String, String> {
+fn validate_statement(statement: &str) -> Result<String, SettingsError> {
     if statement.contains('\n') {
-        return Err(String::from("Invalid statement"));
+        return Err(SettingsError::InvalidStatement);
     }
     Ok(statement.to_string())
 }
 
-fn validate_sign_in_expires_in(expires_in: u64) -> Result<u64, String> {
+fn validate_sign_in_expires_in(expires_in: u64) -> Result<u64, SettingsError> {
     if expires_in == 0 {
-        return Err(String::from("Sign in expires in must be greater than 0"));
+        return Err(SettingsError::InvalidSignInExpiresIn);
+    }
+    if expires_in > MAX_SIGN_IN_EXPIRES_IN {
+        return Err(SettingsError::SignInExpiresInTooLong);
     }
     Ok(expires_in)
 }
 
-fn validate_session_expires_in(expires_in: u64) -> Result<u64, String> {
+pub(crate) fn validate_session_expires_in(
+    expires_in: u64,
+    min_expires_in: u64,
+) -> Result<u64, SettingsError> {
     if expires_in == 0 {
-        return Err(String::from("Session expires in must be greater than 0"));
+        return Err(SettingsError::InvalidSessionExpiresIn);
+    }
+    if expires_in > MAX_SESSION_EXPIRES_IN {
+        return Err(SettingsError::SessionExpiresInTooLong);
+    }
+    if expires_in < min_expires_in {
+        return Err(SettingsError::SessionExpiresInTooShort);
     }
     Ok(expires_in)
 }
 
-fn validate_targets(targets: &Option<Vec<Principal>>) -> Result<Option<Vec<Principal>>, String> {
+fn validate_expiration_rounding(
+    rounding: Option<u64>,
+    session_expires_in: u64,
+) -> Result<Option<u64>, SettingsError> {
+    if let Some(rounding) = rounding {
+        if rounding == 0 || rounding > session_expires_in {
+            return Err(SettingsError::InvalidExpirationRounding);
+        }
+    }
+    Ok(rounding)
+}
+
+fn validate_max_issued_at_skew(skew: u64) -> Result<u64, SettingsError> {
+    if skew > MAX_SIGN_IN_EXPIRES_IN {
+        return Err(SettingsError::IssuedAtSkewTooLong);
+    }
+    Ok(skew)
+}
+
+fn validate_clock_skew(skew: u64) -> Result<u64, SettingsError> {
+    if skew > MAX_CLOCK_SKEW {
+        return Err(SettingsError::ClockSkewTooLong);
+    }
+    Ok(skew)
+}
+
+fn validate_login_retry_window(window: u64) -> Result<u64, SettingsError> {
+    if window > MAX_LOGIN_RETRY_WINDOW {
+        return Err(SettingsError::LoginRetryWindowTooLong);
+    }
+    Ok(window)
+}
+
+fn validate_message_prefixes(prefixes: &BTreeMap<Network, String>) -> Result<(), SettingsError> {
+    if prefixes.values().any(|prefix| prefix.is_empty()) {
+        return Err(SettingsError::EmptyMessagePrefix);
+    }
+    Ok(())
+}
+
+fn validate_message_hash_tag(tag: &Option<String>) -> Result<(), SettingsError> {
+    if tag.as_deref() == Some("") {
+        return Err(SettingsError::EmptyMessageHashTag);
+    }
+    Ok(())
+}
+
+#[cfg(feature = "stretched-seed")]
+fn validate_seed_stretching(stretching: &Option<SeedStretching>) -> Result<(), SettingsError> {
+    if let Some(stretching) = stretching {
+        scrypt::Params::new(stretching.log_n, stretching.r, stretching.p)
+            .map_err(|_| SettingsError::InvalidSeedStretching)?;
+    }
+    Ok(())
+}
+
+fn validate_max_message_bytes(max_bytes: usize) -> Result<usize, SettingsError> {
+    if max_bytes == 0 {
+        return Err(SettingsError::InvalidMaxMessageBytes);
+    }
+    Ok(max_bytes)
+}
+
+fn validate_allowed_address_types(address_types: &[AddressType]) -> Result<(), SettingsError> {
+    let supported = [
+        AddressType::P2pkh,
+        AddressType::P2sh,
+        AddressType::P2wpkh,
+        AddressType::P2tr,
+    ];
+    if address_types.iter().any(|t| !supported.contains(t)) {
+        return Err(SettingsError::UnsupportedAddressType);
+    }
+    Ok(())
+}
+
+fn validate_seed_version(version: u8) -> Result<(), SettingsError> {
+    if !(DEFAULT_SEED_VERSION..=MAX_SEED_VERSION).contains(&version) {
+        return Err(SettingsError::UnsupportedSeedVersion);
+    }
+    Ok(())
+}
+
+fn validate_targets(
+    targets: &Option<Vec<Principal>>,
+) -> Result<Option<Vec<Principal>>, SettingsError> {
     if let Some(targets) = targets {
         if targets.is_empty() {
-            return Err(String::from("Targets cannot be empty"));
+            return Err(SettingsError::EmptyTargets);
         }
 
         // There is a limit of 1000 targets
         if targets.len() > 1000 {
-            return Err(String::from("Too many targets"));
+            return Err(SettingsError::TooManyTargets);
         }
 
         // Duplicate targets are not allowed
@@ -257,21 +881,29 @@ fn validate_targets(targets: &Option<Vec<Principal>>) -> Result<Option<Vec<Princ
         targets_clone.sort();
         targets_clone.dedup();
         if targets_clone.len() != targets.len() {
-            return Err(String::from("Duplicate targets are not allowed"));
+            return Err(SettingsError::DuplicateTargets);
         }
     }
     Ok(targets.clone())
 }
 
-fn validate_network(network: Network) -> Result<Network, String> {
+fn validate_allowed_networks(networks: &[Network]) -> Result<(), SettingsError> {
+    if networks.is_empty() {
+        return Err(SettingsError::NoAllowedNetworks);
+    }
+    for network in networks {
+        validate_network(*network)?;
+    }
+    Ok(())
+}
+
+fn validate_network(network: Network) -> Result<Network, SettingsError> {
     match network {
         Network::Bitcoin => Ok(Network::Bitcoin),
         Network::Testnet => Ok(Network::Testnet),
         Network::Regtest => Ok(Network::Regtest),
         Network::Signet => Ok(Network::Signet),
-        _ => {
-            return Err(String::from("Unrecognized Network"));
-        }
+        _ => Err(SettingsError::UnrecognizedNetwork),
     }
 }
 
@@ -295,8 +927,33 @@ mod tests {
         assert_eq!(settings.statement, DEFAULT_STATEMENT);
         assert_eq!(settings.sign_in_expires_in, DEFAULT_SIGN_IN_EXPIRES_IN);
         assert_eq!(settings.session_expires_in, DEFAULT_SESSION_EXPIRES_IN);
-        assert_eq!(settings.network, Bitcoin);
+        assert_eq!(
+            settings.min_session_expires_in,
+            DEFAULT_MIN_SESSION_EXPIRES_IN
+        );
+        assert_eq!(settings.network(), Bitcoin);
+        assert_eq!(settings.allowed_networks, vec![Bitcoin]);
         assert!(settings.targets.is_none());
+        assert!(settings.double_hash);
+        assert!(settings.expiration_rounding.is_none());
+        assert!(settings.allowed_address_types.is_empty());
+        assert_eq!(settings.seed_version, 1);
+        assert_eq!(settings.salt_generation, 0);
+        assert!(settings.allow_legacy_ecdsa);
+        assert_eq!(settings.max_issued_at_skew, DEFAULT_MAX_ISSUED_AT_SKEW);
+        assert_eq!(settings.max_message_bytes, DEFAULT_MAX_MESSAGE_BYTES);
+        assert_eq!(settings.clock_skew, 0);
+        assert_eq!(settings.login_retry_window, 0);
+        assert!(settings.message_prefixes.is_empty());
+        assert!(settings.message_hash_tag.is_none());
+        assert!(settings.allowed_session_keys.is_none());
+        assert!(settings.is_session_key_allowed(b"any key"));
+        assert!(settings.allowed_domains.is_empty());
+        assert!(settings.allowed_statements.is_empty());
+        assert!(settings.allowed_uris.is_empty());
+        assert!(!settings.strict_message_parsing);
+        assert!(!settings.strict_network);
+        assert!(!settings.bind_canister_id);
     }
 
     // Test successful settings creation with custom values
@@ -317,7 +974,7 @@ mod tests {
         assert_eq!(settings.statement, "Custom statement");
         assert_eq!(settings.sign_in_expires_in, 10_000_000_000);
         assert_eq!(settings.session_expires_in, 20_000_000_000);
-        assert_eq!(settings.network, Bitcoin);
+        assert_eq!(settings.network(), Bitcoin);
         assert_eq!(settings.targets, Some(targets));
     }
 
@@ -325,7 +982,7 @@ mod tests {
     #[test]
     fn test_empty_salt() {
         let builder = SettingsBuilder::new("example.com", "http://example.com", "");
-        assert!(builder.build().is_err());
+        assert_eq!(builder.build().unwrap_err(), SettingsError::EmptySalt);
     }
 
     // Test invalid chain ID
@@ -341,7 +998,7 @@ mod tests {
     fn test_invalid_scheme() {
         let builder =
             SettingsBuilder::new("example.com", "http://example.com", "some_salt").scheme("ftp");
-        assert!(builder.build().is_err());
+        assert_eq!(builder.build().unwrap_err(), SettingsError::InvalidScheme);
     }
 
     // Test invalid statement
@@ -349,7 +1006,7 @@ mod tests {
     fn test_invalid_statement() {
         let builder = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
             .statement("Invalid\nStatement");
-        assert!(builder.build().is_err());
+        assert_eq!(builder.build().unwrap_err(), SettingsError::InvalidStatement);
     }
 
     // Test sign in expires in is zero
@@ -357,7 +1014,10 @@ mod tests {
     fn test_sign_in_expires_in_zero() {
         let builder = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
             .sign_in_expires_in(0);
-        assert!(builder.build().is_err());
+        assert_eq!(
+            builder.build().unwrap_err(),
+            SettingsError::InvalidSignInExpiresIn
+        );
     }
 
     // Test session expires in is zero
@@ -365,7 +1025,262 @@ mod tests {
     fn test_session_expires_in_zero() {
         let builder = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
             .session_expires_in(0);
-        assert!(builder.build().is_err());
+        assert_eq!(
+            builder.build().unwrap_err(),
+            SettingsError::InvalidSessionExpiresIn
+        );
+    }
+
+    // Test expiration rounding bounds
+    #[test]
+    fn test_invalid_expiration_rounding() {
+        for rounding in [0, DEFAULT_SESSION_EXPIRES_IN + 1] {
+            let builder = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+                .expiration_rounding(rounding);
+            assert_eq!(
+                builder.build().unwrap_err(),
+                SettingsError::InvalidExpirationRounding,
+                "Should fail with rounding: {}",
+                rounding
+            );
+        }
+    }
+
+    // Test a max message length of zero
+    #[test]
+    fn test_invalid_max_message_bytes() {
+        let builder = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .max_message_bytes(0);
+        assert_eq!(builder.build().unwrap_err(), SettingsError::InvalidMaxMessageBytes);
+    }
+
+    // Test issued at skew above the maximum
+    #[test]
+    fn test_issued_at_skew_too_long() {
+        let builder = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .max_issued_at_skew(MAX_SIGN_IN_EXPIRES_IN + 1);
+        assert_eq!(builder.build().unwrap_err(), SettingsError::IssuedAtSkewTooLong);
+    }
+
+    // Test clock skew above the maximum
+    #[test]
+    fn test_clock_skew_too_long() {
+        let builder = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .clock_skew(MAX_CLOCK_SKEW + 1);
+        assert_eq!(builder.build().unwrap_err(), SettingsError::ClockSkewTooLong);
+        let builder = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .clock_skew(MAX_CLOCK_SKEW);
+        assert!(builder.build().is_ok());
+    }
+
+    // Test the login retry window above the maximum
+    #[test]
+    fn test_login_retry_window_too_long() {
+        let builder = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .login_retry_window(MAX_LOGIN_RETRY_WINDOW + 1);
+        assert_eq!(
+            builder.build().unwrap_err(),
+            SettingsError::LoginRetryWindowTooLong
+        );
+        let builder = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .login_retry_window(MAX_LOGIN_RETRY_WINDOW);
+        assert!(builder.build().is_ok());
+    }
+
+    // Test allowed domains besides the configured domain
+    #[test]
+    fn test_allowed_domains() {
+        let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .allowed_domains(vec!["app.example.com".to_string()])
+            .build()
+            .unwrap();
+        assert!(settings.is_domain_allowed("example.com"));
+        assert!(settings.is_domain_allowed("app.example.com"));
+        assert!(!settings.is_domain_allowed("evil.com"));
+
+        let builder = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .allowed_domains(vec!["not a domain".to_string()]);
+        assert_eq!(builder.build().unwrap_err(), SettingsError::InvalidDomain);
+    }
+
+    // Test allowed statements and URIs besides the configured ones
+    #[test]
+    fn test_allowed_statements_and_uris() {
+        let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .allowed_statements(vec!["Sign in to the beta".to_string()])
+            .allowed_uris(vec!["http://example.com/beta".to_string()])
+            .build()
+            .unwrap();
+        assert!(settings.is_statement_allowed(DEFAULT_STATEMENT));
+        assert!(settings.is_statement_allowed("Sign in to the beta"));
+        assert!(!settings.is_statement_allowed("Send all your coins"));
+        assert!(settings.is_uri_allowed("http://example.com"));
+        assert!(settings.is_uri_allowed("http://example.com/beta"));
+        assert!(!settings.is_uri_allowed("http://evil.com"));
+
+        let builder = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .allowed_statements(vec!["Two\nlines".to_string()]);
+        assert_eq!(builder.build().unwrap_err(), SettingsError::InvalidStatement);
+        let builder = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .allowed_uris(vec!["not a uri".to_string()]);
+        assert_eq!(builder.build().unwrap_err(), SettingsError::InvalidUri);
+    }
+
+    #[test]
+    fn test_allowed_networks() {
+        let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .allowed_networks(vec![Network::Testnet, Network::Bitcoin, Network::Signet])
+            .build()
+            .unwrap();
+        assert_eq!(settings.network(), Network::Testnet);
+
+        let mainnet = Address::from_str("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq")
+            .unwrap()
+            .assume_checked();
+        let testnet = Address::from_str("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx")
+            .unwrap()
+            .assume_checked();
+        let regtest = Address::from_str("bcrt1q6rhpng9evdsfnn833a4f4vej0asu6dk5srld6x")
+            .unwrap()
+            .assume_checked();
+        assert_eq!(settings.address_network(&mainnet), Some(Network::Bitcoin));
+        // Signet addresses are testnet addresses, the network allowed first wins.
+        assert_eq!(settings.address_network(&testnet), Some(Network::Testnet));
+        assert_eq!(settings.address_network(&regtest), None);
+        assert_eq!(settings.network_for(&regtest), Network::Testnet);
+
+        let builder = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .allowed_networks(vec![]);
+        assert_eq!(
+            builder.build().unwrap_err(),
+            SettingsError::NoAllowedNetworks
+        );
+    }
+
+    // Test per-network message prefixes
+    #[test]
+    fn test_message_prefixes() {
+        let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .message_prefix(Network::Testnet, "Testnet Signed Message:\n")
+            .message_prefix(Network::Regtest, "Regtest Signed Message:\n")
+            .build()
+            .unwrap();
+        assert_eq!(settings.message_prefixes.len(), 2);
+        assert_eq!(
+            settings.message_prefixes[&Network::Testnet],
+            "Testnet Signed Message:\n"
+        );
+
+        assert_eq!(settings.message_prefix(Network::Bitcoin), MAGIC_BYTES);
+        assert_eq!(
+            settings.message_prefix(Network::Regtest),
+            "Regtest Signed Message:\n"
+        );
+
+        let builder = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .message_prefix(Network::Testnet, "");
+        assert_eq!(
+            builder.build().unwrap_err(),
+            SettingsError::EmptyMessagePrefix
+        );
+    }
+
+    // Test the session key allowlist
+    #[test]
+    fn test_allowed_session_keys() {
+        let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .allowed_session_keys(vec![b"kiosk 1".to_vec(), b"kiosk 2".to_vec()])
+            .build()
+            .unwrap();
+        assert!(settings.is_session_key_allowed(b"kiosk 1"));
+        assert!(settings.is_session_key_allowed(b"kiosk 2"));
+        assert!(!settings.is_session_key_allowed(b"kiosk 3"));
+
+        let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .allowed_session_keys(vec![])
+            .build()
+            .unwrap();
+        assert!(!settings.is_session_key_allowed(b"kiosk 1"));
+    }
+
+    // Test the message hash tag
+    #[test]
+    fn test_message_hash_tag() {
+        let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .message_hash_tag("SIWB/message")
+            .build()
+            .unwrap();
+        assert_eq!(settings.message_hash_tag.as_deref(), Some("SIWB/message"));
+
+        let builder = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .message_hash_tag("");
+        assert_eq!(
+            builder.build().unwrap_err(),
+            SettingsError::EmptyMessageHashTag
+        );
+    }
+
+    // Test session expires in below the minimum
+    #[test]
+    fn test_session_expires_in_too_short() {
+        let builder = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .session_expires_in(60_000_000_000)
+            .min_session_expires_in(60_000_000_001);
+        assert_eq!(
+            builder.build().unwrap_err(),
+            SettingsError::SessionExpiresInTooShort
+        );
+        let builder = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .session_expires_in(60_000_000_000)
+            .min_session_expires_in(60_000_000_000);
+        assert!(builder.build().is_ok());
+    }
+
+    // Test clamping per-call session TTL overrides
+    #[test]
+    fn test_clamp_session_expires_in() {
+        let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .session_expires_in(60_000_000_000)
+            .min_session_expires_in(10_000_000_000)
+            .build()
+            .unwrap();
+        assert_eq!(settings.clamp_session_expires_in(1), 10_000_000_000);
+        assert_eq!(
+            settings.clamp_session_expires_in(20_000_000_000),
+            20_000_000_000
+        );
+        assert_eq!(settings.clamp_session_expires_in(u64::MAX), 60_000_000_000);
+    }
+
+    // Test invalid seed stretching parameters
+    #[cfg(feature = "stretched-seed")]
+    #[test]
+    fn test_invalid_seed_stretching() {
+        let builder = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .seed_stretching(SeedStretching {
+                log_n: 14,
+                r: 0,
+                p: 1,
+            });
+        assert_eq!(builder.build().unwrap_err(), SettingsError::InvalidSeedStretching);
+    }
+
+    // Test allowed address types that cannot be used to sign in
+    #[test]
+    fn test_unsupported_allowed_address_types() {
+        let builder = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .allowed_address_types(vec![AddressType::P2tr, AddressType::P2wsh]);
+        assert_eq!(builder.build().unwrap_err(), SettingsError::UnsupportedAddressType);
+    }
+
+    // Test seed versions outside the supported range
+    #[test]
+    fn test_unsupported_seed_version() {
+        for version in [0, MAX_SEED_VERSION + 1] {
+            let builder = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+                .seed_version(version);
+            assert_eq!(builder.build().unwrap_err(), SettingsError::UnsupportedSeedVersion);
+        }
     }
 
     // Test empty targets
@@ -373,7 +1288,7 @@ mod tests {
     fn test_empty_targets() {
         let builder =
             SettingsBuilder::new("example.com", "http://example.com", "some_salt").targets(vec![]);
-        assert!(builder.build().is_err());
+        assert_eq!(builder.build().unwrap_err(), SettingsError::EmptyTargets);
     }
 
     // Test too many targets
@@ -381,7 +1296,7 @@ mod tests {
     fn test_too_many_targets() {
         let builder = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
             .targets(vec![Principal::anonymous(); 1001]);
-        assert!(builder.build().is_err());
+        assert_eq!(builder.build().unwrap_err(), SettingsError::TooManyTargets);
     }
 
     // Test duplicate targets
@@ -389,7 +1304,7 @@ mod tests {
     fn test_duplicate_targets() {
         let builder = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
             .targets(vec![Principal::anonymous(), Principal::anonymous()]);
-        assert!(builder.build().is_err());
+        assert_eq!(builder.build().unwrap_err(), SettingsError::DuplicateTargets);
     }
 
     // Test various valid domain formats
@@ -408,8 +1323,9 @@ mod tests {
         let domains = vec![""];
         for domain in domains {
             let builder = SettingsBuilder::new(domain, "http://example.com", "some_salt");
-            assert!(
-                builder.build().is_err(),
+            assert_eq!(
+                builder.build().unwrap_err(),
+                SettingsError::InvalidDomain,
                 "Should fail with domain: {}",
                 domain
             );
@@ -432,7 +1348,12 @@ mod tests {
         let uris = vec!["", "just_string"];
         for uri in uris {
             let builder = SettingsBuilder::new("example.com", uri, "some_salt");
-            assert!(builder.build().is_err(), "Should fail with URI: {}", uri);
+            assert_eq!(
+                builder.build().unwrap_err(),
+                SettingsError::InvalidUri,
+                "Should fail with URI: {}",
+                uri
+            );
         }
     }
 
@@ -482,10 +1403,36 @@ mod tests {
     fn test_extreme_expiration_values() {
         let builder = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
             .sign_in_expires_in(1)
-            .session_expires_in(u64::MAX);
+            .session_expires_in(MAX_SESSION_EXPIRES_IN);
+        assert!(builder.build().is_ok());
+        let builder = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .sign_in_expires_in(MAX_SIGN_IN_EXPIRES_IN)
+            .session_expires_in(1);
         assert!(builder.build().is_ok());
     }
 
+    // Test sign in expires in above the maximum
+    #[test]
+    fn test_sign_in_expires_in_too_long() {
+        let builder = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .sign_in_expires_in(MAX_SIGN_IN_EXPIRES_IN + 1);
+        assert_eq!(
+            builder.build().unwrap_err(),
+            SettingsError::SignInExpiresInTooLong
+        );
+    }
+
+    // Test session expires in above the maximum
+    #[test]
+    fn test_session_expires_in_too_long() {
+        let builder = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .session_expires_in(u64::MAX);
+        assert_eq!(
+            builder.build().unwrap_err(),
+            SettingsError::SessionExpiresInTooLong
+        );
+    }
+
     // Test Targets with Various Principal Formats
     #[test]
     fn test_targets_with_various_principal_formats() {
@@ -535,7 +1482,7 @@ mod tests {
     #[test]
     fn test_invalid_salt_content() {
         let builder = SettingsBuilder::new("example.com", "http://example.com", "\0invalid_salt");
-        assert!(builder.build().is_err());
+        assert_eq!(builder.build().unwrap_err(), SettingsError::InvalidSalt);
     }
 
     // Test Invalid Statement Formats
@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use bitcoin::Address;
+use candid::{CandidType, Deserialize};
+
+use crate::error::BtcError;
+use crate::login::{check_address_network, check_message_length};
+use crate::settings::Settings;
+use crate::time::get_current_time;
+use crate::verify::{
+    _verify_message_for_address, is_schnorr_key_spend, verify_address_for_network,
+};
+use crate::{with_settings, SIGN_PAYLOADS};
+
+#[derive(Debug)]
+pub enum SignError {
+    PayloadNotFound,
+    BtcError(BtcError),
+}
+
+impl From<BtcError> for SignError {
+    fn from(err: BtcError) -> Self {
+        SignError::BtcError(err)
+    }
+}
+
+impl fmt::Display for SignError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignError::PayloadNotFound => write!(f, "Payload not found"),
+            SignError::BtcError(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<SignError> for String {
+    fn from(error: SignError) -> Self {
+        error.to_string()
+    }
+}
+
+/// A payload that a user has been asked to sign, e.g. a JSON or CBOR document. Unlike a [crate::siwb::SiwbMessage],
+/// the payload is signed verbatim and signing it does not log the user in.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SignPayload {
+    pub payload: String,
+
+    /// The time, in nanoseconds since the UNIX epoch, after which the payload can no longer be signed.
+    pub expiration_time: u64,
+}
+
+impl SignPayload {
+    pub fn is_expired(&self) -> bool {
+        get_current_time() >= self.expiration_time
+    }
+}
+
+/// The SignPayloadMap is a map of payloads keyed by the script pubkey of the address that is asked to sign
+/// them. Payloads are removed once a signature over them has been verified or once they expire.
+#[derive(Default)]
+pub struct SignPayloadMap {
+    map: HashMap<Vec<u8>, SignPayload>,
+}
+
+impl SignPayloadMap {
+    /// Removes payloads that have exceeded their time to live.
+    pub fn prune_expired(&mut self) {
+        self.map.retain(|_, payload| !payload.is_expired());
+    }
+
+    pub fn insert(&mut self, address_bytes: Vec<u8>, payload: SignPayload) {
+        self.map.insert(address_bytes, payload);
+    }
+
+    pub fn get(&self, address_bytes: &[u8]) -> Option<SignPayload> {
+        self.map.get(address_bytes).cloned()
+    }
+
+    pub fn remove(&mut self, address_bytes: &[u8]) {
+        self.map.remove(address_bytes);
+    }
+}
+
+/// Stores `payload` as the payload that `address` is asked to sign, replacing any payload that was
+/// prepared before. The payload expires after the configured `sign_in_expires_in`.
+///
+/// # Returns
+/// The stored [SignPayload], or [BtcError::MalformedMessage] if `payload` is blank. With `strict_network`
+/// enabled, addresses of other networks are rejected with [BtcError::NetworkMismatch].
+pub fn prepare_sign(address: &Address, payload: String) -> Result<SignPayload, BtcError> {
+    if payload.trim().is_empty() {
+        return Err(BtcError::MalformedMessage("payload is empty".to_string()));
+    }
+    check_message_length(&payload)?;
+    check_address_network(address)?;
+
+    let sign_payload = with_settings!(|settings: &Settings| SignPayload {
+        payload,
+        expiration_time: get_current_time().saturating_add(settings.sign_in_expires_in),
+    });
+
+    SIGN_PAYLOADS.with_borrow_mut(|payloads| {
+        payloads.prune_expired();
+        payloads.insert(address.script_pubkey().to_bytes(), sign_payload.clone());
+    });
+
+    Ok(sign_payload)
+}
+
+/// Verifies that `signature` is a signature by `address` over the payload prepared with [prepare_sign].
+/// Signatures are verified like legacy message signatures during login, including Taproot Schnorr
+/// signatures and the `double_hash`, `allow_legacy_ecdsa`, `message_hash_tag` and message prefix settings. On success the payload is removed,
+/// so each prepared payload can only be verified once.
+///
+/// # Returns
+/// The payload that was signed, or [SignError::PayloadNotFound] if no payload was prepared for `address`
+/// or it has expired.
+pub fn verify_signed_payload(
+    address: &Address,
+    signature: &str,
+    public_key: &str,
+) -> Result<String, SignError> {
+    SIGN_PAYLOADS.with_borrow_mut(|payloads| {
+        payloads.prune_expired();
+
+        let address_bytes = address.script_pubkey().to_bytes();
+        let sign_payload = payloads
+            .get(&address_bytes)
+            .ok_or(SignError::PayloadNotFound)?;
+
+        let address_string = address.to_string();
+        let (double_hash, network, allow_legacy_ecdsa, prefix, tag) =
+            with_settings!(|settings: &Settings| (
+                settings.double_hash,
+                settings.network_for(address),
+                settings.allow_legacy_ecdsa,
+                settings
+                    .message_prefix(settings.network_for(address))
+                    .to_string(),
+                settings.message_hash_tag.clone()
+            ));
+        if !allow_legacy_ecdsa && !is_schnorr_key_spend(signature, &address_string) {
+            return Err(BtcError::LegacyEcdsaDisabled.into());
+        }
+
+        let public_key_bytes = _verify_message_for_address(
+            sign_payload.payload.clone(),
+            signature.to_string(),
+            public_key.to_string(),
+            double_hash,
+            &prefix,
+            tag.as_deref(),
+            &address_string,
+            allow_legacy_ecdsa,
+            None,
+        )?;
+        verify_address_for_network(&address_string, public_key_bytes, network)?;
+
+        payloads.remove(&address_bytes);
+        Ok(sign_payload.payload)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::SettingsBuilder;
+    use crate::time::{advance_test_time, set_test_time};
+    use crate::verify::_msg_hash_with;
+    use crate::SETTINGS;
+    use base64::engine::general_purpose;
+    use base64::Engine;
+    use bitcoin::{Network, PublicKey as BitcoinPublicKey};
+    use k256::ecdsa::SigningKey;
+
+    const PAYLOAD: &str = r#"{"a":1,"b":"two","c":[3]}"#;
+
+    fn init() -> (SigningKey, Address) {
+        let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .build()
+            .unwrap();
+        SETTINGS.set(Some(settings));
+
+        let key = SigningKey::from_bytes(&[1; 32].into()).unwrap();
+        let public_key =
+            BitcoinPublicKey::from_slice(key.verifying_key().to_encoded_point(true).as_bytes())
+                .unwrap();
+        let address = Address::p2wpkh(&public_key, Network::Bitcoin).unwrap();
+        (key, address)
+    }
+
+    fn public_key_hex(key: &SigningKey) -> String {
+        hex::encode(key.verifying_key().to_encoded_point(true).as_bytes())
+    }
+
+    fn sign(key: &SigningKey, payload: &str) -> String {
+        let hash = _msg_hash_with(payload.to_string(), true);
+        let (signature, recovery_id) = key.sign_prehash_recoverable(&hash).unwrap();
+        let mut bytes = vec![27 + 4 + recovery_id.to_byte()];
+        bytes.extend_from_slice(&signature.to_bytes());
+        general_purpose::STANDARD.encode(bytes)
+    }
+
+    #[test]
+    fn test_verify_signed_json_payload() {
+        let (key, address) = init();
+        let prepared = prepare_sign(&address, PAYLOAD.to_string()).unwrap();
+        assert_eq!(prepared.payload, PAYLOAD);
+
+        let signature = sign(&key, PAYLOAD);
+        let payload = verify_signed_payload(&address, &signature, &public_key_hex(&key)).unwrap();
+        assert_eq!(payload, PAYLOAD);
+
+        // A payload can only be verified once.
+        assert!(matches!(
+            verify_signed_payload(&address, &signature, &public_key_hex(&key)),
+            Err(SignError::PayloadNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_verify_signature_over_other_payload() {
+        let (key, address) = init();
+        prepare_sign(&address, PAYLOAD.to_string()).unwrap();
+
+        let signature = sign(&key, r#"{"a":2,"b":"two","c":[3]}"#);
+        assert!(matches!(
+            verify_signed_payload(&address, &signature, &public_key_hex(&key)),
+            Err(SignError::BtcError(BtcError::InvalidSignature))
+        ));
+
+        // The payload is kept, so the user can still sign it.
+        let signature = sign(&key, PAYLOAD);
+        assert!(verify_signed_payload(&address, &signature, &public_key_hex(&key)).is_ok());
+    }
+
+    #[test]
+    fn test_verify_unprepared_or_expired_payload() {
+        let (key, address) = init();
+        let signature = sign(&key, PAYLOAD);
+        assert!(matches!(
+            verify_signed_payload(&address, &signature, &public_key_hex(&key)),
+            Err(SignError::PayloadNotFound)
+        ));
+
+        set_test_time(1_000_000_000);
+        let prepared = prepare_sign(&address, PAYLOAD.to_string()).unwrap();
+        advance_test_time(prepared.expiration_time - 1_000_000_000);
+        assert!(matches!(
+            verify_signed_payload(&address, &signature, &public_key_hex(&key)),
+            Err(SignError::PayloadNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_prepare_sign_empty_payload() {
+        let (_, address) = init();
+        assert!(matches!(
+            prepare_sign(&address, " ".to_string()),
+            Err(BtcError::MalformedMessage(_))
+        ));
+    }
+}
@@ -103,6 +103,23 @@ impl SignatureMap {
         }
     }
 
+    /// Returns true if at least one delegation signature is stored for `seed_hash`.
+    pub fn contains(&self, seed_hash: Hash) -> bool {
+        self.certified_map.get(&seed_hash[..]).is_some()
+    }
+
+    /// Returns the number of delegation hashes stored, across all seeds.
+    pub fn len(&self) -> usize {
+        self.certified_map
+            .iter()
+            .map(|(_, delegations)| delegations.iter().count())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.certified_map.iter().next().is_none()
+    }
+
     pub fn root_hash(&self) -> Hash {
         self.certified_map.root_hash()
     }
@@ -121,6 +138,7 @@ impl SignatureMap {
 #[cfg(test)]
 mod signature_map_tests {
     use super::*;
+    use crate::time::set_test_time;
 
     // Utility function to create a random hash for testing
     fn random_hash() -> Hash {
@@ -170,6 +188,25 @@ mod signature_map_tests {
         assert_eq!(pruned, 1);
     }
 
+    #[test]
+    fn test_prune_expired_boundary() {
+        set_test_time(1_000_000_000);
+        let mut map = SignatureMap::default();
+        map.put(random_hash(), random_hash());
+        let expires_at = 1_000_000_000 + DELEGATION_SIGNATURE_EXPIRES_AT;
+        assert_eq!(map.prune_expired(expires_at - 1, 10), 0);
+        assert_eq!(map.prune_expired(expires_at, 10), 1);
+    }
+
+    #[test]
+    fn test_contains() {
+        let mut signature_map = SignatureMap::default();
+        let seed_hash = random_hash();
+        assert!(!signature_map.contains(seed_hash));
+        signature_map.put(seed_hash, random_hash());
+        assert!(signature_map.contains(seed_hash));
+    }
+
     #[test]
     fn test_root_hash() {
         let mut map = SignatureMap::default();
@@ -180,6 +217,25 @@ mod signature_map_tests {
         assert_ne!(hash, Hash::default());
     }
 
+    #[test]
+    fn test_len_and_root_hash_after_put() {
+        let mut map = SignatureMap::default();
+        assert!(map.is_empty());
+        let empty_root = map.root_hash();
+
+        let seed_hash = random_hash();
+        map.put(seed_hash, random_hash());
+        let root = map.root_hash();
+        assert_ne!(root, empty_root);
+        assert_eq!(map.len(), 1);
+
+        // A second delegation for the same seed is counted separately.
+        map.put(seed_hash, random_hash());
+        assert_ne!(map.root_hash(), root);
+        assert_eq!(map.len(), 2);
+        assert!(!map.is_empty());
+    }
+
     #[test]
     fn test_witness_existing() {
         let mut map = SignatureMap::default();
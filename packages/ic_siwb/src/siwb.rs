@@ -1,24 +1,53 @@
+use crate::error::BtcError;
+use crate::hash::hash_bytes;
 use crate::settings::Settings;
 use crate::with_settings;
 use crate::{rand::generate_nonce, time::get_current_time};
 
 use bitcoin::Address;
 use candid::{CandidType, Deserialize};
+use ic_certified_map::Hash;
 use serde::Serialize;
 use std::collections::HashMap;
 use std::fmt;
+use std::str::FromStr;
 use time::format_description::well_known::Rfc3339;
 use time::OffsetDateTime;
 
+/// The fields rendered after the statement of a SIWB message, see [SiwbMessage::parse].
+const MESSAGE_FIELDS: [&str; 9] = [
+    "URI",
+    "Version",
+    "Network",
+    "Nonce",
+    "Issued At",
+    "Expiration Time",
+    "Session Expiration Time",
+    "Canister ID",
+    "Request ID",
+];
+
 #[derive(Debug)]
 pub enum SiwbMessageError {
     MessageNotFound,
+    MessageMismatch,
+    /// The challenge token cannot be parsed, or belongs to another challenge than the one pending.
+    InvalidChallengeToken,
+    /// The challenge token has expired, the user has to sign a newly prepared message.
+    ChallengeExpired,
 }
 
 impl fmt::Display for SiwbMessageError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             SiwbMessageError::MessageNotFound => write!(f, "Message not found"),
+            SiwbMessageError::MessageMismatch => {
+                write!(f, "Message does not match the prepared message")
+            }
+            SiwbMessageError::InvalidChallengeToken => write!(f, "Invalid challenge token"),
+            SiwbMessageError::ChallengeExpired => {
+                write!(f, "Challenge has expired, prepare a new message")
+            }
         }
     }
 }
@@ -29,6 +58,51 @@ impl From<SiwbMessageError> for String {
     }
 }
 
+/// A short-lived token for the challenge, i.e. the SIWB message, prepared for an address. It embeds
+/// the expiration time and the nonce of the message as `<expiration_time>.<nonce>`, the expiration time
+/// in nanoseconds since the UNIX epoch, so that frontends can tell when to prepare a new message before
+/// the user signs an expired one. See [crate::login::check_challenge_token].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChallengeToken {
+    pub expiration_time: u64,
+    pub nonce: String,
+}
+
+impl ChallengeToken {
+    /// Whether the challenge has expired, allowing for the configured `clock_skew` like
+    /// [SiwbMessage::is_expired].
+    pub fn is_expired(&self) -> bool {
+        let clock_skew = with_settings!(|settings: &Settings| settings.clock_skew);
+        get_current_time() >= self.expiration_time.saturating_add(clock_skew)
+    }
+}
+
+impl fmt::Display for ChallengeToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.expiration_time, self.nonce)
+    }
+}
+
+impl FromStr for ChallengeToken {
+    type Err = SiwbMessageError;
+
+    fn from_str(token: &str) -> Result<Self, Self::Err> {
+        let (expiration_time, nonce) = token
+            .split_once('.')
+            .ok_or(SiwbMessageError::InvalidChallengeToken)?;
+        let expiration_time = expiration_time
+            .parse()
+            .map_err(|_| SiwbMessageError::InvalidChallengeToken)?;
+        if nonce.is_empty() {
+            return Err(SiwbMessageError::InvalidChallengeToken);
+        }
+        Ok(ChallengeToken {
+            expiration_time,
+            nonce: nonce.to_string(),
+        })
+    }
+}
+
 /// Represents a SIWB (Sign-In With Bitcoin) message.
 ///
 /// This struct and its implementation methods support all required fields in the [ERC-4361](https://eips.ethereum.org/EIPS/eip-4361)
@@ -46,11 +120,15 @@ impl From<SiwbMessageError> for String {
 ///
 /// URI: http://127.0.0.1:5173
 /// Version: 1
-/// Chain ID: 10
+/// Network: bitcoin
 /// Nonce: ee1ee5ead5b55fe8c8e9
 /// Issued At: 2021-05-06T19:17:10Z
 /// Expiration Time: 2021-05-06T19:17:13Z
+/// Session Expiration Time: 2021-05-14T19:17:10Z
 /// ```
+///
+/// When the message carries a `canister_id` or a `request_id`, they are rendered as final `Canister ID: ...`
+/// and `Request ID: ...` lines.
 #[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
 pub struct SiwbMessage {
     pub scheme: String,
@@ -63,6 +141,14 @@ pub struct SiwbMessage {
     pub nonce: String,
     pub issued_at: u64,
     pub expiration_time: u64,
+
+    /// The principal of the canister the message was prepared by, when the `bind_canister_id` setting is
+    /// enabled. The message can then only be used to log in at that canister.
+    pub canister_id: Option<String>,
+
+    /// An optional id chosen by the frontend to correlate the login with a server-side request, the
+    /// EIP-4361 `requestId`. It is returned unchanged in the [crate::login::LoginDetails].
+    pub request_id: Option<String>,
 }
 
 impl SiwbMessage {
@@ -77,9 +163,26 @@ impl SiwbMessage {
     ///
     /// A `Result` that, on success, contains a new [`SiwbMessage`] instance.
     pub fn new(address: &Address) -> SiwbMessage {
-        let nonce = generate_nonce();
-        let current_time = get_current_time();
-        with_settings!(|settings: &Settings| {
+        Self::new_with_issued_at(address, get_current_time())
+    }
+
+    /// Like [SiwbMessage::new], but the message is issued at `issued_at` (nanoseconds since the UNIX
+    /// epoch) instead of the current time. The caller is responsible for bounding `issued_at`.
+    ///
+    /// Traps if the nonce generator has not been seeded yet, see [SiwbMessage::try_new_with_issued_at].
+    pub fn new_with_issued_at(address: &Address, issued_at: u64) -> SiwbMessage {
+        Self::try_new_with_issued_at(address, issued_at).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Like [SiwbMessage::new_with_issued_at], but returns [BtcError::RngNotSeeded] instead of trapping
+    /// if the message needs a nonce and the nonce generator has not been seeded from `raw_rand` yet, e.g.
+    /// right after the canister has been installed or upgraded.
+    pub fn try_new_with_issued_at(
+        address: &Address,
+        issued_at: u64,
+    ) -> Result<SiwbMessage, BtcError> {
+        let nonce = generate_nonce()?;
+        Ok(with_settings!(|settings: &Settings| {
             SiwbMessage {
                 scheme: settings.scheme.clone(),
                 domain: settings.domain.clone(),
@@ -87,22 +190,199 @@ impl SiwbMessage {
                 statement: settings.statement.clone(),
                 uri: settings.uri.clone(),
                 version: 1,
-                network: settings.network.to_string(),
+                network: settings.network_for(address).to_string(),
                 nonce,
-                issued_at: get_current_time(),
-                expiration_time: current_time.saturating_add(settings.sign_in_expires_in),
+                issued_at,
+                expiration_time: issued_at.saturating_add(settings.sign_in_expires_in),
+                canister_id: None,
+                request_id: None,
+            }
+        }))
+    }
+
+    /// The time, in nanoseconds since the UNIX epoch, at which the session created by signing in with
+    /// this message expires: `issued_at` plus the configured `session_expires_in`, rounded down to the
+    /// configured `expiration_rounding`. This is the expiration of the delegation created by
+    /// [crate::login::login] and the value rendered into the message.
+    pub fn expires_at(&self) -> u64 {
+        with_settings!(|settings: &Settings| {
+            let expires_at = self.issued_at.saturating_add(settings.session_expires_in);
+            match settings.expiration_rounding {
+                Some(rounding) => expires_at - expires_at % rounding,
+                None => expires_at,
             }
         })
     }
 
-    /// Checks if the SIWB message is currently valid.
+    /// Like [SiwbMessage::expires_at], but for a session the client asked to end after at most
+    /// `max_time_to_live` nanoseconds. The requested TTL is clamped to the configured range, see
+    /// [Settings::clamp_session_expires_in]. Expiration rounding is not applied, as it could end a
+    /// short session before it starts.
+    pub fn expires_at_with_time_to_live(&self, max_time_to_live: u64) -> u64 {
+        with_settings!(|settings: &Settings| self
+            .issued_at
+            .saturating_add(settings.clamp_session_expires_in(max_time_to_live)))
+    }
+
+    /// The [ChallengeToken] of this message, to be handed to the frontend along with the message.
+    pub fn challenge_token(&self) -> ChallengeToken {
+        ChallengeToken {
+            expiration_time: self.expiration_time,
+            nonce: self.nonce.clone(),
+        }
+    }
+
+    /// Checks if the SIWB message has exceeded its time to live.
     ///
     /// # Returns
     ///
-    /// `true` if the message is within its valid time period, `false` otherwise.
+    /// `true` once the current time has reached the message expiration time plus the configured
+    /// `clock_skew`, `false` otherwise.
     pub fn is_expired(&self) -> bool {
-        let current_time = get_current_time();
-        self.issued_at < current_time || current_time > self.expiration_time
+        let clock_skew = with_settings!(|settings: &Settings| settings.clock_skew);
+        get_current_time() >= self.expiration_time.saturating_add(clock_skew)
+    }
+
+    /// Checks that the message has a non-blank statement, address and domain and an issue time, so
+    /// that wallets never show the user an empty message to sign. A `request_id` must be non-blank and
+    /// must not contain whitespace or control characters, so that it cannot add lines to the message.
+    ///
+    /// # Returns
+    ///
+    /// [BtcError::MalformedMessage] naming the first missing field.
+    pub fn validate(&self) -> Result<(), BtcError> {
+        let required = [
+            ("domain", &self.domain),
+            ("address", &self.address),
+            ("statement", &self.statement),
+        ];
+        for (field, value) in required {
+            if value.trim().is_empty() {
+                return Err(BtcError::MalformedMessage(format!("{} is empty", field)));
+            }
+        }
+        if let Some(request_id) = &self.request_id {
+            if request_id.is_empty()
+                || request_id
+                    .chars()
+                    .any(|c| c.is_whitespace() || c.is_control())
+            {
+                return Err(BtcError::MalformedMessage(
+                    "request_id is invalid".to_string(),
+                ));
+            }
+        }
+        if self.issued_at == 0 {
+            return Err(BtcError::MalformedMessage(
+                "issued_at is not set".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Serializes the message to CBOR, for consumers that do not use Candid.
+    ///
+    /// The encoding is a map keyed by the field names of [SiwbMessage]. Timestamps and the version are
+    /// unsigned integers, all other fields are text strings.
+    pub fn to_cbor(&self) -> Result<Vec<u8>, serde_cbor::Error> {
+        serde_cbor::to_vec(self)
+    }
+
+    /// Deserializes a message from the CBOR encoding produced by [SiwbMessage::to_cbor].
+    pub fn from_cbor(bytes: &[u8]) -> Result<Self, serde_cbor::Error> {
+        serde_cbor::from_slice(bytes)
+    }
+
+    /// Parses a message in the [ERC-4361](https://eips.ethereum.org/EIPS/eip-4361) string format rendered
+    /// by the canister, e.g. to check the nonce or domain of a signed message. The scheme is not rendered
+    /// and is taken from the settings, the `Session Expiration Time` is derived from the settings and is
+    /// not parsed.
+    ///
+    /// Lines that are not fields rendered by the canister are ignored, and of duplicate fields the first
+    /// is used. With `strict_message_parsing` enabled, such lines are rejected instead, so that fields
+    /// injected into the message cannot go unnoticed.
+    ///
+    /// # Returns
+    ///
+    /// The parsed [SiwbMessage], or [BtcError::MalformedMessage] naming the offending part.
+    pub fn parse(message: &str) -> Result<SiwbMessage, BtcError> {
+        let (scheme, strict) = with_settings!(|settings: &Settings| (
+            settings.scheme.clone(),
+            settings.strict_message_parsing
+        ));
+
+        let mut parts = message.splitn(3, "\n\n");
+        let (header, statement, fields) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(header), Some(statement), Some(fields)) => (header, statement, fields),
+            _ => {
+                return Err(BtcError::MalformedMessage(
+                    "statement or fields are missing".to_string(),
+                ))
+            }
+        };
+        let (domain, address) = header
+            .split_once(" wants you to sign in with your Bitcoin account:\n")
+            .filter(|(_, address)| !address.contains('\n'))
+            .ok_or_else(|| BtcError::MalformedMessage("header is invalid".to_string()))?;
+
+        let mut values: HashMap<&str, &str> = HashMap::new();
+        for line in fields.lines() {
+            match line
+                .split_once(": ")
+                .filter(|(key, _)| MESSAGE_FIELDS.contains(key))
+            {
+                Some((key, value)) => {
+                    if values.contains_key(key) {
+                        if strict {
+                            return Err(BtcError::MalformedMessage(format!(
+                                "{} is duplicated",
+                                key
+                            )));
+                        }
+                    } else {
+                        values.insert(key, value);
+                    }
+                }
+                None if strict => {
+                    return Err(BtcError::MalformedMessage(format!(
+                        "unexpected field: {}",
+                        line
+                    )))
+                }
+                None => {}
+            }
+        }
+
+        let field = |key: &str| {
+            values
+                .get(key)
+                .copied()
+                .ok_or_else(|| BtcError::MalformedMessage(format!("{} is missing", key)))
+        };
+        let invalid = |key: &str| BtcError::MalformedMessage(format!("{} is invalid", key));
+        let timestamp = |key: &str| {
+            OffsetDateTime::parse(field(key)?, &Rfc3339)
+                .ok()
+                .and_then(|datetime| u64::try_from(datetime.unix_timestamp_nanos()).ok())
+                .ok_or_else(|| invalid(key))
+        };
+
+        let message = SiwbMessage {
+            scheme,
+            domain: domain.to_string(),
+            address: address.to_string(),
+            statement: statement.to_string(),
+            uri: field("URI")?.to_string(),
+            version: field("Version")?.parse().map_err(|_| invalid("Version"))?,
+            network: field("Network")?.to_string(),
+            nonce: field("Nonce")?.to_string(),
+            issued_at: timestamp("Issued At")?,
+            expiration_time: timestamp("Expiration Time")?,
+            canister_id: values.get("Canister ID").map(|value| value.to_string()),
+            request_id: values.get("Request ID").map(|value| value.to_string()),
+        };
+        message.validate()?;
+        Ok(message)
     }
 }
 
@@ -128,6 +408,19 @@ impl From<SiwbMessage> for String {
             OffsetDateTime::from_unix_timestamp_nanos(val.expiration_time as i128).unwrap();
         let expiration_iso_8601 = expiration_datetime.format(&Rfc3339).unwrap();
 
+        let session_expiration_datetime =
+            OffsetDateTime::from_unix_timestamp_nanos(val.expires_at() as i128).unwrap();
+        let session_expiration_iso_8601 = session_expiration_datetime.format(&Rfc3339).unwrap();
+
+        let canister_id = match &val.canister_id {
+            Some(canister_id) => format!("\nCanister ID: {}", canister_id),
+            None => String::new(),
+        };
+        let request_id = match &val.request_id {
+            Some(request_id) => format!("\nRequest ID: {}", request_id),
+            None => String::new(),
+        };
+
         format!(
             "{domain} wants you to sign in with your Bitcoin account:\n\
             {address}\n\n\
@@ -137,7 +430,10 @@ impl From<SiwbMessage> for String {
             Network: {network}\n\
             Nonce: {nonce}\n\
             Issued At: {issued_at_iso_8601}\n\
-            Expiration Time: {expiration_iso_8601}",
+            Expiration Time: {expiration_iso_8601}\n\
+            Session Expiration Time: {session_expiration_iso_8601}\
+            {canister_id}\
+            {request_id}",
             domain = val.domain,
             address = val.address,
             statement = val.statement,
@@ -152,8 +448,11 @@ impl From<SiwbMessage> for String {
 /// The SiwbMessageMap is a map of SIWB messages keyed by the Bitcoin address of the user. SIWB messages
 /// are stored in the map during the course of the login process and are removed once the login process
 /// is complete. The map is also pruned periodically to remove expired SIWB messages.
+///
+/// Each message is stored with the digest of the message string as it was prepared, see
+/// [SiwbMessageMap::get_with_digest].
 pub struct SiwbMessageMap {
-    map: HashMap<Vec<u8>, SiwbMessage>,
+    map: HashMap<Vec<u8>, (SiwbMessage, Hash)>,
 }
 
 impl SiwbMessageMap {
@@ -165,19 +464,29 @@ impl SiwbMessageMap {
 
     /// Removes SIWB messages that have exceeded their time to live.
     pub fn prune_expired(&mut self) {
-        let current_time = get_current_time();
-        self.map
-            .retain(|_, message| message.expiration_time > current_time);
+        self.map.retain(|_, (message, _)| !message.is_expired());
     }
 
     /// Adds a SIWB message to the map.
     pub fn insert(&mut self, address_bytes: Vec<u8>, message: SiwbMessage) {
-        self.map.insert(address_bytes, message);
+        let digest = hash_bytes(String::from(message.clone()));
+        self.map.insert(address_bytes, (message, digest));
     }
 
     /// Returns a cloned SIWB message associated with the provided address or an error if the message
     /// does not exist.
     pub fn get(&self, address_bytes: &Vec<u8>) -> Result<SiwbMessage, SiwbMessageError> {
+        self.get_with_digest(address_bytes)
+            .map(|(message, _)| message)
+    }
+
+    /// Like [SiwbMessageMap::get], but also returns the SHA-256 digest of the message string at the
+    /// time the message was inserted. A login verifies the signature against the message string only
+    /// if it still has this digest, so a signature is bound to the exact bytes the user was shown.
+    pub fn get_with_digest(
+        &self,
+        address_bytes: &Vec<u8>,
+    ) -> Result<(SiwbMessage, Hash), SiwbMessageError> {
         self.map
             .get(address_bytes)
             .cloned()
@@ -192,6 +501,13 @@ impl SiwbMessageMap {
     pub fn clear(&mut self) {
         self.map.clear();
     }
+
+    /// Iterates over the stored messages and the script pubkeys they are keyed by, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&Vec<u8>, &SiwbMessage)> {
+        self.map
+            .iter()
+            .map(|(address_bytes, (message, _))| (address_bytes, message))
+    }
 }
 
 impl Default for SiwbMessageMap {
@@ -199,3 +515,193 @@ impl Default for SiwbMessageMap {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::SettingsBuilder;
+    use crate::time::{advance_test_time, set_test_time};
+    use crate::SETTINGS;
+    use bitcoin::Network;
+    use std::str::FromStr;
+
+    const SIGN_IN_EXPIRES_IN: u64 = 60 * 1_000_000_000;
+
+    fn init() -> Address {
+        init_with_network(Network::Bitcoin);
+        Address::from_str("bc1qshqyem2rf8jyla904gd2cvek2k8nz5z3x73p24")
+            .unwrap()
+            .assume_checked()
+    }
+
+    fn init_with_network(network: Network) {
+        let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .network(network)
+            .sign_in_expires_in(SIGN_IN_EXPIRES_IN)
+            .build()
+            .unwrap();
+        SETTINGS.set(Some(settings));
+    }
+
+    #[test]
+    fn test_message_cbor_round_trip() {
+        let address = init();
+        let message = SiwbMessage::new(&address);
+        let decoded = SiwbMessage::from_cbor(&message.to_cbor().unwrap()).unwrap();
+        assert_eq!(decoded.to_string(), message.to_string());
+        assert_eq!(String::from(decoded), String::from(message));
+
+        assert!(SiwbMessage::from_cbor(b"not cbor").is_err());
+    }
+
+    #[test]
+    fn test_parse_round_trip() {
+        let address = init();
+        let mut message = SiwbMessage::new(&address);
+        let parsed = SiwbMessage::parse(&String::from(message.clone())).unwrap();
+        assert_eq!(parsed.to_string(), message.to_string());
+
+        message.request_id = Some("req-42".to_string());
+        let parsed = SiwbMessage::parse(&String::from(message.clone())).unwrap();
+        assert_eq!(parsed.to_string(), message.to_string());
+
+        assert!(matches!(
+            SiwbMessage::parse("not a message"),
+            Err(BtcError::MalformedMessage(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_strict_message_parsing() {
+        let address = init();
+        let message = SiwbMessage::new(&address);
+        let rendered = String::from(message.clone());
+        let injected = [
+            format!("{}\nResources: https://evil.com", rendered),
+            format!("{}\nNonce: attacker", rendered),
+            rendered.replace("\nVersion: 1\n", "\nVersion: 1\nnot a field\n"),
+        ];
+
+        // By default, unknown fields are ignored and the fields rendered by the canister win.
+        for injected in &injected {
+            let parsed = SiwbMessage::parse(injected).unwrap();
+            assert_eq!(parsed.nonce, message.nonce);
+        }
+
+        let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .sign_in_expires_in(SIGN_IN_EXPIRES_IN)
+            .strict_message_parsing(true)
+            .build()
+            .unwrap();
+        SETTINGS.set(Some(settings));
+        assert!(SiwbMessage::parse(&rendered).is_ok());
+        for injected in &injected {
+            assert!(matches!(
+                SiwbMessage::parse(injected),
+                Err(BtcError::MalformedMessage(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_message_network_label() {
+        let testnet_address = Address::from_str("tb1qshqyem2rf8jyla904gd2cvek2k8nz5z3vc2j3x")
+            .unwrap()
+            .assume_checked();
+        let cases = [
+            (Network::Bitcoin, "bitcoin"),
+            (Network::Testnet, "testnet"),
+            (Network::Signet, "signet"),
+            (Network::Regtest, "regtest"),
+        ];
+        for (network, label) in cases {
+            init_with_network(network);
+            let message: String = SiwbMessage::new(&testnet_address).into();
+            assert!(
+                message.contains(&format!("\nNetwork: {}\n", label)),
+                "Missing network label {} in message: {}",
+                label,
+                message
+            );
+        }
+    }
+
+    #[test]
+    fn test_new_message_uses_current_time() {
+        let address = init();
+        set_test_time(1_000_000_000);
+        let message = SiwbMessage::new(&address);
+        assert_eq!(message.issued_at, 1_000_000_000);
+        assert_eq!(message.expiration_time, 1_000_000_000 + SIGN_IN_EXPIRES_IN);
+    }
+
+    #[test]
+    fn test_expires_at_rendered() {
+        let address = init();
+        set_test_time(1_000_000_000);
+        let message = SiwbMessage::new(&address);
+        let expires_at =
+            with_settings!(|settings: &Settings| 1_000_000_000 + settings.session_expires_in);
+        assert_eq!(message.expires_at(), expires_at);
+
+        let rendered: String = message.clone().into();
+        let line = rendered
+            .lines()
+            .find_map(|line| line.strip_prefix("Session Expiration Time: "))
+            .unwrap();
+        let parsed = OffsetDateTime::parse(line, &Rfc3339).unwrap();
+        assert_eq!(parsed.unix_timestamp_nanos(), message.expires_at() as i128);
+    }
+
+    #[test]
+    fn test_is_expired_boundary() {
+        let address = init();
+        set_test_time(1_000_000_000);
+        let message = SiwbMessage::new(&address);
+        assert!(!message.is_expired());
+
+        advance_test_time(SIGN_IN_EXPIRES_IN - 1);
+        assert!(!message.is_expired());
+
+        advance_test_time(1);
+        assert!(message.is_expired());
+    }
+
+    #[test]
+    fn test_is_expired_clock_skew() {
+        let address = init();
+        let drift = 5 * 1_000_000_000;
+        let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .sign_in_expires_in(SIGN_IN_EXPIRES_IN)
+            .clock_skew(drift)
+            .build()
+            .unwrap();
+        SETTINGS.set(Some(settings));
+        set_test_time(1_000_000_000);
+        let message = SiwbMessage::new(&address);
+        assert_eq!(message.expiration_time, 1_000_000_000 + SIGN_IN_EXPIRES_IN);
+
+        advance_test_time(SIGN_IN_EXPIRES_IN + drift - 1);
+        assert!(!message.is_expired());
+
+        advance_test_time(1);
+        assert!(message.is_expired());
+    }
+
+    #[test]
+    fn test_prune_expired_boundary() {
+        let address = init();
+        set_test_time(1_000_000_000);
+        let address_bytes = address.script_pubkey().to_bytes();
+        let mut map = SiwbMessageMap::new();
+        map.insert(address_bytes.clone(), SiwbMessage::new(&address));
+
+        advance_test_time(SIGN_IN_EXPIRES_IN - 1);
+        map.prune_expired();
+        assert!(map.get(&address_bytes).is_ok());
+
+        advance_test_time(1);
+        map.prune_expired();
+        assert!(map.get(&address_bytes).is_err());
+    }
+}
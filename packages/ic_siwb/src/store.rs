@@ -0,0 +1,89 @@
+use std::collections::BTreeMap;
+
+use candid::Principal;
+
+/// Storage for the mapping of Bitcoin addresses to the principals they logged in as. Addresses are
+/// keyed by their script pubkey bytes, see [crate::utils::script_pubkey_for].
+///
+/// The provider canister always keeps the mapping in stable memory. The trait is meant for canisters
+/// that embed the library, which can implement it to keep the mapping in their own storage instead.
+pub trait PrincipalStore {
+    /// Returns the principal of the address with the script pubkey `address`.
+    fn get(&self, address: &[u8]) -> Option<Principal>;
+
+    /// Stores `principal` as the principal of `address`, replacing any earlier principal.
+    fn put(&mut self, address: Vec<u8>, principal: Principal);
+
+    /// Removes the principal of `address` and returns it.
+    fn remove(&mut self, address: &[u8]) -> Option<Principal>;
+
+    /// Returns all addresses and their principals, e.g. to migrate the keys of the mapping.
+    fn entries(&self) -> Vec<(Vec<u8>, Principal)>;
+
+    /// Removes all principals, e.g. after the salt has been rotated, and returns how many were removed.
+    fn clear(&mut self) -> u64;
+}
+
+/// A heap memory store, the mapping is lost on upgrade.
+impl PrincipalStore for BTreeMap<Vec<u8>, Principal> {
+    fn get(&self, address: &[u8]) -> Option<Principal> {
+        BTreeMap::get(self, address).cloned()
+    }
+
+    fn put(&mut self, address: Vec<u8>, principal: Principal) {
+        self.insert(address, principal);
+    }
+
+    fn remove(&mut self, address: &[u8]) -> Option<Principal> {
+        BTreeMap::remove(self, address)
+    }
+
+    fn entries(&self) -> Vec<(Vec<u8>, Principal)> {
+        self.iter()
+            .map(|(address, principal)| (address.clone(), *principal))
+            .collect()
+    }
+
+    fn clear(&mut self) -> u64 {
+        let count = self.len() as u64;
+        BTreeMap::clear(self);
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_btree_map_store() {
+        let mut store: Box<dyn PrincipalStore> = Box::new(BTreeMap::new());
+        let address = vec![0, 20, 1, 2, 3];
+        assert_eq!(store.get(&address), None);
+
+        store.put(address.clone(), Principal::anonymous());
+        assert_eq!(store.get(&address), Some(Principal::anonymous()));
+        store.put(address.clone(), Principal::management_canister());
+        assert_eq!(store.get(&address), Some(Principal::management_canister()));
+
+        assert_eq!(
+            store.remove(&address),
+            Some(Principal::management_canister())
+        );
+        assert_eq!(store.get(&address), None);
+
+        let other = vec![0, 20, 4, 5, 6];
+        store.put(address.clone(), Principal::anonymous());
+        store.put(other.clone(), Principal::management_canister());
+        assert_eq!(
+            store.entries(),
+            vec![
+                (address.clone(), Principal::anonymous()),
+                (other, Principal::management_canister())
+            ]
+        );
+        assert_eq!(store.clear(), 2);
+        assert!(store.entries().is_empty());
+        assert_eq!(store.get(&address), None);
+    }
+}
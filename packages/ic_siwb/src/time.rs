@@ -4,10 +4,49 @@ pub(crate) fn get_current_time() -> u64 {
     ic_cdk::api::time()
 }
 
+#[cfg(test)]
+thread_local! {
+    // Tests can pin the clock to a fixed value to exercise TTL and expiry boundaries
+    // deterministically. Each test runs on its own thread, so the override never leaks between tests.
+    static TEST_TIME: std::cell::Cell<Option<u64>> = const { std::cell::Cell::new(None) };
+}
+
 #[cfg(test)]
 pub(crate) fn get_current_time() -> u64 {
     use std::time::{SystemTime, UNIX_EPOCH};
 
-    let start = SystemTime::now();
-    start.duration_since(UNIX_EPOCH).unwrap().as_nanos() as u64
+    TEST_TIME.with(|t| t.get()).unwrap_or_else(|| {
+        let start = SystemTime::now();
+        start.duration_since(UNIX_EPOCH).unwrap().as_nanos() as u64
+    })
+}
+
+/// Pins the clock returned by [get_current_time] to `time` (nanoseconds since the UNIX epoch).
+#[cfg(test)]
+pub(crate) fn set_test_time(time: u64) {
+    TEST_TIME.with(|t| t.set(Some(time)));
+}
+
+/// Moves the clock forward by `duration` nanoseconds, pinning it first if it was not pinned yet.
+#[cfg(test)]
+pub(crate) fn advance_test_time(duration: u64) {
+    set_test_time(get_current_time().saturating_add(duration));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_test_time() {
+        set_test_time(1_000);
+        assert_eq!(get_current_time(), 1_000);
+    }
+
+    #[test]
+    fn test_advance_test_time() {
+        set_test_time(1_000);
+        advance_test_time(500);
+        assert_eq!(get_current_time(), 1_500);
+    }
 }
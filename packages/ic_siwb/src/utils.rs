@@ -1,4 +1,6 @@
+use crate::error::BtcError;
 use crate::hash::hash_with_domain;
+use bitcoin::address::{NetworkUnchecked, Payload, WitnessVersion};
 use bitcoin::Network::{Bitcoin, Regtest, Testnet};
 use bitcoin::{Address, AddressType, Network, ScriptBuf};
 use candid::Principal;
@@ -49,52 +51,86 @@ pub struct AddressInfo {
     pub address_type: AddressType,
 }
 
-pub fn get_script_from_address(address: String) -> Result<AddressInfo, String> {
-    let mut network = Bitcoin;
-    let mut address_type = AddressType::P2tr;
-
-    if address.starts_with("bc1q") {
-        address_type = AddressType::P2wpkh;
-        network = Bitcoin;
-    } else if address.starts_with("bc1p") {
-        address_type = AddressType::P2tr;
-        network = Bitcoin;
-    } else if address.starts_with('1') {
-        address_type = AddressType::P2pkh;
-        network = Bitcoin;
-    } else if address.starts_with('3') {
-        address_type = AddressType::P2sh;
-        network = Bitcoin;
-    } else if address.starts_with("tb1q") {
-        address_type = AddressType::P2wpkh;
-        network = Testnet;
-    } else if address.starts_with('m') || address.starts_with('n') {
-        address_type = AddressType::P2pkh;
-        network = Testnet;
-    } else if address.starts_with('2') {
-        address_type = AddressType::P2sh;
-        network = Testnet;
-    } else if address.starts_with("tb1p") {
-        address_type = AddressType::P2tr;
-        network = Testnet;
+impl AddressInfo {
+    /// Returns the normalized string form of the address: lowercase bech32 for SegWit and Taproot
+    /// addresses, Base58Check for legacy addresses, both encoded for the address's network. Use it
+    /// wherever an address is displayed or compared as a string, so that all call sites agree.
+    pub fn canonical(&self) -> String {
+        self.address_raw.to_string()
+    }
+}
+
+/// Classifies `address` into the network and type encoded by the address itself. Bech32 addresses may be
+/// written in either case. Testnet and signet share their encodings, so addresses of both networks are
+/// classified as [Testnet]; only the configured network can tell them apart.
+pub fn classify_address(address: &str) -> Result<(Network, AddressType), BtcError> {
+    let parsed = Address::<NetworkUnchecked>::from_str(address)?;
+    check_witness_version(&parsed.payload)?;
+    let network = parsed.network;
+    let address_type = parsed
+        .assume_checked()
+        .address_type()
+        .ok_or(BtcError::AddressTypeNotSupported)?;
+    Ok((network, address_type))
+}
+
+/// Rejects witness programs of versions other than 0 (SegWit) and 1 (Taproot) with
+/// [BtcError::ScriptConversionError]. Addresses of later versions parse and have a script pubkey, but
+/// no address type, and it is not defined yet what a message signature for them proves.
+pub(crate) fn check_witness_version(payload: &Payload) -> Result<(), BtcError> {
+    match payload {
+        Payload::WitnessProgram(program)
+            if !matches!(program.version(), WitnessVersion::V0 | WitnessVersion::V1) =>
+        {
+            Err(BtcError::ScriptConversionError(format!(
+                "unsupported witness version {}",
+                program.version()
+            )))
+        }
+        _ => Ok(()),
     }
-    let addr = Address::from_str(address.as_str())
-        .map_err(|e| format!("Cannot gen address {:?}", e).to_string())?;
+}
 
-    let addr_checked = addr
-        .clone()
+/// Parses `address` and returns its script pubkey along with its type and network. The script pubkey
+/// is the canonical form of the address: bech32 addresses may be written in either case, but both
+/// casings yield the same `script_buf`, so it should be used whenever an address is stored or looked up.
+pub fn get_script_from_address(address: String) -> Result<AddressInfo, String> {
+    let (network, address_type) = classify_address(&address)?;
+    let addr_checked = Address::from_str(address.as_str())
+        .map_err(|e| format!("Cannot gen address {:?}", e).to_string())?
         .require_network(network)
         .map_err(|e| format!("Cannot require network {:?}", e).to_string())?;
 
     Ok(AddressInfo {
         address_raw: addr_checked.clone(),
-        address: addr_checked.clone().to_string(),
-        script_buf: addr_checked.clone().script_pubkey(),
+        address: addr_checked.to_string(),
+        script_buf: addr_checked.script_pubkey(),
         network,
         address_type,
     })
 }
 
+/// Returns the script pubkey of `address`, the key used for the principal to address mappings, without
+/// touching any library state. Addresses that are not valid for `network` are rejected with
+/// [BtcError::NetworkMismatch].
+pub fn script_pubkey_for(address: &str, network: Network) -> Result<ScriptBuf, BtcError> {
+    let parsed = Address::<NetworkUnchecked>::from_str(address)?;
+    if !parsed.is_valid_for_network(network) {
+        return Err(BtcError::NetworkMismatch);
+    }
+    check_witness_version(&parsed.payload)?;
+    Ok(parsed.assume_checked().script_pubkey())
+}
+
+/// Converts a legacy map key holding the textual address into the canonical script pubkey key. Returns
+/// `None` if `key` is not a textual address, which is the case for keys already holding script pubkey
+/// bytes.
+pub fn legacy_address_key_to_script(key: &[u8]) -> Option<Vec<u8>> {
+    let address = std::str::from_utf8(key).ok()?;
+    let address = Address::from_str(address).ok()?.assume_checked();
+    Some(address.script_pubkey().to_bytes())
+}
+
 pub fn from_bitcoin_network(value: BitcoinNetwork) -> Network {
     match value {
         BitcoinNetwork::Mainnet => Bitcoin,
@@ -102,3 +138,233 @@ pub fn from_bitcoin_network(value: BitcoinNetwork) -> Network {
         BitcoinNetwork::Regtest => Regtest,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_address_casings_share_script() {
+        let addresses = [
+            "bc1qshqyem2rf8jyla904gd2cvek2k8nz5z3x73p24",
+            "tb1qshqyem2rf8jyla904gd2cvek2k8nz5z3vc2j3x",
+            "bc1pgvdp7lf89d62zadds5jvyjntxmr7v70yv33g7vqaeu2p0cuexveq9hcwdv",
+        ];
+        for address in addresses {
+            let lower = get_script_from_address(address.to_string()).unwrap();
+            let upper = get_script_from_address(address.to_uppercase()).unwrap();
+            assert_eq!(lower.script_buf, upper.script_buf, "{}", address);
+            assert_eq!(lower.network, upper.network, "{}", address);
+            assert_eq!(lower.address_type, upper.address_type, "{}", address);
+        }
+    }
+
+    #[test]
+    fn test_canonical_address() {
+        let cases = [
+            "1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2",
+            "3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy",
+            "bc1qshqyem2rf8jyla904gd2cvek2k8nz5z3x73p24",
+            "bc1pgvdp7lf89d62zadds5jvyjntxmr7v70yv33g7vqaeu2p0cuexveq9hcwdv",
+            "mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn",
+            "tb1qshqyem2rf8jyla904gd2cvek2k8nz5z3vc2j3x",
+            "bcrt1qshqyem2rf8jyla904gd2cvek2k8nz5z3w3nlx0",
+        ];
+        for address in cases {
+            let info = get_script_from_address(address.to_string()).unwrap();
+            assert_eq!(info.canonical(), address);
+            assert_eq!(info.canonical(), info.address);
+        }
+
+        // Bech32 addresses are lowercased, Base58 addresses are case sensitive and kept as is.
+        let upper = get_script_from_address(
+            "BC1PGVDP7LF89D62ZADDS5JVYJNTXMR7V70YV33G7VQAEU2P0CUEXVEQ9HCWDV".to_string(),
+        )
+        .unwrap();
+        assert_eq!(
+            upper.canonical(),
+            "bc1pgvdp7lf89d62zadds5jvyjntxmr7v70yv33g7vqaeu2p0cuexveq9hcwdv"
+        );
+    }
+
+    #[test]
+    fn test_classify_address() {
+        let cases = [
+            (
+                "1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2",
+                Bitcoin,
+                AddressType::P2pkh,
+            ),
+            (
+                "3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy",
+                Bitcoin,
+                AddressType::P2sh,
+            ),
+            (
+                "bc1qshqyem2rf8jyla904gd2cvek2k8nz5z3x73p24",
+                Bitcoin,
+                AddressType::P2wpkh,
+            ),
+            (
+                "bc1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3qccfmv3",
+                Bitcoin,
+                AddressType::P2wsh,
+            ),
+            (
+                "bc1pgvdp7lf89d62zadds5jvyjntxmr7v70yv33g7vqaeu2p0cuexveq9hcwdv",
+                Bitcoin,
+                AddressType::P2tr,
+            ),
+            (
+                "mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn",
+                Testnet,
+                AddressType::P2pkh,
+            ),
+            (
+                "n3GNqMveyvaPvUbH469vDRadqpJMPc84JA",
+                Testnet,
+                AddressType::P2pkh,
+            ),
+            (
+                "2MzQwSSnBHWHqSAqtTVQ6v47XtaisrJa1Vc",
+                Testnet,
+                AddressType::P2sh,
+            ),
+            (
+                "tb1qshqyem2rf8jyla904gd2cvek2k8nz5z3vc2j3x",
+                Testnet,
+                AddressType::P2wpkh,
+            ),
+            (
+                "tb1pgvdp7lf89d62zadds5jvyjntxmr7v70yv33g7vqaeu2p0cuexveqjlwphr",
+                Testnet,
+                AddressType::P2tr,
+            ),
+            (
+                "bcrt1qshqyem2rf8jyla904gd2cvek2k8nz5z3w3nlx0",
+                Regtest,
+                AddressType::P2wpkh,
+            ),
+        ];
+        for (address, network, address_type) in cases {
+            assert_eq!(
+                classify_address(address).ok(),
+                Some((network, address_type)),
+                "{}",
+                address
+            );
+        }
+
+        assert!(matches!(
+            classify_address("not an address"),
+            Err(BtcError::AddressFormatError(_))
+        ));
+    }
+
+    #[test]
+    fn test_unsupported_witness_version() {
+        // Valid BIP-350 addresses of witness versions 2 and 16.
+        for (address, version) in [
+            ("bc1zw508d6qejxtdg4y5r3zarvaryvaxxpcs", 2),
+            ("BC1SW50QGDZ25J", 16),
+        ] {
+            let expected = format!(
+                "Script conversion error: unsupported witness version {}",
+                version
+            );
+            assert!(matches!(
+                classify_address(address),
+                Err(BtcError::ScriptConversionError(_))
+            ));
+            assert_eq!(
+                get_script_from_address(address.to_string()).err(),
+                Some(expected)
+            );
+            assert!(matches!(
+                script_pubkey_for(address, Network::Bitcoin),
+                Err(BtcError::ScriptConversionError(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_script_pubkey_for() {
+        let cases = [
+            (
+                "1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2",
+                Bitcoin,
+                "76a91477bff20c60e522dfaa3350c39b030a5d004e839a88ac",
+            ),
+            (
+                "3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy",
+                Bitcoin,
+                "a914b472a266d0bd89c13706a4132ccfb16f7c3b9fcb87",
+            ),
+            (
+                "bc1qshqyem2rf8jyla904gd2cvek2k8nz5z3x73p24",
+                Bitcoin,
+                "001485c04ced4349e44ff4afaa1aac3336558f315051",
+            ),
+            (
+                "bc1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3qccfmv3",
+                Bitcoin,
+                "00201863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262",
+            ),
+            (
+                "bc1pgvdp7lf89d62zadds5jvyjntxmr7v70yv33g7vqaeu2p0cuexveq9hcwdv",
+                Bitcoin,
+                "5120431a1f7d272b74a175ad8524c24a6b36c7e679e464628f301dcf1417e3993332",
+            ),
+            // The same witness program on testnet and signet has the same script pubkey.
+            (
+                "tb1qshqyem2rf8jyla904gd2cvek2k8nz5z3vc2j3x",
+                Testnet,
+                "001485c04ced4349e44ff4afaa1aac3336558f315051",
+            ),
+            (
+                "tb1qshqyem2rf8jyla904gd2cvek2k8nz5z3vc2j3x",
+                Network::Signet,
+                "001485c04ced4349e44ff4afaa1aac3336558f315051",
+            ),
+        ];
+        for (address, network, script) in cases {
+            let script_pubkey = script_pubkey_for(address, network).unwrap();
+            assert_eq!(hex::encode(script_pubkey.as_bytes()), script, "{}", address);
+            assert_eq!(
+                script_pubkey,
+                get_script_from_address(address.to_string())
+                    .unwrap()
+                    .script_buf
+            );
+        }
+
+        assert!(matches!(
+            script_pubkey_for("tb1qshqyem2rf8jyla904gd2cvek2k8nz5z3vc2j3x", Bitcoin),
+            Err(BtcError::NetworkMismatch)
+        ));
+        assert!(matches!(
+            script_pubkey_for("not an address", Bitcoin),
+            Err(BtcError::AddressFormatError(_))
+        ));
+    }
+
+    #[test]
+    fn test_legacy_address_key_to_script() {
+        let address = "bc1qshqyem2rf8jyla904gd2cvek2k8nz5z3x73p24";
+        let script = get_script_from_address(address.to_string())
+            .unwrap()
+            .script_buf
+            .to_bytes();
+
+        assert_eq!(
+            legacy_address_key_to_script(address.as_bytes()),
+            Some(script.clone())
+        );
+        assert_eq!(
+            legacy_address_key_to_script(address.to_uppercase().as_bytes()),
+            Some(script.clone())
+        );
+        // Keys that already hold script pubkey bytes are left alone.
+        assert_eq!(legacy_address_key_to_script(&script), None);
+    }
+}
@@ -0,0 +1,1801 @@
+//! Pure Bitcoin message signature verification. Nothing in this module depends on `ic_cdk`, the
+//! library settings or any other thread-local state, so it can be used to verify signatures outside
+//! of a canister, for example in a native verifier or a WASM validator. The stateful login flow in
+//! [crate::login] is built on top of it. With the `debug` feature, failed public key recoveries are
+//! logged to help diagnose signature format mismatches.
+
+use std::mem::size_of;
+use std::ops::Range;
+use std::str::FromStr;
+
+use base64::engine::general_purpose;
+use base64::Engine;
+use bitcoin::address::NetworkUnchecked;
+use bitcoin::bip32::{ChildNumber, ExtendedPubKey};
+use bitcoin::key::{TapTweak, XOnlyPublicKey};
+use bitcoin::secp256k1::{schnorr, Message, Secp256k1};
+use bitcoin::taproot::TapNodeHash;
+use bitcoin::{Address, AddressType, Network, PublicKey as BitcoinPublicKey};
+use byteorder::{ByteOrder, LittleEndian};
+use candid::{CandidType, Deserialize};
+use k256::ecdsa::{RecoveryId, Signature, VerifyingKey};
+use serde::Serialize;
+
+use crate::error::BtcError;
+use crate::error::BtcError::AddressTypeNotSupported;
+use crate::hash::{hash_bytes, Hasher};
+
+/// The prefix that Bitcoin Core frames signed messages with. Settings can configure a different prefix per
+/// network, see [crate::settings::SettingsBuilder::message_prefix].
+pub const MAGIC_BYTES: &str = "Bitcoin Signed Message:\n";
+
+/// The tag of the BIP-340 tagged hash that signatures requested as
+/// [crate::login::SignMessageType::TaggedHash] are verified against, unless settings configure another one,
+/// see [crate::settings::SettingsBuilder::message_hash_tag]. It is the tag of BIP-322 message hashes.
+pub const DEFAULT_MESSAGE_HASH_TAG: &str = "BIP0322-signed-message";
+
+/// The largest number of child addresses [verify_against_xpub] derives in one call, which bounds its
+/// cost.
+pub const MAX_XPUB_DERIVATION_RANGE: u32 = 1_000;
+
+/// The x coordinate of the secp256k1 generator, the public key of secret key 1. Its secret key, and
+/// that of its negation, is known to everyone, so [parse_public_key] rejects both.
+const GENERATOR_X: [u8; 32] = [
+    0x79, 0xbe, 0x66, 0x7e, 0xf9, 0xdc, 0xbb, 0xac, 0x55, 0xa0, 0x62, 0x95, 0xce, 0x87, 0x0b, 0x07,
+    0x02, 0x9b, 0xfc, 0xdb, 0x2d, 0xce, 0x28, 0xd9, 0x59, 0xf2, 0x81, 0x5b, 0x16, 0xf8, 0x17, 0x98,
+];
+
+/// A Bitcoin address whose ownership has been proven by a verified message signature, in its canonical
+/// form, e.g. lowercase for bech32 addresses.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VerifiedAddress(pub String);
+
+impl VerifiedAddress {
+    fn canonical(address: &str) -> Result<Self, BtcError> {
+        let address = Address::from_str(address)
+            .map_err(|e| BtcError::AddressFormatError(e.to_string()))?
+            .assume_checked();
+        Ok(VerifiedAddress(address.to_string()))
+    }
+}
+
+/// The layout of a message signature. Wallets disagree on how they encode and lay out compact ECDSA
+/// signatures, the hint selects how a signature is decoded before it is verified:
+///
+/// * `Base64`: base64 encoded, the header byte precedes `r` and `s`, as defined by BIP-137 and produced by
+///   Bitcoin Core's `signmessage`. Browser wallets such as Unisat, OKX and Xverse return ECDSA message
+///   signatures in this layout.
+/// * `HexRecoveryIdLast`: hex encoded, optionally `0x` prefixed, the recovery id follows `r` and `s`
+///   instead of preceding them.
+/// * `Auto`: tries each of the above in turn.
+///
+/// Any header byte range is accepted by every layout. Taproot Schnorr signatures, with or without a
+/// trailing sighash flag, are verified as such whichever wallet produced them. BIP-322 signatures must be
+/// verified as [crate::login::SignMessageType::Bip322Simple].
+#[derive(CandidType, Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WalletKind {
+    Base64,
+    HexRecoveryIdLast,
+    #[default]
+    Auto,
+}
+
+/// Decodes `signature`, laid out as `wallet` says, into the base64 encoded, header-first layout that
+/// the verification functions expect. A signature may decode in several ways, the candidates are
+/// returned in the order they should be tried.
+///
+/// # Returns
+/// The candidate signatures, or [BtcError::SignatureFormatError] if `signature` is not laid out the way
+/// `wallet` says.
+pub fn signature_candidates(signature: &str, wallet: WalletKind) -> Result<Vec<String>, BtcError> {
+    let base64 = || {
+        general_purpose::STANDARD
+            .decode(signature)
+            .ok()
+            .filter(|bytes| bytes.len() == 64 || bytes.len() == 65)
+    };
+    let hex = || {
+        hex::decode(strip_hex_prefix(signature))
+            .ok()
+            .filter(|bytes| bytes.len() == 64 || bytes.len() == 65)
+    };
+    let recovery_id_last = |mut bytes: Vec<u8>| {
+        if bytes.len() == 65 {
+            bytes.rotate_right(1);
+        }
+        bytes
+    };
+
+    let candidates: Vec<Vec<u8>> = match wallet {
+        WalletKind::Base64 => base64().into_iter().collect(),
+        WalletKind::HexRecoveryIdLast => hex().map(recovery_id_last).into_iter().collect(),
+        WalletKind::Auto => base64()
+            .into_iter()
+            .chain(hex())
+            .chain(hex().map(recovery_id_last))
+            .collect(),
+    };
+    if candidates.is_empty() {
+        return Err(BtcError::SignatureFormatError(format!(
+            "not a {:?} signature",
+            wallet
+        )));
+    }
+
+    let mut unique = Vec::with_capacity(candidates.len());
+    for candidate in candidates {
+        let candidate = general_purpose::STANDARD.encode(candidate);
+        if !unique.contains(&candidate) {
+            unique.push(candidate);
+        }
+    }
+    Ok(unique)
+}
+
+/// Verifies that `message` was signed by the holder of `public_key` and that the key corresponds to
+/// `address`, without touching any library state. No SIWB message is consumed and no delegation is
+/// created, which makes this suitable for attestation flows outside of a login session.
+///
+/// # Parameters
+/// * `message`: The exact message that was signed.
+/// * `signature`: The compact ECDSA signature, or a BIP-340 Schnorr signature for Taproot addresses,
+///   optionally followed by the SIGHASH_ALL flag. Base64 or hex encoded, see [decode_signature].
+/// * `public_key`: The hex encoded public key of the wallet.
+/// * `address`: The Bitcoin address the signer claims to own.
+///
+/// # Returns
+/// The [VerifiedAddress] on success, or a [BtcError] if the signature
+/// does not verify or the public key does not correspond to the address.
+pub fn verify_signed_message(
+    message: String,
+    signature: String,
+    public_key: String,
+    address: &str,
+) -> Result<VerifiedAddress, BtcError> {
+    let recovered_public_key = _verify_message_for_address(
+        message,
+        signature,
+        public_key,
+        true,
+        MAGIC_BYTES,
+        None,
+        address,
+        true,
+        None,
+    )?;
+    verify_address(address, recovered_public_key)?;
+    VerifiedAddress::canonical(address)
+}
+
+/// Like [verify_signed_message], but for a signature over the [tagged_hash] of `message` with `tag`
+/// rather than over the legacy framed message hash.
+pub fn verify_tagged_message(
+    message: String,
+    signature: String,
+    public_key: String,
+    tag: &str,
+    address: &str,
+) -> Result<VerifiedAddress, BtcError> {
+    let recovered_public_key = _verify_message_for_address(
+        message,
+        signature,
+        public_key,
+        true,
+        MAGIC_BYTES,
+        Some(tag),
+        address,
+        true,
+        None,
+    )?;
+    verify_address(address, recovered_public_key)?;
+    VerifiedAddress::canonical(address)
+}
+
+struct BufferWriter {}
+
+impl BufferWriter {
+    fn varint_buf_num(n: i64) -> Vec<u8> {
+        let mut buf = Vec::new();
+        if n < 253 {
+            buf.push(n as u8);
+        } else if n < 0x10000 {
+            buf.push(253);
+            let mut bytes = [0u8; size_of::<u16>()];
+            LittleEndian::write_u16(&mut bytes, n as u16);
+            buf.extend_from_slice(&bytes);
+        } else if n < 0x100000000 {
+            buf.push(254);
+            let mut bytes = [0u8; size_of::<u32>()];
+            LittleEndian::write_u32(&mut bytes, n as u32);
+            buf.extend_from_slice(&bytes);
+        } else {
+            buf.push(255);
+            let mut bytes = [0u8; size_of::<u64>()];
+            LittleEndian::write_i32(&mut bytes[0..4], (n & -1) as i32);
+            LittleEndian::write_u32(&mut bytes[4..8], (n / 0x100000000) as u32);
+            buf.extend_from_slice(&bytes);
+        }
+        buf
+    }
+}
+
+pub fn _msg_hash(message: String) -> Vec<u8> {
+    _msg_hash_with(message, true)
+}
+
+/// Frames `message` for Bitcoin message signing: the length prefixed magic bytes followed by the length
+/// prefixed message. These are the bytes that [_msg_hash_with] hashes.
+pub fn message_frame(message: &str) -> Vec<u8> {
+    message_frame_with_prefix(message, MAGIC_BYTES)
+}
+
+/// Like [message_frame], but frames `message` with `prefix` instead of the Bitcoin magic bytes.
+pub fn message_frame_with_prefix(message: &str, prefix: &str) -> Vec<u8> {
+    let prefix1 = BufferWriter::varint_buf_num(prefix.len() as i64);
+    let message_buffer = message.as_bytes().to_vec();
+    let prefix2 = BufferWriter::varint_buf_num(message_buffer.len() as i64);
+    let mut buf = Vec::new();
+    buf.extend_from_slice(&prefix1);
+    buf.extend_from_slice(prefix.as_bytes());
+    buf.extend_from_slice(&prefix2);
+    buf.extend_from_slice(&message_buffer);
+    buf
+}
+
+/// Hashes the framed message with SHA256, applying a second SHA256 round when `double_hash` is set.
+/// Bitcoin Core message signing uses the double hash, some wallet integrations sign the single hash.
+pub fn _msg_hash_with(message: String, double_hash: bool) -> Vec<u8> {
+    _msg_hash_with_prefix(message, double_hash, MAGIC_BYTES)
+}
+
+/// Like [_msg_hash_with], but for a message framed with `prefix`, see [message_frame_with_prefix].
+pub fn _msg_hash_with_prefix(message: String, double_hash: bool, prefix: &str) -> Vec<u8> {
+    let hash = hash_bytes(message_frame_with_prefix(&message, prefix));
+    if !double_hash {
+        return hash.to_vec();
+    }
+    hash_bytes(hash).to_vec()
+}
+
+/// The BIP-340 tagged hash of `message`, `SHA256(SHA256(tag) || SHA256(tag) || message)`. Some Taproot
+/// wallets sign this digest of the raw message instead of the legacy framed message hash, the tag
+/// separates it from digests signed for other purposes.
+pub fn tagged_hash(tag: &str, message: &[u8]) -> Vec<u8> {
+    let tag_hash = hash_bytes(tag.as_bytes());
+    let mut hasher = Hasher::new();
+    hasher.update(tag_hash);
+    hasher.update(tag_hash);
+    hasher.update(message);
+    hasher.finalize().to_vec()
+}
+
+/// The digest that a message signature signs: the [tagged_hash] of `message` with `tag` if there is one,
+/// otherwise the legacy hash of `message` framed with `prefix`, see [_msg_hash_with_prefix].
+pub fn message_digest(
+    message: &str,
+    double_hash: bool,
+    prefix: &str,
+    tag: Option<&str>,
+) -> Vec<u8> {
+    match tag {
+        Some(tag) => tagged_hash(tag, message.as_bytes()),
+        None => _msg_hash_with_prefix(message.to_string(), double_hash, prefix),
+    }
+}
+
+/// Recovers the public key from `signature` over `message` and checks that it is `public_key`. A
+/// signature that recovers a different key was not made by `public_key` and is rejected with
+/// [BtcError::InvalidSignature].
+pub(crate) fn _verify_message(
+    message: String,
+    signature: String,
+    public_key: String,
+    double_hash: bool,
+) -> Result<Vec<u8>, BtcError> {
+    _verify_message_with_prefix(message, signature, public_key, double_hash, MAGIC_BYTES)
+}
+
+/// Like [_verify_message], but for a message framed with `prefix`.
+pub(crate) fn _verify_message_with_prefix(
+    message: String,
+    signature: String,
+    public_key: String,
+    double_hash: bool,
+    prefix: &str,
+) -> Result<Vec<u8>, BtcError> {
+    let message_prehashed = _msg_hash_with_prefix(message, double_hash, prefix);
+    verify_digest(&message_prehashed, &signature, &public_key, None)
+}
+
+/// Like [_verify_message], but for a signature over `message_prehashed` rather than over a message. With
+/// a `recovery_id`, the key is recovered with it rather than with the recovery byte of the signature.
+fn verify_digest(
+    message_prehashed: &[u8],
+    signature: &str,
+    public_key: &str,
+    recovery_id: Option<u8>,
+) -> Result<Vec<u8>, BtcError> {
+    let signature_bytes = decode_signature(signature)?;
+    let public_key_bytes = decode_public_key(public_key)?;
+
+    // Reading the signature in the wrong layout recovers a different key, so each plausible layout is
+    // tried until one recovers `public_key`.
+    let mut error = None;
+    for layout in SignatureLayout::candidates(&signature_bytes) {
+        let recovered = match recovery_id {
+            Some(recovery_id) => recover_pub_key_with_recovery_id(
+                &signature_bytes,
+                message_prehashed,
+                recovery_id,
+                layout,
+            ),
+            None => recover_pub_key_with_layout(&signature_bytes, message_prehashed, None, layout),
+        };
+        match recovered {
+            Ok(key) if key == public_key_bytes => return Ok(key),
+            Ok(_) => error = Some(BtcError::InvalidSignature),
+            Err(e) => {
+                error.get_or_insert(e);
+            }
+        }
+    }
+    Err(error.unwrap_or(BtcError::InvalidSignature))
+}
+
+/// The only sighash flag accepted after a Taproot Schnorr message signature. A message signature commits
+/// to the message rather than to a transaction, so SIGHASH_ALL, which a 64 byte signature implies, is the
+/// only meaningful flag.
+const SCHNORR_SIGHASH_ALL: u8 = 0x01;
+
+/// Verifies `signature` over `message` the way `address` signs. Taproot key-spend signatures are BIP-340
+/// Schnorr signatures without a recovery id, so for Taproot addresses they are verified against the
+/// output key, i.e. `public_key` tweaked as in [Address::p2tr], rather than being misread as compact
+/// ECDSA signatures. Every other signature is verified with [_verify_message].
+///
+/// Schnorr signatures are 64 bytes, or 65 bytes with an explicit SIGHASH_ALL flag appended, which is
+/// stripped. A 65 byte signature may just as well be a compact ECDSA signature, so if it does not verify
+/// as a Schnorr signature it is verified as an ECDSA signature, unless `allow_legacy_ecdsa` is unset.
+/// Messages are framed with `prefix`, see [message_frame_with_prefix], or, if there is a `tag`, signatures
+/// are verified against their [tagged_hash] instead. ECDSA signatures are recovered with `recovery_id` if
+/// the client supplied one, see [recover_pub_key_with_recovery_id].
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn _verify_message_for_address(
+    message: String,
+    signature: String,
+    public_key: String,
+    double_hash: bool,
+    prefix: &str,
+    tag: Option<&str>,
+    address: &str,
+    allow_legacy_ecdsa: bool,
+    recovery_id: Option<u8>,
+) -> Result<Vec<u8>, BtcError> {
+    let message_prehashed = message_digest(&message, double_hash, prefix, tag);
+    if let Some(schnorr_bytes) = schnorr_key_spend_bytes(&signature, address) {
+        let result = verify_schnorr_key_spend(&schnorr_bytes, &message_prehashed, &public_key);
+        let with_sighash = decode_signature(&signature).is_ok_and(|bytes| bytes.len() == 65);
+        if result.is_ok() || !with_sighash || !allow_legacy_ecdsa {
+            return result;
+        }
+    } else if !allow_legacy_ecdsa {
+        return Err(BtcError::LegacyEcdsaDisabled);
+    }
+    verify_digest(&message_prehashed, &signature, &public_key, recovery_id)
+}
+
+/// Whether [_verify_message_for_address] verifies `signature` as a Taproot Schnorr key-spend signature
+/// rather than only as a legacy compact ECDSA signature.
+pub(crate) fn is_schnorr_key_spend(signature: &str, address: &str) -> bool {
+    schnorr_key_spend_bytes(signature, address).is_some()
+}
+
+/// Returns the 64 byte Schnorr signature if `signature` can be a Taproot key-spend signature for
+/// `address`: 64 bytes, or 65 bytes ending in the SIGHASH_ALL flag. Any other trailing sighash flag is
+/// rejected, such a signature can only be a compact ECDSA signature.
+fn schnorr_key_spend_bytes(signature: &str, address: &str) -> Option<Vec<u8>> {
+    let is_taproot = Address::<NetworkUnchecked>::from_str(address)
+        .is_ok_and(|a| a.assume_checked().address_type() == Some(AddressType::P2tr));
+    if !is_taproot {
+        return None;
+    }
+    let mut bytes = decode_signature(signature).ok()?;
+    match bytes.len() {
+        64 => Some(bytes),
+        65 if bytes[64] == SCHNORR_SIGHASH_ALL => {
+            bytes.truncate(64);
+            Some(bytes)
+        }
+        _ => None,
+    }
+}
+
+fn verify_schnorr_key_spend(
+    signature_bytes: &[u8],
+    message_hash: &[u8],
+    public_key: &str,
+) -> Result<Vec<u8>, BtcError> {
+    let public_key_bytes = decode_public_key(public_key)?;
+    let internal_key = parse_public_key(&public_key_bytes)?;
+    let secp = Secp256k1::verification_only();
+    let (output_key, _) = XOnlyPublicKey::from(internal_key.inner).tap_tweak(&secp, None);
+
+    let signature = schnorr::Signature::from_slice(signature_bytes)
+        .map_err(|e| BtcError::SignatureFormatError(e.to_string()))?;
+    let message = Message::from_slice(message_hash).map_err(|_| BtcError::InvalidSignature)?;
+    secp.verify_schnorr(&signature, &message, &output_key.to_inner())
+        .map_err(|_| BtcError::InvalidSignature)?;
+    Ok(public_key_bytes)
+}
+
+/// Decodes a hex encoded public key as supplied by a wallet. An optional `0x`/`0X` prefix is stripped
+/// and both lower and upper case hex are accepted. Input that does not decode to a valid secp256k1
+/// public key is rejected with [BtcError::InvalidPublicKey], see [parse_public_key].
+pub fn decode_public_key(public_key: &str) -> Result<Vec<u8>, BtcError> {
+    let bytes =
+        hex::decode(strip_hex_prefix(public_key)).map_err(|_| BtcError::InvalidPublicKey)?;
+    parse_public_key(&bytes)?;
+    Ok(bytes)
+}
+
+/// Parses a serialized public key, compressed or uncompressed, that is a point on secp256k1. All-zero
+/// bytes and the encoding of the point at infinity are not, and keys whose secret key is known, such as
+/// the generator, are rejected as well, so that no address is ever derived from them.
+///
+/// # Returns
+/// The public key, or [BtcError::InvalidPublicKey].
+pub fn parse_public_key(bytes: &[u8]) -> Result<BitcoinPublicKey, BtcError> {
+    let public_key = BitcoinPublicKey::from_slice(bytes).map_err(|_| BtcError::InvalidPublicKey)?;
+    if public_key.inner.x_only_public_key().0.serialize() == GENERATOR_X {
+        return Err(BtcError::InvalidPublicKey);
+    }
+    Ok(public_key)
+}
+
+/// Decodes a compact ECDSA or Schnorr message signature of 64 or 65 bytes as supplied by a wallet or
+/// tool, either base64 encoded or hex encoded with an optional `0x`/`0X` prefix. Base64 is tried first,
+/// as a hex string may also be valid base64 of another length.
+///
+/// # Returns
+/// The signature bytes, or [BtcError::SignatureFormatError] if `signature` is neither.
+pub fn decode_signature(signature: &str) -> Result<Vec<u8>, BtcError> {
+    let is_signature = |bytes: &Vec<u8>| bytes.len() == 64 || bytes.len() == 65;
+    general_purpose::STANDARD
+        .decode(signature)
+        .ok()
+        .filter(is_signature)
+        .or_else(|| {
+            hex::decode(strip_hex_prefix(signature))
+                .ok()
+                .filter(is_signature)
+        })
+        .ok_or_else(|| {
+            BtcError::SignatureFormatError(
+                "expected a base64 or hex encoded signature of 64 or 65 bytes".to_string(),
+            )
+        })
+}
+
+/// Strips an optional `0x`/`0X` prefix from a hex string.
+fn strip_hex_prefix(hex_str: &str) -> &str {
+    hex_str
+        .strip_prefix("0x")
+        .or_else(|| hex_str.strip_prefix("0X"))
+        .unwrap_or(hex_str)
+}
+
+/// The position of the recovery byte `v` in a 65 byte compact ECDSA signature.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignatureLayout {
+    /// `v || r || s`, as produced by Bitcoin Core and most Bitcoin wallets. `v` is a header byte from 27
+    /// to 42 that encodes the recovery id and, per BIP-137, the key compression and address type.
+    HeaderFirst,
+    /// `r || s || v`, as produced by Ethereum style signers and some hardware wallet SDKs. `v` is either
+    /// the raw recovery id (0 to 3) or a header byte.
+    RecoveryIdLast,
+}
+
+impl SignatureLayout {
+    /// Returns the layouts that `signature_bytes` can be read in, i.e. those whose `v` byte is a valid
+    /// recovery byte, [SignatureLayout::HeaderFirst] first. Since `r` and `s` are random, both layouts
+    /// can be plausible, only recovering the public key tells them apart. Signatures that fit neither
+    /// layout are read as [SignatureLayout::HeaderFirst], so that recovery reports the error.
+    pub fn candidates(signature_bytes: &[u8]) -> Vec<SignatureLayout> {
+        let is_recovery_byte = |v: &u8| *v <= 3 || (27..=42).contains(v);
+        let mut layouts = Vec::with_capacity(2);
+        if signature_bytes.len() == 65 {
+            if is_recovery_byte(&signature_bytes[0]) {
+                layouts.push(SignatureLayout::HeaderFirst);
+            }
+            if is_recovery_byte(&signature_bytes[64]) {
+                layouts.push(SignatureLayout::RecoveryIdLast);
+            }
+        }
+        if layouts.is_empty() {
+            layouts.push(SignatureLayout::HeaderFirst);
+        }
+        layouts
+    }
+}
+
+/// Recovers the compressed public key from a 65 byte compact signature laid out as `layout` over
+/// `message_hash`. See [recover_pub_key_compact].
+pub fn recover_pub_key_with_layout(
+    signature_bytes: &[u8],
+    message_hash: &[u8],
+    chain_id: Option<u8>,
+    layout: SignatureLayout,
+) -> Result<Vec<u8>, BtcError> {
+    match layout {
+        SignatureLayout::HeaderFirst => {
+            recover_pub_key_compact(signature_bytes, message_hash, chain_id)
+        }
+        SignatureLayout::RecoveryIdLast => {
+            let mut header_first = signature_bytes.to_vec();
+            if header_first.len() == 65 {
+                header_first.rotate_right(1);
+            }
+            recover_pub_key_compact(&header_first, message_hash, chain_id)
+        }
+    }
+}
+
+/// Recovers the compressed public key from a compact signature over `message_hash` with `recovery_id`, 0
+/// to 3, as supplied by the client, instead of deriving it from a recovery byte, whose encoding differs
+/// between wallets. The signature is `r || s`, or a 65 byte signature laid out as `layout` whose recovery
+/// byte is ignored.
+pub fn recover_pub_key_with_recovery_id(
+    signature_bytes: &[u8],
+    message_hash: &[u8],
+    recovery_id: u8,
+    layout: SignatureLayout,
+) -> Result<Vec<u8>, BtcError> {
+    let rs = match (signature_bytes.len(), layout) {
+        (64, _) => signature_bytes,
+        (65, SignatureLayout::HeaderFirst) => &signature_bytes[1..],
+        (65, SignatureLayout::RecoveryIdLast) => &signature_bytes[..64],
+        (len, _) => {
+            return Err(BtcError::SignatureFormatError(format!(
+                "expected 64 or 65 bytes, got {}",
+                len
+            )))
+        }
+    };
+    let recovery_id = RecoveryId::from_byte(recovery_id).ok_or(BtcError::InvalidRecoveryId)?;
+    let signature = Signature::from_slice(rs).map_err(|_| BtcError::InvalidSignature)?;
+    let verifying_key = VerifyingKey::recover_from_prehash(message_hash, &signature, recovery_id)
+        .map_err(|_| BtcError::PublicKeyRecoveryFailure)?;
+    Ok(verifying_key.to_encoded_point(true).to_bytes().to_vec())
+}
+
+/// Recovers the compressed public key from a 65 byte compact signature (header byte followed by `r`
+/// and `s`, see [SignatureLayout::HeaderFirst]) over `message_hash`. Use [recover_pub_key_with_layout]
+/// for signatures with the recovery byte last.
+pub fn recover_pub_key_compact(
+    signature_bytes: &[u8],
+    message_hash: &[u8],
+    chain_id: Option<u8>,
+) -> Result<Vec<u8>, BtcError> {
+    let result = _recover_pub_key_compact(signature_bytes, message_hash, chain_id);
+    #[cfg(feature = "debug")]
+    if let Err(e) = &result {
+        log_recovery_failure(signature_bytes, chain_id, e);
+    }
+    result
+}
+
+/// Logs the header byte `v`, the recovery id derived from it and the signature length, the details
+/// that tell apart the signature formats of different wallets.
+#[cfg(feature = "debug")]
+fn log_recovery_failure(signature_bytes: &[u8], chain_id: Option<u8>, error: &BtcError) {
+    let v = signature_bytes.first().copied();
+    let rid = match v {
+        Some(v) if signature_bytes.len() == 65 => {
+            calculate_sig_recovery(if v < 27 { v + 27 } else { v }, chain_id).to_string()
+        }
+        _ => "none".to_string(),
+    };
+    crate::debug::log(format!(
+        "Public key recovery failed: {}, v = {:?}, rid = {}, signature length = {}",
+        error,
+        v,
+        rid,
+        signature_bytes.len()
+    ));
+}
+
+fn _recover_pub_key_compact(
+    signature_bytes: &[u8],
+    message_hash: &[u8],
+    chain_id: Option<u8>,
+) -> Result<Vec<u8>, BtcError> {
+    if signature_bytes.len() != 65 {
+        return Err(BtcError::SignatureFormatError(format!(
+            "expected 65 bytes, got {}",
+            signature_bytes.len()
+        )));
+    }
+    let mut v = signature_bytes[0];
+    let r: Vec<u8> = signature_bytes[1..33].to_vec();
+    let s: Vec<u8> = signature_bytes[33..65].to_vec();
+    if v < 27 {
+        v = v + 27;
+    }
+
+    let mut bytes = [0u8; 65];
+    let rid = calculate_sig_recovery(v.clone(), chain_id);
+    bytes[0..32].clone_from_slice(&r);
+    bytes[32..64].clone_from_slice(&s);
+    bytes[64] = rid;
+
+    if rid > 3 {
+        return Err(BtcError::InvalidRecoveryId);
+    }
+
+    let recovery_id = RecoveryId::try_from(bytes[64]).map_err(|_| BtcError::InvalidRecoveryId)?;
+
+    let signature = Signature::from_slice(&bytes[..64]).map_err(|_| BtcError::InvalidSignature)?;
+
+    let verifying_key = VerifyingKey::recover_from_prehash(&message_hash, &signature, recovery_id)
+        .map_err(|_| BtcError::PublicKeyRecoveryFailure)?;
+
+    Ok(verifying_key.to_encoded_point(true).to_bytes().to_vec())
+}
+
+pub fn msg_hash(message: String) -> Vec<u8> {
+    _msg_hash(message)
+}
+
+pub fn calculate_sig_recovery(mut v: u8, chain_id: Option<u8>) -> u8 {
+    if v == 0 || v == 1 {
+        return v;
+    }
+
+    return if chain_id.is_none() {
+        v = v - 27;
+        while v > 3 {
+            v = v - 4;
+        }
+        v
+    } else {
+        v = v - (chain_id.unwrap() * 2 + 35);
+        while v > 3 {
+            v = v - 4;
+        }
+        v
+    };
+}
+
+/// Checks that the public key `pub_bytes` corresponds to `address` by reconstructing the address of
+/// `address`'s type and network from the key and comparing it to the input.
+///
+/// The type and network are taken from parsing `address` rather than from its prefix, so malformed
+/// addresses, including bech32 and Base58 addresses with an invalid checksum, are rejected with
+/// [BtcError::AddressFormatError] before any key is derived, and address types that cannot be
+/// derived from a single public key (P2WSH, future witness versions, ...) are rejected with
+/// [BtcError::AddressTypeNotSupported]. A public key that does not correspond to `address` is
+/// rejected with [BtcError::AddressMismatch]. P2PKH addresses match the key in either its compressed
+/// or its uncompressed form, see [p2pkh_addresses].
+pub fn verify_address(address: &str, pub_bytes: Vec<u8>) -> Result<(), BtcError> {
+    verify_address_with_merkle_root(address, pub_bytes, None)
+}
+
+/// Like [verify_address], but reconstructs Taproot addresses from the internal key `pub_bytes` tweaked
+/// with `merkle_root`, the root of the script tree the address commits to. This verifies script-path
+/// Taproot addresses, [verify_address] only matches key-path addresses without a script tree. The
+/// merkle root is ignored for other address types.
+pub fn verify_address_with_merkle_root(
+    address: &str,
+    pub_bytes: Vec<u8>,
+    merkle_root: Option<TapNodeHash>,
+) -> Result<(), BtcError> {
+    let parsed = Address::<NetworkUnchecked>::from_str(address)?;
+    let network = parsed.network;
+    compare_reconstructed_address(parsed.assume_checked(), pub_bytes, network, merkle_root)
+}
+
+/// Like [verify_address], but reconstructs the address on `network` instead of the network implied
+/// by the address. Testnet, signet and regtest share the `2...`, `m...`/`n...` and (apart from
+/// regtest) `tb1...` encodings, so only the configured network can tell them apart. An address that
+/// is not valid for `network` is rejected with [BtcError::NetworkMismatch].
+pub fn verify_address_for_network(
+    address: &str,
+    pub_bytes: Vec<u8>,
+    network: Network,
+) -> Result<(), BtcError> {
+    let parsed = Address::<NetworkUnchecked>::from_str(address)?;
+    if !parsed.is_valid_for_network(network) {
+        return Err(BtcError::NetworkMismatch);
+    }
+    compare_reconstructed_address(parsed.assume_checked(), pub_bytes, network, None)
+}
+
+/// Like [verify_address_for_network], for tools that do not know the network of `address`. Tries each
+/// network `address` is valid for, in the order mainnet, testnet, signet, regtest, and returns the
+/// first network on which `pub_bytes` reconstructs the address, along with the address in its
+/// canonical encoding. Testnet and signet share their encodings, so an address of either is reported
+/// as testnet.
+///
+/// # Returns
+/// The matching `(Network, String)` pair, or the error of the last network that was tried, usually
+/// [BtcError::AddressMismatch].
+pub fn verify_address_any_network(
+    address: &str,
+    pub_bytes: Vec<u8>,
+) -> Result<(Network, String), BtcError> {
+    let parsed = Address::<NetworkUnchecked>::from_str(address)?;
+
+    let mut result = Err(BtcError::NetworkMismatch);
+    for network in [
+        Network::Bitcoin,
+        Network::Testnet,
+        Network::Signet,
+        Network::Regtest,
+    ] {
+        if !parsed.is_valid_for_network(network) {
+            continue;
+        }
+        let checked = parsed.clone().assume_checked();
+        let canonical = checked.to_string();
+        match compare_reconstructed_address(checked, pub_bytes.clone(), network, None) {
+            Ok(()) => return Ok((network, canonical)),
+            Err(e) => result = Err(e),
+        }
+    }
+    result
+}
+
+/// Finds the child of `xpub` that signed `message`, so that users of custody setups can sign in without
+/// telling which of their derived addresses they used. The children `xpub/i` for each non-hardened index
+/// `i` in `derivation_range` are derived and compared with the public key recovered from `signature`.
+/// Pass the xpub of the chain that holds the addresses, e.g. the external chain `.../0`.
+///
+/// # Parameters
+/// * `xpub`: The base58 encoded extended public key.
+/// * `derivation_range`: The child indexes to search, at most [MAX_XPUB_DERIVATION_RANGE] of them.
+/// * `signature`: The base64 or hex encoded compact ECDSA signature. Its BIP-137 header byte selects the
+///   address type of the returned address: P2PKH (31 to 34), P2SH-P2WPKH (35 to 38) or P2WPKH (39 to 42).
+/// * `message`: The exact message that was signed.
+///
+/// # Returns
+/// The address of the signing child on the xpub's network and its index, or [BtcError::AddressMismatch]
+/// if no child in the range signed the message. A header byte of an uncompressed key (27 to 30) is
+/// rejected with [BtcError::UncompressedKeyNotAllowed], any other header with
+/// [BtcError::SignatureFormatError].
+pub fn verify_against_xpub(
+    xpub: &str,
+    derivation_range: Range<u32>,
+    signature: &str,
+    message: &str,
+) -> Result<(String, u32), BtcError> {
+    if derivation_range.len() > MAX_XPUB_DERIVATION_RANGE as usize {
+        return Err(BtcError::DerivationRangeTooLarge(MAX_XPUB_DERIVATION_RANGE));
+    }
+    let xpub = ExtendedPubKey::from_str(xpub).map_err(|_| BtcError::InvalidPublicKey)?;
+    let signature_bytes = decode_signature(signature)?;
+    let recovered =
+        recover_pub_key_compact(&signature_bytes, &_msg_hash(message.to_string()), None)?;
+
+    let secp = Secp256k1::verification_only();
+    for index in derivation_range {
+        let Ok(child_number) = ChildNumber::from_normal_idx(index) else {
+            break;
+        };
+        let public_key = xpub
+            .ckd_pub(&secp, child_number)
+            .map_err(|_| BtcError::InvalidPublicKey)?
+            .to_pub();
+        if public_key.to_bytes() != recovered {
+            continue;
+        }
+
+        let address = match signature_bytes[0] {
+            31..=34 => Address::p2pkh(&public_key, xpub.network),
+            35..=38 => Address::p2shwpkh(&public_key, xpub.network)
+                .map_err(|_| BtcError::InvalidPublicKey)?,
+            39..=42 => Address::p2wpkh(&public_key, xpub.network)
+                .map_err(|_| BtcError::InvalidPublicKey)?,
+            // Extended keys are always compressed.
+            27..=30 => return Err(BtcError::UncompressedKeyNotAllowed),
+            header => {
+                return Err(BtcError::SignatureFormatError(format!(
+                    "invalid header byte {}",
+                    header
+                )))
+            }
+        };
+        return Ok((address.to_string(), index));
+    }
+    Err(BtcError::AddressMismatch)
+}
+
+fn compare_reconstructed_address(
+    parsed: Address,
+    pub_bytes: Vec<u8>,
+    network: Network,
+    merkle_root: Option<TapNodeHash>,
+) -> Result<(), BtcError> {
+    if parsed.address_type() == Some(AddressType::P2pkh) {
+        let public_key = parse_public_key(&pub_bytes)?;
+        let script_pubkey = parsed.script_pubkey();
+        return match p2pkh_addresses(&public_key, network)
+            .iter()
+            .any(|address| address.script_pubkey() == script_pubkey)
+        {
+            true => Ok(()),
+            false => Err(BtcError::AddressMismatch),
+        };
+    }
+
+    let reconstructed = reconstruct_address(&parsed, pub_bytes, network, merkle_root)?;
+
+    // Compare script pubkeys rather than strings, the input may use a different bech32 casing.
+    if reconstructed.script_pubkey() != parsed.script_pubkey() {
+        return Err(BtcError::AddressMismatch);
+    }
+    Ok(())
+}
+
+/// Derives the address of `parsed`'s type on `network` from the public key `pub_bytes`. Taproot
+/// addresses are tweaked with `merkle_root`.
+fn reconstruct_address(
+    parsed: &Address,
+    pub_bytes: Vec<u8>,
+    network: Network,
+    merkle_root: Option<TapNodeHash>,
+) -> Result<Address, BtcError> {
+    let public_key = parse_public_key(&pub_bytes)?;
+    let address_type = parsed.address_type().ok_or(AddressTypeNotSupported)?;
+    address_for_key(&public_key, address_type, network, merkle_root)
+}
+
+/// Derives the two P2PKH addresses on `network` of the key of `public_key`, from its compressed and from
+/// its uncompressed form. A P2PKH address commits to the serialized key, and wallets do not always
+/// return the key in the form the address was generated from, so [verify_address] accepts either.
+pub fn p2pkh_addresses(public_key: &BitcoinPublicKey, network: Network) -> [Address; 2] {
+    [true, false].map(|compressed| {
+        let public_key = BitcoinPublicKey {
+            compressed,
+            inner: public_key.inner,
+        };
+        Address::p2pkh(&public_key, network)
+    })
+}
+
+/// Derives the two P2PKH addresses of `public_key`, like [p2pkh_addresses], but encoded with the Base58
+/// version byte `version` instead of that of a Bitcoin network, for altcoin-derived chains and custom
+/// regtest networks, e.g. 0x30 for Litecoin. Bitcoin mainnet uses 0x00, the test networks 0x6f.
+#[cfg(feature = "custom-p2pkh")]
+pub fn p2pkh_addresses_with_version(public_key: &BitcoinPublicKey, version: u8) -> [String; 2] {
+    use bitcoin::hashes::Hash;
+
+    [true, false].map(|compressed| {
+        let public_key = BitcoinPublicKey {
+            compressed,
+            inner: public_key.inner,
+        };
+        let mut payload = vec![version];
+        payload.extend_from_slice(public_key.pubkey_hash().as_byte_array());
+        bitcoin::base58::encode_check(&payload)
+    })
+}
+
+/// Like [verify_address], but for a P2PKH `address` encoded with the Base58 version byte
+/// `p2pkh_version`, see [p2pkh_addresses_with_version]. Addresses of the standard Bitcoin networks are
+/// verified with [verify_address] when `p2pkh_version` is `None`.
+///
+/// # Returns
+/// [BtcError::NetworkMismatch] if `address` has another version byte, or [BtcError::AddressMismatch]
+/// if `pub_bytes` does not derive it.
+#[cfg(feature = "custom-p2pkh")]
+pub fn verify_p2pkh_address_with_version(
+    address: &str,
+    pub_bytes: Vec<u8>,
+    p2pkh_version: Option<u8>,
+) -> Result<(), BtcError> {
+    let Some(version) = p2pkh_version else {
+        return verify_address(address, pub_bytes);
+    };
+    let payload = bitcoin::base58::decode_check(address)
+        .map_err(|e| BtcError::AddressFormatError(e.to_string()))?;
+    if payload.len() != 21 {
+        return Err(BtcError::AddressFormatError(
+            "not a P2PKH address".to_string(),
+        ));
+    }
+    if payload[0] != version {
+        return Err(BtcError::NetworkMismatch);
+    }
+    let public_key = parse_public_key(&pub_bytes)?;
+    match p2pkh_addresses_with_version(&public_key, version).contains(&address.to_string()) {
+        true => Ok(()),
+        false => Err(BtcError::AddressMismatch),
+    }
+}
+
+/// Derives the address of `address_type` on `network` that `public_key` controls.
+fn address_for_key(
+    public_key: &BitcoinPublicKey,
+    address_type: AddressType,
+    network: Network,
+    merkle_root: Option<TapNodeHash>,
+) -> Result<Address, BtcError> {
+    // Segwit and Taproot addresses can only commit to compressed keys. A wallet that supplies an
+    // uncompressed key for one of them is buggy, so reject the key instead of compressing it.
+    if address_type != AddressType::P2pkh && !public_key.compressed {
+        return Err(BtcError::UncompressedKeyNotAllowed);
+    }
+
+    let secp = Secp256k1::verification_only();
+    let address = match address_type {
+        AddressType::P2pkh => Address::p2pkh(public_key, network),
+        AddressType::P2wpkh => Address::p2wpkh(public_key, network)?,
+        // A P2SH address does not reveal its redeem script, only P2SH-P2WPKH can be derived from a
+        // single key. Other P2SH scripts such as P2SH-P2WSH fail the comparison.
+        AddressType::P2sh => Address::p2shwpkh(public_key, network)?,
+        AddressType::P2tr => {
+            let internal_key = XOnlyPublicKey::from(public_key.inner);
+            Address::p2tr(&secp, internal_key, merkle_root, network)
+        }
+        _ => return Err(AddressTypeNotSupported),
+    };
+    Ok(address)
+}
+
+/// Lists every address that `public_key` can sign in with on `network`: P2PKH, P2WPKH, P2SH-P2WPKH and
+/// key-path P2TR, derived as [verify_address] reconstructs them, so that wallet UIs can let users pick
+/// one. An uncompressed key only yields its P2PKH address, a key that is not a valid secp256k1 public
+/// key yields none.
+pub fn derive_all_addresses(public_key: &[u8], network: Network) -> Vec<(AddressType, String)> {
+    let Ok(public_key) = parse_public_key(public_key) else {
+        return vec![];
+    };
+    [
+        AddressType::P2pkh,
+        AddressType::P2wpkh,
+        AddressType::P2sh,
+        AddressType::P2tr,
+    ]
+    .into_iter()
+    .filter_map(|address_type| {
+        address_for_key(&public_key, address_type, network, None)
+            .ok()
+            .map(|address| (address_type, address.to_string()))
+    })
+    .collect()
+}
+
+/// Tells who signed `message`: recovers the public key from the compact ECDSA `signature`, base64 or hex
+/// encoded with the header byte first, and derives every address on `network` that the key controls,
+/// as [derive_all_addresses] does, plus the P2PKH address of the uncompressed key, see
+/// [p2pkh_addresses]. The message is framed and double hashed like a legacy Bitcoin message signature.
+///
+/// # Returns
+/// The `(AddressType, String)` pairs, or a [BtcError] if the signature cannot be decoded or no key can
+/// be recovered from it.
+pub fn recover_address(
+    signature: &str,
+    message: &str,
+    network: Network,
+) -> Result<Vec<(AddressType, String)>, BtcError> {
+    let signature_bytes = decode_signature(signature)?;
+    let recovered =
+        recover_pub_key_compact(&signature_bytes, &_msg_hash(message.to_string()), None)?;
+    let public_key =
+        BitcoinPublicKey::from_slice(&recovered).map_err(|_| BtcError::PublicKeyRecoveryFailure)?;
+
+    let mut addresses = derive_all_addresses(&recovered, network);
+    let [_, uncompressed] = p2pkh_addresses(&public_key, network);
+    addresses.insert(1, (AddressType::P2pkh, uncompressed.to_string()));
+    Ok(addresses)
+}
+
+/// Recovers the compressed public keys, hex encoded, that can have produced the compact ECDSA `signature`
+/// over `message`, one for each plausible [SignatureLayout]. Messages are framed with `prefix`, see
+/// [message_frame_with_prefix]. A signature that cannot be decoded or recovered yields none.
+pub fn recover_public_keys(
+    message: &str,
+    signature: &str,
+    double_hash: bool,
+    prefix: &str,
+) -> Vec<String> {
+    let message_prehashed = _msg_hash_with_prefix(message.to_string(), double_hash, prefix);
+    recover_public_keys_from_digest(&message_prehashed, signature)
+}
+
+/// Like [recover_public_keys], but for a signature over `message_prehashed`, e.g. a [message_digest].
+pub fn recover_public_keys_from_digest(message_prehashed: &[u8], signature: &str) -> Vec<String> {
+    let Ok(signature_bytes) = decode_signature(signature) else {
+        return vec![];
+    };
+    let mut public_keys = Vec::new();
+    for layout in SignatureLayout::candidates(&signature_bytes) {
+        if let Ok(key) =
+            recover_pub_key_with_layout(&signature_bytes, message_prehashed, None, layout)
+        {
+            let key = hex::encode(key);
+            if !public_keys.contains(&key) {
+                public_keys.push(key);
+            }
+        }
+    }
+    public_keys
+}
+
+/// Returns the address, of the same type as `address` and on `network`, of the key that produced the
+/// compact ECDSA `signature` over `message`. Useful to explain a mismatch, as it shows which address
+/// the wallet actually signed with. Returns `None` if no key can be recovered from the signature.
+/// Messages are framed with `prefix`, see [message_frame_with_prefix].
+pub fn recover_signing_address(
+    message: String,
+    signature: &str,
+    double_hash: bool,
+    prefix: &str,
+    address: &str,
+    network: Network,
+) -> Option<String> {
+    let signature_bytes = decode_signature(signature).ok()?;
+    let message_prehashed = _msg_hash_with_prefix(message, double_hash, prefix);
+    let layout = SignatureLayout::candidates(&signature_bytes)[0];
+    let recovered =
+        recover_pub_key_with_layout(&signature_bytes, &message_prehashed, None, layout).ok()?;
+    let parsed = Address::<NetworkUnchecked>::from_str(address)
+        .ok()?
+        .assume_checked();
+    reconstruct_address(&parsed, recovered, network, None)
+        .ok()
+        .map(|a| a.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // These tests deliberately never initialize the library settings, any use of library state would
+    // make them trap.
+    const PUBLIC_KEY: &str = "03133c85d348d6c0796382966380719397453592e706cd3329119a2d2cb8d2ff7b";
+    const SIGNATURE: &str =
+        "HPVVoaHfyCUER9YB6MC8C+eh3in24rHTScQopgwzzEx6GP9fwZBI+ZIesS1HNzbMzMgLFS10IyhMc6aYbn3zfI4=";
+    const MESSAGE: &str = "{\"a\":1,\"b\":[2,3,4]}";
+    const ADDRESS: &str = "tb1pgvdp7lf89d62zadds5jvyjntxmr7v70yv33g7vqaeu2p0cuexveqjlwphr";
+
+    #[test]
+    fn test_verify_without_library_state() {
+        assert!(crate::SETTINGS.with_borrow(|s| s.is_none()));
+
+        let verified = verify_signed_message(
+            MESSAGE.to_string(),
+            SIGNATURE.to_string(),
+            PUBLIC_KEY.to_string(),
+            ADDRESS,
+        )
+        .unwrap();
+        assert_eq!(verified, VerifiedAddress(ADDRESS.to_string()));
+    }
+
+    #[test]
+    fn test_decode_signature() {
+        let bytes = general_purpose::STANDARD.decode(SIGNATURE).unwrap();
+        assert_eq!(decode_signature(SIGNATURE).unwrap(), bytes);
+        assert_eq!(decode_signature(&hex::encode(&bytes)).unwrap(), bytes);
+        assert_eq!(
+            decode_signature(&format!("0x{}", hex::encode(&bytes))).unwrap(),
+            bytes
+        );
+        assert_eq!(
+            decode_signature(&format!("0X{}", hex::encode_upper(&bytes))).unwrap(),
+            bytes
+        );
+
+        // A 64 byte signature in hex is also valid base64, of 96 bytes.
+        let schnorr = [7u8; 64];
+        assert_eq!(decode_signature(&hex::encode(schnorr)).unwrap(), schnorr);
+
+        for malformed in [
+            "",
+            "0x",
+            "not a signature",
+            "0x0102",
+            &hex::encode([1u8; 66]),
+        ] {
+            assert!(matches!(
+                decode_signature(malformed),
+                Err(BtcError::SignatureFormatError(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_verify_hex_signature() {
+        let bytes = general_purpose::STANDARD.decode(SIGNATURE).unwrap();
+        for signature in [
+            SIGNATURE.to_string(),
+            hex::encode(&bytes),
+            format!("0x{}", hex::encode(&bytes)),
+        ] {
+            let verified = verify_signed_message(
+                MESSAGE.to_string(),
+                signature,
+                PUBLIC_KEY.to_string(),
+                ADDRESS,
+            )
+            .unwrap();
+            assert_eq!(verified, VerifiedAddress(ADDRESS.to_string()));
+        }
+
+        assert!(matches!(
+            verify_signed_message(
+                MESSAGE.to_string(),
+                "0xnot hex".to_string(),
+                PUBLIC_KEY.to_string(),
+                ADDRESS
+            ),
+            Err(BtcError::SignatureFormatError(_))
+        ));
+    }
+
+    #[test]
+    fn test_verify_p2pkh_address_either_key_form() {
+        let compressed = BitcoinPublicKey::from_str(PUBLIC_KEY).unwrap();
+        let uncompressed = BitcoinPublicKey {
+            compressed: false,
+            inner: compressed.inner,
+        };
+        let [compressed_address, uncompressed_address] =
+            p2pkh_addresses(&compressed, Network::Testnet);
+        assert_eq!(
+            compressed_address,
+            Address::p2pkh(&compressed, Network::Testnet)
+        );
+        assert_eq!(
+            uncompressed_address,
+            Address::p2pkh(&uncompressed, Network::Testnet)
+        );
+
+        // Only the uncompressed derivation matches the address, the wallet returned the compressed key.
+        let address = uncompressed_address.to_string();
+        assert_ne!(address, compressed_address.to_string());
+        assert!(verify_address(&address, compressed.to_bytes()).is_ok());
+        assert!(verify_address(&address, uncompressed.to_bytes()).is_ok());
+        assert!(verify_address(&compressed_address.to_string(), uncompressed.to_bytes()).is_ok());
+
+        let other_key = BitcoinPublicKey::from_str(
+            "02e203c98d766554bb4dab431d70b014b505aac66f47b735d9e7cbb4f12108ac3d",
+        )
+        .unwrap();
+        assert!(matches!(
+            verify_address(&address, other_key.to_bytes()),
+            Err(BtcError::AddressMismatch)
+        ));
+    }
+
+    #[test]
+    fn test_recover_address() {
+        let public_key = BitcoinPublicKey::from_str(PUBLIC_KEY).unwrap();
+        let [_, uncompressed] = p2pkh_addresses(&public_key, Network::Testnet);
+        let addresses = recover_address(SIGNATURE, MESSAGE, Network::Testnet).unwrap();
+        assert!(addresses.contains(&(AddressType::P2tr, ADDRESS.to_string())));
+
+        let mut expected = derive_all_addresses(&public_key.to_bytes(), Network::Testnet);
+        expected.insert(1, (AddressType::P2pkh, uncompressed.to_string()));
+        assert_eq!(addresses, expected);
+
+        let bytes = general_purpose::STANDARD.decode(SIGNATURE).unwrap();
+        assert_eq!(
+            recover_address(&hex::encode(bytes), MESSAGE, Network::Testnet).unwrap(),
+            expected
+        );
+
+        // A signature over another message recovers another key.
+        assert!(!recover_address(SIGNATURE, "other", Network::Testnet)
+            .unwrap()
+            .contains(&(AddressType::P2tr, ADDRESS.to_string())));
+        assert!(matches!(
+            recover_address("not a signature", MESSAGE, Network::Testnet),
+            Err(BtcError::SignatureFormatError(_))
+        ));
+    }
+
+    #[test]
+    fn test_recover_public_keys() {
+        let public_keys = recover_public_keys(MESSAGE, SIGNATURE, true, MAGIC_BYTES);
+        assert!(public_keys.contains(&PUBLIC_KEY.to_string()));
+        assert!(!recover_public_keys("other", SIGNATURE, true, MAGIC_BYTES)
+            .contains(&PUBLIC_KEY.to_string()));
+        assert!(recover_public_keys(MESSAGE, "not base64", true, MAGIC_BYTES).is_empty());
+    }
+
+    #[test]
+    fn test_derive_all_addresses() {
+        let addresses = derive_all_addresses(&hex::decode(PUBLIC_KEY).unwrap(), Network::Testnet);
+        let types: Vec<AddressType> = addresses.iter().map(|(t, _)| *t).collect();
+        assert_eq!(
+            types,
+            vec![
+                AddressType::P2pkh,
+                AddressType::P2wpkh,
+                AddressType::P2sh,
+                AddressType::P2tr
+            ]
+        );
+        assert!(addresses.contains(&(AddressType::P2tr, ADDRESS.to_string())));
+        for (_, address) in &addresses {
+            assert!(verify_address(address, hex::decode(PUBLIC_KEY).unwrap()).is_ok());
+        }
+
+        // An uncompressed key only controls a P2PKH address.
+        let mut uncompressed =
+            BitcoinPublicKey::from_slice(&hex::decode(PUBLIC_KEY).unwrap()).unwrap();
+        uncompressed.compressed = false;
+        let addresses = derive_all_addresses(&uncompressed.to_bytes(), Network::Bitcoin);
+        assert_eq!(addresses.len(), 1);
+        assert_eq!(addresses[0].0, AddressType::P2pkh);
+
+        assert!(derive_all_addresses(&[5; 33], Network::Bitcoin).is_empty());
+    }
+
+    #[test]
+    fn test_verify_address_invalid_checksum() {
+        let public_key = hex::decode(PUBLIC_KEY).unwrap();
+        let corrupted = ADDRESS.replace("jlwphr", "jlwphq");
+        for address in [
+            corrupted.as_str(),
+            "bc1qshqyem2rf8jyla904gd2cvek2k8nz5z3x73p25",
+            "1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN3",
+        ] {
+            match verify_address(address, public_key.clone()) {
+                Err(BtcError::AddressFormatError(e)) => {
+                    assert!(e.contains("checksum"), "{}: {}", address, e)
+                }
+                other => panic!("{}: {:?}", address, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_verify_nested_segwit_non_mainnet() {
+        let key = hex::decode("02e203c98d766554bb4dab431d70b014b505aac66f47b735d9e7cbb4f12108ac3d")
+            .unwrap();
+        let address = "2NBbnaYUvZvrvKfd7wqMmt7bZoAMTSkAarU";
+
+        for network in [Network::Testnet, Network::Signet, Network::Regtest] {
+            assert!(
+                verify_address_for_network(address, key.clone(), network).is_ok(),
+                "Expected {} to verify on {}",
+                address,
+                network
+            );
+        }
+        assert!(matches!(
+            verify_address_for_network(address, key.clone(), Network::Bitcoin),
+            Err(BtcError::NetworkMismatch)
+        ));
+
+        let mainnet = "3L3aWoYtxUMa7szaGhjuGAcJap9Hb13EEP";
+        assert!(verify_address_for_network(mainnet, key.clone(), Network::Bitcoin).is_ok());
+        assert!(matches!(
+            verify_address_for_network(mainnet, key.clone(), Network::Signet),
+            Err(BtcError::NetworkMismatch)
+        ));
+
+        let other_key =
+            hex::decode("03133c85d348d6c0796382966380719397453592e706cd3329119a2d2cb8d2ff7b")
+                .unwrap();
+        assert!(matches!(
+            verify_address_for_network(address, other_key, Network::Signet),
+            Err(BtcError::AddressMismatch)
+        ));
+    }
+
+    #[test]
+    fn test_verify_taproot_schnorr_signature() {
+        use bitcoin::key::KeyPair;
+
+        let secp = Secp256k1::new();
+        let keypair = KeyPair::from_seckey_slice(&secp, &[7u8; 32]).unwrap();
+        let public_key = hex::encode(keypair.public_key().serialize());
+        let (internal_key, _) = keypair.x_only_public_key();
+        let address = Address::p2tr(&secp, internal_key, None, Network::Bitcoin).to_string();
+
+        // Key-spend signatures are made with the tweaked key.
+        let tweaked = keypair.tap_tweak(&secp, None).to_inner();
+        let message = "Sign in with Taproot";
+        let hash = msg_hash(message.to_string());
+        let signature =
+            secp.sign_schnorr_no_aux_rand(&Message::from_slice(&hash).unwrap(), &tweaked);
+        let signature = general_purpose::STANDARD.encode(signature.as_ref());
+
+        let verified = verify_signed_message(
+            message.to_string(),
+            signature.clone(),
+            public_key.clone(),
+            &address,
+        )
+        .unwrap();
+        assert_eq!(verified, VerifiedAddress(address.clone()));
+
+        let result = verify_signed_message(
+            "Another message".to_string(),
+            signature.clone(),
+            public_key,
+            &address,
+        );
+        assert!(matches!(result, Err(BtcError::InvalidSignature)));
+
+        let other_key = hex::encode(
+            KeyPair::from_seckey_slice(&secp, &[8u8; 32])
+                .unwrap()
+                .public_key()
+                .serialize(),
+        );
+        let result = verify_signed_message(message.to_string(), signature, other_key, &address);
+        assert!(matches!(result, Err(BtcError::InvalidSignature)));
+    }
+
+    #[test]
+    fn test_tagged_hash() {
+        // The message hashes of the BIP-322 test vectors.
+        assert_eq!(
+            hex::encode(tagged_hash(DEFAULT_MESSAGE_HASH_TAG, b"")),
+            "c90c269c4f8fcbe6880f72a721ddfbf1914268a794cbb21cfafee13770ae19f1"
+        );
+        assert_eq!(
+            hex::encode(tagged_hash(DEFAULT_MESSAGE_HASH_TAG, b"Hello World")),
+            "f0eb03b1a75ac6d9847f55c624a99169b5dccba2a31f5b23bea77ba270de0a7a"
+        );
+        assert_ne!(
+            tagged_hash("SIWB/message", b"Hello World"),
+            tagged_hash(DEFAULT_MESSAGE_HASH_TAG, b"Hello World")
+        );
+    }
+
+    #[test]
+    fn test_verify_tagged_message() {
+        use bitcoin::key::KeyPair;
+
+        // A key-spend signature by the tweaked key of [7; 32] over the tagged hash of the message.
+        const TAGGED_SIGNATURE: &str = "896356ea0fa88cf33567e68b367e8cb6b85c72dc34b53f073a77fadda56ef090053b5949ed7bcfb62db7f139a90fd0533dfeef9e13a54bdefae0266e79312a2c";
+        let secp = Secp256k1::new();
+        let keypair = KeyPair::from_seckey_slice(&secp, &[7u8; 32]).unwrap();
+        let public_key = hex::encode(keypair.public_key().serialize());
+        let (internal_key, _) = keypair.x_only_public_key();
+        let address = Address::p2tr(&secp, internal_key, None, Network::Bitcoin).to_string();
+        let message = "Sign in with Taproot";
+
+        let verified = verify_tagged_message(
+            message.to_string(),
+            TAGGED_SIGNATURE.to_string(),
+            public_key.clone(),
+            "SIWB/message",
+            &address,
+        )
+        .unwrap();
+        assert_eq!(verified, VerifiedAddress(address.clone()));
+
+        // The signature does not verify against the legacy hash, nor against another tag.
+        let result = verify_signed_message(
+            message.to_string(),
+            TAGGED_SIGNATURE.to_string(),
+            public_key.clone(),
+            &address,
+        );
+        assert!(matches!(result, Err(BtcError::InvalidSignature)));
+        let result = verify_tagged_message(
+            message.to_string(),
+            TAGGED_SIGNATURE.to_string(),
+            public_key,
+            DEFAULT_MESSAGE_HASH_TAG,
+            &address,
+        );
+        assert!(matches!(result, Err(BtcError::InvalidSignature)));
+    }
+
+    #[test]
+    fn test_verify_taproot_schnorr_sighash_flag() {
+        use bitcoin::key::KeyPair;
+
+        let secp = Secp256k1::new();
+        let keypair = KeyPair::from_seckey_slice(&secp, &[7u8; 32]).unwrap();
+        let public_key = hex::encode(keypair.public_key().serialize());
+        let (internal_key, _) = keypair.x_only_public_key();
+        let address = Address::p2tr(&secp, internal_key, None, Network::Bitcoin).to_string();
+
+        let tweaked = keypair.tap_tweak(&secp, None).to_inner();
+        let message = "Sign in with Taproot";
+        let hash = msg_hash(message.to_string());
+        let signature =
+            secp.sign_schnorr_no_aux_rand(&Message::from_slice(&hash).unwrap(), &tweaked);
+        let verify = |bytes: &[u8], allow_legacy_ecdsa: bool| {
+            _verify_message_for_address(
+                message.to_string(),
+                general_purpose::STANDARD.encode(bytes),
+                public_key.clone(),
+                true,
+                MAGIC_BYTES,
+                None,
+                &address,
+                allow_legacy_ecdsa,
+                None,
+            )
+        };
+
+        let implied = signature.as_ref().to_vec();
+        assert!(verify(&implied, true).is_ok());
+        assert!(verify(&implied, false).is_ok());
+
+        let mut explicit = implied.clone();
+        explicit.push(0x01);
+        assert!(verify(&explicit, true).is_ok());
+        assert!(verify(&explicit, false).is_ok());
+
+        // Other flags make no sense for a message signature, the signature is not read as Schnorr.
+        for flag in [0x00, 0x02, 0x83] {
+            let mut invalid = implied.clone();
+            invalid.push(flag);
+            assert!(!is_schnorr_key_spend(
+                &general_purpose::STANDARD.encode(&invalid),
+                &address
+            ));
+            assert!(verify(&invalid, true).is_err());
+            assert!(matches!(
+                verify(&invalid, false),
+                Err(BtcError::LegacyEcdsaDisabled)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_signature_candidates() {
+        let bytes: Vec<u8> = (0..65).collect();
+        let base64 = general_purpose::STANDARD.encode(&bytes);
+        let mut rotated = bytes.clone();
+        rotated.rotate_right(1);
+        let rotated = general_purpose::STANDARD.encode(rotated);
+
+        assert_eq!(
+            signature_candidates(&base64, WalletKind::Base64).unwrap(),
+            vec![base64.clone()]
+        );
+        assert_eq!(
+            signature_candidates(&hex::encode(&bytes), WalletKind::HexRecoveryIdLast).unwrap(),
+            vec![rotated.clone()]
+        );
+        assert_eq!(
+            signature_candidates(&format!("0x{}", hex::encode(&bytes)), WalletKind::Auto).unwrap(),
+            vec![base64.clone(), rotated]
+        );
+        assert_eq!(
+            signature_candidates(&base64, WalletKind::Auto).unwrap(),
+            vec![base64.clone()]
+        );
+
+        assert!(matches!(
+            signature_candidates(&base64, WalletKind::HexRecoveryIdLast),
+            Err(BtcError::SignatureFormatError(_))
+        ));
+        assert!(matches!(
+            signature_candidates("not a signature", WalletKind::Auto),
+            Err(BtcError::SignatureFormatError(_))
+        ));
+    }
+
+    #[test]
+    fn test_message_frame() {
+        let frame = message_frame("abc");
+        assert_eq!(frame[0] as usize, MAGIC_BYTES.len());
+        assert_eq!(&frame[1..25], MAGIC_BYTES.as_bytes());
+        assert_eq!(&frame[25..], &[3, b'a', b'b', b'c']);
+        assert_eq!(
+            hash_bytes(hash_bytes(frame)).to_vec(),
+            _msg_hash_with("abc".to_string(), true)
+        );
+    }
+
+    #[test]
+    fn test_message_frame_with_prefix() {
+        let prefix = "Litecoin Signed Message:\n";
+        let frame = message_frame_with_prefix("abc", prefix);
+        assert_eq!(frame[0] as usize, prefix.len());
+        assert_eq!(&frame[1..26], prefix.as_bytes());
+        assert_eq!(&frame[26..], &[3, b'a', b'b', b'c']);
+        assert_eq!(
+            message_frame_with_prefix("abc", MAGIC_BYTES),
+            message_frame("abc")
+        );
+        assert_ne!(
+            _msg_hash_with_prefix("abc".to_string(), true, prefix),
+            _msg_hash_with("abc".to_string(), true)
+        );
+    }
+
+    #[test]
+    fn test_verify_address_any_network() {
+        let key = hex::decode("02e203c98d766554bb4dab431d70b014b505aac66f47b735d9e7cbb4f12108ac3d")
+            .unwrap();
+        let testnet = "2NBbnaYUvZvrvKfd7wqMmt7bZoAMTSkAarU";
+        assert!(matches!(
+            verify_address_for_network(testnet, key.clone(), Network::Bitcoin),
+            Err(BtcError::NetworkMismatch)
+        ));
+        assert_eq!(
+            verify_address_any_network(testnet, key.clone()).unwrap(),
+            (Network::Testnet, testnet.to_string())
+        );
+
+        let mainnet = "3L3aWoYtxUMa7szaGhjuGAcJap9Hb13EEP";
+        assert_eq!(
+            verify_address_any_network(mainnet, key).unwrap(),
+            (Network::Bitcoin, mainnet.to_string())
+        );
+
+        let other_key = hex::decode(PUBLIC_KEY).unwrap();
+        assert!(matches!(
+            verify_address_any_network(testnet, other_key.clone()),
+            Err(BtcError::AddressMismatch)
+        ));
+
+        // Bech32 addresses are returned in their canonical lowercase encoding.
+        assert_eq!(
+            verify_address_any_network(&ADDRESS.to_uppercase(), other_key).unwrap(),
+            (Network::Testnet, ADDRESS.to_string())
+        );
+    }
+
+    #[test]
+    fn test_verify_against_xpub() {
+        use bitcoin::bip32::ExtendedPrivKey;
+        use k256::ecdsa::SigningKey;
+
+        // BIP-32 test vector 1.
+        let secp = Secp256k1::new();
+        let seed = hex::decode("000102030405060708090a0b0c0d0e0f").unwrap();
+        let master = ExtendedPrivKey::new_master(Network::Bitcoin, &seed).unwrap();
+        let xpub = ExtendedPubKey::from_priv(&secp, &master).to_string();
+        assert_eq!(xpub, "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8");
+
+        let child = master
+            .ckd_priv(&secp, ChildNumber::from_normal_idx(7).unwrap())
+            .unwrap();
+        let key = SigningKey::from_bytes(&child.private_key.secret_bytes().into()).unwrap();
+        let (signature, recovery_id) = key
+            .sign_prehash_recoverable(&_msg_hash(MESSAGE.to_string()))
+            .unwrap();
+        let signature_with_header = |header: u8| {
+            let mut bytes = vec![header + recovery_id.to_byte()];
+            bytes.extend_from_slice(&signature.to_bytes());
+            general_purpose::STANDARD.encode(bytes)
+        };
+
+        let child_public_key = child.to_priv().public_key(&secp);
+        let p2wpkh = Address::p2wpkh(&child_public_key, Network::Bitcoin).unwrap();
+        assert_eq!(
+            verify_against_xpub(&xpub, 0..20, &signature_with_header(39), MESSAGE).unwrap(),
+            (p2wpkh.to_string(), 7)
+        );
+        let p2pkh = Address::p2pkh(&child_public_key, Network::Bitcoin);
+        assert_eq!(
+            verify_against_xpub(&xpub, 5..10, &signature_with_header(31), MESSAGE).unwrap(),
+            (p2pkh.to_string(), 7)
+        );
+
+        assert!(matches!(
+            verify_against_xpub(&xpub, 0..7, &signature_with_header(39), MESSAGE),
+            Err(BtcError::AddressMismatch)
+        ));
+
+        // Headers of uncompressed keys are rejected as such, headers outside the BIP-137 range as
+        // malformed.
+        assert!(matches!(
+            verify_against_xpub(&xpub, 0..20, &signature_with_header(27), MESSAGE),
+            Err(BtcError::UncompressedKeyNotAllowed)
+        ));
+        for header in [0, 43] {
+            assert!(matches!(
+                verify_against_xpub(&xpub, 0..20, &signature_with_header(header), MESSAGE),
+                Err(BtcError::SignatureFormatError(_))
+            ));
+        }
+        assert!(matches!(
+            verify_against_xpub(&xpub, 0..1_001, &signature_with_header(39), MESSAGE),
+            Err(BtcError::DerivationRangeTooLarge(1_000))
+        ));
+        assert!(matches!(
+            verify_against_xpub("xpub", 0..20, &signature_with_header(39), MESSAGE),
+            Err(BtcError::InvalidPublicKey)
+        ));
+    }
+
+    #[test]
+    fn test_verify_taproot_script_path_address() {
+        use bitcoin::taproot::LeafVersion;
+        use bitcoin::ScriptBuf;
+
+        let public_key = hex::decode(PUBLIC_KEY).unwrap();
+        let internal_key = XOnlyPublicKey::from_slice(&public_key[1..]).unwrap();
+        let script = ScriptBuf::from_hex("51").unwrap(); // OP_TRUE
+        let merkle_root = TapNodeHash::from_script(&script, LeafVersion::TapScript);
+        let secp = Secp256k1::verification_only();
+        let address =
+            Address::p2tr(&secp, internal_key, Some(merkle_root), Network::Testnet).to_string();
+        assert_ne!(address, ADDRESS);
+
+        assert!(
+            verify_address_with_merkle_root(&address, public_key.clone(), Some(merkle_root))
+                .is_ok()
+        );
+        assert!(matches!(
+            verify_address(&address, public_key.clone()),
+            Err(BtcError::AddressMismatch)
+        ));
+        // The key-path address does not commit to the script tree.
+        assert!(matches!(
+            verify_address_with_merkle_root(ADDRESS, public_key, Some(merkle_root)),
+            Err(BtcError::AddressMismatch)
+        ));
+    }
+
+    #[test]
+    fn test_recover_without_library_state() {
+        let signature = general_purpose::STANDARD.decode(SIGNATURE).unwrap();
+        let hash = msg_hash(MESSAGE.to_string());
+        let recovered = recover_pub_key_compact(&signature, &hash, None).unwrap();
+        assert_eq!(recovered, hex::decode(PUBLIC_KEY).unwrap());
+        assert!(verify_address(ADDRESS, recovered).is_ok());
+    }
+
+    #[test]
+    fn test_signature_layouts() {
+        let header_first = general_purpose::STANDARD.decode(SIGNATURE).unwrap();
+        let mut recovery_id_last = header_first.clone();
+        recovery_id_last.rotate_left(1);
+        let hash = msg_hash(MESSAGE.to_string());
+        let public_key = hex::decode(PUBLIC_KEY).unwrap();
+
+        for (signature, layout) in [
+            (&header_first, SignatureLayout::HeaderFirst),
+            (&recovery_id_last, SignatureLayout::RecoveryIdLast),
+        ] {
+            assert!(SignatureLayout::candidates(signature).contains(&layout));
+            assert_eq!(
+                recover_pub_key_with_layout(signature, &hash, None, layout).unwrap(),
+                public_key
+            );
+            assert!(_verify_message(
+                MESSAGE.to_string(),
+                general_purpose::STANDARD.encode(signature),
+                PUBLIC_KEY.to_string(),
+                true,
+            )
+            .is_ok());
+        }
+
+        // Read in the wrong layout, the signature does not recover the signing key.
+        assert_ne!(
+            recover_pub_key_with_layout(
+                &recovery_id_last,
+                &hash,
+                None,
+                SignatureLayout::HeaderFirst
+            )
+            .ok(),
+            Some(public_key)
+        );
+
+        // A raw recovery id is only valid as the last byte.
+        let mut raw_recovery_id = recovery_id_last.clone();
+        raw_recovery_id[64] = 1;
+        raw_recovery_id[0] = 255;
+        assert_eq!(
+            SignatureLayout::candidates(&raw_recovery_id),
+            vec![SignatureLayout::RecoveryIdLast]
+        );
+    }
+
+    #[test]
+    fn test_degenerate_public_keys() {
+        let zero = "00".repeat(33);
+        let infinity = "00";
+        let generator = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+        let negated_generator =
+            "0379be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+        for public_key in [zero.as_str(), infinity, generator, negated_generator] {
+            assert!(
+                matches!(
+                    decode_public_key(public_key),
+                    Err(BtcError::InvalidPublicKey)
+                ),
+                "{}",
+                public_key
+            );
+        }
+
+        assert!(matches!(
+            verify_address(ADDRESS, vec![0; 33]),
+            Err(BtcError::InvalidPublicKey)
+        ));
+        assert!(derive_all_addresses(&[0; 33], Network::Bitcoin).is_empty());
+
+        // The generator is a valid point and its address exists, it is rejected nonetheless.
+        let generator = hex::decode(generator).unwrap();
+        let p2pkh = Address::p2pkh(
+            &BitcoinPublicKey::from_slice(&generator).unwrap(),
+            Network::Bitcoin,
+        );
+        assert_eq!(p2pkh.to_string(), "1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH");
+        assert!(matches!(
+            verify_address(&p2pkh.to_string(), generator),
+            Err(BtcError::InvalidPublicKey)
+        ));
+    }
+
+    #[cfg(feature = "custom-p2pkh")]
+    #[test]
+    fn test_p2pkh_address_with_custom_version() {
+        let public_key = BitcoinPublicKey::from_slice(&hex::decode(PUBLIC_KEY).unwrap()).unwrap();
+
+        // The Bitcoin version bytes derive the addresses of the standard networks.
+        assert_eq!(
+            p2pkh_addresses_with_version(&public_key, 0x00),
+            p2pkh_addresses(&public_key, Network::Bitcoin).map(|a| a.to_string())
+        );
+        assert_eq!(
+            p2pkh_addresses_with_version(&public_key, 0x6f),
+            p2pkh_addresses(&public_key, Network::Testnet).map(|a| a.to_string())
+        );
+
+        let [litecoin, _] = p2pkh_addresses_with_version(&public_key, 0x30);
+        assert_eq!(litecoin, "LXiyaYBGxxJTV1BqARLKh1ko9QZ1Y72WBu");
+        let pub_bytes = hex::decode(PUBLIC_KEY).unwrap();
+        assert!(
+            verify_p2pkh_address_with_version(&litecoin, pub_bytes.clone(), Some(0x30)).is_ok()
+        );
+        assert!(matches!(
+            verify_p2pkh_address_with_version(&litecoin, pub_bytes.clone(), Some(0x1e)),
+            Err(BtcError::NetworkMismatch)
+        ));
+        assert!(matches!(
+            verify_p2pkh_address_with_version(&litecoin, pub_bytes.clone(), None),
+            Err(BtcError::AddressFormatError(_))
+        ));
+        assert!(matches!(
+            verify_p2pkh_address_with_version(
+                "DHe7rap6BhxgmCgGisLaxkrdpKv2pDXEUL",
+                pub_bytes.clone(),
+                Some(0x1e)
+            ),
+            Ok(())
+        ));
+        assert!(verify_p2pkh_address_with_version(
+            "1DW2KKsStJ4QECVfzHM2Qzh2wCBjTe9TH1",
+            pub_bytes,
+            None
+        )
+        .is_ok());
+    }
+
+    #[cfg(feature = "debug")]
+    #[test]
+    fn test_log_recovery_failure() {
+        use crate::debug::take_test_log;
+
+        let signature = general_purpose::STANDARD.decode(SIGNATURE).unwrap();
+        let hash = msg_hash(MESSAGE.to_string());
+        recover_pub_key_compact(&signature, &hash, None).unwrap();
+        assert!(take_test_log().is_empty());
+
+        assert!(recover_pub_key_compact(&signature[..64], &hash, None).is_err());
+        let log = take_test_log();
+        assert_eq!(log.len(), 1);
+        assert!(log[0].contains("v = Some(28), rid = none, signature length = 64"));
+
+        // A zero `r` is never a valid signature.
+        let mut zero_r = signature.clone();
+        zero_r[1..33].fill(0);
+        assert!(matches!(
+            recover_pub_key_compact(&zero_r, &hash, None),
+            Err(BtcError::InvalidSignature)
+        ));
+        let log = take_test_log();
+        assert_eq!(log.len(), 1);
+        assert!(log[0].contains("Invalid signature, v = Some(28), rid = 1, signature length = 65"));
+    }
+}
@@ -1,11 +1,13 @@
-use crate::service::types::AddressScriptBuf;
+use crate::service::types::{AddressScriptBuf, SaltRotation, SessionKeyBuf, SessionKeyStatus};
+use candid::Principal;
 use ic_cdk::api::set_certified_data;
 use ic_certified_map::{fork_hash, labeled_hash, AsHashTree, Hash, RbTree};
 use ic_siwb::signature_map::SignatureMap;
+use ic_siwb::store::PrincipalStore;
 use ic_stable_structures::{
     memory_manager::{MemoryId, MemoryManager, VirtualMemory},
     storable::Blob,
-    DefaultMemoryImpl, StableBTreeMap,
+    DefaultMemoryImpl, StableBTreeMap, StableCell,
 };
 use std::cell::RefCell;
 
@@ -58,6 +60,234 @@ thread_local! {
             MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(1))),
         )
     );
+
+    // The store of the address to principal mapping, injected by `init`.
+    static PRINCIPAL_STORE: RefCell<Option<Box<dyn PrincipalStore>>> = RefCell::new(None);
+
+    static ADDRESS_LOGIN_COUNT: RefCell<StableBTreeMap<AddressScriptBuf, u64, VirtualMemory<DefaultMemoryImpl>>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(2))),
+        )
+    );
+
+    // The number of delegations issued since the canister was installed. Unlike the size of the signature
+    // map it never shrinks, and it is kept in stable memory so that it survives upgrades.
+    static TOTAL_DELEGATIONS_ISSUED: RefCell<StableCell<u64, VirtualMemory<DefaultMemoryImpl>>> =
+        RefCell::new(init_total_delegations_issued());
+
+    // The salt and seed version set by `rotate_salt`. `siwb_init` prefers them over the salt and seed version
+    // of the settings, so that an upgrade does not bring back a rotated salt.
+    static SALT_ROTATION: RefCell<StableCell<Option<SaltRotation>, VirtualMemory<DefaultMemoryImpl>>> =
+        RefCell::new(init_salt_rotation());
+
+    // The session keys that controllers allowed or disallowed. `siwb_init` applies them on top of the
+    // `allowed_session_keys` of the settings, so that an upgrade does not reset them.
+    static SESSION_KEY_STATUS: RefCell<StableBTreeMap<SessionKeyBuf, SessionKeyStatus, VirtualMemory<DefaultMemoryImpl>>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(5))),
+        )
+    );
+
+    // The last stable memory migration that has run, so that `post_upgrade` runs each migration only once.
+    static MIGRATION_VERSION: RefCell<StableCell<u8, VirtualMemory<DefaultMemoryImpl>>> =
+        RefCell::new(init_migration_version());
+}
+
+/// The version of the latest stable memory migration. Version 1 rekeys legacy address keys, see
+/// [migrate_legacy_address_keys].
+pub(crate) const LATEST_MIGRATION_VERSION: u8 = 1;
+
+fn init_total_delegations_issued() -> StableCell<u64, VirtualMemory<DefaultMemoryImpl>> {
+    StableCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(3))), 0)
+        .expect("Failed to initialize the delegation counter")
+}
+
+fn init_salt_rotation() -> StableCell<Option<SaltRotation>, VirtualMemory<DefaultMemoryImpl>> {
+    StableCell::init(
+        MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(4))),
+        None,
+    )
+    .expect("Failed to initialize the salt rotation")
+}
+
+fn init_migration_version() -> StableCell<u8, VirtualMemory<DefaultMemoryImpl>> {
+    StableCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(6))), 0)
+        .expect("Failed to initialize the migration version")
+}
+
+/// The default [PrincipalStore] of the provider, backed by `ADDRESS_PRINCIPAL` in stable memory.
+pub(crate) struct StablePrincipalStore;
+
+impl PrincipalStore for StablePrincipalStore {
+    fn get(&self, address: &[u8]) -> Option<Principal> {
+        ADDRESS_PRINCIPAL.with_borrow(|ap| {
+            ap.get(&AddressScriptBuf(address.to_vec()))
+                .map(|p| Principal::from_slice(p.as_slice()))
+        })
+    }
+
+    fn put(&mut self, address: Vec<u8>, principal: Principal) {
+        let principal: Blob<29> = principal
+            .as_slice()
+            .try_into()
+            .expect("Principals are at most 29 bytes long");
+        ADDRESS_PRINCIPAL.with_borrow_mut(|ap| ap.insert(AddressScriptBuf(address), principal));
+    }
+
+    fn remove(&mut self, address: &[u8]) -> Option<Principal> {
+        ADDRESS_PRINCIPAL.with_borrow_mut(|ap| {
+            ap.remove(&AddressScriptBuf(address.to_vec()))
+                .map(|p| Principal::from_slice(p.as_slice()))
+        })
+    }
+
+    fn entries(&self) -> Vec<(Vec<u8>, Principal)> {
+        ADDRESS_PRINCIPAL.with_borrow(|ap| {
+            ap.iter()
+                .map(|(address, p)| (address.0, Principal::from_slice(p.as_slice())))
+                .collect()
+        })
+    }
+
+    fn clear(&mut self) -> u64 {
+        ADDRESS_PRINCIPAL.with_borrow_mut(|ap| {
+            let keys: Vec<AddressScriptBuf> = ap.iter().map(|(key, _)| key).collect();
+            for key in &keys {
+                ap.remove(key);
+            }
+            keys.len() as u64
+        })
+    }
+}
+
+/// Replaces the store of the address to principal mapping. `siwb_init` always installs the
+/// [StablePrincipalStore], other stores are only injected by tests.
+pub(crate) fn set_principal_store(store: Box<dyn PrincipalStore>) {
+    PRINCIPAL_STORE.with_borrow_mut(|s| *s = Some(store));
+}
+
+/// Calls `f` with the store of the address to principal mapping. Traps if no store has been injected.
+pub(crate) fn with_principal_store<R>(f: impl FnOnce(&mut dyn PrincipalStore) -> R) -> R {
+    PRINCIPAL_STORE.with_borrow_mut(|s| {
+        let store = s
+            .as_mut()
+            .unwrap_or_else(|| ic_cdk::trap("Principal store is not initialized."));
+        f(store.as_mut())
+    })
+}
+
+/// Increments the number of successful logins of `address` and returns the new count.
+pub(crate) fn increment_login_count(address: &AddressScriptBuf) -> u64 {
+    ADDRESS_LOGIN_COUNT.with_borrow_mut(|counts| {
+        let count = counts.get(address).unwrap_or(0).saturating_add(1);
+        counts.insert(address.clone(), count);
+        count
+    })
+}
+
+/// Returns the number of successful logins of `address`.
+pub(crate) fn login_count(address: &AddressScriptBuf) -> u64 {
+    ADDRESS_LOGIN_COUNT.with_borrow(|counts| counts.get(address).unwrap_or(0))
+}
+
+/// Increments the number of delegations issued and returns the new total.
+pub(crate) fn increment_total_delegations_issued() -> u64 {
+    TOTAL_DELEGATIONS_ISSUED.with_borrow_mut(|total| {
+        let count = total.get().saturating_add(1);
+        total
+            .set(count)
+            .expect("Failed to store the delegation counter");
+        count
+    })
+}
+
+/// Returns the number of delegations issued since the canister was installed.
+pub(crate) fn total_delegations_issued() -> u64 {
+    TOTAL_DELEGATIONS_ISSUED.with_borrow(|total| *total.get())
+}
+
+/// Stores the salt and salt generation set by `rotate_salt`, replacing those of an earlier rotation.
+pub(crate) fn set_salt_rotation(rotation: SaltRotation) {
+    SALT_ROTATION.with_borrow_mut(|cell| {
+        cell.set(Some(rotation))
+            .expect("Failed to store the salt rotation");
+    });
+}
+
+/// Returns the salt and salt generation set by the last `rotate_salt`, if the salt has been rotated.
+pub(crate) fn salt_rotation() -> Option<SaltRotation> {
+    SALT_ROTATION.with_borrow(|cell| cell.get().clone())
+}
+
+/// Records that a controller allowed or disallowed `session_key`, replacing an earlier decision.
+pub(crate) fn set_session_key_status(session_key: &[u8], status: SessionKeyStatus) {
+    SESSION_KEY_STATUS
+        .with_borrow_mut(|statuses| statuses.insert(SessionKeyBuf(session_key.to_vec()), status));
+}
+
+/// Applies the session keys that controllers allowed or disallowed to `settings`. Once a controller
+/// managed a session key, delegations are restricted to allowed session keys, even if `settings` allows
+/// any session key.
+pub(crate) fn apply_session_key_statuses(settings: &mut ic_siwb::settings::Settings) {
+    SESSION_KEY_STATUS.with_borrow(|statuses| {
+        if statuses.is_empty() {
+            return;
+        }
+        let allowed = settings
+            .allowed_session_keys
+            .get_or_insert_with(Default::default);
+        for (session_key, status) in statuses.iter() {
+            match status {
+                SessionKeyStatus::Allowed => allowed.insert(session_key.0),
+                SessionKeyStatus::Disallowed => allowed.remove(&session_key.0),
+            };
+        }
+    });
+}
+
+/// Rekeys the entries of the address to principal mapping that were stored under the textual address
+/// instead of the script pubkey bytes, so that every casing of an address resolves to a single mapping.
+/// Entries that are already keyed canonically are left untouched.
+pub(crate) fn migrate_legacy_address_keys() {
+    with_principal_store(|store| {
+        for (key, principal) in store.entries() {
+            if let Some(script) = ic_siwb::utils::legacy_address_key_to_script(&key) {
+                store.remove(&key);
+                store.put(script, principal);
+            }
+        }
+    });
+}
+
+/// Runs the stable memory migrations that have not run yet and records the latest migration version.
+/// A freshly installed canister has no data to migrate and calls this right away.
+pub(crate) fn run_migrations() {
+    let version = MIGRATION_VERSION.with_borrow(|version| *version.get());
+    if version < 1 {
+        migrate_legacy_address_keys();
+    }
+    if version < LATEST_MIGRATION_VERSION {
+        MIGRATION_VERSION.with_borrow_mut(|version| {
+            version
+                .set(LATEST_MIGRATION_VERSION)
+                .expect("Failed to store the migration version");
+        });
+    }
+}
+
+/// Removes all principal to address and address to principal mappings and returns the number of users
+/// that had a mapping.
+pub(crate) fn clear_principal_mappings() -> u64 {
+    let principals = PRINCIPAL_ADDRESS.with_borrow_mut(|pa| {
+        let keys: Vec<Blob<29>> = pa.iter().map(|(key, _)| key).collect();
+        for key in &keys {
+            pa.remove(key);
+        }
+        keys.len() as u64
+    });
+    let addresses = with_principal_store(|store| store.clear());
+    // Either direction of the mapping can be disabled, the other one still has an entry per user.
+    principals.max(addresses)
 }
 
 pub(crate) fn update_root_hash(asset_hashes: &AssetHashes, signature_map: &SignatureMap) {
@@ -67,3 +297,109 @@ pub(crate) fn update_root_hash(asset_hashes: &AssetHashes, signature_map: &Signa
     );
     set_certified_data(&prefixed_root_hash[..]);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_login_count() {
+        let address = AddressScriptBuf(vec![0, 20, 1, 2, 3]);
+        let other = AddressScriptBuf(vec![0, 20, 4, 5, 6]);
+        assert_eq!(login_count(&address), 0);
+
+        assert_eq!(increment_login_count(&address), 1);
+        assert_eq!(increment_login_count(&address), 2);
+        assert_eq!(login_count(&address), 2);
+        assert_eq!(login_count(&other), 0);
+    }
+
+    #[test]
+    fn test_total_delegations_issued() {
+        assert_eq!(total_delegations_issued(), 0);
+        assert_eq!(increment_total_delegations_issued(), 1);
+        assert_eq!(increment_total_delegations_issued(), 2);
+
+        // An upgrade discards the heap, the counter is read back from stable memory.
+        TOTAL_DELEGATIONS_ISSUED.with_borrow_mut(|total| *total = init_total_delegations_issued());
+        assert_eq!(total_delegations_issued(), 2);
+        assert_eq!(increment_total_delegations_issued(), 3);
+    }
+
+    #[test]
+    fn test_clear_principal_mappings() {
+        set_principal_store(Box::new(StablePrincipalStore));
+        let principal: Blob<29> = Blob::try_from(&[1u8; 29][..]).unwrap();
+        let other_principal: Blob<29> = Blob::try_from(&[2u8; 29][..]).unwrap();
+        let address = AddressScriptBuf(vec![0, 20, 1, 2, 3]);
+        let other = AddressScriptBuf(vec![0, 20, 4, 5, 6]);
+        PRINCIPAL_ADDRESS.with_borrow_mut(|pa| pa.insert(principal, address.clone()));
+        ADDRESS_PRINCIPAL.with_borrow_mut(|ap| {
+            ap.insert(address.clone(), principal);
+            ap.insert(other.clone(), other_principal);
+        });
+
+        assert_eq!(clear_principal_mappings(), 2);
+        assert!(PRINCIPAL_ADDRESS.with_borrow(|pa| pa.is_empty()));
+        assert!(ADDRESS_PRINCIPAL.with_borrow(|ap| ap.is_empty()));
+        assert_eq!(clear_principal_mappings(), 0);
+    }
+
+    #[test]
+    fn test_migrations_run_once() {
+        set_principal_store(Box::new(StablePrincipalStore));
+        let address = "bc1qshqyem2rf8jyla904gd2cvek2k8nz5z3x73p24";
+        let principal = Principal::from_slice(&[1u8; 29]);
+        with_principal_store(|store| store.put(address.as_bytes().to_vec(), principal));
+
+        run_migrations();
+        assert_eq!(
+            MIGRATION_VERSION.with_borrow(|version| *version.get()),
+            LATEST_MIGRATION_VERSION
+        );
+        with_principal_store(|store| assert_eq!(store.get(address.as_bytes()), None));
+
+        // After an upgrade, the migration version is read back from stable memory and the keys are not
+        // scanned again.
+        MIGRATION_VERSION.with_borrow_mut(|version| *version = init_migration_version());
+        with_principal_store(|store| store.put(address.as_bytes().to_vec(), principal));
+        run_migrations();
+        with_principal_store(|store| assert_eq!(store.get(address.as_bytes()), Some(principal)));
+    }
+
+    #[test]
+    fn test_mappings_are_cleared_and_migrated_in_injected_store() {
+        set_principal_store(Box::new(
+            std::collections::BTreeMap::<Vec<u8>, Principal>::new(),
+        ));
+        let address = "bc1qshqyem2rf8jyla904gd2cvek2k8nz5z3x73p24";
+        let script = ic_siwb::utils::legacy_address_key_to_script(address.as_bytes()).unwrap();
+        let principal = Principal::from_slice(&[1u8; 29]);
+        with_principal_store(|store| store.put(address.as_bytes().to_vec(), principal));
+
+        migrate_legacy_address_keys();
+        with_principal_store(|store| {
+            assert_eq!(store.get(address.as_bytes()), None);
+            assert_eq!(store.get(&script), Some(principal));
+        });
+        assert!(ADDRESS_PRINCIPAL.with_borrow(|ap| ap.is_empty()));
+
+        assert_eq!(clear_principal_mappings(), 1);
+        with_principal_store(|store| assert!(store.entries().is_empty()));
+    }
+
+    #[test]
+    fn test_salt_rotation() {
+        assert_eq!(salt_rotation(), None);
+
+        let rotation = SaltRotation {
+            salt: "another_salt".to_string(),
+            salt_generation: 2,
+        };
+        set_salt_rotation(rotation.clone());
+
+        // An upgrade discards the heap, the rotation is read back from stable memory.
+        SALT_ROTATION.with_borrow_mut(|cell| *cell = init_salt_rotation());
+        assert_eq!(salt_rotation(), Some(rotation));
+    }
+}
@@ -0,0 +1,28 @@
+use ic_cdk::update;
+use serde_bytes::ByteBuf;
+
+use super::siwb_login::controller_guard;
+use crate::service::types::SessionKeyStatus;
+use crate::set_session_key_status;
+
+/// Allows `siwb_login` to issue delegations for `session_key`, e.g. when a kiosk or device is
+/// provisioned. Once a session key has been allowed, delegations are only issued for allowed session
+/// keys. Only controllers can call this function.
+///
+/// The session key stays allowed across upgrades, even if it is not in the `allowed_session_keys` of the
+/// upgrade settings.
+///
+/// # Arguments
+/// * `session_key` (ByteBuf): The DER encoded session key to allow.
+///
+/// # Returns
+/// * `Ok(bool)`: Whether the session key was not allowed before.
+/// * `Err(String)`: An error message if the canister is not initialized or the session key is not a DER
+///   encoded public key.
+#[update(guard = "controller_guard")]
+pub(crate) fn allow_session_key(session_key: ByteBuf) -> Result<bool, String> {
+    ic_siwb::delegation::validate_session_key(&session_key)?;
+    let allowed = ic_siwb::init::allow_session_key(&session_key)?;
+    set_session_key_status(&session_key, SessionKeyStatus::Allowed);
+    Ok(allowed)
+}
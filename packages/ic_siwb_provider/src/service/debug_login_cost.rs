@@ -0,0 +1,45 @@
+use ic_cdk::query;
+use ic_siwb::login::{BtcSignature, LoginCost};
+use ic_siwb::utils::{get_script_from_address, AddressInfo};
+use serde_bytes::ByteBuf;
+
+/// Rehearses a login with a compact ECDSA signature over the SIWB message prepared for the given
+/// Bitcoin address and returns the instructions each phase of the login costs, to help operators size
+/// the cycle budget of the canister. The message stays pending and no delegation is stored. Only
+/// compiled in with the `debug` feature, the endpoint is not part of the Candid interface of
+/// production builds.
+///
+/// # Arguments
+/// * `signature` (String): The signature of the SIWB message.
+/// * `address` (String): The Bitcoin address of the user.
+/// * `public_key` (String): The hex encoded public key of the wallet.
+/// * `session_key` (ByteBuf): A unique key that identifies the session.
+///
+/// # Returns
+/// * `Ok(LoginCost)`: The instructions spent hashing, recovering, deriving the address and creating the
+///   delegation, and in total.
+/// * `Err(String)`: An error message if no message awaits signature or the signature does not verify.
+#[query]
+fn debug_login_cost(
+    signature: String,
+    address: String,
+    public_key: String,
+    session_key: ByteBuf,
+) -> Result<LoginCost, String> {
+    ic_siwb::init::ensure_initialized()?;
+
+    // Create an BtcAddress from the string. This validates the address.
+    let AddressInfo {
+        address_raw: address,
+        ..
+    } = get_script_from_address(address)?;
+
+    ic_siwb::login::estimate_login_cost(
+        &BtcSignature(signature),
+        &address,
+        &public_key,
+        session_key,
+        &ic_cdk::api::id(),
+    )
+    .map_err(|e| e.to_string())
+}
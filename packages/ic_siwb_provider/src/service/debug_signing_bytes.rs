@@ -0,0 +1,29 @@
+use ic_cdk::query;
+use ic_siwb::utils::{get_script_from_address, AddressInfo};
+use serde_bytes::ByteBuf;
+
+/// Returns the exact bytes that are hashed and signed for the SIWB message prepared for the given
+/// Bitcoin address, so that client developers can compare them byte for byte with what their wallet
+/// signed. Only compiled in with the `debug` feature, the endpoint is not part of the Candid interface
+/// of production builds.
+///
+/// # Arguments
+/// * `address` (String): The Bitcoin address of the user.
+///
+/// # Returns
+/// * `Ok(ByteBuf)`: The framed message, the magic bytes and the message, each prefixed with its length.
+/// * `Err(String)`: An error message if the address is invalid or no message awaits signature.
+#[query]
+fn debug_signing_bytes(address: String) -> Result<ByteBuf, String> {
+    ic_siwb::init::ensure_initialized()?;
+
+    // Create an BtcAddress from the string. This validates the address.
+    let AddressInfo {
+        address_raw: address,
+        ..
+    } = get_script_from_address(address)?;
+
+    ic_siwb::login::pending_message_signing_bytes(&address)
+        .map(ByteBuf::from)
+        .ok_or_else(|| "No message awaits signature".to_string())
+}
@@ -0,0 +1,28 @@
+use ic_cdk::update;
+use serde_bytes::ByteBuf;
+
+use super::siwb_login::controller_guard;
+use crate::service::types::SessionKeyStatus;
+use crate::set_session_key_status;
+
+/// Stops `siwb_login` from issuing delegations for `session_key`, e.g. when a device is lost.
+/// Delegations that have already been issued stay valid until they expire. Only controllers can call
+/// this function.
+///
+/// The session key stays disallowed across upgrades, even if it is in the `allowed_session_keys` of the
+/// upgrade settings.
+///
+/// # Arguments
+/// * `session_key` (ByteBuf): The DER encoded session key to disallow.
+///
+/// # Returns
+/// * `Ok(bool)`: Whether the session key was allowed before.
+/// * `Err(String)`: An error message if delegations are not restricted to allowed session keys or the
+///   session key is not a DER encoded public key.
+#[update(guard = "controller_guard")]
+pub(crate) fn disallow_session_key(session_key: ByteBuf) -> Result<bool, String> {
+    ic_siwb::delegation::validate_session_key(&session_key)?;
+    let allowed = ic_siwb::init::disallow_session_key(&session_key)?;
+    set_session_key_status(&session_key, SessionKeyStatus::Disallowed);
+    Ok(allowed)
+}
@@ -0,0 +1,21 @@
+use ic_cdk::query;
+use ic_siwb::utils::{get_script_from_address, AddressInfo};
+
+use crate::login_count;
+use crate::service::types::AddressScriptBuf;
+
+/// Retrieves the number of successful logins of the given Bitcoin address.
+///
+/// # Arguments
+/// * `address` - The Bitcoin address.
+///
+/// # Returns
+/// * `Ok(u64)` - The number of logins, zero if the address never logged in.
+/// * `Err(String)` - An error message if the address cannot be converted.
+#[query]
+fn get_login_count(address: String) -> Result<u64, String> {
+    // Create an BtcAddress from the string. This validates the address.
+    let AddressInfo { script_buf, .. } = get_script_from_address(address)?;
+
+    Ok(login_count(&AddressScriptBuf(script_buf.to_bytes())))
+}
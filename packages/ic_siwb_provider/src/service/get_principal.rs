@@ -2,8 +2,7 @@ use ic_cdk::query;
 use ic_siwb::utils::{get_script_from_address, AddressInfo};
 use serde_bytes::ByteBuf;
 
-use crate::service::types::AddressScriptBuf;
-use crate::{ADDRESS_PRINCIPAL, SETTINGS};
+use crate::{with_principal_store, SETTINGS};
 
 /// Retrieves the principal associated with the given Bitcoin address.
 ///
@@ -25,12 +24,10 @@ fn get_principal(address: String) -> Result<ByteBuf, String> {
     // Create an BtcAddress from the string. This validates the address.
     let AddressInfo { script_buf, .. } = get_script_from_address(address)?;
 
-    ADDRESS_PRINCIPAL.with(|ap| {
-        ap.borrow()
-            .get(&AddressScriptBuf(script_buf.to_bytes()))
-            .map_or(
-                Err("No principal found for the given address".to_string()),
-                |p| Ok(ByteBuf::from(p.as_ref().to_vec())),
-            )
+    with_principal_store(|store| {
+        store.get(script_buf.as_bytes()).map_or(
+            Err("No principal found for the given address".to_string()),
+            |p| Ok(ByteBuf::from(p.as_slice().to_vec())),
+        )
     })
 }
@@ -4,9 +4,13 @@ use ic_siwb::bitcoin::Network;
 use ic_siwb::bitcoin::Network::Bitcoin;
 use ic_siwb::settings::SettingsBuilder;
 use serde::Deserialize;
+use serde_bytes::ByteBuf;
 use std::str::FromStr;
 
-use crate::SETTINGS;
+use crate::{
+    apply_session_key_statuses, run_migrations, salt_rotation, set_principal_store,
+    StablePrincipalStore, SETTINGS,
+};
 
 #[derive(CandidType, Debug, Clone, PartialEq, Deserialize)]
 pub enum RuntimeFeature {
@@ -20,6 +24,26 @@ pub enum RuntimeFeature {
     DisablePrincipalToBtcMapping,
 }
 
+/// The Bitcoin address types that can be used to sign in.
+#[derive(CandidType, Debug, Clone, PartialEq, Deserialize)]
+pub enum AddressType {
+    P2pkh,
+    P2sh,
+    P2wpkh,
+    P2tr,
+}
+
+impl From<AddressType> for ic_siwb::bitcoin::AddressType {
+    fn from(address_type: AddressType) -> Self {
+        match address_type {
+            AddressType::P2pkh => ic_siwb::bitcoin::AddressType::P2pkh,
+            AddressType::P2sh => ic_siwb::bitcoin::AddressType::P2sh,
+            AddressType::P2wpkh => ic_siwb::bitcoin::AddressType::P2wpkh,
+            AddressType::P2tr => ic_siwb::bitcoin::AddressType::P2tr,
+        }
+    }
+}
+
 /// Represents the settings that determine the behavior of the SIWB library. It includes settings such as domain, scheme, statement,
 /// and expiration times for sessions and sign-ins.
 #[derive(CandidType, Deserialize, Debug, Clone)]
@@ -33,12 +57,18 @@ pub struct SettingsInput {
     pub uri: String,
 
     /// The salt is used when generating the seed that uniquely identifies each user principal. The salt can only contain
-    /// printable ASCII characters.
+    /// printable ASCII characters and must be at least 8 characters long. Use a random value that is unique to the
+    /// deployment. Once the salt has been rotated with `rotate_salt`, the rotated salt is used instead.
     pub salt: String,
 
     /// The Bitcoin network ic-siwb, defaults to "bitcoin" (Bitcoin mainnet).
     pub network: Option<String>,
 
+    /// The networks whose addresses can log in, e.g. both "bitcoin" and "testnet". The first is the primary
+    /// network, whose principals are derived as if it were the only network. Overrides `network`. Defaults to
+    /// None, which only allows `network`.
+    pub allowed_networks: Option<Vec<String>>,
+
     // The scheme used to serve the frontend that uses SIWB. Defaults to "https".
     pub scheme: Option<String>,
 
@@ -51,11 +81,84 @@ pub struct SettingsInput {
     /// The TTL for a session in nanoseconds.
     pub session_expires_in: Option<u64>,
 
+    /// The shortest TTL for a session in nanoseconds, `session_expires_in` must not be shorter. Defaults to 1.
+    pub min_session_expires_in: Option<u64>,
+
     /// The list of canisters for which the identity delegation is allowed. Defaults to None, which means
     /// that the delegation is allowed for all canisters. If specified, the canister id of this canister must be in the list.
     pub targets: Option<Vec<String>>,
 
     pub runtime_features: Option<Vec<RuntimeFeature>>,
+
+    /// Whether the signed message is hashed twice with SHA256, as Bitcoin Core message signing does.
+    /// Defaults to `true`.
+    pub double_hash: Option<bool>,
+
+    /// Rounds delegation expirations down to a multiple of this many nanoseconds. Defaults to no rounding.
+    pub expiration_rounding: Option<u64>,
+
+    /// The address types that may be used to sign in. Defaults to None, which allows all supported address types.
+    pub allowed_address_types: Option<Vec<AddressType>>,
+
+    /// The version of the seed derivation used to derive user principals. Defaults to 1. Changing it changes the
+    /// principal of every user.
+    pub seed_version: Option<u8>,
+
+    /// Whether legacy compact ECDSA message signatures are accepted. Defaults to `true`. When `false`, only BIP-322
+    /// and Taproot Schnorr signatures can be used to sign in.
+    pub allow_legacy_ecdsa: Option<bool>,
+
+    /// How far, in nanoseconds, an `issued_at` passed to `siwb_prepare_login` may be from the current time. Defaults
+    /// to 1 minute.
+    pub max_issued_at_skew: Option<u64>,
+
+    /// The maximum length in bytes of a message to be signed. Defaults to 4096.
+    pub max_message_bytes: Option<u64>,
+
+    /// A grace period in nanoseconds for clock drift between the canister and the wallet, applied to
+    /// message expiration and `issued_at` checks. Defaults to 0.
+    pub clock_skew: Option<u64>,
+
+    /// For how long, in nanoseconds, a successful `siwb_login` can be retried with the same signature and
+    /// session key and returns the same login details. Defaults to 0, which disables retries.
+    pub login_retry_window: Option<u64>,
+
+    /// The prefix that frames messages for signing on the primary network, for chains whose wallets sign
+    /// with their own prefix. Defaults to "Bitcoin Signed Message:\n".
+    pub message_prefix: Option<String>,
+
+    /// The tag of the BIP-340 tagged hash that message signatures are verified against, for wallets that sign
+    /// tagged hashes. Defaults to None, the legacy message hash.
+    pub message_hash_tag: Option<String>,
+
+    /// The DER encoded session keys that delegations may be issued for, for locked-down deployments. Defaults
+    /// to None, which allows any session key. Session keys allowed or disallowed with `allow_session_key` and
+    /// `disallow_session_key` are kept across upgrades and take precedence over this list.
+    pub allowed_session_keys: Option<Vec<ByteBuf>>,
+
+    /// Further domains, besides `domain`, whose frontends may request SIWB messages by passing their domain to
+    /// `siwb_prepare_login`. Defaults to None, which only allows `domain`.
+    pub allowed_domains: Option<Vec<String>>,
+
+    /// Further statements, besides `statement`, that may be requested with `siwb_prepare_login_full`.
+    /// Defaults to None, which only allows `statement`.
+    pub allowed_statements: Option<Vec<String>>,
+
+    /// Further URIs, besides `uri`, that may be requested with `siwb_prepare_login_full`. Defaults to None,
+    /// which only allows `uri`.
+    pub allowed_uris: Option<Vec<String>>,
+
+    /// Whether messages that are parsed back must only contain the fields the canister renders. Defaults to
+    /// `false`, which ignores unknown fields.
+    pub strict_message_parsing: Option<bool>,
+
+    /// Whether addresses of another network are rejected by `siwb_prepare_login` rather than only at login.
+    /// Defaults to `false`.
+    pub strict_network: Option<bool>,
+
+    /// Whether SIWB messages name this canister, so that a signature cannot be replayed at another canister
+    /// serving the same domain. Defaults to `false`.
+    pub bind_canister_id: Option<bool>,
 }
 
 /// Initialize the SIWB library with the given settings.
@@ -73,13 +176,62 @@ fn siwb_init(settings_input: SettingsInput) {
     );
 
     // Optional fields
-    if let Some(chain_id) = settings_input.network {
-        if let Ok(n) = Network::from_str(&chain_id) {
+    if let Some(chain_id) = &settings_input.network {
+        if let Ok(n) = Network::from_str(chain_id) {
             ic_siwb_settings = ic_siwb_settings.network(n);
         } else {
             ic_siwb_settings = ic_siwb_settings.network(Bitcoin);
         }
     }
+    if let Some(allowed_networks) = &settings_input.allowed_networks {
+        let allowed_networks: Vec<Network> = allowed_networks
+            .iter()
+            .map(|n| {
+                Network::from_str(n)
+                    .unwrap_or_else(|_| panic!("Invalid network in allowed_networks: {}", n))
+            })
+            .collect();
+        ic_siwb_settings = ic_siwb_settings.allowed_networks(allowed_networks);
+    }
+    if let Some(message_prefix) = settings_input.message_prefix {
+        let network = settings_input
+            .allowed_networks
+            .as_ref()
+            .and_then(|networks| networks.first())
+            .or(settings_input.network.as_ref())
+            .and_then(|n| Network::from_str(n).ok())
+            .unwrap_or(Bitcoin);
+        ic_siwb_settings = ic_siwb_settings.message_prefix(network, message_prefix);
+    }
+    if let Some(message_hash_tag) = settings_input.message_hash_tag {
+        ic_siwb_settings = ic_siwb_settings.message_hash_tag(message_hash_tag);
+    }
+    if let Some(allowed_session_keys) = settings_input.allowed_session_keys {
+        ic_siwb_settings = ic_siwb_settings.allowed_session_keys(
+            allowed_session_keys
+                .into_iter()
+                .map(ByteBuf::into_vec)
+                .collect(),
+        );
+    }
+    if let Some(allowed_domains) = settings_input.allowed_domains {
+        ic_siwb_settings = ic_siwb_settings.allowed_domains(allowed_domains);
+    }
+    if let Some(allowed_statements) = settings_input.allowed_statements {
+        ic_siwb_settings = ic_siwb_settings.allowed_statements(allowed_statements);
+    }
+    if let Some(allowed_uris) = settings_input.allowed_uris {
+        ic_siwb_settings = ic_siwb_settings.allowed_uris(allowed_uris);
+    }
+    if let Some(strict_message_parsing) = settings_input.strict_message_parsing {
+        ic_siwb_settings = ic_siwb_settings.strict_message_parsing(strict_message_parsing);
+    }
+    if let Some(strict_network) = settings_input.strict_network {
+        ic_siwb_settings = ic_siwb_settings.strict_network(strict_network);
+    }
+    if let Some(bind_canister_id) = settings_input.bind_canister_id {
+        ic_siwb_settings = ic_siwb_settings.bind_canister_id(bind_canister_id);
+    }
     if let Some(scheme) = settings_input.scheme {
         ic_siwb_settings = ic_siwb_settings.scheme(scheme);
     }
@@ -92,6 +244,38 @@ fn siwb_init(settings_input: SettingsInput) {
     if let Some(session_expire_in) = settings_input.session_expires_in {
         ic_siwb_settings = ic_siwb_settings.session_expires_in(session_expire_in);
     }
+    if let Some(min_session_expires_in) = settings_input.min_session_expires_in {
+        ic_siwb_settings = ic_siwb_settings.min_session_expires_in(min_session_expires_in);
+    }
+    if let Some(double_hash) = settings_input.double_hash {
+        ic_siwb_settings = ic_siwb_settings.double_hash(double_hash);
+    }
+    if let Some(expiration_rounding) = settings_input.expiration_rounding {
+        ic_siwb_settings = ic_siwb_settings.expiration_rounding(expiration_rounding);
+    }
+    if let Some(address_types) = settings_input.allowed_address_types {
+        ic_siwb_settings = ic_siwb_settings
+            .allowed_address_types(address_types.into_iter().map(Into::into).collect());
+    }
+    if let Some(seed_version) = settings_input.seed_version {
+        ic_siwb_settings = ic_siwb_settings.seed_version(seed_version);
+    }
+    if let Some(allow_legacy_ecdsa) = settings_input.allow_legacy_ecdsa {
+        ic_siwb_settings = ic_siwb_settings.allow_legacy_ecdsa(allow_legacy_ecdsa);
+    }
+    if let Some(max_issued_at_skew) = settings_input.max_issued_at_skew {
+        ic_siwb_settings = ic_siwb_settings.max_issued_at_skew(max_issued_at_skew);
+    }
+    if let Some(max_message_bytes) = settings_input.max_message_bytes {
+        ic_siwb_settings = ic_siwb_settings
+            .max_message_bytes(usize::try_from(max_message_bytes).unwrap_or(usize::MAX));
+    }
+    if let Some(clock_skew) = settings_input.clock_skew {
+        ic_siwb_settings = ic_siwb_settings.clock_skew(clock_skew);
+    }
+    if let Some(login_retry_window) = settings_input.login_retry_window {
+        ic_siwb_settings = ic_siwb_settings.login_retry_window(login_retry_window);
+    }
     if let Some(targets) = settings_input.targets {
         let targets: Vec<Principal> = targets
             .into_iter()
@@ -127,9 +311,21 @@ fn siwb_init(settings_input: SettingsInput) {
             }
         }
 
-        // Build and initialize SIWB
-        ic_siwb::init(ic_siwb_settings.build().unwrap()).unwrap();
+        // Build and initialize SIWB. A salt set by `rotate_salt` takes precedence, the salt of the settings
+        // would bring back the principals derived from the rotated salt.
+        let mut settings = ic_siwb_settings.build().unwrap();
+        if let Some(rotation) = salt_rotation() {
+            settings.salt = rotation.salt;
+            settings.salt_generation = rotation.salt_generation;
+        }
+        // Session keys allowed or disallowed by controllers are kept across upgrades.
+        apply_session_key_statuses(&mut settings);
+        ic_siwb::init(settings).unwrap();
     });
+
+    // The provider keeps the address to principal mapping in stable memory. Other stores are for canisters
+    // that embed the library.
+    set_principal_store(Box::new(StablePrincipalStore));
 }
 
 /// `init` is called when the canister is created. It initializes the SIWB library with the given settings.
@@ -142,6 +338,8 @@ fn siwb_init(settings_input: SettingsInput) {
 #[init]
 fn init(settings: SettingsInput) {
     siwb_init(settings);
+    // There is no data to migrate yet, later upgrades only run newer migrations.
+    run_migrations();
 }
 
 /// `post_upgrade` is called when the canister is upgraded. It initializes the SIWB library with the given settings.
@@ -153,5 +351,113 @@ fn init(settings: SettingsInput) {
 /// settings after users have started using the service!
 #[post_upgrade]
 fn upgrade(settings: SettingsInput) {
+    // The migrations go through the principal store, which `siwb_init` installs.
     siwb_init(settings);
+    run_migrations();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::allow_session_key::allow_session_key;
+    use crate::service::disallow_session_key::disallow_session_key;
+    use crate::service::types::SaltRotation;
+    use crate::set_salt_rotation;
+    use ic_siwb::bitcoin::Address;
+
+    /// Settings with only the required fields set, as passed by a minimal `dfx deploy --argument`.
+    fn settings_input(salt: &str) -> SettingsInput {
+        #[derive(CandidType)]
+        struct Required {
+            domain: String,
+            uri: String,
+            salt: String,
+        }
+        let required = Required {
+            domain: "example.com".to_string(),
+            uri: "http://example.com".to_string(),
+            salt: salt.to_string(),
+        };
+        candid::decode_one(&candid::encode_one(required).unwrap()).unwrap()
+    }
+
+    /// A DER encoded Ed25519 public key.
+    fn session_key(seed: u8) -> ByteBuf {
+        let mut session_key = vec![48, 42, 48, 5, 6, 3, 43, 101, 112, 3, 33, 0];
+        session_key.extend_from_slice(&[seed; 32]);
+        ByteBuf::from(session_key)
+    }
+
+    fn seed() -> Vec<u8> {
+        let address = Address::from_str("bc1qshqyem2rf8jyla904gd2cvek2k8nz5z3x73p24")
+            .unwrap()
+            .assume_checked();
+        ic_siwb::delegation::generate_seed(&address, None).to_vec()
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid network in allowed_networks: testnte")]
+    fn test_init_rejects_invalid_allowed_network() {
+        siwb_init(SettingsInput {
+            allowed_networks: Some(vec!["bitcoin".to_string(), "testnte".to_string()]),
+            ..settings_input("some_salt")
+        });
+    }
+
+    #[test]
+    fn test_reinit_keeps_rotated_salt() {
+        siwb_init(settings_input("some_salt"));
+        let original = seed();
+
+        let rotate = |salt: &str| {
+            let salt_generation = ic_siwb::init::rotate_salt(salt).unwrap();
+            set_salt_rotation(SaltRotation {
+                salt: salt.to_string(),
+                salt_generation,
+            });
+            salt_generation
+        };
+        assert_eq!(rotate("another_salt"), 1);
+        let rotated = seed();
+        assert_ne!(rotated, original);
+
+        // An upgrade with the original settings keeps the rotated salt.
+        siwb_init(settings_input("some_salt"));
+        assert_eq!(seed(), rotated);
+
+        // The salt can be rotated again after the upgrade.
+        assert_eq!(rotate("third_salt"), 2);
+        let rotated_again = seed();
+        assert_ne!(rotated_again, rotated);
+        siwb_init(settings_input("some_salt"));
+        assert_eq!(seed(), rotated_again);
+    }
+
+    #[test]
+    fn test_reinit_keeps_managed_session_keys() {
+        let kiosk_1 = session_key(1);
+        let kiosk_2 = session_key(2);
+        assert!(allow_session_key(ByteBuf::from(vec![1, 2, 3])).is_err());
+
+        siwb_init(settings_input("some_salt"));
+        assert_eq!(allow_session_key(kiosk_1.clone()), Ok(true));
+
+        // An upgrade that does not list the allowed session keys keeps logins restricted to them.
+        siwb_init(settings_input("some_salt"));
+        assert_eq!(ic_siwb::init::allow_session_key(&kiosk_1), Ok(false));
+        assert_eq!(disallow_session_key(kiosk_2.clone()), Ok(false));
+
+        // A disallowed session key stays disallowed, even if the upgrade settings list it.
+        let with_kiosks = |salt| SettingsInput {
+            allowed_session_keys: Some(vec![kiosk_1.clone(), kiosk_2.clone()]),
+            ..settings_input(salt)
+        };
+        siwb_init(with_kiosks("some_salt"));
+        assert_eq!(ic_siwb::init::disallow_session_key(&kiosk_2), Ok(false));
+        assert_eq!(disallow_session_key(kiosk_1.clone()), Ok(true));
+
+        // Disallowing every session key does not reopen logins to any session key.
+        siwb_init(settings_input("some_salt"));
+        assert_eq!(ic_siwb::init::disallow_session_key(&kiosk_1), Ok(false));
+    }
 }
@@ -0,0 +1,34 @@
+use ic_cdk::query;
+
+/// Reports whether the SIWB library has been initialized. Until it is, e.g. during a deploy or upgrade,
+/// the other endpoints return a "Settings are not initialized" error. Clients can poll this function to
+/// wait for the canister to become ready.
+///
+/// # Returns
+/// * `true` if the canister is ready to handle logins, `false` otherwise.
+#[query]
+fn is_initialized() -> bool {
+    ic_siwb::init::is_initialized()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::siwb_prepare_login::siwb_prepare_login;
+    use crate::service::types::PrepareLoginError;
+
+    #[test]
+    fn test_endpoint_before_init() {
+        assert!(!is_initialized());
+        assert_eq!(
+            siwb_prepare_login(
+                "bc1qshqyem2rf8jyla904gd2cvek2k8nz5z3x73p24".to_string(),
+                None,
+                None,
+                None
+            )
+            .err(),
+            Some(PrepareLoginError::NotInitialized)
+        );
+    }
+}
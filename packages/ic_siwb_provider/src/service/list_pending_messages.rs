@@ -0,0 +1,18 @@
+use ic_cdk::query;
+use serde_bytes::ByteBuf;
+
+use super::siwb_login::controller_guard;
+
+/// Lists the SIWB messages awaiting signature, for monitoring and abuse investigation. Only controllers
+/// can call this function.
+///
+/// # Returns
+/// * `Vec<(ByteBuf, u64)>`: The script pubkey of each address with a pending message and the time the
+///   message was issued at, ordered by the issue time. Expired messages are not listed.
+#[query(guard = "controller_guard")]
+fn list_pending_messages() -> Vec<(ByteBuf, u64)> {
+    ic_siwb::login::list_pending_messages()
+        .into_iter()
+        .map(|(script_pubkey, issued_at)| (ByteBuf::from(script_pubkey), issued_at))
+        .collect()
+}
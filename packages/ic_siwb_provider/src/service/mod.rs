@@ -1,8 +1,28 @@
+pub mod allow_session_key;
+#[cfg(feature = "debug")]
+pub mod debug_login_cost;
+#[cfg(feature = "debug")]
+pub mod debug_signing_bytes;
+pub mod disallow_session_key;
 pub mod get_address;
 pub mod get_caller_address;
+pub mod get_login_count;
 pub mod get_principal;
 pub mod init_upgrade;
+pub mod is_initialized;
+pub mod list_pending_messages;
+pub mod preview_expiration;
+pub mod prune_signatures;
+pub mod rotate_salt;
+pub mod signature_map_stats;
 pub mod siwb_get_delegation;
+pub mod siwb_has_pending_message;
 pub mod siwb_login;
+pub mod siwb_login_by_address;
+pub mod siwb_login_with_delegation;
+pub mod siwb_login_status;
 pub mod siwb_prepare_login;
+pub mod siwb_prepare_login_full;
+pub mod siwb_revoke_delegation;
 pub mod types;
+pub mod validate_address;
@@ -0,0 +1,25 @@
+use ic_cdk::query;
+use ic_siwb::utils::{get_script_from_address, AddressInfo};
+
+/// Returns when the session would expire if the user signed the SIWB message prepared for the given
+/// Bitcoin address now, so that frontends can tell the user before they sign. This is the expiration
+/// `siwb_login` gives the delegation.
+///
+/// # Arguments
+/// * `address` (String): The Bitcoin address of the user.
+///
+/// # Returns
+/// * `Ok(u64)`: The session expiration in nanoseconds since the UNIX epoch.
+/// * `Err(String)`: An error message if the address is invalid or no message awaits signature.
+#[query]
+fn preview_expiration(address: String) -> Result<u64, String> {
+    ic_siwb::init::ensure_initialized()?;
+
+    // Create an BtcAddress from the string. This validates the address.
+    let AddressInfo {
+        address_raw: address,
+        ..
+    } = get_script_from_address(address)?;
+
+    Ok(ic_siwb::login::preview_expiration(&address)?)
+}
@@ -0,0 +1,26 @@
+use ic_cdk::update;
+
+use super::siwb_login::controller_guard;
+use crate::{update_root_hash, STATE};
+
+/// Prunes up to `max` expired delegation signatures from the signature map. Logins only prune a few
+/// expired signatures each, operators can call this during low-traffic windows to reclaim the space of
+/// the rest. Only controllers can call this function.
+///
+/// # Arguments
+/// * `max` (u64): The maximum number of signatures to prune.
+///
+/// # Returns
+/// * `u64`: The number of signatures removed.
+#[update(guard = "controller_guard")]
+fn prune_signatures(max: u64) -> u64 {
+    STATE.with(|state| {
+        let signature_map = &mut *state.signature_map.borrow_mut();
+        let pruned = ic_siwb::login::prune_expired_signatures(
+            signature_map,
+            usize::try_from(max).unwrap_or(usize::MAX),
+        );
+        update_root_hash(&state.asset_hashes.borrow(), signature_map);
+        pruned as u64
+    })
+}
@@ -0,0 +1,37 @@
+use ic_cdk::update;
+
+use super::siwb_login::controller_guard;
+use crate::service::types::SaltRotation;
+use crate::{clear_principal_mappings, set_salt_rotation, update_root_hash, STATE};
+
+/// Replaces the salt, e.g. after a suspected leak, and bumps the salt generation. The seed version is
+/// left as is, so the salt can be rotated again after another leak. Only controllers can call this
+/// function.
+///
+/// ## 🛑 Important: Rotating the salt changes the principal of every user. All principal and address
+/// mappings and all pending delegations are discarded. Delegations that have already been fetched stay
+/// valid until they expire. The new salt is kept in stable memory and takes precedence over the `salt`
+/// of later upgrade settings.
+///
+/// # Arguments
+/// * `new_salt` (String): The new salt, at least 8 printable ASCII characters.
+///
+/// # Returns
+/// * `Ok(u64)`: The number of users whose mappings were cleared.
+/// * `Err(String)`: An error message if the new salt is invalid.
+#[update(guard = "controller_guard")]
+fn rotate_salt(new_salt: String) -> Result<u64, String> {
+    let salt_generation = ic_siwb::init::rotate_salt(&new_salt)?;
+    set_salt_rotation(SaltRotation {
+        salt: new_salt,
+        salt_generation,
+    });
+
+    STATE.with(|state| {
+        let signature_map = &mut *state.signature_map.borrow_mut();
+        ic_siwb::login::prune_all(signature_map);
+        update_root_hash(&state.asset_hashes.borrow(), signature_map);
+    });
+
+    Ok(clear_principal_mappings())
+}
@@ -0,0 +1,24 @@
+use ic_cdk::query;
+
+use crate::service::types::SignatureMapStats;
+use crate::{total_delegations_issued, STATE};
+
+/// Reports how many delegation hashes the signature map holds and its current root hash. Operators can
+/// use it to check that the certified data follows logins, e.g. when users report that a delegation
+/// cannot be found. The cumulative number of delegations issued helps to spot unusual growth, as the
+/// size of the map shrinks on prune.
+///
+/// # Returns
+/// * `SignatureMapStats`: The number of delegation hashes, the hex encoded root hash and the total
+///   number of delegations issued.
+#[query]
+fn signature_map_stats() -> SignatureMapStats {
+    STATE.with(|state| {
+        let signature_map = state.signature_map.borrow();
+        SignatureMapStats {
+            len: signature_map.len() as u64,
+            root_hex: hex::encode(signature_map.root_hash()),
+            total_delegations_issued: total_delegations_issued(),
+        }
+    })
+}
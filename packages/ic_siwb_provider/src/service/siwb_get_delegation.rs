@@ -1,8 +1,8 @@
 use ic_cdk::{api::data_certificate, query};
 use ic_certified_map::{fork, labeled_hash, AsHashTree, HashTree};
 use ic_siwb::delegation::{
-    create_certified_signature, create_delegation, create_delegation_hash, generate_seed, witness,
-    SignedDelegation,
+    check_certificate_current, create_certified_signature, create_delegation,
+    create_delegation_hash, generate_seed, witness, SignedDelegation,
 };
 use ic_siwb::utils::{get_script_from_address, AddressInfo};
 use serde_bytes::ByteBuf;
@@ -15,16 +15,21 @@ use crate::{LABEL_ASSETS, LABEL_SIG, STATE};
 /// * `address` (String): The Bitcoin address of the user.
 /// * `session_key` (ByteBuf): A unique key that identifies the session.
 /// * `expiration` (u64): The expiration time of the delegation in nanoseconds since the UNIX epoch.
+/// * `realm` (Option<String>): The login realm that was passed to `siwb_login`, if any.
 ///
 /// # Returns
 /// * `Ok(SignedDelegation)`: A signed delegation containing the session key, expiration, and targets if successful.
-/// * `Err(String)`: An error message if there is a failure in creating or certifying the delegation.
+/// * `Err(String)`: An error message if there is a failure in creating or certifying the delegation. A
+///   "Certificate pending" error means the login has not been certified yet and the call should be retried.
 #[query]
 fn siwb_get_delegation(
     address: String,
     session_key: ByteBuf,
     expiration: u64,
+    realm: Option<String>,
 ) -> Result<SignedDelegation, String> {
+    ic_siwb::init::ensure_initialized()?;
+
     // Fetches the certificate for the current call, required for creating a certified signature.
     let certificate =
         data_certificate().expect("siwb_get_delegation must be called using a query call");
@@ -39,7 +44,7 @@ fn siwb_get_delegation(
         let signature_map = s.signature_map.borrow_mut();
 
         // Generate a unique seed based on the user's Bitcoin address.
-        let seed = generate_seed(&address);
+        let seed = generate_seed(&address, realm.as_deref());
 
         // Create a delegation object with the session key and expiration.
         let delegation = create_delegation(session_key, expiration)?;
@@ -59,6 +64,9 @@ fn siwb_get_delegation(
             ic_certified_map::labeled(LABEL_SIG, signature_witness),
         );
 
+        // The signature may already be in the map while the certificate still certifies the previous root hash.
+        check_certificate_current(&certificate, &ic_cdk::id(), tree.reconstruct())?;
+
         // Certify that the delegation is valid by creating a signature.
         let signature = create_certified_signature(certificate, tree)?;
 
@@ -0,0 +1,26 @@
+use ic_cdk::query;
+use ic_siwb::utils::{get_script_from_address, AddressInfo};
+
+/// Checks whether a SIWB message prepared using `siwb_prepare_login` still awaits signature by the
+/// given Bitcoin address. Frontends can use this to resume an interrupted login instead of preparing
+/// a new message.
+///
+/// # Arguments
+/// * `address` (String): The Bitcoin address of the user.
+///
+/// # Returns
+/// * `Ok(Option<u64>)`: The expiration time of the pending message, or `None` if there is no message
+///   or it has expired.
+/// * `Err(String)`: An error message if the address is invalid.
+#[query]
+fn siwb_has_pending_message(address: String) -> Result<Option<u64>, String> {
+    ic_siwb::init::ensure_initialized()?;
+
+    // Create an BtcAddress from the string. This validates the address.
+    let AddressInfo {
+        address_raw: address,
+        ..
+    } = get_script_from_address(address)?;
+
+    Ok(ic_siwb::login::pending_message_expiration(&address))
+}
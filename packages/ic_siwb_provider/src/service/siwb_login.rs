@@ -7,8 +7,11 @@ use ic_siwb::utils::get_script_from_address;
 use ic_stable_structures::storable::Blob;
 use serde_bytes::ByteBuf;
 
-use crate::service::types::AddressScriptBuf;
-use crate::{update_root_hash, ADDRESS_PRINCIPAL, PRINCIPAL_ADDRESS, SETTINGS, STATE};
+use crate::service::types::{AddressScriptBuf, SiwbLoginOptions};
+use crate::{
+    increment_login_count, increment_total_delegations_issued, update_root_hash,
+    with_principal_store, PRINCIPAL_ADDRESS, SETTINGS, STATE,
+};
 
 /// Authenticates the user by verifying the signature of the SIWB message. This function also
 /// prepares the delegation to be fetched in the next step, the `siwb_get_delegation` function.
@@ -16,7 +19,10 @@ use crate::{update_root_hash, ADDRESS_PRINCIPAL, PRINCIPAL_ADDRESS, SETTINGS, ST
 /// # Arguments
 /// * `signature` (String): The signature of the SIWB message.
 /// * `address` (String): The Bitcoin address of the user.
+/// * `public_key` (String): The hex encoded public key of the wallet.
 /// * `session_key` (ByteBuf): A unique key that identifies the session.
+/// * `sign_message_type` (SignMessageType): The message signing scheme used by the wallet.
+/// * `options` (Option<SiwbLoginOptions>): Optional login arguments, see [SiwbLoginOptions].
 ///
 /// # Returns
 /// * `Ok(LoginOkResponse)`: Contains the user canister public key and other login response data if the login is successful.
@@ -28,48 +34,100 @@ fn siwb_login(
     public_key: String,
     session_key: ByteBuf,
     sign_message_type: SignMessageType,
+    options: Option<SiwbLoginOptions>,
 ) -> Result<LoginDetails, String> {
+    ic_siwb::init::ensure_initialized()?;
+
+    let options = options.unwrap_or_default();
+
+    // The primary public key is tried first, followed by any additional candidates.
+    let mut public_keys = vec![public_key];
+    public_keys.extend(options.public_keys.unwrap_or_default());
+
     STATE.with(|state| {
         let signature_map = &mut *state.signature_map.borrow_mut();
 
         // Create an BtcAddress from the string. This validates the address.
         let address = get_script_from_address(address)?;
 
+        if let Some(challenge_token) = &options.challenge_token {
+            ic_siwb::login::check_challenge_token(&address.address_raw, challenge_token)
+                .map_err(|e| e.to_string())?;
+        }
+
         // Create an BtcSignature from the string. This validates the signature.
         let signature = BtcSignature(signature);
 
         // Attempt to log in with the provided signature, address, and session key.
 
-        let login_response = ic_siwb::login::login(
-            &signature,
-            &address.address_raw,
-            public_key,
-            session_key,
-            &mut *signature_map,
-            &ic_cdk::api::id(),
-            sign_message_type,
-        )
+        let (login_response, retried) = match options.recovery_id {
+            Some(recovery_id) => ic_siwb::login::login_with_recovery_id(
+                &signature,
+                &address.address_raw,
+                public_keys,
+                session_key,
+                &mut *signature_map,
+                &ic_cdk::api::id(),
+                sign_message_type,
+                options.wallet.unwrap_or_default(),
+                options.realm.as_deref(),
+                recovery_id,
+            ),
+            None => ic_siwb::login::login(
+                &signature,
+                &address.address_raw,
+                public_keys,
+                session_key,
+                &mut *signature_map,
+                &ic_cdk::api::id(),
+                sign_message_type,
+                options.wallet.unwrap_or_default(),
+                options.realm.as_deref(),
+            ),
+        }
         .map_err(|e| e.to_string())?;
 
         // Update the certified data of the canister due to changes in the signature map.
         update_root_hash(&state.asset_hashes.borrow(), signature_map);
 
-        // Convert the user canister public key to a principal.
-        let principal: Blob<29> =
-            Principal::self_authenticating(&login_response.user_canister_pubkey).as_slice()[..29]
-                .try_into()
-                .map_err(|_| format!("Invalid principal: {:?}", login_response))?;
-
-        // Store the mapping of principal to Bitcoin address and vice versa if the settings allow it.
-        manage_principal_address_mappings(
-            &principal,
+        record_login(
+            &login_response,
+            retried,
             &AddressScriptBuf(address.script_buf.to_bytes()),
-        );
+            options.store_mapping.unwrap_or(true),
+        )?;
 
         Ok(login_response)
     })
 }
 
+/// Stores the mapping of principal to Bitcoin address and vice versa, if the settings allow it and
+/// `store_mapping` is set, and counts the login and the delegation it issued. A `retried` login was
+/// answered from the retry cache of an earlier login, which has already been recorded.
+pub(crate) fn record_login(
+    login_details: &LoginDetails,
+    retried: bool,
+    address: &AddressScriptBuf,
+    store_mapping: bool,
+) -> Result<(), String> {
+    if retried {
+        return Ok(());
+    }
+
+    // Convert the user canister public key to a principal.
+    let principal: Blob<29> = Principal::self_authenticating(&login_details.user_canister_pubkey)
+        .as_slice()[..29]
+        .try_into()
+        .map_err(|_| format!("Invalid principal: {:?}", login_details))?;
+
+    if store_mapping {
+        manage_principal_address_mappings(&principal, address);
+    }
+    increment_login_count(address);
+    increment_total_delegations_issued();
+    Ok(())
+}
+
 #[update(name = "prune_sigs", guard = "controller_guard")]
 #[candid_method(update, rename = "prune_sigs")]
 fn prune_sigs() {
@@ -87,15 +145,18 @@ fn manage_principal_address_mappings(principal: &Blob<29>, address: &AddressScri
             });
         }
         if !s.borrow().disable_btc_to_principal_mapping {
-            ADDRESS_PRINCIPAL.with(|ap| {
-                ap.borrow_mut().insert(address.clone(), *principal);
+            with_principal_store(|store| {
+                store.put(
+                    address.0.clone(),
+                    Principal::from_slice(principal.as_slice()),
+                )
             });
         }
     });
 }
 
 #[inline]
-fn controller_guard() -> Result<(), String> {
+pub(crate) fn controller_guard() -> Result<(), String> {
     match is_controller(&ic_cdk::caller()) {
         true => Ok(()),
         false => {
@@ -103,3 +164,101 @@ fn controller_guard() -> Result<(), String> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{login_count, set_principal_store, total_delegations_issued, ADDRESS_PRINCIPAL};
+    use ic_siwb::login::LoginAddressType;
+    use ic_siwb::store::PrincipalStore;
+    use std::cell::RefCell;
+    use std::collections::BTreeMap;
+    use std::rc::Rc;
+
+    /// Records into a map that the test keeps a handle to.
+    struct MockStore(Rc<RefCell<BTreeMap<Vec<u8>, Principal>>>);
+
+    impl PrincipalStore for MockStore {
+        fn get(&self, address: &[u8]) -> Option<Principal> {
+            self.0.borrow().get(address).cloned()
+        }
+
+        fn put(&mut self, address: Vec<u8>, principal: Principal) {
+            self.0.borrow_mut().insert(address, principal);
+        }
+
+        fn remove(&mut self, address: &[u8]) -> Option<Principal> {
+            self.0.borrow_mut().remove(address)
+        }
+
+        fn entries(&self) -> Vec<(Vec<u8>, Principal)> {
+            self.0.borrow().entries()
+        }
+
+        fn clear(&mut self) -> u64 {
+            PrincipalStore::clear(&mut *self.0.borrow_mut())
+        }
+    }
+
+    #[test]
+    fn test_mappings_use_injected_store() {
+        let map = Rc::new(RefCell::new(BTreeMap::new()));
+        set_principal_store(Box::new(MockStore(map.clone())));
+
+        let principal = Principal::from_slice(&[7u8; 29]);
+        let blob: Blob<29> = principal.as_slice().try_into().unwrap();
+        let address = AddressScriptBuf(vec![0, 20, 1, 2, 3]);
+        manage_principal_address_mappings(&blob, &address);
+
+        assert_eq!(map.borrow().get(&address.0), Some(&principal));
+        assert!(ADDRESS_PRINCIPAL.with_borrow(|ap| ap.is_empty()));
+        assert!(PRINCIPAL_ADDRESS.with_borrow(|pa| pa.get(&blob)) == Some(address));
+    }
+
+    #[test]
+    fn test_record_login_without_mapping() {
+        let map = Rc::new(RefCell::new(BTreeMap::new()));
+        set_principal_store(Box::new(MockStore(map.clone())));
+
+        let login_details = LoginDetails {
+            expiration: 0,
+            user_canister_pubkey: ByteBuf::from(vec![1, 2, 3]),
+            public_key: None,
+            request_id: None,
+            address_type: LoginAddressType::P2wpkh,
+            targets: None,
+        };
+        let address = AddressScriptBuf(vec![0, 20, 1, 2, 3]);
+        record_login(&login_details, false, &address, false).unwrap();
+
+        assert!(map.borrow().is_empty());
+        assert!(PRINCIPAL_ADDRESS.with_borrow(|pa| pa.is_empty()));
+
+        record_login(&login_details, false, &address, true).unwrap();
+        assert_eq!(map.borrow().len(), 1);
+        assert_eq!(PRINCIPAL_ADDRESS.with_borrow(|pa| pa.len()), 1);
+    }
+
+    #[test]
+    fn test_record_login_skips_retries() {
+        set_principal_store(Box::new(MockStore(Rc::new(RefCell::new(BTreeMap::new())))));
+
+        let login_details = LoginDetails {
+            expiration: 0,
+            user_canister_pubkey: ByteBuf::from(vec![1, 2, 3]),
+            public_key: None,
+            request_id: None,
+            address_type: LoginAddressType::P2wpkh,
+            targets: None,
+        };
+        let address = AddressScriptBuf(vec![0, 20, 1, 2, 3]);
+        record_login(&login_details, false, &address, true).unwrap();
+        assert_eq!(login_count(&address), 1);
+        assert_eq!(total_delegations_issued(), 1);
+
+        // A retry answered from the retry cache issued no new delegation.
+        record_login(&login_details, true, &address, true).unwrap();
+        assert_eq!(login_count(&address), 1);
+        assert_eq!(total_delegations_issued(), 1);
+    }
+}
@@ -0,0 +1,65 @@
+use ic_cdk::update;
+use ic_siwb::login::{BtcSignature, LoginDetails};
+use ic_siwb::utils::get_script_from_address;
+use serde_bytes::ByteBuf;
+
+use crate::service::siwb_login::record_login;
+use crate::service::types::{AddressScriptBuf, SiwbLoginOptions};
+use crate::{update_root_hash, STATE};
+
+/// Authenticates the user like `siwb_login` with a legacy ECDSA message signature, but without the
+/// public key of the wallet, for wallets that only return a signature. The public key is recovered from
+/// the signature and returned in the login details. Only P2PKH, P2WPKH and P2SH-P2WPKH addresses can
+/// log in this way.
+///
+/// # Arguments
+/// * `signature` (String): The signature of the SIWB message.
+/// * `address` (String): The Bitcoin address of the user.
+/// * `session_key` (ByteBuf): A unique key that identifies the session.
+/// * `options` (Option<SiwbLoginOptions>): Optional login arguments, see [SiwbLoginOptions]. The
+///   `public_keys` are ignored.
+///
+/// # Returns
+/// * `Ok(LoginDetails)`: The login details if the login is successful.
+/// * `Err(String)`: An error message if the login process fails.
+#[update]
+fn siwb_login_by_address(
+    signature: String,
+    address: String,
+    session_key: ByteBuf,
+    options: Option<SiwbLoginOptions>,
+) -> Result<LoginDetails, String> {
+    ic_siwb::init::ensure_initialized()?;
+
+    let options = options.unwrap_or_default();
+
+    STATE.with(|state| {
+        let signature_map = &mut *state.signature_map.borrow_mut();
+
+        // Create an BtcAddress from the string. This validates the address.
+        let address = get_script_from_address(address)?;
+
+        let (login_response, retried) = ic_siwb::login::login_by_address(
+            &BtcSignature(signature),
+            &address.address_raw,
+            session_key,
+            &mut *signature_map,
+            &ic_cdk::api::id(),
+            options.wallet.unwrap_or_default(),
+            options.realm.as_deref(),
+        )
+        .map_err(|e| e.to_string())?;
+
+        // Update the certified data of the canister due to changes in the signature map.
+        update_root_hash(&state.asset_hashes.borrow(), signature_map);
+
+        record_login(
+            &login_response,
+            retried,
+            &AddressScriptBuf(address.script_buf.to_bytes()),
+            options.store_mapping.unwrap_or(true),
+        )?;
+
+        Ok(login_response)
+    })
+}
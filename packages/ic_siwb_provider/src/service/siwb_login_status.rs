@@ -0,0 +1,36 @@
+use ic_cdk::query;
+use ic_siwb::login::LoginStatus;
+use ic_siwb::utils::{get_script_from_address, AddressInfo};
+
+use crate::STATE;
+
+/// Reports the progress of the login for the given Bitcoin address. Frontends of wallets that sign
+/// asynchronously can poll this function instead of retrying `siwb_login`.
+///
+/// # Arguments
+/// * `address` (String): The Bitcoin address of the user.
+/// * `realm` (Option<String>): The login realm that was passed to `siwb_login`, if any.
+///
+/// # Returns
+/// * `Ok(LoginStatus)`: `Pending` while a SIWB message awaits signing, `Ready` once a delegation can be
+///   fetched using `siwb_get_delegation`, and `Expired` otherwise.
+/// * `Err(String)`: An error message if the address is invalid.
+#[query]
+fn siwb_login_status(address: String, realm: Option<String>) -> Result<LoginStatus, String> {
+    ic_siwb::init::ensure_initialized()?;
+
+    // Create an BtcAddress from the string. This validates the address.
+    let AddressInfo {
+        address_raw: address,
+        ..
+    } = get_script_from_address(address)?;
+
+    STATE.with(|s| {
+        let signature_map = s.signature_map.borrow();
+        Ok(ic_siwb::login::login_status(
+            &address,
+            &signature_map,
+            realm.as_deref(),
+        ))
+    })
+}
@@ -0,0 +1,89 @@
+use ic_cdk::api::data_certificate;
+use ic_cdk::update;
+use ic_certified_map::{fork, labeled_hash, AsHashTree, HashTree};
+use ic_siwb::login::{BtcSignature, LoginBundle, SignMessageType};
+use ic_siwb::utils::get_script_from_address;
+use serde_bytes::ByteBuf;
+
+use crate::service::siwb_login::record_login;
+use crate::service::types::{AddressScriptBuf, SiwbLoginOptions};
+use crate::{update_root_hash, LABEL_ASSETS, LABEL_SIG, STATE};
+
+/// Authenticates the user like `siwb_login` and returns the signed delegation in the same response
+/// when it can be certified, saving the `siwb_get_delegation` round-trip.
+///
+/// The signature added by this call is only certified once the call has completed, so the signed
+/// delegation is only returned when a certificate for it is already available. In particular, update
+/// calls have no certificate. When `signed_delegation` is `None`, fetch the delegation with
+/// `siwb_get_delegation` using the expiration of the returned login details.
+///
+/// # Arguments
+/// Same as `siwb_login`.
+///
+/// # Returns
+/// * `Ok(LoginBundle)`: The login details and, if available, the signed delegation.
+/// * `Err(String)`: An error message if the login process fails.
+#[update]
+fn siwb_login_with_delegation(
+    signature: String,
+    address: String,
+    public_key: String,
+    session_key: ByteBuf,
+    sign_message_type: SignMessageType,
+    options: Option<SiwbLoginOptions>,
+) -> Result<LoginBundle, String> {
+    ic_siwb::init::ensure_initialized()?;
+
+    let options = options.unwrap_or_default();
+
+    // The primary public key is tried first, followed by any additional candidates.
+    let mut public_keys = vec![public_key];
+    public_keys.extend(options.public_keys.unwrap_or_default());
+
+    let certificate = data_certificate();
+
+    STATE.with(|state| {
+        let signature_map = &mut *state.signature_map.borrow_mut();
+
+        // Create an BtcAddress from the string. This validates the address.
+        let address = get_script_from_address(address)?;
+
+        if let Some(challenge_token) = &options.challenge_token {
+            ic_siwb::login::check_challenge_token(&address.address_raw, challenge_token)
+                .map_err(|e| e.to_string())?;
+        }
+
+        let assets_hash = labeled_hash(LABEL_ASSETS, &state.asset_hashes.borrow().root_hash());
+        let (bundle, retried) = ic_siwb::login::login_with_delegation(
+            &BtcSignature(signature),
+            &address.address_raw,
+            public_keys,
+            session_key,
+            &mut *signature_map,
+            &ic_cdk::api::id(),
+            sign_message_type,
+            options.wallet.unwrap_or_default(),
+            options.realm.as_deref(),
+            certificate.as_deref(),
+            |signature_witness| {
+                fork(
+                    HashTree::Pruned(assets_hash),
+                    ic_certified_map::labeled(LABEL_SIG, signature_witness),
+                )
+            },
+        )
+        .map_err(|e| e.to_string())?;
+
+        // Update the certified data of the canister due to changes in the signature map.
+        update_root_hash(&state.asset_hashes.borrow(), signature_map);
+
+        record_login(
+            &bundle.login_details,
+            retried,
+            &AddressScriptBuf(address.script_buf.to_bytes()),
+            options.store_mapping.unwrap_or(true),
+        )?;
+
+        Ok(bundle)
+    })
+}
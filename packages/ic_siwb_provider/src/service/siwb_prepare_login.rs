@@ -1,14 +1,109 @@
 use ic_cdk::update;
+use ic_siwb::login::PreparedMessage;
 use ic_siwb::utils::get_script_from_address;
 
-// Prepare the login by generating a challenge (the SIWB message) and returning it to the caller.
+use crate::service::types::{PrepareLoginDetails, PrepareLoginError};
+
+// Prepare the login by generating a challenge (the SIWB message) and returning it to the caller, as the
+// string to be signed and as JSON, along with its expiration time and challenge token. `issued_at`
+// optionally overrides the issue time of the message, it must be within the configured
+// `max_issued_at_skew` of the current time. `domain` optionally selects the frontend the message is for,
+// it must be the configured domain or one of the `allowed_domains`. `request_id` is optionally rendered
+// into the message and returned in the login details, to correlate the login with a server-side request.
+// With `bind_canister_id` enabled, the message names this canister. Errors are reported as a
+// [PrepareLoginError], so that frontends can handle each case.
 #[update]
-fn siwb_prepare_login(address: String) -> Result<String, String> {
+pub(crate) fn siwb_prepare_login(
+    address: String,
+    issued_at: Option<u64>,
+    domain: Option<String>,
+    request_id: Option<String>,
+) -> Result<PrepareLoginDetails, PrepareLoginError> {
+    ic_siwb::init::ensure_initialized()?;
+
     // Create an BtcAddress from the string. This validates the address.
-    let address = get_script_from_address(address)?;
+    let address = get_script_from_address(address).map_err(PrepareLoginError::InvalidAddress)?;
+
+    match ic_siwb::login::prepare_login_for_canister(
+        &address.address_raw,
+        &ic_cdk::api::id(),
+        domain.as_deref(),
+        issued_at,
+        request_id.as_deref(),
+    ) {
+        Ok(m) => {
+            let expiration = m.expiration_time;
+            let challenge_token = m.challenge_token().to_string();
+            let prepared = PreparedMessage::from(m);
+            Ok(PrepareLoginDetails {
+                expiration,
+                challenge_token,
+                siwb_message: prepared.string,
+                siwb_message_json: prepared.json,
+            })
+        }
+        Err(e) => Err(e.into()), // Converts BtcError to PrepareLoginError
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_siwb::error::BtcError;
+    use ic_siwb::settings::SettingsBuilder;
+
+    #[test]
+    fn test_invalid_address() {
+        let settings = SettingsBuilder::new("example.com", "http://example.com", "some_salt")
+            .build()
+            .unwrap();
+        ic_siwb::init::init(settings).unwrap();
+
+        assert!(matches!(
+            siwb_prepare_login("no address".to_string(), None, None, None),
+            Err(PrepareLoginError::InvalidAddress(_))
+        ));
+    }
+
+    #[test]
+    fn test_prepare_login_error_from_btc_error() {
+        let cases = [
+            (
+                BtcError::AddressFormatError("bad checksum".to_string()),
+                PrepareLoginError::InvalidAddress("Address format error: bad checksum".to_string()),
+            ),
+            (
+                BtcError::AddressTypeNotSupported,
+                PrepareLoginError::InvalidAddress("Address type not supported".to_string()),
+            ),
+            (
+                BtcError::NetworkMismatch,
+                PrepareLoginError::NetworkMismatch,
+            ),
+            (
+                BtcError::AddressTypeNotAllowed,
+                PrepareLoginError::AddressTypeNotAllowed,
+            ),
+            (
+                BtcError::DomainNotAllowed,
+                PrepareLoginError::DomainNotAllowed,
+            ),
+            (
+                BtcError::IssuedAtOutOfBounds,
+                PrepareLoginError::IssuedAtOutOfBounds,
+            ),
+            (
+                BtcError::MessageTooLong(4096),
+                PrepareLoginError::MessageTooLarge(4096),
+            ),
+        ];
+        for (error, expected) in cases {
+            assert_eq!(PrepareLoginError::from(error), expected);
+        }
 
-    match ic_siwb::login::prepare_login(&address.address_raw) {
-        Ok(m) => Ok(m.into()),   // Converts SiwbMessage to String
-        Err(e) => Err(e.into()), // Converts BtcError to String
+        assert!(matches!(
+            PrepareLoginError::from(BtcError::RngNotSeeded),
+            PrepareLoginError::Other(_)
+        ));
     }
 }
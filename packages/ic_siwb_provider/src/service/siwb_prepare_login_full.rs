@@ -0,0 +1,25 @@
+use ic_cdk::update;
+use ic_siwb::login::{PrepareLoginOptions, PreparedLogin};
+use ic_siwb::utils::get_script_from_address;
+
+// Prepare the login like `siwb_prepare_login`, with the domain, statement, nonce and URI of the message
+// optionally overridden by `options`. The domain, statement and URI must be the configured ones or be
+// listed in `allowed_domains`, `allowed_statements` and `allowed_uris`. Returns the message along with
+// the hex encoded digest that a legacy message signature signs.
+#[update]
+pub(crate) fn siwb_prepare_login_full(
+    address: String,
+    options: Option<PrepareLoginOptions>,
+) -> Result<PreparedLogin, String> {
+    ic_siwb::init::ensure_initialized()?;
+
+    // Create an BtcAddress from the string. This validates the address.
+    let address = get_script_from_address(address)?;
+
+    ic_siwb::login::prepare_login_full(
+        &address.address_raw,
+        Some(&ic_cdk::api::id()),
+        &options.unwrap_or_default(),
+    )
+    .map_err(|e| e.into())
+}
@@ -0,0 +1,54 @@
+use ic_cdk::update;
+use ic_siwb::delegation::{revoke, user_principal};
+use ic_siwb::utils::{get_script_from_address, AddressInfo};
+use serde_bytes::ByteBuf;
+
+use crate::{update_root_hash, STATE};
+
+/// Revokes a delegation created by `siwb_login`, e.g. on logout, so that it can no longer be retrieved
+/// using `siwb_get_delegation`. Only the principal the delegation was created for can revoke it. A
+/// delegation that has already been retrieved stays valid until it expires.
+///
+/// # Arguments
+/// * `address` (String): The Bitcoin address of the user.
+/// * `session_key` (ByteBuf): The session key the delegation was created for.
+/// * `expiration` (u64): The expiration time of the delegation in nanoseconds since the UNIX epoch.
+/// * `realm` (Option<String>): The login realm that was passed to `siwb_login`, if any.
+///
+/// # Returns
+/// * `Ok(())`: If the delegation was revoked.
+/// * `Err(String)`: An error message if the caller does not own the delegation or there is no such delegation.
+#[update]
+fn siwb_revoke_delegation(
+    address: String,
+    session_key: ByteBuf,
+    expiration: u64,
+    realm: Option<String>,
+) -> Result<(), String> {
+    ic_siwb::init::ensure_initialized()?;
+
+    // Create an BtcAddress from the string. This validates the address.
+    let AddressInfo {
+        address_raw: address,
+        ..
+    } = get_script_from_address(address)?;
+
+    if user_principal(&ic_cdk::id(), &address, realm.as_deref())? != ic_cdk::caller() {
+        return Err("Only the owner of the delegation can revoke it".to_string());
+    }
+
+    STATE.with(|state| {
+        let signature_map = &mut *state.signature_map.borrow_mut();
+        revoke(
+            signature_map,
+            &address,
+            session_key,
+            expiration,
+            realm.as_deref(),
+        )?;
+
+        // Update the certified data of the canister due to changes in the signature map.
+        update_root_hash(&state.asset_hashes.borrow(), signature_map);
+        Ok(())
+    })
+}
@@ -1,7 +1,13 @@
 use std::borrow::Cow;
 
+use candid::CandidType;
+use ic_siwb::delegation::MAX_SESSION_KEY_LEN;
+use ic_siwb::error::BtcError;
+use ic_siwb::init::NotInitialized;
+use ic_siwb::verify::WalletKind;
 use ic_stable_structures::storable::Bound;
 use ic_stable_structures::Storable;
+use serde::Deserialize;
 
 #[derive(Ord, Eq, PartialEq, PartialOrd, Clone)]
 pub struct AddressScriptBuf(pub Vec<u8>);
@@ -21,6 +27,192 @@ impl Storable for AddressScriptBuf {
     };
 }
 
+/// A DER encoded session key, the key of the session keys managed with `allow_session_key` and
+/// `disallow_session_key`.
+#[derive(Ord, Eq, PartialEq, PartialOrd, Clone)]
+pub struct SessionKeyBuf(pub Vec<u8>);
+
+impl Storable for SessionKeyBuf {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        self.0.to_bytes()
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Self(bytes.to_vec())
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: MAX_SESSION_KEY_LEN as u32,
+        is_fixed_size: false,
+    };
+}
+
+/// Whether a controller allowed or disallowed a session key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SessionKeyStatus {
+    Allowed,
+    Disallowed,
+}
+
+impl Storable for SessionKeyStatus {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        match self {
+            SessionKeyStatus::Allowed => Cow::Borrowed(&[1]),
+            SessionKeyStatus::Disallowed => Cow::Borrowed(&[0]),
+        }
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        match bytes.as_ref() {
+            [0] => SessionKeyStatus::Disallowed,
+            _ => SessionKeyStatus::Allowed,
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 1,
+        is_fixed_size: true,
+    };
+}
+
+/// The salt and salt generation set by `rotate_salt`. They are kept in stable memory, so that an
+/// upgrade with the salt of the original settings cannot bring back the rotated salt.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct SaltRotation {
+    pub salt: String,
+    pub salt_generation: u64,
+}
+
+impl Storable for SaltRotation {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(candid::encode_one(self).expect("Failed to encode the salt rotation"))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).expect("Failed to decode the salt rotation")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Optional arguments for `siwb_login`. Every field is optional, so callers that omit the argument
+/// entirely, or omit individual fields, keep working as new options are added.
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct SiwbLoginOptions {
+    /// Additional candidate public keys, tried in order after `public_key`. Useful for wallets that
+    /// expose several keys and cannot tell which one signed the message.
+    pub public_keys: Option<Vec<String>>,
+
+    /// The login realm. The same address derives a different principal in each realm, which allows
+    /// several independent apps to share one provider. The same realm must be passed to
+    /// `siwb_get_delegation`.
+    pub realm: Option<String>,
+
+    /// The layout of an ECDSA signature, selects how the signature is decoded. Defaults to trying each known
+    /// signature layout.
+    pub wallet: Option<WalletKind>,
+
+    /// Whether the principal to address mappings are stored for this login. Defaults to the settings,
+    /// set it to `false` for an ephemeral session that leaves no mapping behind. Setting it to `true`
+    /// does not enable mappings that are disabled in the settings.
+    pub store_mapping: Option<bool>,
+
+    /// The recovery id, 0 to 3, of an ECDSA signature, used instead of the recovery byte of the signature,
+    /// which may then be just `r || s`. Only used by `siwb_login`.
+    pub recovery_id: Option<u8>,
+
+    /// The `challenge_token` returned by `siwb_prepare_login` along with the signed message. When passed,
+    /// a login with an expired challenge fails with a distinct "Challenge has expired" error.
+    pub challenge_token: Option<String>,
+}
+
+/// Returned by `siwb_prepare_login`. Contains the SIWB message to be signed and the time at which it
+/// expires, so that frontends can prompt the user to prepare a new message in time.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct PrepareLoginDetails {
+    /// The SIWB message to be signed by the wallet.
+    pub siwb_message: String,
+
+    /// The same SIWB message as a JSON object, for frontends that render the message themselves.
+    pub siwb_message_json: String,
+
+    /// The expiration time of the SIWB message in nanoseconds since the UNIX epoch.
+    pub expiration: u64,
+
+    /// A token for the challenge, `<expiration>.<nonce>`, to be passed back in the login options, so
+    /// that a login with an expired challenge is reported as such.
+    pub challenge_token: String,
+}
+
+/// The error returned by `siwb_prepare_login`, so that frontends can tell apart the cases they can
+/// recover from, e.g. by asking the user for an address of another network, from those they cannot.
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum PrepareLoginError {
+    /// The canister is being installed or upgraded, see `is_initialized`.
+    NotInitialized,
+
+    /// The address cannot be parsed, or is of a type that cannot sign in at all.
+    InvalidAddress(String),
+
+    /// The address is not valid for any of the allowed networks.
+    NetworkMismatch,
+
+    /// The address is of a type that is not among the allowed address types.
+    AddressTypeNotAllowed,
+
+    /// The requested domain is neither the configured domain nor one of the allowed domains.
+    DomainNotAllowed,
+
+    /// The requested `issued_at` is further from the current time than the configured skew allows.
+    IssuedAtOutOfBounds,
+
+    /// The message would be longer than the given maximum number of bytes.
+    MessageTooLarge(u64),
+
+    /// Any other error, e.g. randomness that is not available yet right after an upgrade.
+    Other(String),
+}
+
+impl From<NotInitialized> for PrepareLoginError {
+    fn from(_: NotInitialized) -> Self {
+        PrepareLoginError::NotInitialized
+    }
+}
+
+impl From<BtcError> for PrepareLoginError {
+    fn from(error: BtcError) -> Self {
+        match error {
+            BtcError::AddressFormatError(_)
+            | BtcError::ScriptConversionError(_)
+            | BtcError::AddressTypeNotSupported => {
+                PrepareLoginError::InvalidAddress(error.to_string())
+            }
+            BtcError::NetworkMismatch => PrepareLoginError::NetworkMismatch,
+            BtcError::AddressTypeNotAllowed => PrepareLoginError::AddressTypeNotAllowed,
+            BtcError::DomainNotAllowed => PrepareLoginError::DomainNotAllowed,
+            BtcError::IssuedAtOutOfBounds => PrepareLoginError::IssuedAtOutOfBounds,
+            BtcError::MessageTooLong(max_bytes) => {
+                PrepareLoginError::MessageTooLarge(max_bytes as u64)
+            }
+            error => PrepareLoginError::Other(error.to_string()),
+        }
+    }
+}
+
+/// Returned by `signature_map_stats`. Describes the delegation signatures awaiting retrieval.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SignatureMapStats {
+    /// The number of delegation hashes in the signature map.
+    pub len: u64,
+
+    /// The hex encoded root hash of the signature map, which is part of the certified data.
+    pub root_hex: String,
+
+    /// The number of delegations issued since the canister was installed, including those that have
+    /// since expired or been pruned.
+    pub total_delegations_issued: u64,
+}
+
 // #[derive(CandidType, Serialize, Deserialize)]
 // pub struct SiwbLoginParams {
 //     pub signature: String,
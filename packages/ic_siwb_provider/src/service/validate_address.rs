@@ -0,0 +1,19 @@
+use ic_cdk::query;
+use ic_siwb::login::AddressMeta;
+
+/// Validates a user-entered Bitcoin address without side effects, so that frontends can give
+/// immediate feedback before calling `siwb_prepare_login`. The address must be of one of the allowed
+/// networks and address types.
+///
+/// # Arguments
+/// * `address` (String): The Bitcoin address to validate.
+///
+/// # Returns
+/// * `Ok(AddressMeta)`: The network the address logs in on, its type and its canonical form.
+/// * `Err(String)`: An error message if the address is invalid or cannot log in.
+#[query]
+fn validate_address(address: String) -> Result<AddressMeta, String> {
+    ic_siwb::init::ensure_initialized()?;
+
+    ic_siwb::login::validate_address(&address).map_err(String::from)
+}